@@ -0,0 +1,128 @@
+/*!
+Deciding which records get operated on at all.
+
+Records that aren't selected are passed through untouched when
+replacing, and ignored when extracting.
+*/
+use std::str::FromStr;
+
+use regex::bytes::Regex;
+
+/**
+A range of record numbers (counting from 1), written `START:END` or
+`START:END:STEP`. Both ends are inclusive, and either may be omitted to
+leave that end of the range open. A lone `N` is the range `N:N`.
+*/
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct LineRange {
+    start: usize,
+    end: Option<usize>,
+    step: usize,
+}
+
+impl LineRange {
+    pub fn contains(&self, n: usize) -> bool {
+        n >= self.start
+            && self.end.map_or(true, |end| n <= end)
+            && (n - self.start) % self.step == 0
+    }
+}
+
+impl FromStr for LineRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let parse = |part: &str| -> Result<Option<usize>, String> {
+            if part.is_empty() {
+                Ok(None)
+            } else {
+                part.parse::<usize>()
+                    .map(Some)
+                    .map_err(|e| format!("invalid line number {:?}: {}", part, e))
+            }
+        };
+
+        let parts: Vec<&str> = s.split(':').collect();
+        let (start, end, step) = match parts[..] {
+            [n] => {
+                let n = parse(n)?;
+                (n, n, None)
+            }
+            [start, end] => (parse(start)?, parse(end)?, None),
+            [start, end, step] => (parse(start)?, parse(end)?, parse(step)?),
+            _ => return Err("expected START:END or START:END:STEP".into()),
+        };
+
+        let range = LineRange {
+            start: start.unwrap_or(1),
+            end,
+            step: step.unwrap_or(1),
+        };
+        if range.start == 0 || range.step == 0 {
+            return Err("line numbers and steps start at 1".into());
+        }
+        Ok(range)
+    }
+}
+
+#[derive(Debug, Default)]
+pub struct Address {
+    /// Only operate on records that match this.
+    pub guard: Option<Regex>,
+    /// Don't operate on records that match this.
+    pub unless: Option<Regex>,
+    /// If not empty, only operate on records in one of these ranges.
+    pub lines: Vec<LineRange>,
+    /// Only operate on blocks of records that start with one matching
+    /// the first regex and end with one matching the second.
+    pub between: Option<(Regex, Regex)>,
+    /// Number of records seen so far.
+    n_records: usize,
+    /// Whether the last record was inside a `between` block (and not the
+    /// end of one).
+    in_block: bool,
+}
+
+impl Address {
+    /**
+    Whether `record` should be operated on. This needs to be called once
+    for each record, in order, so record numbers can be tracked.
+    */
+    pub fn selects(&mut self, record: &[u8]) -> bool {
+        self.n_records += 1;
+
+        // As in sed, the end of a block is only looked for starting with
+        // the record after the one that began it.
+        if let Some((ref start, ref end)) = self.between {
+            if self.in_block {
+                self.in_block = !end.is_match(record);
+            } else if start.is_match(record) {
+                self.in_block = true;
+            } else {
+                return false;
+            }
+        }
+
+        if !self.lines.is_empty() && !self.lines.iter().any(|r| r.contains(self.n_records)) {
+            return false;
+        }
+        if let Some(ref re) = self.guard {
+            if !re.is_match(record) {
+                return false;
+            }
+        }
+        if let Some(ref re) = self.unless {
+            if re.is_match(record) {
+                return false;
+            }
+        }
+        true
+    }
+
+    /// Start counting records (and looking for blocks) over again, as for
+    /// a new file.
+    pub fn reset(&mut self) {
+        self.n_records = 0;
+        self.in_block = false;
+    }
+}
@@ -0,0 +1,571 @@
+/*!
+Reading the members of zip and tar archives.
+
+An archive is recognized by its contents: a zip file by its signature, a
+tar file (which may be gzipped, as a `.tar.gz` or `.tgz`) by its first
+header's checksum. Each regular file in it is read as a separate input;
+directories, links and the like are passed over.
+
+Zip members may be stored or deflated (the latter needs the `gzip`
+feature), but not encrypted, and ZIP64 archives (over 4 GiB) aren't
+supported. The whole of a zip file is read into memory, since its index is
+at the end.
+*/
+use std::{
+    cell::{Cell, RefCell},
+    io::{self, Cursor, Read, Write},
+    rc::Rc,
+};
+
+use crate::decompress;
+
+/// A file in an archive.
+pub struct Member {
+    /// Its path in the archive.
+    pub name: String,
+    pub data: Box<dyn Read>,
+}
+
+type Members = Box<dyn Iterator<Item = io::Result<Member>>>;
+
+fn invalid(msg: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, msg)
+}
+
+const TAR_BLOCK: usize = 512;
+
+/*
+Whether `header` is a tar header, going by its checksum (which is of the
+header with the checksum field itself taken as spaces).
+*/
+fn is_tar_header(header: &[u8]) -> bool {
+    if header.len() < TAR_BLOCK {
+        return false;
+    }
+    let sum: u32 = header[..TAR_BLOCK]
+        .iter()
+        .enumerate()
+        .map(|(n, &b)| {
+            if (148..156).contains(&n) {
+                32
+            } else {
+                u32::from(b)
+            }
+        })
+        .sum();
+    octal(&header[148..156]) == Some(u64::from(sum))
+}
+
+/* The value of a NUL- or space-terminated octal field of a tar header. */
+fn octal(field: &[u8]) -> Option<u64> {
+    // Large sizes are stored in binary, flagged by the top bit.
+    if field.first().is_some_and(|b| b & 0x80 != 0) {
+        return Some(field[1..].iter().fold(0, |n, &b| n << 8 | u64::from(b)));
+    }
+    let digits = field
+        .iter()
+        .skip_while(|&&b| b == b' ')
+        .take_while(|&&b| (b'0'..=b'7').contains(&b));
+    let mut n: u64 = 0;
+    let mut any = false;
+    for &d in digits {
+        n = n * 8 + u64::from(d - b'0');
+        any = true;
+    }
+    any.then_some(n)
+}
+
+/* A NUL-terminated string field. */
+fn text(field: &[u8]) -> String {
+    let len = field.iter().position(|&b| b == 0).unwrap_or(field.len());
+    String::from_utf8_lossy(&field[..len]).into_owned()
+}
+
+/// Iterates over the regular files in a tar archive.
+struct Tar<R: Read> {
+    inner: R,
+    /// The name for the next member, from a GNU or pax extended header.
+    long_name: Option<String>,
+    done: bool,
+}
+
+impl<R: Read> Tar<R> {
+    fn member(&mut self) -> io::Result<Option<Member>> {
+        loop {
+            let mut header = [0u8; TAR_BLOCK];
+            if let Err(e) = self.inner.read_exact(&mut header) {
+                return match e.kind() {
+                    // Some writers leave off the end-of-archive blocks.
+                    io::ErrorKind::UnexpectedEof => Ok(None),
+                    _ => Err(e),
+                };
+            }
+            if header.iter().all(|&b| b == 0) {
+                return Ok(None);
+            }
+            if !is_tar_header(&header) {
+                return Err(invalid("tar: bad header checksum".into()));
+            }
+            let size =
+                octal(&header[124..136]).ok_or_else(|| invalid("tar: bad member size".into()))?;
+            let mut data: Vec<u8> = Vec::new();
+            (&mut self.inner).take(size).read_to_end(&mut data)?;
+            if (data.len() as u64) < size {
+                return Err(invalid("tar: truncated archive".into()));
+            }
+            let padding = (TAR_BLOCK as u64 - size % TAR_BLOCK as u64) % TAR_BLOCK as u64;
+            io::copy(&mut (&mut self.inner).take(padding), &mut io::sink())?;
+
+            match header[156] {
+                b'L' => self.long_name = Some(text(&data)),
+                b'x' => {
+                    if let Some(path) = pax_path(&data) {
+                        self.long_name = Some(path);
+                    }
+                }
+                b'0' | 0 => {
+                    let name = self.long_name.take().unwrap_or_else(|| {
+                        let (name, prefix) = (text(&header[..100]), text(&header[345..500]));
+                        match header[257..262] == *b"ustar" && !prefix.is_empty() {
+                            true => format!("{}/{}", prefix, name),
+                            false => name,
+                        }
+                    });
+                    // As written by `tar cf x.tar .`, for one.
+                    let mut name = name.as_str();
+                    while let Some(rest) = name.strip_prefix("./") {
+                        name = rest;
+                    }
+                    return Ok(Some(Member {
+                        name: name.to_string(),
+                        data: Box::new(Cursor::new(data)),
+                    }));
+                }
+                _ => self.long_name = None,
+            }
+        }
+    }
+}
+
+/* The path given in a pax extended header, if any. */
+fn pax_path(data: &[u8]) -> Option<String> {
+    // Each record is "<length> <key>=<value>\n".
+    let text = String::from_utf8_lossy(data);
+    text.lines()
+        .rev()
+        .find_map(|record| record.split_once(' ')?.1.strip_prefix("path="))
+        .map(String::from)
+}
+
+impl<R: Read> Iterator for Tar<R> {
+    type Item = io::Result<Member>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let member = self.member().transpose();
+        // There's no telling where the next member starts after an error.
+        self.done = !matches!(member, Some(Ok(_)));
+        member
+    }
+}
+
+fn u16_at(data: &[u8], pos: usize) -> Option<usize> {
+    let bytes = data.get(pos..pos + 2)?;
+    Some(u16::from_le_bytes([bytes[0], bytes[1]]) as usize)
+}
+
+fn u32_at(data: &[u8], pos: usize) -> Option<usize> {
+    let bytes = data.get(pos..pos + 4)?;
+    Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize)
+}
+
+const ZIP_LOCAL: &[u8] = b"PK\x03\x04";
+const ZIP_CENTRAL: &[u8] = b"PK\x01\x02";
+const ZIP_END: &[u8] = b"PK\x05\x06";
+/// The length of the end of central directory record, before its comment.
+const ZIP_END_LEN: usize = 22;
+
+/// A file in a zip archive, as found in its central directory.
+struct ZipEntry {
+    name: String,
+    method: usize,
+    flags: usize,
+    size: usize,
+    /// Where its local header is.
+    offset: usize,
+}
+
+/*
+Read the central directory of the zip file `data`, listing its regular
+files.
+*/
+fn zip_entries(data: &[u8]) -> Option<Vec<ZipEntry>> {
+    // The end record is last, but may be followed by a comment.
+    let end = (0..=data.len().checked_sub(ZIP_END_LEN)?)
+        .rev()
+        .find(|&pos| data[pos..].starts_with(ZIP_END))?;
+    let count = u16_at(data, end + 10)?;
+    let mut pos = u32_at(data, end + 16)?;
+    let mut entries: Vec<ZipEntry> = Vec::with_capacity(count);
+    for _ in 0..count {
+        if !data.get(pos..)?.starts_with(ZIP_CENTRAL) {
+            return None;
+        }
+        let name_len = u16_at(data, pos + 28)?;
+        let name = data.get(pos + 46..pos + 46 + name_len)?;
+        let entry = ZipEntry {
+            name: String::from_utf8_lossy(name).into_owned(),
+            method: u16_at(data, pos + 10)?,
+            flags: u16_at(data, pos + 8)?,
+            size: u32_at(data, pos + 20)?,
+            offset: u32_at(data, pos + 42)?,
+        };
+        pos += 46 + name_len + u16_at(data, pos + 30)? + u16_at(data, pos + 32)?;
+        if !entry.name.ends_with('/') {
+            entries.push(entry);
+        }
+    }
+    Some(entries)
+}
+
+/* Open `entry` of the zip file `data`. */
+fn zip_member(data: &Rc<Vec<u8>>, entry: ZipEntry) -> io::Result<Member> {
+    let fail = |msg: &str| invalid(format!("{}: {}", entry.name, msg));
+    if entry.offset == u32::MAX as usize || entry.size == u32::MAX as usize {
+        return Err(fail("ZIP64 archives aren't supported"));
+    }
+    if entry.flags & 1 != 0 {
+        return Err(fail("encrypted members aren't supported"));
+    }
+    let pos = entry.offset;
+    let start = match (u16_at(data, pos + 26), u16_at(data, pos + 28)) {
+        (Some(name_len), Some(extra_len)) if data[pos..].starts_with(ZIP_LOCAL) => {
+            pos + 30 + name_len + extra_len
+        }
+        _ => return Err(fail("bad local header")),
+    };
+    let raw = data
+        .get(start..start + entry.size)
+        .ok_or_else(|| fail("truncated archive"))?
+        .to_vec();
+    let data: Box<dyn Read> = match entry.method {
+        0 => Box::new(Cursor::new(raw)),
+        #[cfg(feature = "gzip")]
+        8 => decompress::inflate(Box::new(Cursor::new(raw))),
+        n => return Err(fail(&format!("compression method {} isn't supported", n))),
+    };
+    Ok(Member {
+        name: entry.name,
+        data,
+    })
+}
+
+/**
+Iterate over the regular files in the archive `input`, which may be a zip
+or (possibly gzipped) tar file.
+*/
+pub fn members(input: Box<dyn Read>) -> io::Result<Members> {
+    let mut input = decompress::wrap(input)?;
+    let mut head: Vec<u8> = Vec::with_capacity(TAR_BLOCK);
+    (&mut input).take(TAR_BLOCK as u64).read_to_end(&mut head)?;
+    if head.starts_with(ZIP_LOCAL) || head.starts_with(ZIP_END) {
+        let mut data = head;
+        input.read_to_end(&mut data)?;
+        let entries = zip_entries(&data).ok_or_else(|| invalid("bad zip file".into()))?;
+        let data = Rc::new(data);
+        Ok(Box::new(
+            entries
+                .into_iter()
+                .map(move |entry| zip_member(&data, entry)),
+        ))
+    } else if head.is_empty() || is_tar_header(&head) {
+        Ok(Box::new(Tar {
+            inner: Cursor::new(head).chain(input),
+            long_name: None,
+            done: false,
+        }))
+    } else {
+        Err(invalid("not a zip or tar archive".into()))
+    }
+}
+
+/// The prefix a `Prefixed` writer puts on each line, shared with whatever
+/// changes it between members.
+#[derive(Default)]
+pub struct Prefix {
+    text: RefCell<Vec<u8>>,
+    /// Set if the last line written didn't end (yet).
+    mid_line: Cell<bool>,
+}
+
+impl Prefix {
+    /// Use `text` as the prefix from the next line on.
+    pub fn set(&self, text: Vec<u8>) {
+        self.text.replace(text);
+    }
+
+    /// Whether the last line written is unfinished, as the last line of a
+    /// member without a newline at the end would be.
+    pub fn mid_line(&self) -> bool {
+        self.mid_line.get()
+    }
+}
+
+/**
+Writes each line of output with a prefix (naming the member it came from),
+which can be changed between members.
+*/
+pub struct Prefixed {
+    inner: Box<dyn Write>,
+    prefix: Rc<Prefix>,
+}
+
+impl Prefixed {
+    pub fn new(inner: Box<dyn Write>, prefix: Rc<Prefix>) -> Prefixed {
+        Prefixed { inner, prefix }
+    }
+}
+
+impl Write for Prefixed {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for line in buf.split_inclusive(|&b| b == b'\n') {
+            if !self.prefix.mid_line() {
+                self.inner.write_all(&self.prefix.text.borrow())?;
+            }
+            self.inner.write_all(line)?;
+            self.prefix.mid_line.set(!line.ends_with(b"\n"));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* A tar header for a member of type `kind`, of `size` bytes. */
+    fn tar_header(name: &str, size: usize, kind: u8) -> Vec<u8> {
+        let mut header = vec![0u8; TAR_BLOCK];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", size).as_bytes());
+        header[156] = kind;
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        header
+    }
+
+    /* A tar member of type `kind`, with its data padded out to a block. */
+    fn tar_member(name: &str, kind: u8, data: &[u8]) -> Vec<u8> {
+        let mut member = tar_header(name, data.len(), kind);
+        member.extend_from_slice(data);
+        member.resize(member.len().div_ceil(TAR_BLOCK) * TAR_BLOCK, 0);
+        member
+    }
+
+    /*
+    A zip file of these members: names, compression methods, flags, and
+    (already compressed) data.
+    */
+    fn zip(members: &[(&str, u16, u16, &[u8])]) -> Vec<u8> {
+        let (mut file, mut central): (Vec<u8>, Vec<u8>) = (Vec::new(), Vec::new());
+        for &(name, method, flags, data) in members {
+            let offset = file.len() as u32;
+            let (name_len, size) = (name.len() as u16, data.len() as u32);
+            file.extend_from_slice(ZIP_LOCAL);
+            file.extend_from_slice(&[20, 0]);
+            file.extend_from_slice(&flags.to_le_bytes());
+            file.extend_from_slice(&method.to_le_bytes());
+            file.extend_from_slice(&[0; 8]);
+            file.extend_from_slice(&size.to_le_bytes());
+            file.extend_from_slice(&size.to_le_bytes());
+            file.extend_from_slice(&name_len.to_le_bytes());
+            file.extend_from_slice(&[0, 0]);
+            file.extend_from_slice(name.as_bytes());
+            file.extend_from_slice(data);
+
+            central.extend_from_slice(ZIP_CENTRAL);
+            central.extend_from_slice(&[20, 0, 20, 0]);
+            central.extend_from_slice(&flags.to_le_bytes());
+            central.extend_from_slice(&method.to_le_bytes());
+            central.extend_from_slice(&[0; 8]);
+            central.extend_from_slice(&size.to_le_bytes());
+            central.extend_from_slice(&size.to_le_bytes());
+            central.extend_from_slice(&name_len.to_le_bytes());
+            central.extend_from_slice(&[0; 12]);
+            central.extend_from_slice(&offset.to_le_bytes());
+            central.extend_from_slice(name.as_bytes());
+        }
+        let (count, central_len) = (members.len() as u16, central.len() as u32);
+        let central_at = file.len() as u32;
+        file.append(&mut central);
+        file.extend_from_slice(ZIP_END);
+        file.extend_from_slice(&[0; 4]);
+        file.extend_from_slice(&count.to_le_bytes());
+        file.extend_from_slice(&count.to_le_bytes());
+        file.extend_from_slice(&central_len.to_le_bytes());
+        file.extend_from_slice(&central_at.to_le_bytes());
+        file.extend_from_slice(&[0, 0]);
+        file
+    }
+
+    /* The names and contents of the members of `archive`, or an error. */
+    fn read(archive: Vec<u8>) -> io::Result<Vec<(String, String)>> {
+        members(Box::new(Cursor::new(archive)))?
+            .map(|member| {
+                let mut member = member?;
+                let mut text = String::new();
+                member.data.read_to_string(&mut text)?;
+                Ok((member.name, text))
+            })
+            .collect()
+    }
+
+    fn named(members: &[(&str, &str)]) -> Vec<(String, String)> {
+        members
+            .iter()
+            .map(|(name, text)| (name.to_string(), text.to_string()))
+            .collect()
+    }
+
+    #[test]
+    fn tar_members() {
+        let mut archive: Vec<u8> = Vec::new();
+        archive.extend(tar_member("dir/", b'5', b""));
+        archive.extend(tar_member("dir/a.txt", b'0', b"alpha\n"));
+        archive.extend(tar_member("link", b'2', b""));
+        archive.extend(tar_member("./b.txt", b'0', &[b'b'; 600]));
+        archive.extend(tar_member("empty", 0, b""));
+        archive.extend([0; 2 * TAR_BLOCK]);
+        let b = "b".repeat(600);
+        let expected = [
+            ("dir/a.txt", "alpha\n"),
+            ("b.txt", b.as_str()),
+            ("empty", ""),
+        ];
+        assert_eq!(read(archive).unwrap(), named(&expected));
+    }
+
+    #[test]
+    fn tar_long_names() {
+        let long = format!("{}/file.txt", "d".repeat(120));
+        let mut archive: Vec<u8> = Vec::new();
+        archive.extend(tar_member(
+            "././@LongLink",
+            b'L',
+            format!("{}\0", long).as_bytes(),
+        ));
+        archive.extend(tar_member("truncated", b'0', b"gnu"));
+        let pax = b"30 mtime=1700000000.123456789\n21 path=pax/name.txt\n";
+        archive.extend(tar_member("PaxHeaders/x", b'x', pax));
+        archive.extend(tar_member("short", b'0', b"pax"));
+        let mut ustar = tar_member("name.txt", b'0', b"ustar");
+        ustar[345..351].copy_from_slice(b"prefix");
+        ustar[148..156].copy_from_slice(b"        ");
+        let sum: u32 = ustar[..TAR_BLOCK].iter().map(|&b| u32::from(b)).sum();
+        ustar[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        archive.extend(ustar);
+        // Some writers leave off the blocks of zeros at the end.
+        let expected = [
+            (long.as_str(), "gnu"),
+            ("pax/name.txt", "pax"),
+            ("prefix/name.txt", "ustar"),
+        ];
+        assert_eq!(read(archive).unwrap(), named(&expected));
+    }
+
+    #[test]
+    fn bad_tar() {
+        let mut archive = tar_member("a.txt", b'0', b"alpha");
+        archive.extend(tar_member("b.txt", b'0', b"beta"));
+        archive[2 * TAR_BLOCK + 1] ^= 1;
+        let mut members = members(Box::new(Cursor::new(archive))).unwrap();
+        assert_eq!(members.next().unwrap().unwrap().name, "a.txt");
+        assert!(members.next().unwrap().is_err());
+        assert!(members.next().is_none());
+
+        let mut archive = tar_member("a.txt", b'0', &[b'a'; 1000]);
+        archive.truncate(TAR_BLOCK + 100);
+        assert!(read(archive).is_err());
+
+        assert_eq!(read(Vec::new()).unwrap(), Vec::new());
+        assert!(read(b"just some text".to_vec()).is_err());
+    }
+
+    #[test]
+    fn zip_members() {
+        let archive = zip(&[
+            ("dir/", 0, 0, b""),
+            ("dir/a.txt", 0, 0, b"alpha\n"),
+            ("b.txt", 0, 0, b"beta"),
+        ]);
+        let expected = [("dir/a.txt", "alpha\n"), ("b.txt", "beta")];
+        assert_eq!(read(archive.clone()).unwrap(), named(&expected));
+
+        // The end record can be followed by a comment.
+        let mut commented = archive;
+        let len = commented.len();
+        commented[len - 2..].copy_from_slice(&[7, 0]);
+        commented.extend_from_slice(b"PK fun!");
+        assert_eq!(read(commented).unwrap(), named(&expected));
+    }
+
+    #[cfg(feature = "gzip")]
+    #[test]
+    fn zip_deflated() {
+        // One final, stored deflate block.
+        let deflated = b"\x01\x05\x00\xfa\xffhello";
+        let archive = zip(&[("d.txt", 8, 0, deflated)]);
+        assert_eq!(read(archive).unwrap(), named(&[("d.txt", "hello")]));
+    }
+
+    #[test]
+    fn bad_zip() {
+        let archive = zip(&[("secret", 0, 1, b"xxxx")]);
+        let err = read(archive).err().unwrap();
+        assert!(err.to_string().contains("encrypted"), "{}", err);
+
+        let archive = zip(&[("odd", 12, 0, b"xxxx")]);
+        let err = read(archive).err().unwrap();
+        assert!(err.to_string().contains("method 12"), "{}", err);
+
+        let mut archive = zip(&[("a.txt", 0, 0, b"alpha")]);
+        archive.truncate(archive.len() - 10);
+        assert!(read(archive).is_err());
+    }
+
+    #[test]
+    fn prefixed_lines() {
+        let prefix: Rc<Prefix> = Rc::default();
+        let out = Rc::new(RefCell::new(Vec::new()));
+        struct Shared(Rc<RefCell<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+                self.0.borrow_mut().write(buf)
+            }
+            fn flush(&mut self) -> io::Result<()> {
+                Ok(())
+            }
+        }
+        let mut writer = Prefixed::new(Box::new(Shared(out.clone())), prefix.clone());
+        prefix.set(b"a:".to_vec());
+        writer.write_all(b"one\ntw").unwrap();
+        assert!(prefix.mid_line());
+        writer.write_all(b"o\n").unwrap();
+        assert!(!prefix.mid_line());
+        prefix.set(b"b:".to_vec());
+        writer.write_all(b"three\n\nfour").unwrap();
+        assert_eq!(
+            String::from_utf8_lossy(&out.borrow()),
+            "a:one\na:two\nb:three\nb:\nb:four"
+        );
+    }
+}
@@ -0,0 +1,127 @@
+/*!
+Byte order marks.
+
+A BOM at the start of an input is removed before anything is matched
+against it, so a pattern anchored at the start of the first record can
+match. This happens after transcoding (see the `encoding` module), so a
+UTF-16 BOM is removed when the input is read as UTF-16; otherwise only a
+UTF-8 BOM is recognized.
+
+The output can start with a BOM if the first input did, or always. A file
+edited in place starts with one if it did before.
+*/
+use std::{
+    cell::Cell,
+    io::{self, Read, Write},
+    rc::Rc,
+};
+
+/// A BOM, as UTF-8 (which is how the output is written before it's
+/// transcoded).
+pub const BOM: &[u8] = "\u{feff}".as_bytes();
+
+/**
+Whether the first input started with a BOM; `None` until it's been read
+from.
+*/
+pub type FirstBom = Rc<Cell<Option<bool>>>;
+
+/// Removes a BOM from the start of the input.
+pub struct Stripper<R: Read> {
+    inner: R,
+    /// Input read while checking for a BOM that turned out not to be one.
+    head: Vec<u8>,
+    checked: bool,
+    first: FirstBom,
+}
+
+impl<R: Read> Stripper<R> {
+    /// Strip any BOM from `inner`, recording whether there was one in
+    /// `first` if this is the first input.
+    pub fn new(inner: R, first: FirstBom) -> Stripper<R> {
+        Stripper {
+            inner,
+            head: Vec::new(),
+            checked: false,
+            first,
+        }
+    }
+}
+
+impl<R: Read> Read for Stripper<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.checked {
+            self.checked = true;
+            (&mut self.inner)
+                .take(BOM.len() as u64)
+                .read_to_end(&mut self.head)?;
+            let found = self.head == BOM;
+            if found {
+                self.head.clear();
+            }
+            if self.first.get().is_none() {
+                self.first.set(Some(found));
+            }
+        }
+        if !self.head.is_empty() {
+            let n = buf.len().min(self.head.len());
+            buf[..n].copy_from_slice(&self.head[..n]);
+            self.head.drain(..n);
+            return Ok(n);
+        }
+        self.inner.read(buf)
+    }
+}
+
+/// Writes a BOM before anything else, if it should.
+pub struct Writer {
+    inner: Box<dyn Write>,
+    /// Always write a BOM.
+    add: bool,
+    /// Write one if the first input had one.
+    keep: Option<FirstBom>,
+    started: bool,
+}
+
+impl Writer {
+    fn start(&mut self) -> io::Result<()> {
+        if !self.started {
+            self.started = true;
+            let kept = self.keep.as_ref().and_then(|first| first.get());
+            if self.add || kept == Some(true) {
+                self.inner.write_all(BOM)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Write for Writer {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.start()?;
+        self.inner.write(buf)
+    }
+
+    // The output is always flushed at the end, so even empty output
+    // gets its BOM.
+    fn flush(&mut self) -> io::Result<()> {
+        self.start()?;
+        self.inner.flush()
+    }
+}
+
+/**
+Wrap `output` to start with a BOM if `add` is true, or if `keep` is given
+and the first input had one.
+*/
+pub fn writer(output: Box<dyn Write>, add: bool, keep: Option<FirstBom>) -> Box<dyn Write> {
+    if !add && keep.is_none() {
+        return output;
+    }
+    Box::new(Writer {
+        inner: output,
+        add,
+        keep,
+        started: false,
+    })
+}
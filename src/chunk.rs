@@ -0,0 +1,131 @@
+/*!
+Splitting input into delimited records without ever holding more than so
+much of one in memory, for `--max-record-bytes`.
+
+A record that turns out to be longer than the limit is read past rather
+than kept, so a huge file without a single delimiter in it costs no more
+memory than a short one.
+*/
+use std::io::{self, ErrorKind, Read};
+
+use regex::bytes::Regex;
+
+/// How much to read at a time, unless told otherwise.
+const READ_SIZE: usize = 8 * 1024;
+
+/*
+How much of the end of an overlong record to keep while reading past it,
+in case a delimiter starts there. (A delimiter longer than this could be
+missed, but then it'd be a very odd delimiter.)
+*/
+const OVERLAP: usize = 1024;
+
+/// What's found next in the input.
+pub enum Chunk {
+    /// A record, with the delimiter after it (if there is one).
+    Record(Vec<u8>),
+    /// A record longer than the limit, which was this many bytes long
+    /// (with its delimiter), and has been read past.
+    Skipped(usize),
+}
+
+/**
+Splits its input into records, each ending with a match of `delimiter`
+(which is kept, like a `ByteChunker` with `MatchDisposition::Append`),
+except that a record longer than `limit` bytes (not counting the
+delimiter) is skipped.
+*/
+pub struct Bounded {
+    input: Box<dyn Read>,
+    delimiter: Regex,
+    limit: usize,
+    read_size: usize,
+    /// What's been read, but not yet split off.
+    buff: Vec<u8>,
+    /// How much of `buff` has been searched for a delimiter already.
+    searched: usize,
+    /// How much of the current record has been thrown away already, if
+    /// it's being skipped.
+    skipped: Option<usize>,
+    done: bool,
+}
+
+impl Bounded {
+    pub fn new(input: Box<dyn Read>, delimiter: Regex, limit: usize) -> Bounded {
+        Bounded {
+            input,
+            delimiter,
+            limit,
+            read_size: READ_SIZE,
+            buff: Vec::new(),
+            searched: 0,
+            skipped: None,
+            done: false,
+        }
+    }
+
+    /// Read `size` bytes at a time.
+    pub fn read_size(mut self, size: usize) -> Self {
+        self.read_size = size;
+        self
+    }
+
+    /* Split off the record whose delimiter is at `start..end`. */
+    fn split(&mut self, start: usize, end: usize) -> Chunk {
+        self.searched = 0;
+        let rest = self.buff.split_off(end);
+        let record = std::mem::replace(&mut self.buff, rest);
+        match self.skipped.take() {
+            Some(skipped) => Chunk::Skipped(skipped + record.len()),
+            None if start > self.limit => Chunk::Skipped(record.len()),
+            None => Chunk::Record(record),
+        }
+    }
+}
+
+impl Iterator for Bounded {
+    type Item = io::Result<Chunk>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            // A delimiter could have begun at the end of what was searched
+            // before, so that's searched again.
+            let from = self.searched.saturating_sub(OVERLAP);
+            if let Some(m) = self.delimiter.find_at(&self.buff, from) {
+                return Some(Ok(self.split(m.start(), m.end())));
+            }
+            self.searched = self.buff.len();
+            if self.done {
+                return match self.buff.len() {
+                    0 if self.skipped.is_none() => None,
+                    len => Some(Ok(self.split(len, len))),
+                };
+            }
+            // There's no delimiter in what's been read, so all but the
+            // last bit of it is part of the record, and if that's too
+            // long already, the record will be skipped.
+            if self.buff.len() > self.limit + OVERLAP {
+                let cut = self.buff.len() - OVERLAP;
+                self.buff.drain(..cut);
+                self.searched -= cut;
+                self.skipped = Some(self.skipped.unwrap_or(0) + cut);
+            }
+            let len = self.buff.len();
+            self.buff.resize(len + self.read_size, 0);
+            let res = self.input.read(&mut self.buff[len..]);
+            self.buff.truncate(len + res.as_ref().map_or(0, |n| *n));
+            match res {
+                Ok(0) => self.done = true,
+                Ok(_) => {}
+                Err(e) if e.kind() == ErrorKind::Interrupted => {}
+                Err(e) => {
+                    // Give up on this input after an error.
+                    self.done = true;
+                    self.buff.clear();
+                    self.skipped = None;
+                    return Some(Err(e));
+                }
+            }
+        }
+    }
+}
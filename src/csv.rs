@@ -0,0 +1,308 @@
+/*!
+Reading CSV, and picking out one column to match against.
+
+In CSV mode each record is a row, which (unlike a line) can contain line
+breaks inside quoted fields. Fields are separated by commas; a field that
+starts with `"` runs to the next lone `"`, with `""` standing for a `"`
+in its value.
+
+With a column given, only that field's value (without any quotes) is
+matched against. When it's replaced, the row is put back together with
+just that field changed; the field is quoted if it was before, or if its
+new value needs to be.
+*/
+use std::{
+    borrow::Cow,
+    io::{self, BufRead, BufReader, Read},
+    ops::Range,
+    str::FromStr,
+};
+
+use crate::field::Target;
+
+const SEP: u8 = b',';
+const QUOTE: u8 = b'"';
+
+/// A column, by number (counting from 1) or by name.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum Column {
+    Index(usize),
+    Name(String),
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.parse::<usize>() {
+            Ok(0) => Err("columns are numbered from 1".into()),
+            Ok(n) => Ok(Column::Index(n)),
+            Err(_) if s.is_empty() => Err("empty column name".into()),
+            Err(_) => Ok(Column::Name(s.to_string())),
+        }
+    }
+}
+
+/// Iterates over the rows of CSV input, each with its line break (if it
+/// has one).
+pub struct Rows<R: Read> {
+    inner: BufReader<R>,
+    done: bool,
+}
+
+impl<R: Read> Rows<R> {
+    pub fn new(inner: R) -> Rows<R> {
+        Rows {
+            inner: BufReader::new(inner),
+            done: false,
+        }
+    }
+}
+
+impl<R: Read> Iterator for Rows<R> {
+    type Item = io::Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.done {
+            return None;
+        }
+        let mut row: Vec<u8> = Vec::new();
+        let mut n_quotes: usize = 0;
+        loop {
+            let start = row.len();
+            match self.inner.read_until(b'\n', &mut row) {
+                Ok(0) => {
+                    self.done = true;
+                    break;
+                }
+                Ok(_) => {}
+                Err(e) => {
+                    self.done = true;
+                    return Some(Err(e));
+                }
+            }
+            n_quotes += row[start..].iter().filter(|&&b| b == QUOTE).count();
+            // An odd number of quotes means the line break is quoted.
+            if n_quotes % 2 == 0 {
+                break;
+            }
+        }
+        if row.is_empty() && self.done {
+            return None;
+        }
+        Some(Ok(row))
+    }
+}
+
+/// The spans of each of the fields of `row` (including any quotes).
+pub fn fields(row: &[u8]) -> Vec<Range<usize>> {
+    let mut spans: Vec<Range<usize>> = Vec::new();
+    let mut start: usize = 0;
+    let mut quoted = false;
+    for (n, &b) in row.iter().enumerate() {
+        match b {
+            QUOTE => quoted = !quoted,
+            SEP if !quoted => {
+                spans.push(start..n);
+                start = n + 1;
+            }
+            _ => {}
+        }
+    }
+    spans.push(start..row.len());
+    spans
+}
+
+/// The value of a field (without any quotes).
+pub fn unquote(field: &[u8]) -> Cow<'_, [u8]> {
+    match field.strip_prefix(&[QUOTE]) {
+        Some(inner) => {
+            let inner = inner.strip_suffix(&[QUOTE]).unwrap_or(inner);
+            let mut value: Vec<u8> = Vec::with_capacity(inner.len());
+            let mut bytes = inner.iter().peekable();
+            while let Some(&b) = bytes.next() {
+                value.push(b);
+                if b == QUOTE && bytes.peek() == Some(&&QUOTE) {
+                    bytes.next();
+                }
+            }
+            Cow::Owned(value)
+        }
+        None => Cow::Borrowed(field),
+    }
+}
+
+/// Append `value` to `out` as a field, quoted if `quote` is true or it
+/// needs to be.
+pub fn write_field(value: &[u8], quote: bool, out: &mut Vec<u8>) {
+    let quote = quote
+        || value
+            .iter()
+            .any(|b| matches!(b, b',' | b'"' | b'\n' | b'\r'));
+    if !quote {
+        out.extend_from_slice(value);
+        return;
+    }
+    out.push(QUOTE);
+    for &b in value.iter() {
+        if b == QUOTE {
+            out.push(QUOTE);
+        }
+        out.push(b);
+    }
+    out.push(QUOTE);
+}
+
+/// Picks out the field of each row to match against.
+pub struct ColumnTarget {
+    column: Column,
+    /// Which field that is, once known.
+    index: Option<usize>,
+}
+
+impl ColumnTarget {
+    pub fn new(column: Column) -> ColumnTarget {
+        let index = match column {
+            Column::Index(n) => Some(n - 1),
+            Column::Name(_) => None,
+        };
+        ColumnTarget { column, index }
+    }
+}
+
+impl Target for ColumnTarget {
+    /**
+    If the column is named and `row` is the first row (the header), learn
+    which column has that name, and return `true`.
+    */
+    fn header(&mut self, row: &[u8]) -> Result<bool, String> {
+        if self.index.is_some() {
+            return Ok(false);
+        }
+        let name = match self.column {
+            Column::Name(ref name) => name.as_bytes(),
+            Column::Index(_) => return Ok(false),
+        };
+        let index = fields(row)
+            .into_iter()
+            .position(|span| unquote(&row[span]) == name)
+            .ok_or_else(|| {
+                format!(
+                    "there's no column named {:?}; the header is {:?}",
+                    String::from_utf8_lossy(name),
+                    String::from_utf8_lossy(row)
+                )
+            })?;
+        self.index = Some(index);
+        Ok(true)
+    }
+
+    fn field<'a>(&self, row: &'a [u8]) -> Option<(Range<usize>, Cow<'a, [u8]>)> {
+        let span = fields(row).into_iter().nth(self.index?)?;
+        let value = unquote(&row[span.clone()]);
+        Some((span, value))
+    }
+
+    fn splice(&self, row: &[u8], span: Range<usize>, value: &[u8], out: &mut Vec<u8>) {
+        let quoted = row[span.clone()].first() == Some(&QUOTE);
+        out.extend_from_slice(&row[..span.start]);
+        write_field(value, quoted, out);
+        out.extend_from_slice(&row[span.end..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rows(input: &str) -> Vec<String> {
+        Rows::new(input.as_bytes())
+            .map(|row| String::from_utf8(row.unwrap()).unwrap())
+            .collect()
+    }
+
+    fn values(row: &str) -> Vec<String> {
+        let row = row.as_bytes();
+        fields(row)
+            .into_iter()
+            .map(|span| String::from_utf8(unquote(&row[span]).into_owned()).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn quoted_line_breaks() {
+        let input = "id,note\n1,\"two\nlines\"\n2,\"\"\"quoted\"\"\nand, more\"\n";
+        assert_eq!(
+            rows(input),
+            [
+                "id,note\n",
+                "1,\"two\nlines\"\n",
+                "2,\"\"\"quoted\"\"\nand, more\"\n"
+            ]
+        );
+    }
+
+    #[test]
+    fn crlf_and_last_row() {
+        assert_eq!(
+            rows("a,b\r\n1,\"x\r\ny\"\r\n2,z"),
+            ["a,b\r\n", "1,\"x\r\ny\"\r\n", "2,z"]
+        );
+        assert_eq!(rows(""), Vec::<String>::new());
+        assert_eq!(rows("\n\n"), ["\n", "\n"]);
+    }
+
+    #[test]
+    fn field_values() {
+        assert_eq!(values("1,\"Smith, J\",x"), ["1", "Smith, J", "x"]);
+        assert_eq!(values("\"say \"\"hi\"\"\",\"\""), ["say \"hi\"", ""]);
+        assert_eq!(values("\"a\nb\",,"), ["a\nb", "", ""]);
+        assert_eq!(values(""), [""]);
+    }
+
+    #[test]
+    fn writing_fields() {
+        let written = |value: &str, quote: bool| {
+            let mut out: Vec<u8> = Vec::new();
+            write_field(value.as_bytes(), quote, &mut out);
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(written("plain", false), "plain");
+        assert_eq!(written("plain", true), "\"plain\"");
+        assert_eq!(written("a,b", false), "\"a,b\"");
+        assert_eq!(written("say \"hi\"", false), "\"say \"\"hi\"\"\"");
+        assert_eq!(written("two\r\nlines", false), "\"two\r\nlines\"");
+    }
+
+    #[test]
+    fn columns() {
+        assert_eq!("2".parse(), Ok(Column::Index(2)));
+        assert_eq!("name".parse(), Ok(Column::Name("name".into())));
+        assert!("0".parse::<Column>().is_err());
+        assert!("".parse::<Column>().is_err());
+
+        let mut target = ColumnTarget::new(Column::Name("note".into()));
+        assert_eq!(target.header(b"id,\"note\""), Ok(true));
+        assert_eq!(target.header(b"1,x"), Ok(false));
+        assert!(ColumnTarget::new(Column::Name("nope".into()))
+            .header(b"id,note")
+            .is_err());
+
+        // Replacing the column quotes it if it needs to be now, and keeps
+        // it quoted if it was.
+        let spliced = |row: &[u8], value: &[u8]| {
+            let (span, _) = target.field(row).unwrap();
+            let mut out: Vec<u8> = Vec::new();
+            target.splice(row, span, value, &mut out);
+            String::from_utf8(out).unwrap()
+        };
+        assert_eq!(spliced(b"1,x,y", b"a,b"), "1,\"a,b\",y");
+        assert_eq!(spliced(b"1,x", b"6\" tall"), "1,\"6\"\" tall\"");
+        assert_eq!(spliced(b"1,\"x\"", b"z"), "1,\"z\"");
+        assert_eq!(
+            target.field(b"1,\"a \"\"b\"\"\"").unwrap().1.as_ref(),
+            b"a \"b\""
+        );
+        assert!(target.field(b"1").is_none());
+    }
+}
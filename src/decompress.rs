@@ -0,0 +1,631 @@
+/*!
+Transparent decompression of compressed inputs.
+
+Inputs are recognized as compressed by their first few bytes, whatever
+their names. Only gzip can actually be decompressed (by a small inflater
+written to RFC 1951 and RFC 1952, with the `gzip` feature); inputs in
+the other common formats are reported as unsupported rather than being
+searched as if they were text.
+*/
+use std::io::{self, Cursor, Read};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Format {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Format {
+    /// Recognize the format of data beginning with `head`, if compressed.
+    pub fn sniff(head: &[u8]) -> Option<Format> {
+        if head.starts_with(&[0x1f, 0x8b]) {
+            Some(Format::Gzip)
+        } else if head.starts_with(b"BZh") {
+            Some(Format::Bzip2)
+        } else if head.starts_with(&[0xfd, b'7', b'z', b'X', b'Z', 0]) {
+            Some(Format::Xz)
+        } else if head.starts_with(&[0x28, 0xb5, 0x2f, 0xfd]) {
+            Some(Format::Zstd)
+        } else {
+            None
+        }
+    }
+
+    fn name(&self) -> &'static str {
+        match self {
+            Format::Gzip => "gzip",
+            Format::Bzip2 => "bzip2",
+            Format::Xz => "xz",
+            Format::Zstd => "zstd",
+        }
+    }
+}
+
+/// How many bytes `Format::sniff()` needs to see.
+const SNIFF_LEN: usize = 6;
+
+/**
+Read the first few bytes of `input`, returning them (there may be fewer
+than asked for, if `input` is short) and the format they indicate.
+*/
+pub fn sniff(input: &mut dyn Read) -> io::Result<(Vec<u8>, Option<Format>)> {
+    let mut head: Vec<u8> = Vec::with_capacity(SNIFF_LEN);
+    input.take(SNIFF_LEN as u64).read_to_end(&mut head)?;
+    let format = Format::sniff(&head);
+    Ok((head, format))
+}
+
+/**
+Wrap `input` so it reads decompressed data if it's compressed (or just
+passes it through if not).
+*/
+pub fn wrap(mut input: Box<dyn Read>) -> io::Result<Box<dyn Read>> {
+    let (head, format) = sniff(&mut input)?;
+    let input = Box::new(Cursor::new(head).chain(input));
+    match format {
+        None => Ok(input),
+        #[cfg(feature = "gzip")]
+        Some(Format::Gzip) => Ok(Box::new(gzip::GzDecoder::new(input))),
+        Some(format) => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            unsupported(format),
+        )),
+    }
+}
+
+/* Why input in `format` can't be read, and what to do about it. */
+fn unsupported(format: Format) -> String {
+    let why = match format {
+        Format::Gzip => "fresh was built without the \"gzip\" feature",
+        _ if cfg!(feature = "gzip") => "only gzip is",
+        _ => "fresh can't decompress anything",
+    };
+    format!(
+        "{}-compressed input isn't supported ({}); decompress it first, as with `{} -dc`",
+        format.name(),
+        why,
+        format.name()
+    )
+}
+
+/// Read bare deflate data from `input`.
+#[cfg(feature = "gzip")]
+pub fn inflate(input: Box<dyn Read>) -> Box<dyn Read> {
+    Box::new(gzip::GzDecoder::raw(input))
+}
+
+#[cfg(feature = "gzip")]
+mod gzip {
+    use std::io::{self, BufReader, Read};
+
+    fn invalid(msg: &str) -> io::Error {
+        io::Error::new(io::ErrorKind::InvalidData, format!("gzip: {}", msg))
+    }
+
+    static LEN_BASE: [u16; 29] = [
+        3, 4, 5, 6, 7, 8, 9, 10, 11, 13, 15, 17, 19, 23, 27, 31, 35, 43, 51, 59, 67, 83, 99, 115,
+        131, 163, 195, 227, 258,
+    ];
+    static LEN_EXTRA: [u8; 29] = [
+        0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1, 2, 2, 2, 2, 3, 3, 3, 3, 4, 4, 4, 4, 5, 5, 5, 5, 0,
+    ];
+    static DIST_BASE: [u16; 30] = [
+        1, 2, 3, 4, 5, 7, 9, 13, 17, 25, 33, 49, 65, 97, 129, 193, 257, 385, 513, 769, 1025, 1537,
+        2049, 3073, 4097, 6145, 8193, 12289, 16385, 24577,
+    ];
+    static DIST_EXTRA: [u8; 30] = [
+        0, 0, 0, 0, 1, 1, 2, 2, 3, 3, 4, 4, 5, 5, 6, 6, 7, 7, 8, 8, 9, 9, 10, 10, 11, 11, 12, 12,
+        13, 13,
+    ];
+    /// The order in which code length code lengths are given.
+    static CLEN_ORDER: [usize; 19] = [
+        16, 17, 18, 0, 8, 7, 9, 6, 10, 5, 11, 4, 12, 3, 13, 2, 14, 1, 15,
+    ];
+
+    const WINDOW: usize = 32 * 1024;
+
+    /// Reads the input a bit at a time, least significant bit first.
+    struct Bits<R: Read> {
+        inner: BufReader<R>,
+        buff: u32,
+        n_bits: u32,
+    }
+
+    impl<R: Read> Bits<R> {
+        /*
+        Try to make sure at least `n` bits are buffered, returning whether
+        there are.
+        */
+        fn fill(&mut self, n: u32) -> io::Result<bool> {
+            let mut byte = [0u8; 1];
+            while self.n_bits < n {
+                if self.inner.read(&mut byte)? == 0 {
+                    return Ok(false);
+                }
+                self.buff |= u32::from(byte[0]) << self.n_bits;
+                self.n_bits += 8;
+            }
+            Ok(true)
+        }
+
+        fn bits(&mut self, n: u32) -> io::Result<u32> {
+            if !self.fill(n)? {
+                return Err(invalid("unexpected end of input"));
+            }
+            let val = self.buff & ((1u32 << n) - 1);
+            self.buff = self.buff.checked_shr(n).unwrap_or(0);
+            self.n_bits -= n;
+            Ok(val)
+        }
+
+        fn byte(&mut self) -> io::Result<u8> {
+            Ok(self.bits(8)? as u8)
+        }
+
+        fn u16(&mut self) -> io::Result<u16> {
+            Ok(self.bits(16)? as u16)
+        }
+
+        fn u32(&mut self) -> io::Result<u32> {
+            Ok(self.bits(16)? | (self.bits(16)? << 16))
+        }
+
+        /// Skip to the next byte boundary.
+        fn align(&mut self) {
+            let extra = self.n_bits % 8;
+            self.buff >>= extra;
+            self.n_bits -= extra;
+        }
+
+        fn at_end(&mut self) -> io::Result<bool> {
+            Ok(!self.fill(1)?)
+        }
+    }
+
+    /// A canonical Huffman code, decoded a bit at a time (as in zlib's
+    /// `puff.c`).
+    struct Huffman {
+        /// Number of codes of each length.
+        count: [u16; 16],
+        /// Symbols, ordered by code.
+        symbol: Vec<u16>,
+    }
+
+    impl Huffman {
+        fn new(lengths: &[u8]) -> Huffman {
+            let mut count = [0u16; 16];
+            for &len in lengths.iter() {
+                count[len as usize] += 1;
+            }
+            let mut offsets = [0u16; 16];
+            for len in 1..15 {
+                offsets[len + 1] = offsets[len] + count[len];
+            }
+            let mut symbol = vec![0u16; lengths.len()];
+            for (sym, &len) in lengths.iter().enumerate() {
+                if len != 0 {
+                    symbol[offsets[len as usize] as usize] = sym as u16;
+                    offsets[len as usize] += 1;
+                }
+            }
+            Huffman { count, symbol }
+        }
+
+        fn fixed() -> (Huffman, Huffman) {
+            let mut lengths = [8u8; 288];
+            lengths[144..256].fill(9);
+            lengths[256..280].fill(7);
+            (Huffman::new(&lengths), Huffman::new(&[5u8; 30]))
+        }
+
+        fn decode<R: Read>(&self, bits: &mut Bits<R>) -> io::Result<u16> {
+            let (mut code, mut first, mut index): (i32, i32, i32) = (0, 0, 0);
+            for &count in self.count[1..].iter() {
+                code |= bits.bits(1)? as i32;
+                let count = i32::from(count);
+                if code - count < first {
+                    return Ok(self.symbol[(index + code - first) as usize]);
+                }
+                index += count;
+                first = (first + count) << 1;
+                code <<= 1;
+            }
+            Err(invalid("bad Huffman code"))
+        }
+    }
+
+    enum Block {
+        /// Between blocks.
+        Header,
+        /// In a stored block with this many bytes left.
+        Stored(usize),
+        /// In a compressed block with these literal/length and distance
+        /// codes.
+        Codes(Huffman, Huffman),
+        /// Past the end of a gzip member.
+        Trailer,
+        Done,
+    }
+
+    fn crc32_table() -> [u32; 256] {
+        let mut table = [0u32; 256];
+        for (n, entry) in table.iter_mut().enumerate() {
+            let mut c = n as u32;
+            for _ in 0..8 {
+                c = if c & 1 != 0 {
+                    0xedb8_8320 ^ (c >> 1)
+                } else {
+                    c >> 1
+                };
+            }
+            *entry = c;
+        }
+        table
+    }
+
+    pub struct GzDecoder<R: Read> {
+        bits: Bits<R>,
+        block: Block,
+        last_block: bool,
+        /// The most recent output, for back-references.
+        window: Vec<u8>,
+        pos: usize,
+        filled: usize,
+        /// A back-reference being copied: (distance, bytes left).
+        copy: (usize, usize),
+        crc_table: [u32; 256],
+        crc: u32,
+        size: u32,
+        /// Set from a gzip member's header until its trailer is read.
+        in_member: bool,
+        /// Bare deflate data, with no gzip header or trailer.
+        raw: bool,
+    }
+
+    impl<R: Read> GzDecoder<R> {
+        pub fn new(inner: R) -> GzDecoder<R> {
+            GzDecoder {
+                bits: Bits {
+                    inner: BufReader::new(inner),
+                    buff: 0,
+                    n_bits: 0,
+                },
+                block: Block::Trailer,
+                last_block: false,
+                window: vec![0; WINDOW],
+                pos: 0,
+                filled: 0,
+                copy: (0, 0),
+                crc_table: crc32_table(),
+                crc: !0,
+                size: 0,
+                in_member: false,
+                raw: false,
+            }
+        }
+
+        /// Decompress bare deflate data (as in a zip file) rather than gzip.
+        pub fn raw(inner: R) -> GzDecoder<R> {
+            GzDecoder {
+                block: Block::Header,
+                raw: true,
+                ..GzDecoder::new(inner)
+            }
+        }
+
+        /// Read a member header, leaving the input at its deflate data.
+        fn member_header(&mut self) -> io::Result<()> {
+            let b = &mut self.bits;
+            if b.byte()? != 0x1f || b.byte()? != 0x8b {
+                return Err(invalid("bad header"));
+            }
+            if b.byte()? != 8 {
+                return Err(invalid("unknown compression method"));
+            }
+            let flags = b.byte()?;
+            // Modification time, extra flags, operating system
+            for _ in 0..6 {
+                b.byte()?;
+            }
+            if flags & 0x04 != 0 {
+                for _ in 0..b.u16()? {
+                    b.byte()?;
+                }
+            }
+            // Name and comment are each NUL-terminated.
+            for flag in [0x08, 0x10] {
+                if flags & flag != 0 {
+                    while b.byte()? != 0 {}
+                }
+            }
+            if flags & 0x02 != 0 {
+                b.u16()?;
+            }
+            self.crc = !0;
+            self.size = 0;
+            self.filled = 0;
+            self.last_block = false;
+            self.in_member = true;
+            Ok(())
+        }
+
+        fn block_header(&mut self) -> io::Result<Block> {
+            self.last_block = self.bits.bits(1)? == 1;
+            match self.bits.bits(2)? {
+                0 => {
+                    self.bits.align();
+                    let len = self.bits.u16()?;
+                    if self.bits.u16()? != !len {
+                        return Err(invalid("bad stored block length"));
+                    }
+                    Ok(Block::Stored(len as usize))
+                }
+                1 => {
+                    let (lit, dist) = Huffman::fixed();
+                    Ok(Block::Codes(lit, dist))
+                }
+                2 => self.dynamic_codes(),
+                _ => Err(invalid("bad block type")),
+            }
+        }
+
+        fn dynamic_codes(&mut self) -> io::Result<Block> {
+            let b = &mut self.bits;
+            let n_lit = b.bits(5)? as usize + 257;
+            let n_dist = b.bits(5)? as usize + 1;
+            let n_clen = b.bits(4)? as usize + 4;
+            let mut clens = [0u8; 19];
+            for &n in CLEN_ORDER[..n_clen].iter() {
+                clens[n] = b.bits(3)? as u8;
+            }
+            let clen_code = Huffman::new(&clens);
+
+            let mut lengths: Vec<u8> = Vec::with_capacity(n_lit + n_dist);
+            while lengths.len() < n_lit + n_dist {
+                let (len, repeat) = match clen_code.decode(b)? {
+                    sym @ 0..=15 => (sym as u8, 1),
+                    16 => match lengths.last() {
+                        Some(&prev) => (prev, 3 + b.bits(2)?),
+                        None => return Err(invalid("repeat with no previous length")),
+                    },
+                    17 => (0, 3 + b.bits(3)?),
+                    _ => (0, 11 + b.bits(7)?),
+                };
+                lengths.extend(std::iter::repeat(len).take(repeat as usize));
+            }
+            if lengths.len() > n_lit + n_dist {
+                return Err(invalid("too many code lengths"));
+            }
+            Ok(Block::Codes(
+                Huffman::new(&lengths[..n_lit]),
+                Huffman::new(&lengths[n_lit..]),
+            ))
+        }
+
+        fn emit(&mut self, byte: u8, out: &mut [u8], n: &mut usize) {
+            out[*n] = byte;
+            *n += 1;
+            self.window[self.pos] = byte;
+            self.pos = (self.pos + 1) % WINDOW;
+            self.filled = (self.filled + 1).min(WINDOW);
+            self.crc =
+                self.crc_table[((self.crc ^ u32::from(byte)) & 0xff) as usize] ^ (self.crc >> 8);
+            self.size = self.size.wrapping_add(1);
+        }
+    }
+
+    impl<R: Read> Read for GzDecoder<R> {
+        fn read(&mut self, out: &mut [u8]) -> io::Result<usize> {
+            let mut n: usize = 0;
+            while n < out.len() {
+                let (dist, left) = self.copy;
+                if left > 0 {
+                    let byte = self.window[(self.pos + WINDOW - dist) % WINDOW];
+                    self.emit(byte, out, &mut n);
+                    self.copy = (dist, left - 1);
+                    continue;
+                }
+
+                match self.block {
+                    Block::Header if self.last_block && self.raw => self.block = Block::Done,
+                    Block::Header if self.last_block => self.block = Block::Trailer,
+                    Block::Header => self.block = self.block_header()?,
+                    Block::Stored(0) => self.block = Block::Header,
+                    Block::Stored(ref mut left) => {
+                        *left -= 1;
+                        let byte = self.bits.byte()?;
+                        self.emit(byte, out, &mut n);
+                    }
+                    Block::Codes(ref lit, ref dist) => {
+                        let sym = lit.decode(&mut self.bits)? as usize;
+                        if sym < 256 {
+                            self.emit(sym as u8, out, &mut n);
+                            continue;
+                        } else if sym == 256 {
+                            self.block = Block::Header;
+                            continue;
+                        }
+                        let sym = sym - 257;
+                        if sym >= LEN_BASE.len() {
+                            return Err(invalid("bad length code"));
+                        }
+                        let len = LEN_BASE[sym] as usize
+                            + self.bits.bits(u32::from(LEN_EXTRA[sym]))? as usize;
+                        let sym = dist.decode(&mut self.bits)? as usize;
+                        if sym >= DIST_BASE.len() {
+                            return Err(invalid("bad distance code"));
+                        }
+                        let d = DIST_BASE[sym] as usize
+                            + self.bits.bits(u32::from(DIST_EXTRA[sym]))? as usize;
+                        if d > self.filled {
+                            return Err(invalid("distance too far back"));
+                        }
+                        self.copy = (d, len);
+                    }
+                    Block::Trailer => {
+                        // The end of one member (even an empty one), unless
+                        // this is the start.
+                        if self.in_member {
+                            self.bits.align();
+                            let crc = self.bits.u32()?;
+                            let size = self.bits.u32()?;
+                            if crc != !self.crc || size != self.size {
+                                return Err(invalid("checksum mismatch"));
+                            }
+                            self.in_member = false;
+                        }
+                        // Concatenated gzip files are one stream.
+                        if self.bits.at_end()? {
+                            self.block = Block::Done;
+                        } else {
+                            self.member_header()?;
+                            self.block = Block::Header;
+                        }
+                    }
+                    Block::Done => break,
+                }
+            }
+            Ok(n)
+        }
+    }
+}
+
+#[cfg(all(test, feature = "gzip"))]
+mod tests {
+    use super::*;
+
+    // Made with Python's gzip module (with no timestamp): `fox()` at levels
+    // 1 and 9 (dynamic Huffman blocks), `HELLO` at level 9 (a fixed one) and
+    // 0 (stored), and nothing; and `HELLO` as bare deflate data with zlib.
+    const FOX_1: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x03, 0x9d, 0xd2, 0xc9, 0x15, 0x40,
+        0x30, 0x14, 0x46, 0xe1, 0xbd, 0x2a, 0x5e, 0x09, 0x62, 0xa6, 0x1b, 0x43, 0x10, 0x22, 0x8f,
+        0x10, 0x53, 0xf5, 0x0e, 0x1d, 0xf8, 0xd7, 0xf7, 0x7c, 0xbb, 0xab, 0x95, 0x91, 0xe4, 0x17,
+        0xb4, 0xf5, 0x92, 0x16, 0xa7, 0xea, 0x91, 0x2a, 0xcb, 0x87, 0xa1, 0x96, 0x4f, 0x1a, 0xdc,
+        0x34, 0xaf, 0xc4, 0xbb, 0xb4, 0x5f, 0xd6, 0xe5, 0x7d, 0x51, 0xc3, 0x9d, 0xa7, 0x5f, 0x23,
+        0x00, 0x13, 0x00, 0x26, 0x04, 0x4c, 0x04, 0x98, 0x18, 0x30, 0x09, 0x60, 0x52, 0xc0, 0x64,
+        0x80, 0xc9, 0x01, 0x23, 0xa0, 0x11, 0x7e, 0x9e, 0xf0, 0x00, 0x36, 0xc2, 0x31, 0x25, 0x72,
+        0x02, 0x00, 0x00,
+    ];
+
+    const FOX_9: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x9d, 0xd2, 0xb7, 0x11, 0x80,
+        0x30, 0x00, 0x43, 0xd1, 0x9e, 0x29, 0x34, 0x02, 0x39, 0x6d, 0x43, 0x30, 0x60, 0x30, 0x36,
+        0xc9, 0xa4, 0xe9, 0x39, 0xd8, 0x00, 0xd5, 0xba, 0x57, 0xe9, 0x2b, 0xa9, 0x05, 0xdc, 0x1c,
+        0x5b, 0x27, 0x30, 0x5b, 0x59, 0x0d, 0x28, 0x17, 0x73, 0x68, 0x34, 0xe6, 0x44, 0x6f, 0xc7,
+        0x69, 0x85, 0xd9, 0xc5, 0xf2, 0xcd, 0xaa, 0xb8, 0x2f, 0xd4, 0xa6, 0x75, 0xd4, 0x6b, 0x3c,
+        0xc2, 0xf8, 0x84, 0x09, 0x08, 0x13, 0x12, 0x26, 0x22, 0x4c, 0x4c, 0x98, 0x84, 0x30, 0x29,
+        0x61, 0x32, 0xe6, 0x53, 0x2a, 0x84, 0x9f, 0x25, 0x3c, 0x36, 0xc2, 0x31, 0x25, 0x72, 0x02,
+        0x00, 0x00,
+    ];
+
+    const HELLO_FIXED: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0xcb, 0x48, 0xcd, 0xc9, 0xc9,
+        0xd7, 0x51, 0xc8, 0x40, 0xa2, 0x14, 0xca, 0xf3, 0x8b, 0x72, 0x52, 0xb8, 0x00, 0x87, 0x5d,
+        0x46, 0x2b, 0x1a, 0x00, 0x00, 0x00,
+    ];
+
+    const HELLO_STORED: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x04, 0x03, 0x01, 0x1a, 0x00, 0xe5, 0xff,
+        0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x68, 0x65, 0x6c, 0x6c, 0x6f, 0x2c, 0x20, 0x68,
+        0x65, 0x6c, 0x6c, 0x6f, 0x20, 0x77, 0x6f, 0x72, 0x6c, 0x64, 0x0a, 0x87, 0x5d, 0x46, 0x2b,
+        0x1a, 0x00, 0x00, 0x00,
+    ];
+
+    const EMPTY: &[u8] = &[
+        0x1f, 0x8b, 0x08, 0x00, 0x00, 0x00, 0x00, 0x00, 0x02, 0x03, 0x03, 0x00, 0x00, 0x00, 0x00,
+        0x00, 0x00, 0x00, 0x00, 0x00,
+    ];
+
+    const HELLO_RAW: &[u8] = &[
+        0xcb, 0x48, 0xcd, 0xc9, 0xc9, 0xd7, 0x51, 0xc8, 0x40, 0xa2, 0x14, 0xca, 0xf3, 0x8b, 0x72,
+        0x52, 0xb8, 0x00,
+    ];
+
+    fn fox() -> Vec<u8> {
+        (0..12)
+            .flat_map(|n| {
+                format!("line {}: the quick brown fox jumps over the lazy dog\n", n).into_bytes()
+            })
+            .collect()
+    }
+
+    const HELLO: &[u8] = b"hello, hello, hello world\n";
+
+    fn gunzip(data: &[u8]) -> io::Result<Vec<u8>> {
+        let mut out: Vec<u8> = Vec::new();
+        wrap(Box::new(Cursor::new(data.to_vec())))?.read_to_end(&mut out)?;
+        Ok(out)
+    }
+
+    #[test]
+    fn dynamic_blocks() {
+        assert_eq!(gunzip(FOX_1).unwrap(), fox());
+        assert_eq!(gunzip(FOX_9).unwrap(), fox());
+    }
+
+    #[test]
+    fn fixed_and_stored_blocks() {
+        assert_eq!(gunzip(HELLO_FIXED).unwrap(), HELLO);
+        assert_eq!(gunzip(HELLO_STORED).unwrap(), HELLO);
+    }
+
+    #[test]
+    fn raw_deflate() {
+        let mut out: Vec<u8> = Vec::new();
+        inflate(Box::new(Cursor::new(HELLO_RAW)))
+            .read_to_end(&mut out)
+            .unwrap();
+        assert_eq!(out, HELLO);
+    }
+
+    #[test]
+    fn empty_member() {
+        assert_eq!(gunzip(EMPTY).unwrap(), b"");
+    }
+
+    #[test]
+    fn concatenated_members() {
+        let data = [EMPTY, HELLO_FIXED, EMPTY, FOX_9, HELLO_STORED].concat();
+        let expected = [HELLO, &fox(), HELLO].concat();
+        assert_eq!(gunzip(&data).unwrap(), expected);
+    }
+
+    #[test]
+    fn bad_trailer() {
+        // The CRC is the trailer's first four bytes, and the size the rest.
+        for at in [8, 1] {
+            let mut data = FOX_9.to_vec();
+            let n = data.len() - at;
+            data[n] ^= 0x01;
+            let err = gunzip(&data).unwrap_err();
+            assert_eq!(err.kind(), io::ErrorKind::InvalidData);
+        }
+    }
+
+    #[test]
+    fn truncated() {
+        for len in [3, 10, 20, HELLO_STORED.len() - 4] {
+            assert!(gunzip(&HELLO_STORED[..len]).is_err());
+            assert!(gunzip(&FOX_1[..len]).is_err());
+        }
+    }
+
+    #[test]
+    fn unsupported_formats() {
+        let err = gunzip(b"BZh91AY&SY").unwrap_err();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert_eq!(
+            err.to_string(),
+            "bzip2-compressed input isn't supported (only gzip is); \
+             decompress it first, as with `bzip2 -dc`"
+        );
+        let err = gunzip(b"\xfd7zXZ\0").unwrap_err();
+        assert!(err.to_string().starts_with("xz-compressed"), "{}", err);
+        let err = gunzip(b"\x28\xb5\x2f\xfd").unwrap_err();
+        assert!(err.to_string().starts_with("zstd-compressed"), "{}", err);
+        assert_eq!(gunzip(b"plain text").unwrap(), b"plain text");
+    }
+}
@@ -0,0 +1,362 @@
+/*!
+Unified diffs, for showing what editing files in place would change,
+without changing them (see `--diff`).
+
+Files are compared line by line with Myers' algorithm (in its linear
+space form), which finds a shortest set of lines to delete and insert,
+and the changes are grouped into hunks with three lines of context, as
+`diff -u` does.
+*/
+use std::{
+    cell::RefCell,
+    io::{self, Write},
+    rc::Rc,
+};
+
+/// Lines of unchanged context around each change.
+const CONTEXT: usize = 3;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Edit {
+    Same,
+    Delete,
+    Insert,
+}
+
+/*
+A shortest edit script turning `a` into `b`: for each line of either, in
+order, whether it's kept, deleted from `a`, or inserted from `b`.
+*/
+fn edits(a: &[&[u8]], b: &[&[u8]]) -> Vec<Edit> {
+    let mut script: Vec<Edit> = Vec::with_capacity(a.len() + b.len());
+    let diagonals = 2 * (a.len() + b.len()) + 3;
+    let (mut forward, mut backward) = (vec![0; diagonals], vec![0; diagonals]);
+    divide(a, b, &mut forward, &mut backward, &mut script);
+    // Where lines are replaced, the old ones come first, as with diff.
+    for run in script.split_mut(|&edit| edit == Edit::Same) {
+        run.sort_unstable_by_key(|&edit| edit == Edit::Insert);
+    }
+    script
+}
+
+/*
+Append a shortest edit script turning `a` into `b` to `script`, by
+splitting both in two where a shortest one goes through the middle and
+doing each half the same way, so only linear space is needed (rather than
+the quadratic space of keeping every round of the search to retrace it).
+`forward` and `backward` are for `middle()` to work in.
+*/
+fn divide(
+    a: &[&[u8]],
+    b: &[&[u8]],
+    forward: &mut [usize],
+    backward: &mut [usize],
+    script: &mut Vec<Edit>,
+) {
+    let prefix = a.iter().zip(b).take_while(|(x, y)| x == y).count();
+    let (a, b) = (&a[prefix..], &b[prefix..]);
+    let suffix = a
+        .iter()
+        .rev()
+        .zip(b.iter().rev())
+        .take_while(|(x, y)| x == y)
+        .count();
+    let (a, b) = (&a[..a.len() - suffix], &b[..b.len() - suffix]);
+    script.extend(std::iter::repeat(Edit::Same).take(prefix));
+    if a.is_empty() {
+        script.extend(std::iter::repeat(Edit::Insert).take(b.len()));
+    } else if b.is_empty() {
+        script.extend(std::iter::repeat(Edit::Delete).take(a.len()));
+    } else {
+        let (x, y) = middle(a, b, forward, backward);
+        divide(&a[..x], &b[..y], forward, backward, script);
+        divide(&a[x..], &b[y..], forward, backward, script);
+    }
+    script.extend(std::iter::repeat(Edit::Same).take(suffix));
+}
+
+/*
+A point that a shortest path from the start of `a` and `b` to their ends
+goes through, not at either end, found with Myers' "middle snake": the
+furthest paths with d edits from the start and from the end are followed
+at once, for each d, until they meet. `a` and `b` mustn't be empty, nor
+start or end with the same line.
+*/
+fn middle(
+    a: &[&[u8]],
+    b: &[&[u8]],
+    forward: &mut [usize],
+    backward: &mut [usize],
+) -> (usize, usize) {
+    let (n, m) = (a.len() as isize, b.len() as isize);
+    let delta = n - m;
+    // forward[k] is how far along `a` the furthest path from the start on
+    // diagonal k (x - y) has got, and backward[k] how far back from the
+    // end of `a` the furthest path from the end on diagonal k (counting
+    // from the end) has; diagonals are offset to be indices.
+    let offset = n + m + 1;
+    let at = |k: isize| (k + offset) as usize;
+    forward[at(1)] = 0;
+    backward[at(1)] = 0;
+    for d in 0..=(n + m + 1) / 2 {
+        for k in (-d..=d).step_by(2) {
+            let mut x = match k == -d || (k != d && forward[at(k - 1)] < forward[at(k + 1)]) {
+                true => forward[at(k + 1)] as isize,
+                false => forward[at(k - 1)] as isize + 1,
+            };
+            let mut y = x - k;
+            let start = (x, y);
+            while x < n && y < m && a[x as usize] == b[y as usize] {
+                x += 1;
+                y += 1;
+            }
+            forward[at(k)] = x as usize;
+            // With an odd difference in length, the paths can only meet
+            // on a diagonal the path from the end has been on for d - 1
+            // edits.
+            let back = delta - k;
+            if delta % 2 != 0 && back.abs() < d && x + backward[at(back)] as isize >= n {
+                return (start.0 as usize, start.1 as usize);
+            }
+        }
+        for k in (-d..=d).step_by(2) {
+            let mut x = match k == -d || (k != d && backward[at(k - 1)] < backward[at(k + 1)]) {
+                true => backward[at(k + 1)] as isize,
+                false => backward[at(k - 1)] as isize + 1,
+            };
+            let mut y = x - k;
+            while x < n && y < m && a[(n - x - 1) as usize] == b[(m - y - 1) as usize] {
+                x += 1;
+                y += 1;
+            }
+            backward[at(k)] = x as usize;
+            let ahead = delta - k;
+            if delta % 2 == 0 && ahead.abs() <= d && forward[at(ahead)] as isize + x >= n {
+                return ((n - x) as usize, (m - y) as usize);
+            }
+        }
+    }
+    unreachable!("the paths always meet")
+}
+
+/* The start and length of a hunk's lines in one file, as diff gives them. */
+fn range(start: usize, len: usize) -> String {
+    // An empty range is said to start at the line before it.
+    let start = if len == 0 { start } else { start + 1 };
+    match len {
+        1 => start.to_string(),
+        _ => format!("{},{}", start, len),
+    }
+}
+
+fn write_line(mark: u8, line: &[u8], out: &mut Vec<u8>) {
+    out.push(mark);
+    out.extend_from_slice(line);
+    if !line.ends_with(b"\n") {
+        out.extend_from_slice(b"\n\\ No newline at end of file\n");
+    }
+}
+
+/**
+Append a unified diff of `old` (called `old_name`) and `new` (called
+`new_name`) to `out`; nothing, if they're the same. Returns whether they
+differ.
+*/
+pub fn unified(old: &[u8], new: &[u8], old_name: &str, new_name: &str, out: &mut Vec<u8>) -> bool {
+    if old == new {
+        return false;
+    }
+    let a: Vec<&[u8]> = old.split_inclusive(|&b| b == b'\n').collect();
+    let b: Vec<&[u8]> = new.split_inclusive(|&b| b == b'\n').collect();
+    let script = edits(&a, &b);
+
+    // Where each edit is in each file.
+    let mut positions: Vec<(usize, usize)> = Vec::with_capacity(script.len() + 1);
+    let (mut x, mut y) = (0, 0);
+    for edit in script.iter() {
+        positions.push((x, y));
+        match edit {
+            Edit::Same => (x, y) = (x + 1, y + 1),
+            Edit::Delete => x += 1,
+            Edit::Insert => y += 1,
+        }
+    }
+    positions.push((x, y));
+
+    out.extend_from_slice(format!("--- {}\n+++ {}\n", old_name, new_name).as_bytes());
+    let changes: Vec<usize> = (0..script.len())
+        .filter(|&n| script[n] != Edit::Same)
+        .collect();
+    let mut n = 0;
+    while n < changes.len() {
+        let start = changes[n].saturating_sub(CONTEXT);
+        let mut end = changes[n] + 1;
+        n += 1;
+        // Changes close enough together to share context share a hunk.
+        while n < changes.len() && changes[n] <= end + 2 * CONTEXT {
+            end = changes[n] + 1;
+            n += 1;
+        }
+        let end = (end + CONTEXT).min(script.len());
+
+        let ((a_start, b_start), (a_end, b_end)) = (positions[start], positions[end]);
+        out.extend_from_slice(
+            format!(
+                "@@ -{} +{} @@\n",
+                range(a_start, a_end - a_start),
+                range(b_start, b_end - b_start)
+            )
+            .as_bytes(),
+        );
+        for (edit, &(x, y)) in script[start..end].iter().zip(&positions[start..end]) {
+            match edit {
+                Edit::Same => write_line(b' ', a[x], out),
+                Edit::Delete => write_line(b'-', a[x], out),
+                Edit::Insert => write_line(b'+', b[y], out),
+            }
+        }
+    }
+    true
+}
+
+/// Collects what's written to it, to be had from a shared buffer.
+pub struct Capture {
+    buff: Rc<RefCell<Vec<u8>>>,
+}
+
+impl Capture {
+    pub fn new(buff: Rc<RefCell<Vec<u8>>>) -> Capture {
+        Capture { buff }
+    }
+}
+
+impl Write for Capture {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buff.borrow_mut().extend_from_slice(buf);
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn lines(text: &str) -> Vec<&[u8]> {
+        text.as_bytes().split_inclusive(|&b| b == b'\n').collect()
+    }
+
+    /* Check that `edits()` turns `a` into `b`, and as few edits as can. */
+    fn check(a: &[&[u8]], b: &[&[u8]]) {
+        let script = edits(a, b);
+        let (mut x, mut y) = (0, 0);
+        let mut result: Vec<&[u8]> = Vec::new();
+        for edit in script.iter() {
+            match edit {
+                Edit::Same => {
+                    assert_eq!(a[x], b[y]);
+                    result.push(a[x]);
+                    (x, y) = (x + 1, y + 1);
+                }
+                Edit::Delete => x += 1,
+                Edit::Insert => {
+                    result.push(b[y]);
+                    y += 1;
+                }
+            }
+        }
+        assert_eq!((x, y), (a.len(), b.len()));
+        assert_eq!(result, b);
+
+        // The longest common subsequence is what's kept by a shortest
+        // script.
+        let mut lcs = vec![vec![0usize; b.len() + 1]; a.len() + 1];
+        for x in (0..a.len()).rev() {
+            for y in (0..b.len()).rev() {
+                lcs[x][y] = match a[x] == b[y] {
+                    true => lcs[x + 1][y + 1] + 1,
+                    false => lcs[x + 1][y].max(lcs[x][y + 1]),
+                };
+            }
+        }
+        let kept = script.iter().filter(|&&edit| edit == Edit::Same).count();
+        assert_eq!(kept, lcs[0][0]);
+
+        assert!(script
+            .windows(2)
+            .all(|pair| pair != [Edit::Insert, Edit::Delete]));
+    }
+
+    #[test]
+    fn empty_files() {
+        check(&[], &[]);
+        check(&lines("a\nb\n"), &[]);
+        check(&[], &lines("a\nb\n"));
+    }
+
+    #[test]
+    fn odd_and_even_differences() {
+        check(&lines("a\nb\nc\nd\ne\n"), &lines("b\nx\ne\n"));
+        check(&lines("a\nb\nc\nd\ne\n"), &lines("b\ne\n"));
+        check(&lines("a\nb\nc\n"), &lines("c\nb\na\n"));
+        check(&lines("a\nb\n"), &lines("x\na\ny\nb\nz\n"));
+        check(&lines("a\nb\n"), &lines("x\na\ny\nb\n"));
+    }
+
+    #[test]
+    fn arbitrary_files() {
+        // A simple LCG, for files of a few lines from a small alphabet,
+        // which have plenty of lines in common.
+        let mut seed: u32 = 1;
+        let mut next = move |n: u32| {
+            seed = seed.wrapping_mul(1_103_515_245).wrapping_add(12_345);
+            (seed >> 16) % n
+        };
+        let words: [&[u8]; 4] = [b"a\n", b"b\n", b"c\n", b"d\n"];
+        for _ in 0..500 {
+            let a: Vec<&[u8]> = (0..next(12)).map(|_| words[next(4) as usize]).collect();
+            let b: Vec<&[u8]> = (0..next(12)).map(|_| words[next(4) as usize]).collect();
+            check(&a, &b);
+        }
+    }
+
+    fn diff(old: &str, new: &str) -> String {
+        let mut out: Vec<u8> = Vec::new();
+        unified(old.as_bytes(), new.as_bytes(), "f", "f", &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn hunks() {
+        assert_eq!(diff("1\n2\n3\n", "1\n2\n3\n"), "");
+        assert_eq!(
+            diff("1\n2\n3\n", "1\nX\n3\n"),
+            "--- f\n+++ f\n@@ -1,3 +1,3 @@\n 1\n-2\n+X\n 3\n"
+        );
+        let old: String = (1..=20).map(|n| format!("{}\n", n)).collect();
+        let new: String = (1..=20)
+            .filter(|&n| n != 19)
+            .map(|n| match n {
+                2 => "X\n".to_string(),
+                n => format!("{}\n", n),
+            })
+            .collect();
+        assert_eq!(
+            diff(&old, &new),
+            "--- f\n+++ f\n@@ -1,5 +1,5 @@\n 1\n-2\n+X\n 3\n 4\n 5\n\
+             @@ -16,5 +16,4 @@\n 16\n 17\n 18\n-19\n 20\n"
+        );
+    }
+
+    #[test]
+    fn last_newline() {
+        assert_eq!(
+            diff("a\nb\n", "a\nb"),
+            "--- f\n+++ f\n@@ -1,2 +1,2 @@\n a\n-b\n+b\n\\ No newline at end of file\n"
+        );
+        assert_eq!(diff("", "a\n"), "--- f\n+++ f\n@@ -0,0 +1 @@\n+a\n");
+    }
+}
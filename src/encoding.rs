@@ -0,0 +1,440 @@
+/*!
+Transcoding inputs and output that aren't UTF-8.
+
+Patterns and replacements are always UTF-8, so an input in some other
+encoding is converted to UTF-8 as it's read, and the output converted
+back (or to some other encoding) as it's written. Supported encodings are
+UTF-8, Latin-1 (ISO-8859-1), Windows-1252, and UTF-16 in either byte
+order. Multi-byte East Asian encodings (Shift-JIS, EUC-JP, GBK, Big5,
+EUC-KR), which each take a large mapping table, aren't supported; they're
+recognized by name, so asking for one gets an error saying so.
+
+With `auto`, each input's encoding is guessed from its start: a byte
+order mark decides it if there is one; otherwise it's UTF-8 if it's valid
+UTF-8, and Windows-1252 if not.
+*/
+use std::{
+    fmt::{self, Display, Formatter},
+    io::{self, Read, Write},
+    str::FromStr,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    Latin1,
+    Windows1252,
+    Utf16Le,
+    Utf16Be,
+    /// Guess (only for input).
+    Auto,
+}
+
+impl FromStr for Encoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.to_ascii_lowercase().as_str() {
+            "utf-8" | "utf8" => Ok(Encoding::Utf8),
+            "latin1" | "latin-1" | "iso-8859-1" => Ok(Encoding::Latin1),
+            "windows-1252" | "cp1252" => Ok(Encoding::Windows1252),
+            "utf-16le" | "utf16le" => Ok(Encoding::Utf16Le),
+            "utf-16be" | "utf16be" => Ok(Encoding::Utf16Be),
+            "auto" => Ok(Encoding::Auto),
+            "shift-jis" | "shift_jis" | "sjis" | "euc-jp" | "gbk" | "gb2312" | "big5"
+            | "euc-kr" => Err(format!(
+                "the {} encoding isn't supported; convert to UTF-8 first, as with \
+                `iconv -f {} -t UTF-8`",
+                s, s
+            )),
+            _ => Err(format!(
+                "unknown encoding {:?}; known encodings are utf-8, latin1, \
+                windows-1252, utf-16le, utf-16be, and auto",
+                s
+            )),
+        }
+    }
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Encoding::Utf8 => "utf-8",
+            Encoding::Latin1 => "latin1",
+            Encoding::Windows1252 => "windows-1252",
+            Encoding::Utf16Le => "utf-16le",
+            Encoding::Utf16Be => "utf-16be",
+            Encoding::Auto => "auto",
+        };
+        f.write_str(name)
+    }
+}
+
+/// The characters Windows-1252 has in place of Latin-1's C1 controls.
+static WINDOWS_1252: [u16; 32] = [
+    0x20AC, 0x81, 0x201A, 0x0192, 0x201E, 0x2026, 0x2020, 0x2021, 0x02C6, 0x2030, 0x0160, 0x2039,
+    0x0152, 0x8D, 0x017D, 0x8F, 0x90, 0x2018, 0x2019, 0x201C, 0x201D, 0x2022, 0x2013, 0x2014,
+    0x02DC, 0x2122, 0x0161, 0x203A, 0x0153, 0x9D, 0x017E, 0x0178,
+];
+
+/// How much of an input `Auto` examines.
+const SNIFF_LEN: usize = 8192;
+
+/*
+Guess the encoding of text beginning with `head`, which might end partway
+through a character.
+*/
+fn guess(head: &[u8]) -> Encoding {
+    if head.starts_with(&[0xFF, 0xFE]) {
+        Encoding::Utf16Le
+    } else if head.starts_with(&[0xFE, 0xFF]) {
+        Encoding::Utf16Be
+    } else {
+        match std::str::from_utf8(head) {
+            Ok(_) => Encoding::Utf8,
+            // Only cut off at the end.
+            Err(e) if e.error_len().is_none() => Encoding::Utf8,
+            Err(_) => Encoding::Windows1252,
+        }
+    }
+}
+
+/// Reads text in some encoding as UTF-8.
+pub struct Decoder<R: Read> {
+    inner: R,
+    encoding: Encoding,
+    /// Undecoded input (at most a partial UTF-16 code unit or surrogate
+    /// pair after each read).
+    raw: Vec<u8>,
+    /// Decoded output not yet returned.
+    decoded: Vec<u8>,
+    at_end: bool,
+}
+
+impl<R: Read> Decoder<R> {
+    pub fn new(inner: R, encoding: Encoding) -> Decoder<R> {
+        Decoder {
+            inner,
+            encoding,
+            raw: Vec::new(),
+            decoded: Vec::new(),
+            at_end: false,
+        }
+    }
+
+    /* Read more of the input into `raw`, returning whether there was any. */
+    fn fill(&mut self, n: usize) -> io::Result<bool> {
+        let start = self.raw.len();
+        self.raw.resize(start + n, 0);
+        let n_read = self.inner.read(&mut self.raw[start..])?;
+        self.raw.truncate(start + n_read);
+        Ok(n_read > 0)
+    }
+
+    /* Decode as much of `raw` as possible into `decoded`. */
+    fn decode(&mut self) {
+        let mut buff = [0u8; 4];
+        let used = match self.encoding {
+            Encoding::Utf8 | Encoding::Auto => {
+                self.decoded.append(&mut self.raw);
+                0
+            }
+            Encoding::Latin1 => {
+                for &b in self.raw.iter() {
+                    let c = char::from(b);
+                    self.decoded
+                        .extend_from_slice(c.encode_utf8(&mut buff).as_bytes());
+                }
+                self.raw.len()
+            }
+            Encoding::Windows1252 => {
+                for &b in self.raw.iter() {
+                    let c = match b {
+                        0x80..=0x9F => {
+                            char::from_u32(WINDOWS_1252[(b - 0x80) as usize] as u32).unwrap()
+                        }
+                        _ => char::from(b),
+                    };
+                    self.decoded
+                        .extend_from_slice(c.encode_utf8(&mut buff).as_bytes());
+                }
+                self.raw.len()
+            }
+            Encoding::Utf16Le | Encoding::Utf16Be => {
+                let big = self.encoding == Encoding::Utf16Be;
+                let units: Vec<u16> = self
+                    .raw
+                    .chunks_exact(2)
+                    .map(|p| match big {
+                        true => u16::from_be_bytes([p[0], p[1]]),
+                        false => u16::from_le_bytes([p[0], p[1]]),
+                    })
+                    .collect();
+                // Don't split a surrogate pair between reads.
+                let n_units = match units.last() {
+                    Some(0xD800..=0xDBFF) if !self.at_end => units.len() - 1,
+                    _ => units.len(),
+                };
+                for c in char::decode_utf16(units[..n_units].iter().copied()) {
+                    let c = c.unwrap_or(char::REPLACEMENT_CHARACTER);
+                    self.decoded
+                        .extend_from_slice(c.encode_utf8(&mut buff).as_bytes());
+                }
+                n_units * 2
+            }
+        };
+        self.raw.drain(..used);
+        if self.at_end && !self.raw.is_empty() {
+            // An odd byte out at the end of UTF-16.
+            self.raw.clear();
+            let c = char::REPLACEMENT_CHARACTER;
+            self.decoded
+                .extend_from_slice(c.encode_utf8(&mut buff).as_bytes());
+        }
+    }
+}
+
+impl<R: Read> Read for Decoder<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if self.encoding == Encoding::Auto {
+            while self.raw.len() < SNIFF_LEN && self.fill(SNIFF_LEN - self.raw.len())? {}
+            self.encoding = guess(&self.raw);
+        }
+        while self.decoded.is_empty() && !self.at_end {
+            self.at_end = !self.fill(buf.len().max(4))?;
+            self.decode();
+        }
+        let n = buf.len().min(self.decoded.len());
+        buf[..n].copy_from_slice(&self.decoded[..n]);
+        self.decoded.drain(..n);
+        Ok(n)
+    }
+}
+
+/// Writes UTF-8 text in some other encoding.
+pub struct Encoder<W: Write> {
+    inner: W,
+    encoding: Encoding,
+    /// A partial UTF-8 character left over from the last write.
+    pending: Vec<u8>,
+}
+
+impl<W: Write> Encoder<W> {
+    pub fn new(inner: W, encoding: Encoding) -> Encoder<W> {
+        Encoder {
+            inner,
+            encoding,
+            pending: Vec::new(),
+        }
+    }
+
+    fn encode(&self, text: &str, out: &mut Vec<u8>) -> io::Result<()> {
+        for c in text.chars() {
+            let byte = match self.encoding {
+                Encoding::Latin1 => u8::try_from(c).ok(),
+                Encoding::Windows1252 => {
+                    match WINDOWS_1252.iter().position(|&u| u as u32 == c as u32) {
+                        Some(n) => Some(0x80 + n as u8),
+                        None => u8::try_from(c).ok().filter(|b| !(0x80..=0x9F).contains(b)),
+                    }
+                }
+                Encoding::Utf16Le | Encoding::Utf16Be => {
+                    let mut units = [0u16; 2];
+                    for &u in c.encode_utf16(&mut units).iter() {
+                        match self.encoding {
+                            Encoding::Utf16Be => out.extend_from_slice(&u.to_be_bytes()),
+                            _ => out.extend_from_slice(&u.to_le_bytes()),
+                        }
+                    }
+                    continue;
+                }
+                Encoding::Utf8 | Encoding::Auto => {
+                    out.extend_from_slice(c.to_string().as_bytes());
+                    continue;
+                }
+            };
+            match byte {
+                Some(b) => out.push(b),
+                None => {
+                    return Err(io::Error::new(
+                        io::ErrorKind::InvalidData,
+                        format!("{:?} can't be written in {}", c, self.encoding),
+                    ))
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> Write for Encoder<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.pending.extend_from_slice(buf);
+        let valid = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(_) => {
+                self.pending.clear();
+                return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!(
+                        "output isn't valid UTF-8, so can't be written in {}",
+                        self.encoding
+                    ),
+                ));
+            }
+        };
+        let text = std::str::from_utf8(&self.pending[..valid]).unwrap();
+        let mut out: Vec<u8> = Vec::with_capacity(valid * 2);
+        let res = self.encode(text, &mut out);
+        self.pending.drain(..valid);
+        res?;
+        self.inner.write_all(&out)?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+/// Wrap `input` to read it as UTF-8, if it's in some other `encoding`.
+pub fn reader(input: Box<dyn Read>, encoding: Option<Encoding>) -> Box<dyn Read> {
+    match encoding {
+        None | Some(Encoding::Utf8) => input,
+        Some(encoding) => Box::new(Decoder::new(input, encoding)),
+    }
+}
+
+/// Wrap `output` to write UTF-8 in some other `encoding`.
+pub fn writer(output: Box<dyn Write>, encoding: Option<Encoding>) -> Box<dyn Write> {
+    match encoding {
+        None | Some(Encoding::Utf8) | Some(Encoding::Auto) => output,
+        Some(encoding) => Box::new(Encoder::new(output, encoding)),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* Hands out its bytes one at a time, to split everything it can. */
+    struct Trickle<'a>(&'a [u8]);
+
+    impl Read for Trickle<'_> {
+        fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+            match (self.0.split_first(), buf.first_mut()) {
+                (Some((&b, rest)), Some(first)) => {
+                    *first = b;
+                    self.0 = rest;
+                    Ok(1)
+                }
+                _ => Ok(0),
+            }
+        }
+    }
+
+    fn decoded(bytes: &[u8], encoding: Encoding) -> String {
+        let mut whole = String::new();
+        Decoder::new(bytes, encoding)
+            .read_to_string(&mut whole)
+            .unwrap();
+        let mut trickled = String::new();
+        Decoder::new(Trickle(bytes), encoding)
+            .read_to_string(&mut trickled)
+            .unwrap();
+        assert_eq!(whole, trickled);
+        whole
+    }
+
+    fn encoded(text: &str, encoding: Encoding) -> io::Result<Vec<u8>> {
+        let mut out: Vec<u8> = Vec::new();
+        let mut encoder = Encoder::new(&mut out, encoding);
+        // A byte at a time, so characters are split between writes.
+        for b in text.as_bytes().chunks(1) {
+            encoder.write_all(b)?;
+        }
+        Ok(out)
+    }
+
+    #[test]
+    fn names() {
+        assert_eq!("UTF-16LE".parse(), Ok(Encoding::Utf16Le));
+        assert_eq!("cp1252".parse(), Ok(Encoding::Windows1252));
+        assert_eq!("ISO-8859-1".parse(), Ok(Encoding::Latin1));
+        assert!("ebcdic".parse::<Encoding>().is_err());
+        assert_eq!(
+            "Shift_JIS".parse::<Encoding>(),
+            Err(
+                "the Shift_JIS encoding isn't supported; convert to UTF-8 first, \
+                 as with `iconv -f Shift_JIS -t UTF-8`"
+                    .into()
+            )
+        );
+        assert!("big5"
+            .parse::<Encoding>()
+            .unwrap_err()
+            .contains("isn't supported"));
+        for encoding in [Encoding::Latin1, Encoding::Windows1252, Encoding::Utf16Be] {
+            assert_eq!(encoding.to_string().parse(), Ok(encoding));
+        }
+    }
+
+    #[test]
+    fn single_bytes() {
+        let bytes = b"caf\xe9 \x80 \x93x\x94 \x81\xff";
+        assert_eq!(
+            decoded(bytes, Encoding::Latin1),
+            "caf\u{e9} \u{80} \u{93}x\u{94} \u{81}\u{ff}"
+        );
+        assert_eq!(
+            decoded(bytes, Encoding::Windows1252),
+            "caf\u{e9} \u{20ac} \u{201c}x\u{201d} \u{81}\u{ff}"
+        );
+        let text = "caf\u{e9} \u{20ac}5";
+        assert_eq!(
+            encoded(text, Encoding::Windows1252).unwrap(),
+            b"caf\xe9 \x805"
+        );
+        assert!(encoded(text, Encoding::Latin1).is_err());
+        assert!(encoded("\u{80}", Encoding::Windows1252).is_err());
+        assert!(encoded("\u{4e2d}", Encoding::Windows1252).is_err());
+    }
+
+    #[test]
+    fn utf16() {
+        let text = "a\u{e9}\u{4e2d}\u{1f600}";
+        let le = b"a\0\xe9\0\x2d\x4e\x3d\xd8\x00\xde";
+        let be = b"\0a\0\xe9\x4e\x2d\xd8\x3d\xde\x00";
+        assert_eq!(decoded(le, Encoding::Utf16Le), text);
+        assert_eq!(decoded(be, Encoding::Utf16Be), text);
+        assert_eq!(encoded(text, Encoding::Utf16Le).unwrap(), le);
+        assert_eq!(encoded(text, Encoding::Utf16Be).unwrap(), be);
+        // A lone surrogate, or an odd byte at the end, can't be decoded.
+        assert_eq!(decoded(b"\x3d\xd8a\0", Encoding::Utf16Le), "\u{fffd}a");
+        assert_eq!(decoded(b"a\0b", Encoding::Utf16Le), "a\u{fffd}");
+        assert_eq!(decoded(b"a\0\x3d\xd8", Encoding::Utf16Le), "a\u{fffd}");
+    }
+
+    #[test]
+    fn guessing() {
+        assert_eq!(guess(b"\xff\xfea\0"), Encoding::Utf16Le);
+        assert_eq!(guess(b"\xfe\xff\0a"), Encoding::Utf16Be);
+        assert_eq!(guess("caf\u{e9}".as_bytes()), Encoding::Utf8);
+        // Cut off partway through a character is still UTF-8.
+        assert_eq!(guess(b"caf\xc3"), Encoding::Utf8);
+        assert_eq!(guess(b"caf\xe9!"), Encoding::Windows1252);
+        assert_eq!(
+            decoded(b"caf\xe9 \x80", Encoding::Auto),
+            "caf\u{e9} \u{20ac}"
+        );
+        assert_eq!(decoded("caf\u{e9}".as_bytes(), Encoding::Auto), "caf\u{e9}");
+    }
+
+    #[test]
+    fn bad_utf8_output() {
+        let mut out: Vec<u8> = Vec::new();
+        let mut encoder = Encoder::new(&mut out, Encoding::Latin1);
+        assert!(encoder.write_all(b"ok \xff").is_err());
+    }
+}
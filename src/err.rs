@@ -29,6 +29,12 @@ impl From<regex::Error> for FrErr {
     }
 }
 
+impl From<aho_corasick::BuildError> for FrErr {
+    fn from(e: aho_corasick::BuildError) -> Self {
+        FrErr::Misc(Box::new(e))
+    }
+}
+
 impl From<RcErr> for FrErr {
     fn from(e: RcErr) -> Self {
         match e {
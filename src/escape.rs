@@ -0,0 +1,89 @@
+/*!
+Interpreting backslash escape sequences and hex strings in verbatim text.
+
+Recognized escape sequences are `\n`, `\t`, `\r`, `\0`, `\\`, and `\xNN`
+(where `NN` is two hex digits); a backslash before anything else is left
+alone.
+*/
+
+/// Return the value of the hex digit `b`.
+pub fn hex_digit(b: u8) -> Option<u8> {
+    match b {
+        b'0'..=b'9' => Some(b - b'0'),
+        b'a'..=b'f' => Some(b - b'a' + 10),
+        b'A'..=b'F' => Some(b - b'A' + 10),
+        _ => None,
+    }
+}
+
+/// Replace the escape sequences in `text` with the bytes they stand for.
+pub fn unescape(text: &str) -> Result<Vec<u8>, String> {
+    let bytes = text.as_bytes();
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    let mut n: usize = 0;
+
+    while let Some(&b) = bytes.get(n) {
+        n += 1;
+        if b != b'\\' {
+            out.push(b);
+            continue;
+        }
+
+        match bytes.get(n) {
+            Some(b'n') => out.push(b'\n'),
+            Some(b't') => out.push(b'\t'),
+            Some(b'r') => out.push(b'\r'),
+            Some(b'0') => out.push(b'\0'),
+            Some(b'\\') => out.push(b'\\'),
+            Some(b'x') => {
+                let hi = bytes.get(n + 1).copied().and_then(hex_digit);
+                let lo = bytes.get(n + 2).copied().and_then(hex_digit);
+                match (hi, lo) {
+                    (Some(hi), Some(lo)) => out.push(hi * 16 + lo),
+                    _ => return Err(r"\x must be followed by two hex digits".into()),
+                }
+                n += 2;
+            }
+            _ => {
+                out.push(b'\\');
+                continue;
+            }
+        }
+        n += 1;
+    }
+
+    Ok(out)
+}
+
+/**
+Decode a string of hex digits (like `deadbeef`) into the bytes it
+represents. Whitespace between bytes is ignored.
+*/
+pub fn decode_hex(text: &str) -> Result<Vec<u8>, String> {
+    let digits: Vec<u8> = text
+        .bytes()
+        .filter(|b| !b.is_ascii_whitespace())
+        .map(|b| hex_digit(b).ok_or_else(|| format!("{:?} isn't a hex digit", b as char)))
+        .collect::<Result<_, _>>()?;
+    if digits.len() % 2 != 0 {
+        return Err("odd number of hex digits".into());
+    }
+
+    Ok(digits
+        .chunks(2)
+        .map(|pair| pair[0] * 16 + pair[1])
+        .collect())
+}
+
+/// Double any `$`s in `bytes`, so that replacement templates insert them
+/// verbatim.
+pub fn escape_dollars(bytes: &[u8]) -> Vec<u8> {
+    let mut out: Vec<u8> = Vec::with_capacity(bytes.len());
+    for &b in bytes.iter() {
+        if b == b'$' {
+            out.push(b'$');
+        }
+        out.push(b);
+    }
+    out
+}
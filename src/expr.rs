@@ -0,0 +1,293 @@
+/*!
+Tiny arithmetic expressions, for calculating with captured numbers in
+replacement templates.
+
+Expressions are made of numbers (integer or decimal), variables written
+`{name}`, the binary operators `+`, `-`, `*`, `/`, and `%` (with the usual
+precedence), unary `-`, and parentheses. Arithmetic is done on integers
+unless a decimal is involved; integer division truncates. An expression
+that can't be worked out (one that divides by zero, overflows, or uses a
+variable that isn't a number) has no value, and expands to nothing.
+*/
+use std::fmt::{self, Display};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Num {
+    Int(i64),
+    Float(f64),
+}
+
+impl Num {
+    /// Parse captured text as a number.
+    pub fn parse(text: &str) -> Option<Num> {
+        let text = text.trim();
+        match text.parse::<i64>() {
+            Ok(n) => Some(Num::Int(n)),
+            Err(_) => text
+                .parse::<f64>()
+                .ok()
+                .filter(|x| x.is_finite())
+                .map(Num::Float),
+        }
+    }
+
+    pub fn as_f64(self) -> f64 {
+        match self {
+            Num::Int(n) => n as f64,
+            Num::Float(x) => x,
+        }
+    }
+}
+
+impl Display for Num {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Num::Int(n) => write!(f, "{}", n),
+            Num::Float(x) => write!(f, "{}", x),
+        }
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum Op {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+}
+
+impl Op {
+    fn apply(self, a: Num, b: Num) -> Option<Num> {
+        match (a, b) {
+            (Num::Int(a), Num::Int(b)) => match self {
+                Op::Add => a.checked_add(b),
+                Op::Sub => a.checked_sub(b),
+                Op::Mul => a.checked_mul(b),
+                Op::Div => a.checked_div(b),
+                Op::Rem => a.checked_rem(b),
+            }
+            .map(Num::Int),
+            (a, b) => {
+                let (a, b) = (a.as_f64(), b.as_f64());
+                let x = match self {
+                    Op::Add => a + b,
+                    Op::Sub => a - b,
+                    Op::Mul => a * b,
+                    Op::Div => a / b,
+                    Op::Rem => a % b,
+                };
+                x.is_finite().then_some(Num::Float(x))
+            }
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub enum Expr {
+    Num(Num),
+    /// Index into the list of variable names returned by `parse()`.
+    Var(usize),
+    Neg(Box<Expr>),
+    Bin(Op, Box<Expr>, Box<Expr>),
+}
+
+/*
+Recursive-descent parser over the bytes of an expression.
+*/
+struct Parser<'a> {
+    text: &'a [u8],
+    pos: usize,
+    vars: Vec<String>,
+}
+
+impl Parser<'_> {
+    fn peek(&mut self) -> Option<u8> {
+        while self
+            .text
+            .get(self.pos)
+            .is_some_and(|b| b.is_ascii_whitespace())
+        {
+            self.pos += 1;
+        }
+        self.text.get(self.pos).copied()
+    }
+
+    fn expr(&mut self) -> Option<Expr> {
+        let mut lhs = self.term()?;
+        loop {
+            let op = match self.peek() {
+                Some(b'+') => Op::Add,
+                Some(b'-') => Op::Sub,
+                _ => return Some(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Bin(op, Box::new(lhs), Box::new(self.term()?));
+        }
+    }
+
+    fn term(&mut self) -> Option<Expr> {
+        let mut lhs = self.factor()?;
+        loop {
+            let op = match self.peek() {
+                Some(b'*') => Op::Mul,
+                Some(b'/') => Op::Div,
+                Some(b'%') => Op::Rem,
+                _ => return Some(lhs),
+            };
+            self.pos += 1;
+            lhs = Expr::Bin(op, Box::new(lhs), Box::new(self.factor()?));
+        }
+    }
+
+    fn factor(&mut self) -> Option<Expr> {
+        match self.peek()? {
+            b'-' => {
+                self.pos += 1;
+                Some(Expr::Neg(Box::new(self.factor()?)))
+            }
+            b'(' => {
+                self.pos += 1;
+                let e = self.expr()?;
+                if self.peek()? != b')' {
+                    return None;
+                }
+                self.pos += 1;
+                Some(e)
+            }
+            b'{' => {
+                let rest = &self.text[self.pos + 1..];
+                let end = rest.iter().position(|&b| b == b'}')?;
+                let name = std::str::from_utf8(&rest[..end]).ok()?.trim();
+                self.pos += end + 2;
+                let n = match self.vars.iter().position(|v| v == name) {
+                    Some(n) => n,
+                    None => {
+                        self.vars.push(name.to_string());
+                        self.vars.len() - 1
+                    }
+                };
+                Some(Expr::Var(n))
+            }
+            b'0'..=b'9' | b'.' => {
+                let start = self.pos;
+                while self
+                    .text
+                    .get(self.pos)
+                    .is_some_and(|b| b.is_ascii_digit() || *b == b'.')
+                {
+                    self.pos += 1;
+                }
+                let text = std::str::from_utf8(&self.text[start..self.pos]).ok()?;
+                Num::parse(text).map(Expr::Num)
+            }
+            _ => None,
+        }
+    }
+}
+
+impl Expr {
+    /**
+    Parse `text` as an expression, also returning the names of the
+    variables it uses (which `Expr::Var`s index).
+    */
+    pub fn parse(text: &[u8]) -> Option<(Expr, Vec<String>)> {
+        let mut p = Parser {
+            text,
+            pos: 0,
+            vars: Vec::new(),
+        };
+        let e = p.expr()?;
+        match p.peek() {
+            None => Some((e, p.vars)),
+            Some(_) => None,
+        }
+    }
+
+    /// Evaluate with the given variable values. Returns `None` if a
+    /// variable has no value or the arithmetic fails.
+    pub fn eval(&self, vars: &[Option<Num>]) -> Option<Num> {
+        match self {
+            Expr::Num(n) => Some(*n),
+            Expr::Var(n) => vars.get(*n).copied().flatten(),
+            Expr::Neg(e) => match e.eval(vars)? {
+                Num::Int(n) => n.checked_neg().map(Num::Int),
+                Num::Float(x) => Some(Num::Float(-x)),
+            },
+            Expr::Bin(op, a, b) => op.apply(a.eval(vars)?, b.eval(vars)?),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn eval(text: &str, vars: &[(&str, &str)]) -> Option<Num> {
+        let (expr, names) = Expr::parse(text.as_bytes())?;
+        let values: Vec<Option<Num>> = names
+            .iter()
+            .map(|name| {
+                let (_, value) = vars.iter().find(|(n, _)| n == name)?;
+                Num::parse(value)
+            })
+            .collect();
+        expr.eval(&values)
+    }
+
+    #[test]
+    fn precedence() {
+        assert_eq!(eval("1 + 2 * 3", &[]), Some(Num::Int(7)));
+        assert_eq!(eval("(1 + 2) * 3", &[]), Some(Num::Int(9)));
+        assert_eq!(eval("10 - 4 - 3", &[]), Some(Num::Int(3)));
+        assert_eq!(eval("100 / 10 / 5", &[]), Some(Num::Int(2)));
+        assert_eq!(eval("7 % 4 * 2", &[]), Some(Num::Int(6)));
+        assert_eq!(eval("-2 * -(3 + 1)", &[]), Some(Num::Int(8)));
+        assert_eq!(eval("2 - -1", &[]), Some(Num::Int(3)));
+    }
+
+    #[test]
+    fn integers_and_decimals() {
+        assert_eq!(eval("7 / 2", &[]), Some(Num::Int(3)));
+        assert_eq!(eval("-7 / 2", &[]), Some(Num::Int(-3)));
+        assert_eq!(eval("7.0 / 2", &[]), Some(Num::Float(3.5)));
+        assert_eq!(eval("0.5 + .25", &[]), Some(Num::Float(0.75)));
+        assert_eq!(eval("5.5 % 2", &[]), Some(Num::Float(1.5)));
+        assert_eq!(Num::Float(2.5).to_string(), "2.5");
+        assert_eq!(Num::parse(" 42 "), Some(Num::Int(42)));
+        assert_eq!(Num::parse("1e3"), Some(Num::Float(1000.0)));
+        assert_eq!(Num::parse("inf"), None);
+        assert_eq!(Num::parse("abc"), None);
+    }
+
+    #[test]
+    fn variables() {
+        let vars = [("1", "12"), ("price", "2.50"), ("n", "x")];
+        assert_eq!(eval("{1} * 2", &vars), Some(Num::Int(24)));
+        assert_eq!(eval("{ price } * {1}", &vars), Some(Num::Float(30.0)));
+        assert_eq!(eval("{1} + {1}", &vars), Some(Num::Int(24)));
+        // Variables that aren't numbers, or aren't there, have no value.
+        assert_eq!(eval("{n} + 1", &vars), None);
+        assert_eq!(eval("{2} + 1", &vars), None);
+        let (_, names) = Expr::parse(b"{a} + {b} * {a}").unwrap();
+        assert_eq!(names, ["a", "b"]);
+    }
+
+    #[test]
+    fn failed_arithmetic() {
+        assert_eq!(eval("1 / 0", &[]), None);
+        assert_eq!(eval("1 % 0", &[]), None);
+        assert_eq!(eval("1.0 / 0", &[]), None);
+        assert_eq!(eval("1.5 % 0", &[]), None);
+        assert_eq!(eval("9223372036854775807 + 1", &[]), None);
+        assert_eq!(eval("-(-9223372036854775807 - 1)", &[]), None);
+        assert_eq!(eval("(-9223372036854775807 - 1) / -1", &[]), None);
+    }
+
+    #[test]
+    fn bad_syntax() {
+        for text in ["", "1 +", "(1", "1)", "1 2", "{1", "* 2", "1..2", "a"] {
+            assert!(Expr::parse(text.as_bytes()).is_none(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,1284 @@
+/*!
+A backtracking regex engine, for `--engine fancy`: patterns can use
+backreferences (`\1`, `\k<name>`), lookahead and lookbehind (`(?=...)`,
+`(?!...)`, `(?<=...)`, `(?<!...)`), and atomic groups (`(?>...)`), which
+the `regex` crate leaves out.
+
+As with the fancy-regex crate, this only takes over where it has to: a
+pattern the `regex` crate can compile is run by it (see `main::matcher()`),
+and only the rest are compiled here. The syntax is otherwise the `regex`
+crate's, less Unicode classes like `\pL` and class set operations like
+`[a-z&&[^aeiou]]`, and matching is leftmost-first, as there. In Unicode
+mode, `\d`, `\w` and `\s` are Rust's ideas of numeric, alphanumeric and
+whitespace characters. Lookbehind can be of any bounded length.
+
+Backtracking can take exponentially long on some patterns (like
+`(a*)*b`), so a search that takes too many steps is given up on, with a
+warning.
+*/
+use std::cell::Cell;
+
+use crate::{
+    matcher::{chars_eq_nocase, decode_char, Caps, Matcher},
+    opt::RegexFlags,
+};
+
+/// How many steps a search from one place can take before it's given up.
+const STEP_LIMIT: usize = 1_000_000;
+
+/// The most a counted repetition can be repeated.
+const MAX_REPEAT: u32 = 1000;
+
+/// The most instructions a compiled pattern can have.
+const MAX_INSTS: usize = 100_000;
+
+#[derive(Clone, Copy, Debug)]
+struct Flags {
+    ignore_case: bool,
+    multi_line: bool,
+    dot_matches_new_line: bool,
+    ignore_whitespace: bool,
+    unicode: bool,
+    swap_greed: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Look {
+    TextStart,
+    TextEnd,
+    LineStart,
+    LineEnd,
+    WordBoundary { unicode: bool },
+    NotWordBoundary { unicode: bool },
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Perl {
+    Digit,
+    Word,
+    Space,
+}
+
+impl Perl {
+    fn matches(&self, c: char, unicode: bool) -> bool {
+        match (self, unicode) {
+            (Perl::Digit, true) => c.is_numeric(),
+            (Perl::Digit, false) => c.is_ascii_digit(),
+            (Perl::Word, true) => c.is_alphanumeric() || c == '_',
+            (Perl::Word, false) => c.is_ascii_alphanumeric() || c == '_',
+            (Perl::Space, true) => c.is_whitespace(),
+            (Perl::Space, false) => c.is_ascii_whitespace() || c == '\x0b',
+        }
+    }
+}
+
+/* The ASCII character classes that can be named like `[[:alpha:]]`. */
+fn posix(name: &str) -> Option<fn(&char) -> bool> {
+    let class: fn(&char) -> bool = match name {
+        "alnum" => char::is_ascii_alphanumeric,
+        "alpha" => char::is_ascii_alphabetic,
+        "ascii" => char::is_ascii,
+        "blank" => |c| *c == ' ' || *c == '\t',
+        "cntrl" => char::is_ascii_control,
+        "digit" => char::is_ascii_digit,
+        "graph" => char::is_ascii_graphic,
+        "lower" => char::is_ascii_lowercase,
+        "print" => |c| c.is_ascii_graphic() || *c == ' ',
+        "punct" => char::is_ascii_punctuation,
+        "space" => |c| c.is_ascii_whitespace() || *c == '\x0b',
+        "upper" => char::is_ascii_uppercase,
+        "word" => |c| c.is_ascii_alphanumeric() || *c == '_',
+        "xdigit" => char::is_ascii_hexdigit,
+        _ => return None,
+    };
+    Some(class)
+}
+
+#[derive(Clone, Debug)]
+enum Item {
+    Range(char, char),
+    Perl(Perl, bool),
+    Posix(fn(&char) -> bool, bool),
+}
+
+#[derive(Clone, Debug)]
+struct Class {
+    items: Vec<Item>,
+    negated: bool,
+    ignore_case: bool,
+    unicode: bool,
+}
+
+impl Class {
+    fn contains(&self, c: char) -> bool {
+        let has = |c: char| {
+            self.items.iter().any(|item| match item {
+                Item::Range(lo, hi) => (*lo..=*hi).contains(&c),
+                Item::Perl(perl, negated) => perl.matches(c, self.unicode) != *negated,
+                Item::Posix(class, negated) => class(&c) != *negated,
+            })
+        };
+        let found = has(c)
+            || (self.ignore_case && (c.to_lowercase().any(has) || c.to_uppercase().any(has)));
+        found != self.negated
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Node {
+    Empty,
+    Char {
+        c: char,
+        ignore_case: bool,
+        unicode: bool,
+    },
+    Any {
+        new_line: bool,
+        unicode: bool,
+    },
+    Class(Class),
+    Assert(Look),
+    Group(Option<usize>, Box<Node>),
+    Concat(Vec<Node>),
+    Alt(Vec<Node>),
+    Repeat {
+        node: Box<Node>,
+        min: u32,
+        max: Option<u32>,
+        greedy: bool,
+    },
+    Backref {
+        group: usize,
+        ignore_case: bool,
+    },
+    NamedBackref {
+        name: String,
+        ignore_case: bool,
+    },
+    Look {
+        node: Box<Node>,
+        behind: bool,
+        negate: bool,
+    },
+    Atomic(Box<Node>),
+}
+
+impl Node {
+    /* The most bytes this can match, if there's a limit. */
+    fn max_len(&self) -> Option<usize> {
+        match self {
+            Node::Empty | Node::Assert(_) | Node::Look { .. } => Some(0),
+            Node::Char {
+                c, unicode: true, ..
+            } => Some(c.len_utf8()),
+            Node::Char { .. } => Some(1),
+            Node::Any { unicode, .. } => Some(if *unicode { 4 } else { 1 }),
+            Node::Class(class) => Some(if class.unicode { 4 } else { 1 }),
+            Node::Group(_, node) | Node::Atomic(node) => node.max_len(),
+            Node::Concat(nodes) => nodes.iter().map(Node::max_len).sum(),
+            Node::Alt(nodes) => nodes
+                .iter()
+                .map(Node::max_len)
+                .try_fold(0, |a, b| Some(a.max(b?))),
+            Node::Repeat { node, max, .. } => Some(node.max_len()? * (*max)? as usize),
+            Node::Backref { .. } | Node::NamedBackref { .. } => None,
+        }
+    }
+}
+
+/* A literal character; without Unicode, one of more than a byte is its UTF-8. */
+fn literal(c: char, flags: &Flags) -> Node {
+    let char = |c: char| Node::Char {
+        c,
+        ignore_case: flags.ignore_case,
+        unicode: flags.unicode,
+    };
+    if flags.unicode || c.is_ascii() {
+        return char(c);
+    }
+    let mut buff = [0u8; 4];
+    let bytes = c.encode_utf8(&mut buff).bytes();
+    let node = Node::Concat(bytes.map(|b| char(char::from(b))).collect());
+    Node::Group(None, Box::new(node))
+}
+
+struct Parser {
+    chars: Vec<char>,
+    pos: usize,
+    /// The names of the capture groups so far, counting the whole match.
+    names: Vec<Option<String>>,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<char> {
+        self.chars.get(self.pos).copied()
+    }
+
+    fn peek_at(&self, n: usize) -> Option<char> {
+        self.chars.get(self.pos + n).copied()
+    }
+
+    fn eat(&mut self, c: char) -> bool {
+        let found = self.peek() == Some(c);
+        if found {
+            self.pos += 1;
+        }
+        found
+    }
+
+    fn eat_str(&mut self, s: &str) -> bool {
+        let found = s
+            .chars()
+            .enumerate()
+            .all(|(n, c)| self.peek_at(n) == Some(c));
+        if found {
+            self.pos += s.chars().count();
+        }
+        found
+    }
+
+    fn next(&mut self) -> Result<char, String> {
+        let c = self.peek().ok_or("unexpected end of pattern")?;
+        self.pos += 1;
+        Ok(c)
+    }
+
+    /* Skip whitespace and comments, in `x` mode. */
+    fn skip_space(&mut self, flags: &Flags) {
+        if !flags.ignore_whitespace {
+            return;
+        }
+        while let Some(c) = self.peek() {
+            if c.is_whitespace() {
+                self.pos += 1;
+            } else if c == '#' {
+                while !matches!(self.peek(), None | Some('\n')) {
+                    self.pos += 1;
+                }
+            } else {
+                break;
+            }
+        }
+    }
+
+    fn alternation(&mut self, flags: &mut Flags) -> Result<Node, String> {
+        let mut alts = vec![self.concat(flags)?];
+        while self.eat('|') {
+            alts.push(self.concat(flags)?);
+        }
+        Ok(match alts.len() {
+            1 => alts.pop().unwrap(),
+            _ => Node::Alt(alts),
+        })
+    }
+
+    fn concat(&mut self, flags: &mut Flags) -> Result<Node, String> {
+        let mut nodes: Vec<Node> = Vec::new();
+        loop {
+            self.skip_space(flags);
+            match self.peek() {
+                None | Some('|') | Some(')') => break,
+                _ => {}
+            }
+            let atom = self.atom(flags)?;
+            self.skip_space(flags);
+            nodes.push(self.repetition(atom, flags)?);
+        }
+        Ok(match nodes.len() {
+            0 => Node::Empty,
+            1 => nodes.pop().unwrap(),
+            _ => Node::Concat(nodes),
+        })
+    }
+
+    fn number(&mut self) -> Option<u32> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_ascii_digit()) {
+            self.pos += 1;
+        }
+        self.chars[start..self.pos]
+            .iter()
+            .collect::<String>()
+            .parse()
+            .ok()
+    }
+
+    fn repetition(&mut self, atom: Node, flags: &Flags) -> Result<Node, String> {
+        let (min, max) = match self.peek() {
+            Some('*') => (0, None),
+            Some('+') => (1, None),
+            Some('?') => (0, Some(1)),
+            Some('{') => {
+                let start = self.pos;
+                self.pos += 1;
+                let min = self.number();
+                let max = match self.eat(',') {
+                    true => self.number(),
+                    false => min,
+                };
+                match (min, self.peek()) {
+                    (Some(min), Some('}')) => (min, max),
+                    _ => {
+                        self.pos = start;
+                        return Err("bad counted repetition".into());
+                    }
+                }
+            }
+            _ => return Ok(atom),
+        };
+        self.pos += 1;
+        if max.is_some_and(|max| max < min) {
+            return Err("repetition has a maximum less than its minimum".into());
+        }
+        if min.max(max.unwrap_or(0)) > MAX_REPEAT {
+            return Err(format!("repetitions are limited to {}", MAX_REPEAT));
+        }
+        if matches!(atom, Node::Empty | Node::Assert(_) | Node::Look { .. }) {
+            return Err("nothing to repeat".into());
+        }
+        let lazy = self.eat('?');
+        if matches!(self.peek(), Some('*' | '+' | '?' | '{')) {
+            return Err("repetition of a repetition".into());
+        }
+        Ok(Node::Repeat {
+            node: Box::new(atom),
+            min,
+            max,
+            greedy: lazy == flags.swap_greed,
+        })
+    }
+
+    fn atom(&mut self, flags: &mut Flags) -> Result<Node, String> {
+        let c = self.next()?;
+        let node = match c {
+            '(' => return self.group(flags),
+            '[' => Node::Class(self.class(flags)?),
+            '.' => Node::Any {
+                new_line: flags.dot_matches_new_line,
+                unicode: flags.unicode,
+            },
+            '^' => Node::Assert(match flags.multi_line {
+                true => Look::LineStart,
+                false => Look::TextStart,
+            }),
+            '$' => Node::Assert(match flags.multi_line {
+                true => Look::LineEnd,
+                false => Look::TextEnd,
+            }),
+            '\\' => self.escape(flags)?,
+            '*' | '+' | '?' | '{' => return Err("repetition with nothing to repeat".into()),
+            c => literal(c, flags),
+        };
+        Ok(node)
+    }
+
+    fn group_name(&mut self) -> Result<String, String> {
+        let start = self.pos;
+        while self.peek().is_some_and(|c| c.is_alphanumeric() || c == '_') {
+            self.pos += 1;
+        }
+        let name: String = self.chars[start..self.pos].iter().collect();
+        if name.is_empty() || !self.eat('>') {
+            return Err("bad group name".into());
+        }
+        Ok(name)
+    }
+
+    fn group(&mut self, flags: &mut Flags) -> Result<Node, String> {
+        let mut inner = *flags;
+        let node = if self.eat_str("?:") {
+            Node::Group(None, Box::new(self.alternation(&mut inner)?))
+        } else if self.eat_str("?=") || self.eat_str("?!") {
+            let negate = self.chars[self.pos - 1] == '!';
+            let node = Box::new(self.alternation(&mut inner)?);
+            Node::Look {
+                node,
+                behind: false,
+                negate,
+            }
+        } else if self.eat_str("?<=") || self.eat_str("?<!") {
+            let negate = self.chars[self.pos - 1] == '!';
+            let node = Box::new(self.alternation(&mut inner)?);
+            if node.max_len().is_none() {
+                return Err("lookbehind must have a bounded length".into());
+            }
+            Node::Look {
+                node,
+                behind: true,
+                negate,
+            }
+        } else if self.eat_str("?>") {
+            Node::Atomic(Box::new(self.alternation(&mut inner)?))
+        } else if self.eat_str("?P<") || self.eat_str("?<") {
+            let name = self.group_name()?;
+            if self.names.contains(&Some(name.clone())) {
+                return Err(format!("duplicate group name {}", name));
+            }
+            let index = self.names.len();
+            self.names.push(Some(name));
+            Node::Group(Some(index), Box::new(self.alternation(&mut inner)?))
+        } else if self.eat('?') {
+            // Flags, either for the rest of the group this is in, or for
+            // the group they start.
+            let mut on = true;
+            loop {
+                match self.next()? {
+                    'i' => flags.ignore_case = on,
+                    'm' => flags.multi_line = on,
+                    's' => flags.dot_matches_new_line = on,
+                    'x' => flags.ignore_whitespace = on,
+                    'u' => flags.unicode = on,
+                    'U' => flags.swap_greed = on,
+                    'R' => {}
+                    '-' if on => on = false,
+                    ')' => return Ok(Node::Empty),
+                    ':' => break,
+                    c => return Err(format!("unknown flag {:?}", c)),
+                }
+            }
+            // They were only for this group after all.
+            let mut scoped = *flags;
+            *flags = inner;
+            Node::Group(None, Box::new(self.alternation(&mut scoped)?))
+        } else {
+            let index = self.names.len();
+            self.names.push(None);
+            Node::Group(Some(index), Box::new(self.alternation(&mut inner)?))
+        };
+        if !self.eat(')') {
+            return Err("unclosed group".into());
+        }
+        Ok(node)
+    }
+
+    /* The character after `\x`, `\u` or `\U`, with `digits` hex digits. */
+    fn hex(&mut self, digits: usize) -> Result<char, String> {
+        let start = self.pos;
+        let end = match self.eat('{') {
+            true => {
+                let len = self.chars[start..].iter().position(|&c| c == '}');
+                let len = len.ok_or("unclosed hex escape")?;
+                self.pos = start + len + 1;
+                start + len
+            }
+            false => {
+                self.pos = (start + digits).min(self.chars.len());
+                self.pos
+            }
+        };
+        let digits: String = self.chars[start..end]
+            .iter()
+            .filter(|&&c| c != '{')
+            .collect();
+        u32::from_str_radix(&digits, 16)
+            .ok()
+            .and_then(char::from_u32)
+            .ok_or_else(|| "bad hex escape".into())
+    }
+
+    /*
+    What follows a backslash: a character, or (outside a class) something
+    else. In a class, a Perl class comes back as one.
+    */
+    fn escape(&mut self, flags: &Flags) -> Result<Node, String> {
+        let c = self.next()?;
+        let perl = |perl: Perl, negated: bool| {
+            Node::Class(Class {
+                items: vec![Item::Perl(perl, negated)],
+                negated: false,
+                ignore_case: false,
+                unicode: flags.unicode,
+            })
+        };
+        let c = match c {
+            'd' => return Ok(perl(Perl::Digit, false)),
+            'D' => return Ok(perl(Perl::Digit, true)),
+            'w' => return Ok(perl(Perl::Word, false)),
+            'W' => return Ok(perl(Perl::Word, true)),
+            's' => return Ok(perl(Perl::Space, false)),
+            'S' => return Ok(perl(Perl::Space, true)),
+            'A' => return Ok(Node::Assert(Look::TextStart)),
+            'z' => return Ok(Node::Assert(Look::TextEnd)),
+            'b' => {
+                return Ok(Node::Assert(Look::WordBoundary {
+                    unicode: flags.unicode,
+                }))
+            }
+            'B' => {
+                return Ok(Node::Assert(Look::NotWordBoundary {
+                    unicode: flags.unicode,
+                }))
+            }
+            '1'..='9' => {
+                self.pos -= 1;
+                let group = self.number().ok_or("bad backreference")? as usize;
+                return Ok(Node::Backref {
+                    group,
+                    ignore_case: flags.ignore_case,
+                });
+            }
+            'k' if self.eat('<') => {
+                return Ok(Node::NamedBackref {
+                    name: self.group_name()?,
+                    ignore_case: flags.ignore_case,
+                })
+            }
+            'p' | 'P' => return Err("Unicode classes aren't supported by --engine fancy".into()),
+            'n' => '\n',
+            't' => '\t',
+            'r' => '\r',
+            'f' => '\x0c',
+            'v' => '\x0b',
+            'a' => '\x07',
+            'x' => match (self.hex(2)?, flags.unicode) {
+                (c, true) => c,
+                // Without Unicode, this is a byte, not a character.
+                (c, false) if u32::from(c) < 0x100 => {
+                    return Ok(Node::Char {
+                        c,
+                        ignore_case: flags.ignore_case,
+                        unicode: false,
+                    })
+                }
+                _ => return Err("hex escapes above \\xFF need Unicode".into()),
+            },
+            'u' => self.hex(4)?,
+            'U' => self.hex(8)?,
+            c if c.is_ascii_punctuation() || c == ' ' => c,
+            c => return Err(format!("unknown escape \\{}", c)),
+        };
+        Ok(literal(c, flags))
+    }
+
+    /* A character of a class, as in a range. */
+    fn class_char(&mut self, flags: &Flags) -> Result<Item, String> {
+        match self.next()? {
+            '\\' => match self.escape(flags)? {
+                Node::Char { c, .. } => Ok(Item::Range(c, c)),
+                Node::Class(mut class) => Ok(class.items.pop().unwrap()),
+                _ => Err("bad escape in a class".into()),
+            },
+            '[' if self.peek() == Some(':') => {
+                self.pos += 1;
+                let negated = self.eat('^');
+                let start = self.pos;
+                while self.peek().is_some_and(|c| c.is_ascii_lowercase()) {
+                    self.pos += 1;
+                }
+                let name: String = self.chars[start..self.pos].iter().collect();
+                match (posix(&name), self.eat_str(":]")) {
+                    (Some(class), true) => Ok(Item::Posix(class, negated)),
+                    _ => Err(format!("unknown class [:{}:]", name)),
+                }
+            }
+            '[' => Err("nested classes aren't supported by --engine fancy".into()),
+            '&' if self.peek() == Some('&') => {
+                Err("class set operations aren't supported by --engine fancy".into())
+            }
+            c => Ok(Item::Range(c, c)),
+        }
+    }
+
+    fn class(&mut self, flags: &Flags) -> Result<Class, String> {
+        let negated = self.eat('^');
+        let mut items: Vec<Item> = Vec::new();
+        let mut first = true;
+        loop {
+            match self.peek() {
+                None => return Err("unclosed class".into()),
+                Some(']') if !first => {
+                    self.pos += 1;
+                    break;
+                }
+                _ => {}
+            }
+            first = false;
+            let item = self.class_char(flags)?;
+            let is_range = self.peek() == Some('-') && !matches!(self.peek_at(1), None | Some(']'));
+            match item {
+                Item::Range(lo, _) if is_range => {
+                    self.pos += 1;
+                    match self.class_char(flags)? {
+                        Item::Range(hi, _) if lo <= hi => items.push(Item::Range(lo, hi)),
+                        _ => return Err("bad class range".into()),
+                    }
+                }
+                item => items.push(item),
+            }
+        }
+        Ok(Class {
+            items,
+            negated,
+            ignore_case: flags.ignore_case,
+            unicode: flags.unicode,
+        })
+    }
+}
+
+#[derive(Clone, Debug)]
+enum Inst {
+    Char {
+        c: char,
+        ignore_case: bool,
+        unicode: bool,
+    },
+    Any {
+        new_line: bool,
+        unicode: bool,
+    },
+    Class(Class),
+    Assert(Look),
+    /// Try the first, and then (if that fails) the second.
+    Split(usize, usize),
+    Jump(usize),
+    /// Note where a capture group starts or ends.
+    Save(usize),
+    /// Note where a loop's iteration starts...
+    Mark(usize),
+    /// ...and fail if it hasn't got anywhere since.
+    Progress(usize),
+    Backref {
+        group: usize,
+        ignore_case: bool,
+    },
+    /// Lookaround, for the subprogram that follows (ending with `Match`),
+    /// continuing at `next`; `behind` is how far it could reach back.
+    Look {
+        behind: Option<usize>,
+        negate: bool,
+        next: usize,
+    },
+    Atomic {
+        next: usize,
+    },
+    Match,
+}
+
+struct Compiler {
+    prog: Vec<Inst>,
+    n_marks: usize,
+    names: Vec<Option<String>>,
+}
+
+impl Compiler {
+    fn push(&mut self, inst: Inst) -> usize {
+        self.prog.push(inst);
+        self.prog.len() - 1
+    }
+
+    fn compile(&mut self, node: &Node) -> Result<(), String> {
+        if self.prog.len() > MAX_INSTS {
+            return Err("pattern is too big once repetitions are expanded".into());
+        }
+        match node {
+            Node::Empty => {}
+            Node::Char {
+                c,
+                ignore_case,
+                unicode,
+            } => {
+                self.push(Inst::Char {
+                    c: *c,
+                    ignore_case: *ignore_case,
+                    unicode: *unicode,
+                });
+            }
+            Node::Any { new_line, unicode } => {
+                self.push(Inst::Any {
+                    new_line: *new_line,
+                    unicode: *unicode,
+                });
+            }
+            Node::Class(class) => {
+                self.push(Inst::Class(class.clone()));
+            }
+            Node::Assert(look) => {
+                self.push(Inst::Assert(*look));
+            }
+            Node::Group(None, node) => self.compile(node)?,
+            Node::Group(Some(n), node) => {
+                self.push(Inst::Save(2 * n));
+                self.compile(node)?;
+                self.push(Inst::Save(2 * n + 1));
+            }
+            Node::Concat(nodes) => {
+                for node in nodes {
+                    self.compile(node)?;
+                }
+            }
+            Node::Alt(nodes) => {
+                let mut jumps: Vec<usize> = Vec::new();
+                for (n, node) in nodes.iter().enumerate() {
+                    if n + 1 == nodes.len() {
+                        self.compile(node)?;
+                        break;
+                    }
+                    let split = self.push(Inst::Split(0, 0));
+                    self.compile(node)?;
+                    jumps.push(self.push(Inst::Jump(0)));
+                    self.prog[split] = Inst::Split(split + 1, self.prog.len());
+                }
+                let end = self.prog.len();
+                for jump in jumps {
+                    self.prog[jump] = Inst::Jump(end);
+                }
+            }
+            Node::Repeat {
+                node,
+                min,
+                max,
+                greedy,
+            } => {
+                for _ in 0..*min {
+                    self.compile(node)?;
+                }
+                match max {
+                    Some(max) => {
+                        // Each optional repetition is inside the one before.
+                        let mut splits: Vec<usize> = Vec::new();
+                        for _ in *min..*max {
+                            splits.push(self.push(Inst::Split(0, 0)));
+                            self.compile(node)?;
+                        }
+                        let end = self.prog.len();
+                        for split in splits {
+                            self.prog[split] = self.split(*greedy, split + 1, end);
+                        }
+                    }
+                    None => {
+                        let mark = self.n_marks;
+                        self.n_marks += 1;
+                        let split = self.push(Inst::Split(0, 0));
+                        self.push(Inst::Mark(mark));
+                        self.compile(node)?;
+                        self.push(Inst::Progress(mark));
+                        self.push(Inst::Jump(split));
+                        self.prog[split] = self.split(*greedy, split + 1, self.prog.len());
+                    }
+                }
+            }
+            Node::Backref { group, ignore_case } => {
+                if *group >= self.names.len() {
+                    return Err(format!(
+                        "backreference to a group that doesn't exist: {}",
+                        group
+                    ));
+                }
+                self.push(Inst::Backref {
+                    group: *group,
+                    ignore_case: *ignore_case,
+                });
+            }
+            Node::NamedBackref { name, ignore_case } => {
+                let group = self.names.iter().position(|n| n.as_deref() == Some(name));
+                let group = group.ok_or_else(|| format!("no group named {}", name))?;
+                self.push(Inst::Backref {
+                    group,
+                    ignore_case: *ignore_case,
+                });
+            }
+            Node::Look {
+                node,
+                behind,
+                negate,
+            } => {
+                let look = self.push(Inst::Match);
+                self.compile(node)?;
+                self.push(Inst::Match);
+                self.prog[look] = Inst::Look {
+                    behind: match behind {
+                        true => node.max_len(),
+                        false => None,
+                    },
+                    negate: *negate,
+                    next: self.prog.len(),
+                };
+            }
+            Node::Atomic(node) => {
+                let atomic = self.push(Inst::Match);
+                self.compile(node)?;
+                self.push(Inst::Match);
+                self.prog[atomic] = Inst::Atomic {
+                    next: self.prog.len(),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn split(&self, greedy: bool, body: usize, end: usize) -> Inst {
+        match greedy {
+            true => Inst::Split(body, end),
+            false => Inst::Split(end, body),
+        }
+    }
+}
+
+/* What to undo when backtracking to an earlier choice. */
+enum Frame {
+    Retry { pc: usize, pos: usize },
+    Slot(usize, Option<usize>),
+    Slots(Vec<Option<usize>>),
+    Mark(usize, usize),
+}
+
+/* The state of a search. */
+struct Search<'h> {
+    haystack: &'h [u8],
+    steps: usize,
+}
+
+impl Search<'_> {
+    /* The character at `pos` (or byte, without Unicode), and its length. */
+    fn char_at(&self, pos: usize, unicode: bool) -> Option<(char, usize)> {
+        match unicode {
+            true => decode_char(self.haystack.get(pos..)?),
+            false => self.haystack.get(pos).map(|&b| (char::from(b), 1)),
+        }
+    }
+
+    /* Whether the character that ends at `pos` is a word character. */
+    fn word_before(&self, pos: usize, unicode: bool) -> bool {
+        let hay = self.haystack;
+        let start = match unicode {
+            true => (pos.saturating_sub(4)..pos)
+                .rev()
+                .find(|&n| !(0x80..0xc0).contains(&hay[n])),
+            false => pos.checked_sub(1),
+        };
+        match start.and_then(|start| Some((start, self.char_at(start, unicode)?))) {
+            Some((start, (c, len))) if start + len == pos => Perl::Word.matches(c, unicode),
+            _ => false,
+        }
+    }
+
+    fn assert(&self, look: Look, pos: usize) -> bool {
+        let hay = self.haystack;
+        match look {
+            Look::TextStart => pos == 0,
+            Look::TextEnd => pos == hay.len(),
+            Look::LineStart => pos == 0 || hay[pos - 1] == b'\n',
+            Look::LineEnd => pos == hay.len() || hay[pos] == b'\n',
+            Look::WordBoundary { unicode } | Look::NotWordBoundary { unicode } => {
+                let before = self.word_before(pos, unicode);
+                let after = self
+                    .char_at(pos, unicode)
+                    .is_some_and(|(c, _)| Perl::Word.matches(c, unicode));
+                (before != after) == matches!(look, Look::WordBoundary { .. })
+            }
+        }
+    }
+
+    /* Whether the text of `group` is at `pos`, returning where it ends. */
+    fn backref(
+        &self,
+        slots: &[Option<usize>],
+        group: usize,
+        ignore_case: bool,
+        pos: usize,
+    ) -> Option<usize> {
+        let (start, end) = match (slots[2 * group], slots[2 * group + 1]) {
+            (Some(start), Some(end)) => (start, end),
+            // A group that hasn't matched matches nothing.
+            _ => return None,
+        };
+        let text = &self.haystack[start..end];
+        if self.haystack[pos..].starts_with(text) {
+            return Some(pos + text.len());
+        }
+        if !ignore_case {
+            return None;
+        }
+        let (mut at, mut here) = (0, pos);
+        while at < text.len() {
+            let (a, a_len) = decode_char(&text[at..])?;
+            let (b, b_len) = decode_char(&self.haystack[here..])?;
+            if !chars_eq_nocase(a, b) {
+                return None;
+            }
+            (at, here) = (at + a_len, here + b_len);
+        }
+        Some(here)
+    }
+
+    /*
+    Run `prog` from `pc` at `pos`, returning where it matches up to (which
+    must be `end`, if given), with the capture groups in `slots`.
+    */
+    fn run(
+        &mut self,
+        prog: &[Inst],
+        mut pc: usize,
+        mut pos: usize,
+        end: Option<usize>,
+        slots: &mut Vec<Option<usize>>,
+        marks: &mut Vec<usize>,
+    ) -> Option<usize> {
+        let mut stack: Vec<Frame> = Vec::new();
+        loop {
+            if self.steps >= STEP_LIMIT {
+                return None;
+            }
+            self.steps += 1;
+            let ok = match &prog[pc] {
+                Inst::Char {
+                    c,
+                    ignore_case,
+                    unicode,
+                } => match self.char_at(pos, *unicode) {
+                    Some((h, len)) if h == *c || (*ignore_case && chars_eq_nocase(h, *c)) => {
+                        pos += len;
+                        true
+                    }
+                    _ => false,
+                },
+                Inst::Any { new_line, unicode } => match self.char_at(pos, *unicode) {
+                    Some((h, len)) if *new_line || h != '\n' => {
+                        pos += len;
+                        true
+                    }
+                    _ => false,
+                },
+                Inst::Class(class) => match self.char_at(pos, class.unicode) {
+                    Some((h, len)) if class.contains(h) => {
+                        pos += len;
+                        true
+                    }
+                    _ => false,
+                },
+                Inst::Assert(look) => self.assert(*look, pos),
+                Inst::Split(first, second) => {
+                    stack.push(Frame::Retry { pc: *second, pos });
+                    pc = *first;
+                    continue;
+                }
+                Inst::Jump(to) => {
+                    pc = *to;
+                    continue;
+                }
+                Inst::Save(slot) => {
+                    stack.push(Frame::Slot(*slot, slots[*slot]));
+                    slots[*slot] = Some(pos);
+                    true
+                }
+                Inst::Mark(mark) => {
+                    stack.push(Frame::Mark(*mark, marks[*mark]));
+                    marks[*mark] = pos;
+                    true
+                }
+                Inst::Progress(mark) => marks[*mark] != pos,
+                Inst::Backref { group, ignore_case } => {
+                    match self.backref(slots, *group, *ignore_case, pos) {
+                        Some(to) => {
+                            pos = to;
+                            true
+                        }
+                        None => false,
+                    }
+                }
+                Inst::Look {
+                    behind,
+                    negate,
+                    next,
+                } => {
+                    let mut inner = slots.clone();
+                    let found = match behind {
+                        None => self
+                            .run(prog, pc + 1, pos, None, &mut inner, marks)
+                            .is_some(),
+                        Some(reach) => (pos.saturating_sub(*reach)..=pos).any(|from| {
+                            self.run(prog, pc + 1, from, Some(pos), &mut inner, marks)
+                                .is_some()
+                        }),
+                    };
+                    if found && !negate {
+                        stack.push(Frame::Slots(std::mem::replace(slots, inner)));
+                    }
+                    pc = *next;
+                    if found != *negate {
+                        continue;
+                    }
+                    false
+                }
+                Inst::Atomic { next } => {
+                    let mut inner = slots.clone();
+                    match self.run(prog, pc + 1, pos, None, &mut inner, marks) {
+                        Some(to) => {
+                            stack.push(Frame::Slots(std::mem::replace(slots, inner)));
+                            pos = to;
+                            pc = *next;
+                            continue;
+                        }
+                        None => false,
+                    }
+                }
+                Inst::Match => {
+                    if end.map_or(true, |end| end == pos) {
+                        return Some(pos);
+                    }
+                    false
+                }
+            };
+            if ok {
+                pc += 1;
+                continue;
+            }
+            // Back up to the last choice made, undoing what's been done
+            // since.
+            loop {
+                match stack.pop()? {
+                    Frame::Retry { pc: to, pos: at } => {
+                        (pc, pos) = (to, at);
+                        break;
+                    }
+                    Frame::Slot(slot, old) => slots[slot] = old,
+                    Frame::Slots(old) => *slots = old,
+                    Frame::Mark(mark, old) => marks[mark] = old,
+                }
+            }
+        }
+    }
+}
+
+/**
+A regex compiled for the backtracking engine.
+*/
+pub struct Fancy {
+    prog: Vec<Inst>,
+    names: Vec<Option<String>>,
+    n_marks: usize,
+    /// Whether matches have to start on character boundaries.
+    unicode: bool,
+    /// Set once a search has been given up on (and a warning printed).
+    gave_up: Cell<bool>,
+}
+
+impl Fancy {
+    pub fn new(pattern: &str, ignore_case: bool, flags: &RegexFlags) -> Result<Fancy, String> {
+        let mut flags = Flags {
+            ignore_case,
+            multi_line: flags.multi_line,
+            dot_matches_new_line: flags.dot_matches_new_line,
+            ignore_whitespace: flags.ignore_whitespace,
+            unicode: flags.unicode,
+            swap_greed: false,
+        };
+        let unicode = flags.unicode;
+        let mut parser = Parser {
+            chars: pattern.chars().collect(),
+            pos: 0,
+            names: vec![None],
+        };
+        let node = parser.alternation(&mut flags)?;
+        if parser.pos < parser.chars.len() {
+            return Err("unopened group".into());
+        }
+        let mut compiler = Compiler {
+            prog: Vec::new(),
+            n_marks: 0,
+            names: parser.names,
+        };
+        compiler.push(Inst::Save(0));
+        compiler.compile(&node)?;
+        compiler.push(Inst::Save(1));
+        compiler.push(Inst::Match);
+        Ok(Fancy {
+            prog: compiler.prog,
+            names: compiler.names,
+            n_marks: compiler.n_marks,
+            unicode,
+            gave_up: Cell::new(false),
+        })
+    }
+}
+
+impl Matcher for Fancy {
+    fn find_at(&self, haystack: &[u8], start: usize, caps: &mut Caps) -> bool {
+        let mut slots: Vec<Option<usize>> = vec![None; 2 * self.names.len()];
+        let mut marks: Vec<usize> = vec![0; self.n_marks];
+        for pos in start..=haystack.len() {
+            if self.unicode
+                && haystack
+                    .get(pos)
+                    .is_some_and(|&b| (0x80..0xc0).contains(&b))
+            {
+                continue;
+            }
+            let mut search = Search { haystack, steps: 0 };
+            slots.fill(None);
+            if search
+                .run(&self.prog, 0, pos, None, &mut slots, &mut marks)
+                .is_some()
+            {
+                caps.set_groups(slots.chunks(2).map(|pair| pair[0].zip(pair[1])));
+                return true;
+            }
+            if search.steps >= STEP_LIMIT {
+                if !self.gave_up.replace(true) {
+                    eprintln!("warning: gave up on a search that backtracked too much");
+                }
+                return false;
+            }
+        }
+        false
+    }
+
+    fn group_names(&self, _pattern: usize) -> Vec<Option<&str>> {
+        self.names.iter().map(Option::as_deref).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAGS: RegexFlags = RegexFlags {
+        multi_line: false,
+        dot_matches_new_line: false,
+        ignore_whitespace: false,
+        unicode: true,
+    };
+
+    /* The groups of the first match of `pattern` in `haystack`, as text. */
+    fn find(pattern: &str, haystack: &str) -> Option<Vec<Option<String>>> {
+        let fancy = Fancy::new(pattern, false, &FLAGS).unwrap();
+        let mut caps = Caps::default();
+        if !fancy.find_at(haystack.as_bytes(), 0, &mut caps) {
+            return None;
+        }
+        let groups = (0..caps.n_groups())
+            .map(|n| {
+                caps.get(n)
+                    .map(|(start, end)| haystack[start..end].to_string())
+            })
+            .collect();
+        Some(groups)
+    }
+
+    /* The whole of the first match, if any. */
+    fn matched(pattern: &str, haystack: &str) -> Option<String> {
+        find(pattern, haystack).and_then(|groups| groups[0].clone())
+    }
+
+    #[test]
+    fn backreferences() {
+        assert_eq!(
+            matched(r"(\w+) \1", "say hello hello there"),
+            Some("hello hello".into())
+        );
+        assert_eq!(matched(r"(a)|b\1", "b"), None);
+        assert_eq!(matched(r"(?i)(ab)\1", "xAbaB"), Some("AbaB".into()));
+        assert_eq!(
+            matched(r"(?<q>['\x22]).*?\k<q>", r#"a 'b" c' d"#),
+            Some(r#"'b" c'"#.into())
+        );
+        assert_eq!(matched(r"(é)\1", "éé"), Some("éé".into()));
+    }
+
+    #[test]
+    fn lookaround() {
+        assert_eq!(
+            matched(r"\d+(?= dollars)", "1 cent, 20 dollars"),
+            Some("20".into())
+        );
+        assert_eq!(
+            matched(r"\b\d+\b(?! cents?)", "1 cent, 20 dollars"),
+            Some("20".into())
+        );
+        assert_eq!(matched(r"(?<=\$)\d+", "5 or $6"), Some("6".into()));
+        assert_eq!(matched(r"(?<!\$)\b\d+", "$5 or 6"), Some("6".into()));
+        assert_eq!(matched(r"(?<=ab|c)d", "abd"), Some("d".into()));
+        // What lookahead captures is kept.
+        let groups = find(r"(?=(\w+))\w", "hi").unwrap();
+        assert_eq!(groups, vec![Some("h".into()), Some("hi".into())]);
+    }
+
+    #[test]
+    fn atomic_groups() {
+        assert_eq!(matched(r"(?>a+)b", "aaab"), Some("aaab".into()));
+        assert_eq!(matched(r"(?>a+)a", "aaa"), None);
+        assert_eq!(matched(r"(?>a|ab)c", "abc"), None);
+    }
+
+    #[test]
+    fn leftmost_first() {
+        assert_eq!(matched(r"a|ab", "ab"), Some("a".into()));
+        assert_eq!(matched(r"a+?", "aaa"), Some("a".into()));
+        assert_eq!(matched(r"(?U)a+", "aaa"), Some("a".into()));
+        assert_eq!(matched(r"a{2,3}", "aaaa"), Some("aaa".into()));
+        assert_eq!(matched(r"x*", "abc"), Some("".into()));
+        assert_eq!(matched(r"(a*)*b", "aab"), Some("aab".into()));
+    }
+
+    #[test]
+    fn groups() {
+        let groups = find(r"(?<year>\d{4})-(\d\d)(-(\d\d))?", "on 2024-05!").unwrap();
+        let expected = [Some("2024-05"), Some("2024"), Some("05"), None, None];
+        assert_eq!(groups, expected.map(|g| g.map(String::from)));
+        let fancy = Fancy::new(r"(?<year>\d{4})-(?P<m>\d\d)\1", false, &FLAGS).unwrap();
+        assert_eq!(fancy.group_names(0), vec![None, Some("year"), Some("m")]);
+    }
+
+    #[test]
+    fn classes_and_flags() {
+        assert_eq!(matched(r"[^\d\s]+", "12 ab3"), Some("ab".into()));
+        assert_eq!(matched(r"[[:upper:]]+", "abCDe"), Some("CD".into()));
+        assert_eq!(matched(r"(?i)[a-c]+", "xAbC"), Some("AbC".into()));
+        assert_eq!(
+            matched(r"(?x) a b  \# # comment", "ab#"),
+            Some("ab#".into())
+        );
+        assert_eq!(matched(r"(?m)^b$", "a\nb\nc"), Some("b".into()));
+        assert_eq!(matched(r"^b$", "a\nb\nc"), None);
+        assert_eq!(matched(r"(?s)a.b", "a\nb"), Some("a\nb".into()));
+        assert_eq!(matched(r"a.b", "a\nb"), None);
+        assert_eq!(matched(r"\bé\w", "x éa"), Some("éa".into()));
+        assert_eq!(matched(r"(?i:A)a", "aA aa"), Some("aa".into()));
+    }
+
+    #[test]
+    fn without_unicode() {
+        let flags = RegexFlags {
+            unicode: false,
+            ..FLAGS
+        };
+        let fancy = Fancy::new(r"(\xFF)\1", false, &flags).unwrap();
+        let mut caps = Caps::default();
+        assert!(fancy.find_at(b"a\xff\xff", 0, &mut caps));
+        assert_eq!(caps.span(), (1, 3));
+        let fancy = Fancy::new(r"(..)\1", false, &flags).unwrap();
+        assert!(fancy.find_at("éé".as_bytes(), 0, &mut caps));
+        assert_eq!(caps.span(), (0, 4));
+    }
+
+    #[test]
+    fn errors() {
+        for bad in [
+            r"(a",
+            r"a)",
+            r"*",
+            r"a**",
+            r"[a",
+            r"[z-a]",
+            r"(a)\2",
+            r"\k<x>",
+            r"(?<=a+)b",
+            r"\pL",
+            r"[a&&b]",
+            r"a{1001}",
+            r"a{3,2}",
+            r"(?<n>a)(?<n>b)",
+            r"(?z)",
+        ] {
+            assert!(Fancy::new(bad, false, &FLAGS).is_err(), "{}", bad);
+        }
+    }
+
+    #[test]
+    fn gives_up() {
+        let fancy = Fancy::new(r"(a*)*\1b", false, &FLAGS).unwrap();
+        let haystack = "a".repeat(30);
+        assert!(!fancy.find_at(haystack.as_bytes(), 0, &mut Caps::default()));
+        assert!(fancy.gave_up.get());
+    }
+}
@@ -0,0 +1,88 @@
+/*!
+Matching against just one field of each record, rather than all of it.
+
+A `Target` picks the field out of a record (decoding it, if it's quoted or
+escaped), and puts a new value for it back (encoding it the same way), so
+the rest of the record is left exactly as it was.
+*/
+use std::{borrow::Cow, ops::Range};
+
+use regex::bytes::Regex;
+
+pub trait Target {
+    /**
+    If `record` is a header, which isn't data to be matched against (and
+    should be left alone), take note of anything it says, and return
+    `true`.
+    */
+    fn header(&mut self, _record: &[u8]) -> Result<bool, String> {
+        Ok(false)
+    }
+
+    /**
+    The span of the target field in `record`, and its value, if `record`
+    has that field.
+    */
+    fn field<'a>(&self, record: &'a [u8]) -> Option<(Range<usize>, Cow<'a, [u8]>)>;
+
+    /// Append `record` to `out`, with the field at `span` replaced by
+    /// `value`.
+    fn splice(&self, record: &[u8], span: Range<usize>, value: &[u8], out: &mut Vec<u8>);
+}
+
+/**
+Picks out a field of text that's delimited by matches of a regex, or (like
+`awk`) by runs of whitespace, ignoring any at the start.
+*/
+pub struct Delimited {
+    /// Which field (from 0).
+    index: usize,
+    sep: Option<Regex>,
+}
+
+impl Delimited {
+    pub fn new(index: usize, sep: Option<Regex>) -> Delimited {
+        Delimited { index, sep }
+    }
+}
+
+/* The spans of the whitespace-separated fields of `record`. */
+fn whitespace_fields(record: &[u8]) -> impl Iterator<Item = Range<usize>> + '_ {
+    let mut pos: usize = 0;
+    std::iter::from_fn(move || {
+        let start = pos
+            + record[pos..]
+                .iter()
+                .position(|b| !b.is_ascii_whitespace())?;
+        let len = record[start..]
+            .iter()
+            .position(|b| b.is_ascii_whitespace())
+            .unwrap_or(record.len() - start);
+        pos = start + len;
+        Some(start..pos)
+    })
+}
+
+impl Target for Delimited {
+    fn field<'a>(&self, record: &'a [u8]) -> Option<(Range<usize>, Cow<'a, [u8]>)> {
+        let span = match self.sep {
+            None => whitespace_fields(record).nth(self.index)?,
+            Some(ref sep) => {
+                let mut start: usize = 0;
+                let mut seps = sep.find_iter(record).filter(|m| !m.is_empty());
+                for _ in 0..self.index {
+                    start = seps.next()?.end();
+                }
+                let end = seps.next().map_or(record.len(), |m| m.start());
+                start..end
+            }
+        };
+        Some((span.clone(), Cow::Borrowed(&record[span])))
+    }
+
+    fn splice(&self, record: &[u8], span: Range<usize>, value: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(&record[..span.start]);
+        out.extend_from_slice(value);
+        out.extend_from_slice(&record[span.end..]);
+    }
+}
@@ -0,0 +1,92 @@
+/*!
+Following a file as it grows, like `tail -f`.
+
+When the end of the file is reached, it's checked periodically for more
+data. If the file is truncated, reading starts over from the beginning; if
+it's replaced (as when a log is rotated), the new file is opened and read
+from the start.
+
+There are no change notifications (like inotify): the file is polled
+every quarter of a second, so new lines can take that long to show up.
+Telling a replaced file from the old one takes the inode number, so off
+Unix, rotation is only noticed if the new file is shorter than what had
+been read of the old one. A followed file is read as it is, never
+decompressed.
+*/
+use std::{
+    fs::{self, File, Metadata},
+    io::{self, Read, Seek, SeekFrom},
+    path::PathBuf,
+    thread,
+    time::Duration,
+};
+
+/// How long to wait between checks for more data.
+const POLL: Duration = Duration::from_millis(250);
+
+/*
+Something identifying which file `meta` is about, to tell when the path
+refers to a new one.
+*/
+#[cfg(unix)]
+fn identity(meta: &Metadata) -> Option<(u64, u64)> {
+    use std::os::unix::fs::MetadataExt;
+    Some((meta.dev(), meta.ino()))
+}
+
+#[cfg(not(unix))]
+fn identity(_meta: &Metadata) -> Option<(u64, u64)> {
+    None
+}
+
+pub struct Follow {
+    file: File,
+    path: PathBuf,
+    /// How far into the file has been read.
+    pos: u64,
+    id: Option<(u64, u64)>,
+}
+
+impl Follow {
+    pub fn new(file: File, path: PathBuf) -> io::Result<Follow> {
+        let id = identity(&file.metadata()?);
+        Ok(Follow {
+            file,
+            path,
+            pos: 0,
+            id,
+        })
+    }
+
+    /* Catch up with rotation or truncation of the file, if any. */
+    fn check(&mut self) -> io::Result<()> {
+        // The file may be missing for a moment while it's being rotated.
+        let meta = match fs::metadata(&self.path) {
+            Ok(meta) => meta,
+            Err(_) => return Ok(()),
+        };
+        if identity(&meta) != self.id {
+            self.file = File::open(&self.path)?;
+            self.id = identity(&self.file.metadata()?);
+            self.pos = 0;
+        } else if meta.len() < self.pos {
+            self.file.seek(SeekFrom::Start(0))?;
+            self.pos = 0;
+        }
+        Ok(())
+    }
+}
+
+impl Read for Follow {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        loop {
+            let n = self.file.read(buf)?;
+            if n > 0 || buf.is_empty() {
+                self.pos += n as u64;
+                return Ok(n);
+            }
+            thread::sleep(POLL);
+            self.check()?;
+        }
+    }
+}
@@ -0,0 +1,376 @@
+/*!
+Writing extracted matches as structured data, for other programs to read,
+instead of as plain text.
+
+With `--json`, each match is a JSON object on a line of its own:
+
+```text
+{"file":"app.log","record":3,"start":120,"end":131,"text":"user=bob ok","captures":{"user":"bob"}}
+```
+
+`record` is the number of the record the match is in, and `start` and
+`end` are its offsets (in bytes) in the file. `captures` has the match's
+named capture groups, with `null` for any that didn't take part in it.
+
+With `--format csv` (or `tsv`), each match is a row with a column for each
+of its capture groups (or just one, for the whole match, if it has none),
+optionally after a row of the groups' names. TSV fields can't have tabs or
+line breaks in them, so those (and backslashes) are escaped as `\t`, `\n`,
+`\r`, and `\\`.
+
+With `--format grep`, each match is a line like `file:line:column:record`
+(where `column` counts bytes from 1), like ripgrep's --vimgrep output, for
+vim's quickfix list and emacs's compilation mode.
+
+With `--format github`, each match is a GitHub Actions workflow command
+like `::warning file=app.py,line=3,col=5,endColumn=8::text`, which shows
+up as an annotation on the line it's on in a pull request.
+
+With `--output-format`, each match is written according to a template of
+the user's own, in which
+
+  * `{file}` is the name of the file it's in;
+  * `{line}` is the number of the record it's in;
+  * `{offset}` is its offset (in bytes) in the file;
+  * `{column}` is its column (in bytes, from 1) in the record;
+  * `{match}` is the matched text;
+  * `{N}` or `{name}` is the text of a capture group.
+
+`{{` and `}}` stand for literal braces, and the usual escapes (`\t` and so
+on) work too.
+*/
+use std::str::FromStr;
+
+use crate::{
+    csv,
+    escape::unescape,
+    json,
+    matcher::{Caps, Matcher},
+};
+
+/// How to write each match.
+#[derive(Clone, Debug)]
+pub enum Format {
+    Json,
+    Csv,
+    Tsv,
+    Grep,
+    Github,
+    Template(MatchTemplate),
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "json" => Ok(Format::Json),
+            "csv" => Ok(Format::Csv),
+            "tsv" => Ok(Format::Tsv),
+            "grep" => Ok(Format::Grep),
+            "github" => Ok(Format::Github),
+            _ => Err(format!(
+                "unknown format {:?} (expected one of json, csv, tsv, grep, github)",
+                s
+            )),
+        }
+    }
+}
+
+/// A match, and where it was found.
+pub struct Found<'a> {
+    /// The name of the file it's in.
+    pub file: &'a str,
+    /// The number of the record it's in...
+    pub record: usize,
+    /// ...and the record itself.
+    pub line: &'a [u8],
+    /// Where the text searched starts in the file...
+    pub offset: usize,
+    /// ...and in the record.
+    pub column: usize,
+    /// The text searched (the record, or the field of it matched against).
+    pub text: &'a [u8],
+    pub caps: &'a Caps,
+}
+
+#[derive(Clone, Debug)]
+enum Piece {
+    Literal(Vec<u8>),
+    File,
+    Line,
+    Offset,
+    Column,
+    Group(usize),
+    Named(String),
+}
+
+/// A template for writing each match (see `--output-format`).
+#[derive(Clone, Debug)]
+pub struct MatchTemplate {
+    pieces: Vec<Piece>,
+}
+
+impl FromStr for MatchTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pieces: Vec<Piece> = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let mut name = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => name.push(c),
+                            None => return Err("unmatched { (use {{ for a literal one)".into()),
+                        }
+                    }
+                    let piece = match name.as_str() {
+                        "file" => Piece::File,
+                        "line" => Piece::Line,
+                        "offset" => Piece::Offset,
+                        "column" => Piece::Column,
+                        "match" => Piece::Group(0),
+                        "" => return Err("empty placeholder {}".into()),
+                        name => match name.parse::<usize>() {
+                            Ok(n) => Piece::Group(n),
+                            Err(_) => Piece::Named(name.to_string()),
+                        },
+                    };
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(unescape(&std::mem::take(&mut literal))?));
+                    }
+                    pieces.push(piece);
+                }
+                '}' => return Err("unmatched } (use }} for a literal one)".into()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(unescape(&literal)?));
+        }
+        Ok(MatchTemplate { pieces })
+    }
+}
+
+impl MatchTemplate {
+    /* Append `found` to `out`. */
+    fn write(&self, found: &Found, matcher: &dyn Matcher, out: &mut Vec<u8>) {
+        let group = |n: usize, out: &mut Vec<u8>| {
+            if let Some((start, end)) = found.caps.get(n) {
+                out.extend_from_slice(&found.text[start..end]);
+            }
+        };
+        for piece in self.pieces.iter() {
+            match piece {
+                Piece::Literal(text) => out.extend_from_slice(text),
+                Piece::File => out.extend_from_slice(found.file.as_bytes()),
+                Piece::Line => out.extend_from_slice(found.record.to_string().as_bytes()),
+                Piece::Offset => {
+                    let offset = found.offset + found.caps.span().0;
+                    out.extend_from_slice(offset.to_string().as_bytes());
+                }
+                Piece::Column => out.extend_from_slice(column(found).to_string().as_bytes()),
+                Piece::Group(n) => group(*n, out),
+                Piece::Named(name) => {
+                    if let Some(n) = matcher.group_index(found.caps.pattern(), name) {
+                        group(n, out);
+                    }
+                }
+            }
+        }
+    }
+}
+
+impl Format {
+    /// Make sure the format only refers to capture groups `matcher` has.
+    pub fn check(&self, matcher: &dyn Matcher) -> Result<(), String> {
+        let template = match self {
+            Format::Template(template) => template,
+            _ => return Ok(()),
+        };
+        let n_groups = matcher.group_names(0).len();
+        for piece in template.pieces.iter() {
+            match piece {
+                Piece::Group(n) if *n >= n_groups => {
+                    return Err(format!("--output-format: there's no group {}", n))
+                }
+                Piece::Named(name) if matcher.group_index(0, name).is_none() => {
+                    return Err(format!(
+                        "--output-format: there's no group named {:?}",
+                        name
+                    ))
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Append a header row naming the columns to `out`, if the format has
+    /// them.
+    pub fn write_header(&self, matcher: &dyn Matcher, newline: &[u8], out: &mut Vec<u8>) {
+        let names = matcher.group_names(0);
+        let names: Vec<Vec<u8>> = match names.len() {
+            1 => vec![b"match".to_vec()],
+            _ => names
+                .iter()
+                .enumerate()
+                .skip(1)
+                .map(|(n, name)| match name {
+                    Some(name) => name.as_bytes().to_vec(),
+                    None => n.to_string().into_bytes(),
+                })
+                .collect(),
+        };
+        match self {
+            Format::Json | Format::Grep | Format::Github | Format::Template(_) => return,
+            Format::Csv => write_row(names.iter().map(Vec::as_slice), b',', csv::write_field, out),
+            Format::Tsv => write_row(names.iter().map(Vec::as_slice), b'\t', tsv_field, out),
+        }
+        out.extend_from_slice(newline);
+    }
+
+    /// Append `found` to `out`, as a line ending with `newline`.
+    pub fn write(&self, found: &Found, matcher: &dyn Matcher, newline: &[u8], out: &mut Vec<u8>) {
+        match self {
+            Format::Json => write_json(found, matcher, out),
+            Format::Csv => write_row(columns(found), b',', csv::write_field, out),
+            Format::Tsv => write_row(columns(found), b'\t', tsv_field, out),
+            Format::Grep => {
+                out.extend_from_slice(
+                    format!("{}:{}:{}:", found.file, found.record, column(found)).as_bytes(),
+                );
+                out.extend_from_slice(found.line);
+            }
+            Format::Github => write_github(found, out),
+            Format::Template(template) => template.write(found, matcher, out),
+        }
+        out.extend_from_slice(newline);
+    }
+}
+
+/* The column `found` starts at in its record, counting bytes from 1. */
+fn column(found: &Found) -> usize {
+    found.column + found.caps.span().0 + 1
+}
+
+fn write_github(found: &Found, out: &mut Vec<u8>) {
+    let (start, end) = found.caps.span();
+    out.extend_from_slice(b"::warning file=");
+    github_escape(found.file.as_bytes(), true, out);
+    out.extend_from_slice(
+        format!(
+            ",line={},col={},endColumn={}::",
+            found.record,
+            column(found),
+            // The last column the match is in.
+            column(found) + (end - start).saturating_sub(1)
+        )
+        .as_bytes(),
+    );
+    github_escape(&found.text[start..end], false, out);
+}
+
+/*
+Append `value` to `out`, escaped to go in a GitHub workflow command; a
+`property`'s value has more to escape than the message does.
+*/
+fn github_escape(value: &[u8], property: bool, out: &mut Vec<u8>) {
+    for &b in value.iter() {
+        match b {
+            b'%' => out.extend_from_slice(b"%25"),
+            b'\r' => out.extend_from_slice(b"%0D"),
+            b'\n' => out.extend_from_slice(b"%0A"),
+            b':' if property => out.extend_from_slice(b"%3A"),
+            b',' if property => out.extend_from_slice(b"%2C"),
+            _ => out.push(b),
+        }
+    }
+}
+
+fn write_json(found: &Found, matcher: &dyn Matcher, out: &mut Vec<u8>) {
+    let (start, end) = found.caps.span();
+    out.extend_from_slice(b"{\"file\":");
+    json::escape(found.file.as_bytes(), out);
+    out.extend_from_slice(
+        format!(
+            ",\"record\":{},\"start\":{},\"end\":{},\"text\":",
+            found.record,
+            found.offset + start,
+            found.offset + end
+        )
+        .as_bytes(),
+    );
+    json::escape(&found.text[start..end], out);
+    out.extend_from_slice(b",\"captures\":{");
+    let names = matcher.group_names(found.caps.pattern());
+    let named = names
+        .iter()
+        .enumerate()
+        .filter_map(|(n, name)| Some((n, (*name)?)));
+    for (i, (n, name)) in named.enumerate() {
+        if i > 0 {
+            out.push(b',');
+        }
+        json::escape(name.as_bytes(), out);
+        out.push(b':');
+        match found.caps.get(n) {
+            Some((start, end)) => json::escape(&found.text[start..end], out),
+            None => out.extend_from_slice(b"null"),
+        }
+    }
+    out.extend_from_slice(b"}}");
+}
+
+/*
+The text of each of `found`'s capture groups (empty for any that didn't
+take part), or of the whole match if there are none.
+*/
+fn columns<'a>(found: &'a Found) -> impl Iterator<Item = &'a [u8]> {
+    let caps = found.caps;
+    let first = usize::from(caps.n_groups() > 1);
+    (first..caps.n_groups()).map(move |n| match caps.get(n) {
+        Some((start, end)) => &found.text[start..end],
+        None => &[],
+    })
+}
+
+fn write_row<'a>(
+    fields: impl Iterator<Item = &'a [u8]>,
+    sep: u8,
+    write_field: fn(&[u8], bool, &mut Vec<u8>),
+    out: &mut Vec<u8>,
+) {
+    for (n, field) in fields.enumerate() {
+        if n > 0 {
+            out.push(sep);
+        }
+        write_field(field, false, out);
+    }
+}
+
+/* Append `value` to `out` as a TSV field, escaping what can't be in one. */
+fn tsv_field(value: &[u8], _quote: bool, out: &mut Vec<u8>) {
+    for &b in value.iter() {
+        match b {
+            b'\t' => out.extend_from_slice(b"\\t"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            _ => out.push(b),
+        }
+    }
+}
@@ -0,0 +1,141 @@
+/*!
+Approximate verbatim matching.
+
+A match is any stretch of text within a given
+[Levenshtein distance](https://en.wikipedia.org/wiki/Levenshtein_distance)
+of the pattern; distance is counted in characters. Matches are found
+with Sellers' variant of the usual dynamic programming algorithm. The
+end of a match is found scanning forward, then its start by scanning
+backward from there.
+*/
+use crate::matcher::{chars_eq_nocase, decode_char, Caps, Matcher};
+
+/*
+A character of the haystack, along with the byte offset where it starts.
+Bytes that aren't valid UTF-8 are `None`, and never match anything.
+*/
+type Unit = (Option<char>, usize);
+
+/*
+Decode the `Unit` at `pos` in `haystack`, returning it and its length.
+*/
+fn unit_at(haystack: &[u8], pos: usize) -> (Unit, usize) {
+    match decode_char(&haystack[pos..]) {
+        Some((c, len)) => ((Some(c), pos), len),
+        None => ((None, pos), 1),
+    }
+}
+
+pub struct Fuzzy {
+    pattern: Vec<char>,
+    distance: usize,
+    ignore_case: bool,
+}
+
+impl Fuzzy {
+    /// Match `pattern` with up to `distance` edits. `distance` should
+    /// be less than the number of characters in `pattern`, or
+    /// everything will match.
+    pub fn new(pattern: &str, distance: usize) -> Self {
+        Fuzzy {
+            pattern: pattern.chars().collect(),
+            distance,
+            ignore_case: false,
+        }
+    }
+
+    /// Builder-pattern method for matching without regard to case.
+    pub fn ignore_case(mut self, yes: bool) -> Self {
+        self.ignore_case = yes;
+        self
+    }
+
+    fn cost(&self, p: char, h: Option<char>) -> usize {
+        match h {
+            Some(h) if p == h => 0,
+            Some(h) if self.ignore_case && chars_eq_nocase(p, h) => 0,
+            _ => 1,
+        }
+    }
+
+    /*
+    Advance the column `col` of the distance table by one haystack
+    character. If `anchored`, the match may not start after the
+    beginning of the haystack.
+    */
+    fn step<'a, I>(&self, pattern: I, col: &mut [usize], h: Option<char>, anchored: bool)
+    where
+        I: Iterator<Item = &'a char>,
+    {
+        let mut diag = col[0];
+        if anchored {
+            col[0] += 1;
+        }
+        for (i, &p) in pattern.enumerate() {
+            let val = (diag + self.cost(p, h)).min(col[i + 1] + 1).min(col[i] + 1);
+            diag = col[i + 1];
+            col[i + 1] = val;
+        }
+    }
+
+    /*
+    Return the byte offset of the start of the best match that ends at
+    `units[end]`.
+    */
+    fn find_start(&self, units: &[Unit], end: usize) -> usize {
+        let m = self.pattern.len();
+        let mut col: Vec<usize> = (0..=m).collect();
+        let mut best = (col[m], end);
+        let lowest = end.saturating_sub(m + self.distance);
+
+        for n in (lowest..end).rev() {
+            self.step(self.pattern.iter().rev(), &mut col, units[n].0, true);
+            if col[m] <= best.0 {
+                best = (col[m], n);
+            }
+        }
+
+        best.1
+    }
+}
+
+impl Matcher for Fuzzy {
+    fn find_at(&self, haystack: &[u8], start: usize, caps: &mut Caps) -> bool {
+        let m = self.pattern.len();
+        let mut units: Vec<Unit> = Vec::new();
+        let mut pos = start;
+
+        let mut col: Vec<usize> = (0..=m).collect();
+        let mut end: Option<usize> = if col[m] <= self.distance {
+            Some(0)
+        } else {
+            None
+        };
+
+        while pos < haystack.len() {
+            let (unit, len) = unit_at(haystack, pos);
+            units.push(unit);
+            pos += len;
+
+            let prev = col[m];
+            self.step(self.pattern.iter(), &mut col, unit.0, false);
+            match end {
+                // Keep extending a match as long as it keeps improving.
+                Some(_) if col[m] < prev => end = Some(units.len()),
+                Some(_) => break,
+                None if col[m] <= self.distance => end = Some(units.len()),
+                None => {}
+            }
+        }
+
+        match end {
+            Some(end) => {
+                let begin = self.find_start(&units, end);
+                let offset_of = |n: usize| units.get(n).map(|u| u.1).unwrap_or(pos);
+                *caps = Caps::whole(offset_of(begin), offset_of(end));
+                true
+            }
+            None => false,
+        }
+    }
+}
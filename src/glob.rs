@@ -0,0 +1,230 @@
+/*!
+Translating shell-style glob patterns into regular expressions.
+
+Supported syntax:
+  * `*` matches any run of characters, and `?` matches any one character
+    (but see below)
+  * `[abc]`, `[a-z]`, and `[!abc]` (or `[^abc]`) match character classes
+  * `{foo,bar}` matches any of the comma-separated alternatives
+  * `\` escapes the following character
+
+Because a glob is matched against running text, `*` and `?` won't match
+whitespace; `*.log` picks out `app.log` rather than everything from the
+start of the line up through `.log`.
+
+Globs can also be matched against file paths (with `/` separators), in
+which case `*` and `?` won't match `/`, but `**` matches any number of
+whole directories: `src/**/*.rs` matches both `src/main.rs` and
+`src/a/b/c.rs`.
+*/
+
+/// What `*` and `?` are allowed to match.
+const WILD: &str = r"[^\s]";
+/// What `*` and `?` are allowed to match in paths.
+const PATH_WILD: &str = "[^/]";
+
+/*
+Return the index of the `}` closing the `{` at the start of `chars`.
+*/
+fn closing_brace(chars: &[char]) -> Option<usize> {
+    let mut depth: usize = 0;
+    let mut escaped = false;
+    for (n, &c) in chars.iter().enumerate() {
+        match c {
+            _ if escaped => escaped = false,
+            '\\' => escaped = true,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(n);
+                }
+            }
+            _ => {}
+        }
+    }
+    None
+}
+
+/*
+Translate the character class at the start of `chars` (which starts with
+`[`), returning the translation and the number of characters consumed.
+*/
+fn class(chars: &[char]) -> Option<(String, usize)> {
+    let mut re = String::from("[");
+    let mut n: usize = 1;
+    if matches!(chars.get(n), Some('!') | Some('^')) {
+        re.push('^');
+        n += 1;
+    }
+    // A `]` right at the beginning is part of the class.
+    if chars.get(n) == Some(&']') {
+        re.push_str(r"\]");
+        n += 1;
+    }
+
+    while let Some(&c) = chars.get(n) {
+        n += 1;
+        match c {
+            ']' => {
+                re.push(']');
+                return Some((re, n));
+            }
+            '\\' | '[' | '&' | '~' | '^' => {
+                re.push('\\');
+                re.push(c);
+            }
+            _ => re.push(c),
+        }
+    }
+
+    None
+}
+
+/// Translate `glob` into an equivalent regular expression.
+pub fn to_regex(glob: &str) -> String {
+    translate(glob, false)
+}
+
+/// Translate `glob` into a regular expression that matches whole paths.
+pub fn path_regex(glob: &str) -> String {
+    format!(r"\A(?:{})\z", translate(glob, true))
+}
+
+fn translate(glob: &str, path: bool) -> String {
+    let wild = if path { PATH_WILD } else { WILD };
+    let chars: Vec<char> = glob.chars().collect();
+    let mut re = String::new();
+    // Positions of the `}`s that close currently-open `{`s.
+    let mut closers: Vec<usize> = Vec::new();
+    let mut n: usize = 0;
+
+    while let Some(&c) = chars.get(n) {
+        match c {
+            '*' if path && chars.get(n + 1) == Some(&'*') => {
+                while chars.get(n + 1) == Some(&'*') {
+                    n += 1;
+                }
+                // `**/` is any number of directories (including none).
+                if chars.get(n + 1) == Some(&'/') {
+                    n += 1;
+                    re.push_str("(?:.*/)?");
+                } else {
+                    re.push_str(".*");
+                }
+            }
+            '*' => {
+                while chars.get(n + 1) == Some(&'*') {
+                    n += 1;
+                }
+                re.push_str(wild);
+                re.push('*');
+            }
+            '?' => re.push_str(wild),
+            '[' => match class(&chars[n..]) {
+                Some((class, len)) => {
+                    re.push_str(&class);
+                    n += len - 1;
+                }
+                None => re.push_str(r"\["),
+            },
+            '{' => match closing_brace(&chars[n..]) {
+                Some(len) => {
+                    closers.push(n + len);
+                    re.push_str("(?:");
+                }
+                None => re.push_str(r"\{"),
+            },
+            '}' if closers.last() == Some(&n) => {
+                closers.pop();
+                re.push(')');
+            }
+            ',' if !closers.is_empty() => re.push('|'),
+            '\\' => {
+                if let Some(&next) = chars.get(n + 1) {
+                    n += 1;
+                    re.push_str(&regex::escape(&next.to_string()));
+                } else {
+                    re.push_str(r"\\");
+                }
+            }
+            _ => re.push_str(&regex::escape(&c.to_string())),
+        }
+        n += 1;
+    }
+
+    re
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use regex::Regex;
+
+    /* Everything `glob` picks out of `text`. */
+    fn found(glob: &str, text: &str) -> Vec<String> {
+        let re = Regex::new(&to_regex(glob)).unwrap();
+        re.find_iter(text).map(|m| m.as_str().to_string()).collect()
+    }
+
+    fn path_matches(glob: &str, path: &str) -> bool {
+        Regex::new(&path_regex(glob)).unwrap().is_match(path)
+    }
+
+    #[test]
+    fn wildcards_in_text() {
+        assert_eq!(
+            found("*.log", "see app.log and db.log."),
+            ["app.log", "db.log"]
+        );
+        assert_eq!(found("v?.?", "v1.2 v10.3 v4.x"), ["v1.2", "v4.x"]);
+        assert_eq!(found("a**b", "aXXb ab"), ["aXXb", "ab"]);
+        assert_eq!(found("*", "two words"), ["two", "words"]);
+    }
+
+    #[test]
+    fn classes() {
+        assert_eq!(found("[abc]x", "ax bx dx"), ["ax", "bx"]);
+        assert_eq!(found("[!abc]x", "ax bx dx"), ["dx"]);
+        assert_eq!(found("[^a-c]x", "ax bx dx"), ["dx"]);
+        assert_eq!(found("[]x]", "a]x"), ["]", "x"]);
+        assert_eq!(found(r"[\^&]", r"a^b&c\d"), ["^", "&", r"\"]);
+        // An unclosed `[` is just a `[`.
+        assert_eq!(found("[ab", "x[aby"), ["[ab"]);
+    }
+
+    #[test]
+    fn alternatives() {
+        assert_eq!(
+            found("*.{jpg,png}", "a.jpg b.gif c.png"),
+            ["a.jpg", "c.png"]
+        );
+        assert_eq!(found("{a,b{c,d}}!", "a! bc! bd! b!"), ["a!", "bc!", "bd!"]);
+        assert_eq!(found("{a,b", "{a,b"), ["{a,b"]);
+        assert_eq!(found("a,b}", "a,b}"), ["a,b}"]);
+    }
+
+    #[test]
+    fn escapes() {
+        assert_eq!(found(r"\*.txt", "*.txt a.txt"), ["*.txt"]);
+        assert_eq!(found(r"\{a,b\}", "{a,b} a"), ["{a,b}"]);
+        assert_eq!(found("a+b(c)", "a+b(c)"), ["a+b(c)"]);
+        assert_eq!(found(r"x\", r"x\"), [r"x\"]);
+    }
+
+    #[test]
+    fn paths() {
+        assert!(path_matches("src/*.rs", "src/main.rs"));
+        assert!(!path_matches("src/*.rs", "src/a/main.rs"));
+        assert!(path_matches("src/**/*.rs", "src/main.rs"));
+        assert!(path_matches("src/**/*.rs", "src/a/b/c.rs"));
+        assert!(!path_matches("src/**/*.rs", "lib/a.rs"));
+        assert!(path_matches("**", "a/b/c"));
+        assert!(path_matches("logs/**", "logs/a.log"));
+        assert!(path_matches("a?c", "abc"));
+        assert!(!path_matches("a?c", "a/c"));
+        assert!(path_matches("*.{c,h}", "x.h"));
+        assert!(!path_matches("*.c", "x.c.bak"));
+        assert!(path_matches("file name.txt", "file name.txt"));
+    }
+}
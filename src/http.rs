@@ -0,0 +1,322 @@
+/*!
+Reading inputs from URLs.
+
+This is a deliberately small HTTP/1.0 client: it makes a single `GET`
+request, follows a few redirects, and streams the response body. HTTP/1.0
+is used so that the body is never chunked; it just runs until the server
+closes the connection. There's no TLS, so `https://` URLs are recognized
+but can't be fetched; it's an error to try, rather than looking for a
+file by that name.
+*/
+use std::{
+    io::{self, BufRead, BufReader, Read, Write},
+    net::TcpStream,
+    time::Duration,
+};
+
+/// How many redirects to follow before giving up.
+const MAX_REDIRECTS: usize = 5;
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn err(msg: String) -> io::Error {
+    io::Error::other(msg)
+}
+
+/// Whether the input `name` is a URL (rather than a path).
+pub fn is_url(name: &str) -> bool {
+    name.starts_with("http://") || name.starts_with("https://")
+}
+
+struct Url<'a> {
+    host: &'a str,
+    port: u16,
+    path: &'a str,
+}
+
+impl Url<'_> {
+    /// The host (and port, unless it's 80), as written in a URL.
+    fn authority(&self) -> String {
+        let host = match self.host.contains(':') {
+            true => format!("[{}]", self.host),
+            false => self.host.to_string(),
+        };
+        match self.port {
+            80 => host,
+            port => format!("{}:{}", host, port),
+        }
+    }
+}
+
+fn parse(url: &str) -> io::Result<Url<'_>> {
+    let rest = match url.strip_prefix("http://") {
+        Some(rest) => rest,
+        None if url.starts_with("https://") => {
+            return Err(io::Error::new(
+                io::ErrorKind::Unsupported,
+                "https URLs aren't supported (there's no TLS); \
+                 fetch it first, as with `curl -s URL | fresh ...`",
+            ));
+        }
+        None => return Err(err(format!("not an http URL: {}", url))),
+    };
+    // The fragment is only for the client.
+    let rest = rest.split_once('#').map_or(rest, |(rest, _)| rest);
+    let (authority, path) = match rest.find(['/', '?']) {
+        Some(n) if rest[n..].starts_with('?') => (&rest[..n], &rest[n..]),
+        Some(n) => rest.split_at(n),
+        None => (rest, "/"),
+    };
+    // An IPv6 address is in brackets, since it has colons of its own.
+    let (host, port) = match authority.strip_prefix('[') {
+        Some(rest) => match rest.split_once(']') {
+            Some((host, "")) => (host, None),
+            Some((host, port)) => (host, Some(port.strip_prefix(':').unwrap_or(port))),
+            None => return Err(err(format!("bad host in URL: {}", url))),
+        },
+        None => match authority.rsplit_once(':') {
+            Some((host, port)) => (host, Some(port)),
+            None => (authority, None),
+        },
+    };
+    let port = match port.map(str::parse::<u16>) {
+        None => 80,
+        Some(Ok(port)) => port,
+        Some(Err(_)) => return Err(err(format!("bad port in URL: {}", url))),
+    };
+    if host.is_empty() {
+        return Err(err(format!("no host in URL: {}", url)));
+    }
+    Ok(Url { host, port, path })
+}
+
+/* The URL a redirect from `base` to `location` goes to. */
+fn redirect(base: &Url, location: &str) -> String {
+    let host = base.authority();
+    if location.contains("://") {
+        location.to_string()
+    } else if let Some(rest) = location.strip_prefix("//") {
+        format!("http://{}", rest)
+    } else if location.starts_with('/') {
+        format!("http://{}{}", host, location)
+    } else {
+        // Relative to the directory of the path, without its query.
+        let path = base
+            .path
+            .split_once('?')
+            .map_or(base.path, |(path, _)| path);
+        let dir = &path[..path.rfind('/').map_or(0, |n| n + 1)];
+        format!(
+            "http://{}/{}{}",
+            host,
+            dir.trim_start_matches('/'),
+            location
+        )
+    }
+}
+
+/*
+Read the status line and headers of a response from `stream`, returning
+the status code, its reason, and the `Location` header (if any).
+*/
+fn head(stream: &mut impl BufRead) -> io::Result<(u16, String, Option<String>)> {
+    let mut line = String::new();
+    stream.read_line(&mut line)?;
+    let mut words = line.trim_end().splitn(3, ' ');
+    let status = match (words.next(), words.next()) {
+        (Some(version), Some(code)) if version.starts_with("HTTP/") => code.parse::<u16>().ok(),
+        _ => None,
+    };
+    let status = status.ok_or_else(|| err(format!("bad response: {:?}", line.trim_end())))?;
+    let reason = words.next().unwrap_or_default().to_string();
+
+    let mut location: Option<String> = None;
+    let mut header = String::new();
+    loop {
+        header.clear();
+        if stream.read_line(&mut header)? == 0 || header.trim_end().is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("location") {
+                location = Some(value.trim().to_string());
+            }
+        }
+    }
+    Ok((status, reason, location))
+}
+
+/*
+Make a `GET` request for `url`, returning the response's status code,
+`Location` header (if any), and the stream positioned at the start of its
+body.
+*/
+fn get(url: &Url) -> io::Result<(u16, String, Option<String>, BufReader<TcpStream>)> {
+    let mut stream = TcpStream::connect((url.host, url.port))?;
+    stream.set_read_timeout(Some(TIMEOUT))?;
+    // All at once, rather than a piece at a time.
+    let request = format!(
+        "GET {}{} HTTP/1.0\r\nHost: {}\r\nUser-Agent: fresh/{}\r\nAccept: */*\r\n\r\n",
+        if url.path.starts_with('/') { "" } else { "/" },
+        url.path,
+        url.authority(),
+        env!("CARGO_PKG_VERSION")
+    );
+    stream.write_all(request.as_bytes())?;
+
+    let mut stream = BufReader::new(stream);
+    let (status, reason, location) = head(&mut stream)?;
+    Ok((status, reason, location, stream))
+}
+
+/**
+Fetch the resource at `url`, returning a reader over its body. Responses
+other than success (after following redirects) are errors.
+*/
+pub fn open(url: &str) -> io::Result<Box<dyn Read>> {
+    let mut url = url.to_string();
+    for _ in 0..=MAX_REDIRECTS {
+        let (status, reason, location, body) = get(&parse(&url)?)?;
+        match (status, location) {
+            (200..=299, _) => return Ok(Box::new(body)),
+            (300..=399, Some(location)) => url = redirect(&parse(&url)?, &location),
+            _ => return Err(err(format!("HTTP {} {}", status, reason).trim().into())),
+        }
+    }
+    Err(err("too many redirects".into()))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{net::TcpListener, thread};
+
+    fn parts(url: &str) -> (String, u16, String) {
+        let url = parse(url).unwrap();
+        (url.host.into(), url.port, url.path.into())
+    }
+
+    /*
+    Serve `responses` to requests for their paths, one connection each,
+    returning the port.
+    */
+    fn serve(responses: &[(&str, &str)]) -> u16 {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let responses: Vec<(String, String)> = responses
+            .iter()
+            .map(|(path, response)| {
+                (
+                    path.to_string(),
+                    response.replace("PORT", &port.to_string()),
+                )
+            })
+            .collect();
+        thread::spawn(move || {
+            for _ in 0..responses.len() {
+                let mut stream = BufReader::new(listener.accept().unwrap().0);
+                let mut request = String::new();
+                stream.read_line(&mut request).unwrap();
+                let mut header = String::new();
+                while stream.read_line(&mut header).unwrap() > 2 {
+                    header.clear();
+                }
+                let path = request.split(' ').nth(1).unwrap_or_default();
+                let response = responses.iter().find(|(p, _)| p == path);
+                let response = response.map_or("HTTP/1.0 404 Not Found\r\n\r\n", |(_, r)| r);
+                stream.get_mut().write_all(response.as_bytes()).unwrap();
+            }
+        });
+        port
+    }
+
+    fn fetch(url: &str) -> io::Result<String> {
+        let mut body = String::new();
+        open(url)?.read_to_string(&mut body)?;
+        Ok(body)
+    }
+
+    #[test]
+    fn urls() {
+        assert_eq!(
+            parts("http://example.com"),
+            ("example.com".into(), 80, "/".into())
+        );
+        assert_eq!(
+            parts("http://example.com:8080/a/b?c=d#e"),
+            ("example.com".into(), 8080, "/a/b?c=d".into())
+        );
+        assert_eq!(parts("http://h?q=1"), ("h".into(), 80, "?q=1".into()));
+        assert_eq!(parts("http://[::1]:81/x"), ("::1".into(), 81, "/x".into()));
+        assert_eq!(parts("http://[::1]/x"), ("::1".into(), 80, "/x".into()));
+        let err = parse("https://example.com/").err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+        assert!(err.to_string().starts_with("https URLs aren't supported"));
+        for bad in ["ftp://x/", "http:///path", "http://h:port/", "http://[::1/"] {
+            assert!(parse(bad).is_err(), "{}", bad);
+        }
+        assert!(is_url("https://example.com"));
+        assert!(!is_url("./http:/x"));
+    }
+
+    #[test]
+    fn redirects() {
+        let base = parse("http://h:8080/a/b.txt?x=1").unwrap();
+        assert_eq!(redirect(&base, "http://other/c"), "http://other/c");
+        assert_eq!(redirect(&base, "//other/c"), "http://other/c");
+        assert_eq!(redirect(&base, "/c"), "http://h:8080/c");
+        assert_eq!(redirect(&base, "c.txt"), "http://h:8080/a/c.txt");
+        let base = parse("http://[::1]/b").unwrap();
+        assert_eq!(redirect(&base, "c"), "http://[::1]/c");
+        assert_eq!(base.authority(), "[::1]");
+    }
+
+    #[test]
+    fn response_heads() {
+        let mut response =
+            "HTTP/1.1 301 Moved Permanently\r\nLOCATION:  /new \r\nX: y\r\n\r\nbody".as_bytes();
+        let (status, reason, location) = head(&mut response).unwrap();
+        assert_eq!((status, reason.as_str()), (301, "Moved Permanently"));
+        assert_eq!(location.as_deref(), Some("/new"));
+        assert_eq!(response, b"body");
+
+        let (status, reason, location) = head(&mut "HTTP/1.0 204\n\n".as_bytes()).unwrap();
+        assert_eq!((status, reason.as_str(), location), (204, "", None));
+        assert!(head(&mut "SSH-2.0-OpenSSH\r\n".as_bytes()).is_err());
+        assert!(head(&mut "".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn fetching() {
+        let port = serve(&[
+            (
+                "/start",
+                "HTTP/1.0 302 Found\r\nLocation: /dir/next\r\n\r\n",
+            ),
+            ("/dir/next", "HTTP/1.0 301 Moved\r\nLocation: last\r\n\r\n"),
+            (
+                "/dir/last",
+                "HTTP/1.0 200 OK\r\nContent-Type: text/plain\r\n\r\nline one\nline two\n",
+            ),
+        ]);
+        let body = fetch(&format!("http://127.0.0.1:{}/start", port)).unwrap();
+        assert_eq!(body, "line one\nline two\n");
+
+        let port = serve(&[("/gone", "HTTP/1.0 410 Gone\r\n\r\nsorry")]);
+        let err = fetch(&format!("http://127.0.0.1:{}/gone", port))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "HTTP 410 Gone");
+
+        let looping = "HTTP/1.0 302 Found\r\nLocation: http://127.0.0.1:PORT/loop\r\n\r\n";
+        let port = serve(&[("/loop", looping); MAX_REDIRECTS + 1]);
+        let err = fetch(&format!("http://127.0.0.1:{}/loop", port))
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "too many redirects");
+
+        let secure = "HTTP/1.0 301 Moved\r\nLocation: https://127.0.0.1/\r\n\r\n";
+        let port = serve(&[("/", secure)]);
+        let err = fetch(&format!("http://127.0.0.1:{}/", port)).err().unwrap();
+        assert_eq!(err.kind(), io::ErrorKind::Unsupported);
+    }
+}
@@ -0,0 +1,105 @@
+/*!
+Editing files in place.
+
+The edited version of a file is written to a temporary file in the same
+directory, which is synced to disk and then renamed over the original,
+so the original is never left half-written, even if fresh is killed or
+the disk fills up.
+
+The edited file always gets the original's permissions; it can also be
+given the original's timestamps and (on Unix) owner and group.
+*/
+use std::{
+    ffi::OsString,
+    fs::{self, File, FileTimes, Metadata},
+    io::{Cursor, Read, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{decompress, FrErr};
+
+/*
+A name for the temporary file that `path` will be edited into. It's
+hidden, so it's unlikely to be picked up by anything else in the
+meantime.
+*/
+fn temp_path(path: &Path) -> PathBuf {
+    let mut name = OsString::from(".");
+    name.push(path.file_name().unwrap_or_default());
+    name.push(format!(".fresh-{}.tmp", std::process::id()));
+    path.with_file_name(name)
+}
+
+/*
+Give `file` the original's owner and group. Only root can give a file
+away, so (like `sed -i`) this is done on a best-effort basis.
+*/
+#[cfg(unix)]
+fn copy_owner(file: &File, meta: &Metadata) {
+    use std::os::unix::fs::{fchown, MetadataExt};
+    let _ = fchown(file, Some(meta.uid()), Some(meta.gid()))
+        .or_else(|_| fchown(file, None, Some(meta.gid())));
+}
+
+#[cfg(not(unix))]
+fn copy_owner(_file: &File, _meta: &Metadata) {}
+
+/**
+Replace the contents of the file at `path` with what `edit` writes, given
+the original contents. If `backup` is given, the original is kept with
+that suffix added to its name. If `preserve` is true, the edited file
+keeps the original's timestamps and ownership.
+
+`edit` returns whether it changed anything; if it didn't, or if it fails,
+the file is left untouched. The output isn't buffered; that's up to
+`edit`.
+*/
+pub fn edit<F>(path: &Path, backup: Option<&str>, preserve: bool, edit: F) -> Result<(), FrErr>
+where
+    F: FnOnce(Box<dyn Read>, Box<dyn Write>) -> Result<bool, FrErr>,
+{
+    let mut input = File::open(path)?;
+    let meta = input.metadata()?;
+    if !meta.is_file() {
+        return Err(FrErr::Misc("not a regular file".into()));
+    }
+    // Writing the edit back uncompressed would be a nasty surprise.
+    let (head, format) = decompress::sniff(&mut input)?;
+    if format.is_some() {
+        return Err(FrErr::Misc("can't edit a compressed file in place".into()));
+    }
+    let input = Cursor::new(head).chain(input);
+
+    let temp = temp_path(path);
+    let output = File::create(&temp)?;
+    let result = output.try_clone().map_err(FrErr::from).and_then(|edited| {
+        if !edit(Box::new(input), Box::new(output))? {
+            fs::remove_file(&temp)?;
+            return Ok(());
+        }
+        // Changing the owner can clear setuid bits, so do that first.
+        if preserve {
+            copy_owner(&edited, &meta);
+        }
+        edited.set_permissions(meta.permissions())?;
+        if preserve {
+            let times = FileTimes::new()
+                .set_accessed(meta.accessed()?)
+                .set_modified(meta.modified()?);
+            edited.set_times(times)?;
+        }
+        edited.sync_all()?;
+        if let Some(suffix) = backup {
+            let mut backup_path = path.as_os_str().to_owned();
+            backup_path.push(suffix);
+            fs::copy(path, backup_path)?;
+        }
+        fs::rename(&temp, path)?;
+        Ok(())
+    });
+
+    if result.is_err() {
+        let _ = fs::remove_file(&temp);
+    }
+    result
+}
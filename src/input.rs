@@ -0,0 +1,247 @@
+/*!
+Opening the input files, one after another.
+
+Files (or URLs; see the `http` module) are opened lazily, as they're
+needed, and decompressed on the fly if they're compressed (see the
+`decompress` module) and transcoded to UTF-8 if they're in some other
+encoding (see the `encoding` module), with any byte order mark removed
+(see the `bom` module). One that can't be opened is reported (on stderr)
+and skipped, so the rest still get processed; the number of such
+failures is kept so `main()` can exit unsuccessfully.
+*/
+use std::{
+    cell::{Cell, RefCell},
+    fs::File,
+    io::{Cursor, Read},
+    path::{Path, PathBuf},
+    rc::Rc,
+};
+
+#[cfg(feature = "http")]
+use crate::http;
+use crate::{
+    bom::{self, FirstBom},
+    decompress,
+    encoding::{self, Encoding},
+    follow::Follow,
+    stats::{self, Stats},
+};
+
+/// The input path that means "read stdin".
+pub const STDIN: &str = "-";
+
+/// How much of a file to examine when deciding whether it's binary.
+const BINARY_SNIFF_LEN: u64 = 8192;
+
+/**
+Whether the file at `path` looks binary (has a NUL byte near the start,
+once it's decoded from `encoding`, since every other byte of ASCII text
+in UTF-16 is a NUL). Compressed files don't count; what's in them is
+checked once they're opened and decompressed.
+*/
+pub fn is_binary(path: &Path, encoding: Option<Encoding>) -> std::io::Result<bool> {
+    let mut head: Vec<u8> = Vec::new();
+    File::open(path)?
+        .take(BINARY_SNIFF_LEN)
+        .read_to_end(&mut head)?;
+    if decompress::Format::sniff(&head).is_some() {
+        return Ok(false);
+    }
+    let mut text: Vec<u8> = Vec::new();
+    encoding::reader(Box::new(Cursor::new(head)), encoding).read_to_end(&mut text)?;
+    Ok(text.contains(&0))
+}
+
+/**
+Check whether `input` looks binary, returning it (with what was read of it
+put back) if not.
+*/
+pub fn text_only(mut input: Box<dyn Read>) -> std::io::Result<Option<Box<dyn Read>>> {
+    let mut head: Vec<u8> = Vec::new();
+    (&mut input).take(BINARY_SNIFF_LEN).read_to_end(&mut head)?;
+    if head.contains(&0) {
+        return Ok(None);
+    }
+    Ok(Some(Box::new(Cursor::new(head).chain(input))))
+}
+
+pub struct Inputs {
+    /// An already-open input to yield before any of `paths`.
+    opened: Option<Box<dyn Read>>,
+    paths: std::vec::IntoIter<PathBuf>,
+    failures: Rc<Cell<usize>>,
+    encoding: Option<Encoding>,
+    first_bom: FirstBom,
+    /// Keep reading files as they grow (see the `follow` module).
+    follow: bool,
+    /// Skip (with a notice) files that look binary.
+    skip_binary: bool,
+    /// The input is the rest of one already partly read, so there's no BOM
+    /// at the start of it.
+    mid_stream: bool,
+    /// The name of the input most recently opened.
+    name: Rc<RefCell<String>>,
+    /// Where to count the inputs, and the bytes read from them.
+    stats: Option<Rc<Stats>>,
+}
+
+impl Inputs {
+    /**
+    Iterate over readers for each of `paths` in turn, counting the ones
+    that can't be opened in `failures`.
+    */
+    pub fn new(paths: Vec<PathBuf>, failures: Rc<Cell<usize>>) -> Inputs {
+        Inputs {
+            opened: None,
+            paths: paths.into_iter(),
+            failures,
+            encoding: None,
+            first_bom: FirstBom::default(),
+            follow: false,
+            skip_binary: false,
+            mid_stream: false,
+            name: Rc::default(),
+            stats: None,
+        }
+    }
+
+    /// "Iterate" over just the one input that's already open.
+    pub fn opened(input: Box<dyn Read>) -> Inputs {
+        Inputs {
+            opened: Some(input),
+            paths: Vec::new().into_iter(),
+            failures: Rc::default(),
+            encoding: None,
+            first_bom: FirstBom::default(),
+            follow: false,
+            skip_binary: false,
+            mid_stream: false,
+            name: Rc::default(),
+            stats: None,
+        }
+    }
+
+    /// Builder-pattern method for reading inputs in the given encoding
+    /// (`None` is UTF-8).
+    pub fn encoding(mut self, encoding: Option<Encoding>) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Builder-pattern method for following files as they grow, instead
+    /// of stopping at the end.
+    pub fn follow(mut self, follow: bool) -> Self {
+        self.follow = follow;
+        self
+    }
+
+    /// Builder-pattern method for skipping input files (though not stdin)
+    /// that look binary, after saying so on stderr.
+    pub fn skip_binary(mut self, skip_binary: bool) -> Self {
+        self.skip_binary = skip_binary;
+        self
+    }
+
+    /// Builder-pattern method for an input that carries on from where
+    /// another left off, so a BOM at the start of it is just text.
+    pub fn mid_stream(mut self) -> Self {
+        self.mid_stream = true;
+        self
+    }
+
+    /// Builder-pattern method for counting the inputs (and the bytes read
+    /// from them) in `stats`.
+    pub fn stats(mut self, stats: Option<Rc<Stats>>) -> Self {
+        self.stats = stats;
+        self
+    }
+
+    /// Builder-pattern method for giving an already-open input a name.
+    pub fn named(self, name: String) -> Self {
+        *self.name.borrow_mut() = name;
+        self
+    }
+
+    /// The name of the input most recently opened (its path, or what
+    /// grep calls stdin), once one has been.
+    pub fn name(&self) -> Rc<RefCell<String>> {
+        self.name.clone()
+    }
+
+    /// Whether the first input started with a byte order mark, once it's
+    /// been read from.
+    pub fn first_bom(&self) -> FirstBom {
+        self.first_bom.clone()
+    }
+
+    fn prepare(&self, input: Box<dyn Read>) -> Box<dyn Read> {
+        if let Some(ref stats) = self.stats {
+            stats.files.add(1);
+        }
+        let input = stats::reader(input, &self.stats);
+        let input = encoding::reader(input, self.encoding);
+        if self.mid_stream {
+            return input;
+        }
+        Box::new(bom::Stripper::new(input, self.first_bom.clone()))
+    }
+}
+
+/**
+Open the input at `path` (a file, an http:// URL, or `-` for stdin),
+decompressing it if need be, or following it if `follow` is true.
+*/
+pub fn open(path: &Path, follow: bool) -> std::io::Result<Box<dyn Read>> {
+    #[cfg(feature = "http")]
+    if let Some(url) = path.to_str().filter(|p| http::is_url(p)) {
+        return decompress::wrap(http::open(url)?);
+    }
+    let input: Box<dyn Read> = if path == Path::new(STDIN) {
+        Box::new(std::io::stdin().lock())
+    } else if follow {
+        // A growing file won't be compressed.
+        return Ok(Box::new(Follow::new(
+            File::open(path)?,
+            path.to_path_buf(),
+        )?));
+    } else {
+        Box::new(File::open(path)?)
+    };
+    decompress::wrap(input)
+}
+
+impl Iterator for Inputs {
+    type Item = Box<dyn Read>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(input) = self.opened.take() {
+            return Some(self.prepare(input));
+        }
+        while let Some(path) = self.paths.next() {
+            // A file being followed may not have enough in it to tell yet.
+            let check = self.skip_binary && !self.follow && path.as_os_str() != STDIN;
+            let opened = open(&path, self.follow).and_then(|input| match check {
+                true => text_only(self.prepare(input)),
+                false => Ok(Some(self.prepare(input))),
+            });
+            match opened {
+                Ok(Some(input)) => {
+                    *self.name.borrow_mut() = match path.as_os_str() == STDIN {
+                        true => "(standard input)".into(),
+                        false => path.display().to_string(),
+                    };
+                    return Some(input);
+                }
+                Ok(None) => eprintln!(
+                    "{}: binary file skipped (use -a to process it anyway)",
+                    path.display()
+                ),
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    self.failures.set(self.failures.get() + 1);
+                }
+            }
+        }
+        None
+    }
+}
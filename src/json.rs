@@ -0,0 +1,360 @@
+/*!
+Picking out a string in each line of JSON Lines input to match against.
+
+The field is given as a path of object keys (and array indices) separated
+by dots, like `request.headers.0`. Each record is scanned just far enough
+to find the string at that path; it's unescaped to be matched against,
+and the replacement escaped again and put in its place, so nothing else
+in the record changes (not even whitespace or key order). Records that
+aren't JSON, or don't have a string at that path, are left alone.
+*/
+use std::{borrow::Cow, ops::Range};
+
+use crate::field::Target;
+
+/// Finds the string at a path in each record.
+pub struct PathTarget {
+    path: Vec<String>,
+}
+
+impl PathTarget {
+    pub fn new(path: &str) -> PathTarget {
+        PathTarget {
+            path: path.split('.').map(String::from).collect(),
+        }
+    }
+}
+
+fn skip_ws(text: &[u8], mut pos: usize) -> usize {
+    while let Some(b' ' | b'\t' | b'\r' | b'\n') = text.get(pos) {
+        pos += 1;
+    }
+    pos
+}
+
+/// The end of the string starting (with its `"`) at `pos`.
+fn string_end(text: &[u8], mut pos: usize) -> Option<usize> {
+    pos += 1;
+    loop {
+        match text.get(pos)? {
+            b'"' => return Some(pos + 1),
+            b'\\' => pos += 2,
+            _ => pos += 1,
+        }
+    }
+}
+
+/// The end of the value starting at `pos`.
+fn value_end(text: &[u8], pos: usize) -> Option<usize> {
+    match text.get(pos)? {
+        b'"' => string_end(text, pos),
+        b'{' | b'[' => {
+            let mut depth: usize = 0;
+            let mut pos = pos;
+            loop {
+                match text.get(pos)? {
+                    b'"' => {
+                        pos = string_end(text, pos)?;
+                        continue;
+                    }
+                    b'{' | b'[' => depth += 1,
+                    b'}' | b']' => {
+                        depth -= 1;
+                        if depth == 0 {
+                            return Some(pos + 1);
+                        }
+                    }
+                    _ => {}
+                }
+                pos += 1;
+            }
+        }
+        _ => {
+            // A number, true, false, or null.
+            let len = text[pos..]
+                .iter()
+                .position(|b| matches!(b, b',' | b'}' | b']' | b' ' | b'\t' | b'\r' | b'\n'))
+                .unwrap_or(text.len() - pos);
+            Some(pos + len)
+        }
+    }
+}
+
+/*
+Find the value at `path` in the value starting at `pos`, returning its
+span.
+*/
+fn find(text: &[u8], pos: usize, path: &[String]) -> Option<Range<usize>> {
+    let pos = skip_ws(text, pos);
+    let (key, rest) = match path.split_first() {
+        Some(split) => split,
+        None => return Some(pos..value_end(text, pos)?),
+    };
+    let (object, close) = match text.get(pos)? {
+        b'{' => (true, b'}'),
+        b'[' => (false, b']'),
+        _ => return None,
+    };
+    let index: Option<usize> = key.parse().ok();
+    let mut pos = skip_ws(text, pos + 1);
+    let mut n: usize = 0;
+    if text.get(pos) == Some(&close) {
+        return None;
+    }
+    loop {
+        let this_one = if object {
+            let end = string_end(text, pos)?;
+            let name = unescape(&text[pos..end])?;
+            pos = skip_ws(text, end);
+            if text.get(pos) != Some(&b':') {
+                return None;
+            }
+            pos += 1;
+            name == key.as_bytes()
+        } else {
+            index == Some(n)
+        };
+        if this_one {
+            return find(text, pos, rest);
+        }
+        pos = skip_ws(text, pos);
+        pos = skip_ws(text, value_end(text, pos)?);
+        match text.get(pos)? {
+            b',' => pos = skip_ws(text, pos + 1),
+            _ => return None,
+        }
+        n += 1;
+    }
+}
+
+/*
+The value of the JSON string `text` (including its quotes).
+*/
+fn unescape(text: &[u8]) -> Option<Vec<u8>> {
+    let inner = text.strip_prefix(b"\"")?.strip_suffix(b"\"")?;
+    let mut value: Vec<u8> = Vec::with_capacity(inner.len());
+    let mut bytes = inner.iter();
+    let mut buff = [0u8; 4];
+    // A high surrogate waiting for its other half.
+    let mut high: Option<u16> = None;
+    let replacement = char::REPLACEMENT_CHARACTER
+        .encode_utf8(&mut buff)
+        .as_bytes()
+        .to_vec();
+    while let Some(&b) = bytes.next() {
+        let escaped = match b {
+            b'\\' => Some(*bytes.next()?),
+            _ => None,
+        };
+        // A high surrogate without its low one is no character at all.
+        if escaped != Some(b'u') && high.take().is_some() {
+            value.extend_from_slice(&replacement);
+        }
+        let c = match escaped {
+            None => {
+                value.push(b);
+                continue;
+            }
+            Some(escaped) => escaped,
+        };
+        let c = match c {
+            b'"' => '"',
+            b'\\' => '\\',
+            b'/' => '/',
+            b'b' => '\u{8}',
+            b'f' => '\u{c}',
+            b'n' => '\n',
+            b'r' => '\r',
+            b't' => '\t',
+            b'u' => {
+                let hex: Vec<u8> = bytes.by_ref().take(4).copied().collect();
+                if hex.len() < 4 || !hex.iter().all(u8::is_ascii_hexdigit) {
+                    return None;
+                }
+                let unit = u16::from_str_radix(std::str::from_utf8(&hex).ok()?, 16).ok()?;
+                match (high.take(), unit) {
+                    (Some(high), 0xDC00..=0xDFFF) => char::decode_utf16([high, unit])
+                        .next()?
+                        .unwrap_or(char::REPLACEMENT_CHARACTER),
+                    (pending, _) => {
+                        if pending.is_some() {
+                            value.extend_from_slice(&replacement);
+                        }
+                        if let 0xD800..=0xDBFF = unit {
+                            high = Some(unit);
+                            continue;
+                        }
+                        char::from_u32(u32::from(unit)).unwrap_or(char::REPLACEMENT_CHARACTER)
+                    }
+                }
+            }
+            _ => return None,
+        };
+        value.extend_from_slice(c.encode_utf8(&mut buff).as_bytes());
+    }
+    if high.is_some() {
+        value.extend_from_slice(&replacement);
+    }
+    Some(value)
+}
+
+/// Append `value` to `out` as a JSON string.
+pub fn escape(value: &[u8], out: &mut Vec<u8>) {
+    out.push(b'"');
+    for &b in value.iter() {
+        match b {
+            b'"' => out.extend_from_slice(b"\\\""),
+            b'\\' => out.extend_from_slice(b"\\\\"),
+            b'\n' => out.extend_from_slice(b"\\n"),
+            b'\r' => out.extend_from_slice(b"\\r"),
+            b'\t' => out.extend_from_slice(b"\\t"),
+            0..=0x1f | 0x7f => out.extend_from_slice(format!("\\u{:04x}", b).as_bytes()),
+            _ => out.push(b),
+        }
+    }
+    out.push(b'"');
+}
+
+impl Target for PathTarget {
+    fn field<'a>(&self, record: &'a [u8]) -> Option<(Range<usize>, Cow<'a, [u8]>)> {
+        let span = find(record, 0, &self.path)?;
+        let value = unescape(&record[span.clone()])?;
+        Some((span, Cow::Owned(value)))
+    }
+
+    fn splice(&self, record: &[u8], span: Range<usize>, value: &[u8], out: &mut Vec<u8>) {
+        out.extend_from_slice(&record[..span.start]);
+        // An unchanged string keeps its original escapes.
+        if unescape(&record[span.clone()]).as_deref() == Some(value) {
+            out.extend_from_slice(&record[span.clone()]);
+        } else {
+            escape(value, out);
+        }
+        out.extend_from_slice(&record[span.end..]);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /* The value at `path` in `record`, as the field to match against. */
+    fn field(path: &str, record: &str) -> Option<String> {
+        let (_, value) = PathTarget::new(path).field(record.as_bytes())?;
+        Some(String::from_utf8(value.into_owned()).unwrap())
+    }
+
+    fn spliced(path: &str, record: &str, value: &str) -> String {
+        let target = PathTarget::new(path);
+        let (span, _) = target.field(record.as_bytes()).unwrap();
+        let mut out: Vec<u8> = Vec::new();
+        target.splice(record.as_bytes(), span, value.as_bytes(), &mut out);
+        String::from_utf8(out).unwrap()
+    }
+
+    #[test]
+    fn unicode_escapes() {
+        assert_eq!(
+            field("s", r#"{"s": "caf\u00e9"}"#),
+            Some("caf\u{e9}".into())
+        );
+        assert_eq!(
+            field("s", r#"{"s": "\u00E9\u4e2d"}"#),
+            Some("\u{e9}\u{4e2d}".into())
+        );
+        // A surrogate pair is one character.
+        assert_eq!(
+            field("s", r#"{"s": "\ud83d\ude00!"}"#),
+            Some("\u{1f600}!".into())
+        );
+        // Unpaired surrogates can't be decoded, but the rest can.
+        assert_eq!(field("s", r#"{"s": "\ud83dA"}"#), Some("\u{fffd}A".into()));
+        assert_eq!(
+            field("s", r#"{"s": "\ud83d\n"}"#),
+            Some("\u{fffd}\n".into())
+        );
+        assert_eq!(field("s", r#"{"s": "\ud83d"}"#), Some("\u{fffd}".into()));
+        assert_eq!(
+            field("s", r#"{"s": "\ude00\ud83d"}"#),
+            Some("\u{fffd}\u{fffd}".into())
+        );
+        assert_eq!(
+            field("s", r#"{"s": "\"q\" \\ \/ \b\f\n\r\t"}"#),
+            Some("\"q\" \\ / \u{8}\u{c}\n\r\t".into())
+        );
+        assert_eq!(field("k\u{e9}y", r#"{"k\u00e9y": "x"}"#), Some("x".into()));
+    }
+
+    #[test]
+    fn nested_paths() {
+        let record = r#"{"a": {"b": [1, {"c": "x"}, "y"], "d": "z"}, "e": ["p", ["q"]]}"#;
+        assert_eq!(field("a.b.1.c", record), Some("x".into()));
+        assert_eq!(field("a.b.2", record), Some("y".into()));
+        assert_eq!(field("a.d", record), Some("z".into()));
+        assert_eq!(field("e.1.0", record), Some("q".into()));
+        // Only strings are matched against.
+        assert_eq!(field("a.b.0", record), None);
+        assert_eq!(field("a", record), None);
+    }
+
+    #[test]
+    fn skipping_values() {
+        let record =
+            r#"{ "n": -1.5e+10, "m": 2E-3, "t": true, "z": null, "o": {"s": "}"}, "s" : "here" }"#;
+        assert_eq!(field("s", record), Some("here".into()));
+        assert_eq!(field("o.s", record), Some("}".into()));
+        assert_eq!(field("1", r#"[1e5,"x"]"#), Some("x".into()));
+    }
+
+    #[test]
+    fn missing_fields() {
+        let record = r#"{"a": {"b": "x"}, "c": []}"#;
+        assert_eq!(field("b", record), None);
+        assert_eq!(field("a.c", record), None);
+        assert_eq!(field("a.b.c", record), None);
+        assert_eq!(field("c.0", record), None);
+        assert_eq!(field("a.x", r#"{"a": {}}"#), None);
+    }
+
+    #[test]
+    fn invalid_records() {
+        for record in [
+            "",
+            "not json",
+            "{",
+            "[",
+            r#"{"a""#,
+            r#"{"a": "#,
+            r#"{"a": "unterminated"#,
+            r#"{"a" "b"}"#,
+            r#"{"x": 1 "a": "b"}"#,
+            r#"{"a": "bad \q escape"}"#,
+            r#"{"a": "\u12"}"#,
+            r#"{"a": "\"#,
+            r#"]"a""#,
+        ] {
+            assert_eq!(field("a", record), None, "{}", record);
+        }
+    }
+
+    #[test]
+    fn splicing() {
+        assert_eq!(
+            spliced("a", r#"{"a": "x", "b": 1}"#, "y"),
+            r#"{"a": "y", "b": 1}"#
+        );
+        assert_eq!(
+            spliced("a", r#"{"a":"x"}"#, "say \"hi\"\n\t\\ \u{1}"),
+            r#"{"a":"say \"hi\"\n\t\\ \u0001"}"#
+        );
+        // An unchanged value keeps its escapes.
+        assert_eq!(
+            spliced("a", r#"{"a":"\u00e9"}"#, "\u{e9}"),
+            r#"{"a":"\u00e9"}"#
+        );
+        assert_eq!(
+            spliced("a", r#"{"a":"\u00e9"}"#, "\u{fc}"),
+            "{\"a\":\"\u{fc}\"}"
+        );
+    }
+}
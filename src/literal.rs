@@ -0,0 +1,79 @@
+/*!
+Finding a literal string that every match of a regex has to contain.
+
+Searching a record for a literal with `memmem` is much quicker than running
+the regex over it, so records without the literal can be passed over
+without running the regex at all (like ripgrep's "inner literal"
+optimization). For `\w+@example\.com`, that's `@example.com`.
+*/
+use regex_syntax::{
+    hir::{Hir, HirKind},
+    ParserBuilder,
+};
+
+use crate::opt::RegexFlags;
+
+/**
+The longest literal that any match of `pattern` (compiled with these
+flags) must contain, if there is one that's sure to be there.
+*/
+pub fn required(pattern: &str, ignore_case: bool, flags: &RegexFlags) -> Option<Vec<u8>> {
+    let hir = ParserBuilder::new()
+        .case_insensitive(ignore_case)
+        .multi_line(flags.multi_line)
+        .dot_matches_new_line(flags.dot_matches_new_line)
+        .ignore_whitespace(flags.ignore_whitespace)
+        .unicode(flags.unicode)
+        // As with `regex::bytes`, which can match invalid UTF-8.
+        .utf8(false)
+        .build()
+        .parse(pattern)
+        .ok()?;
+    in_hir(&hir).filter(|lit| !lit.is_empty())
+}
+
+/* The one string `hir` matches, if that's all it can match. */
+fn exact(hir: &Hir) -> Option<Vec<u8>> {
+    match hir.kind() {
+        HirKind::Literal(lit) => Some(lit.0.to_vec()),
+        HirKind::Class(class) => class.literal(),
+        _ => None,
+    }
+}
+
+fn in_hir(hir: &Hir) -> Option<Vec<u8>> {
+    match hir.kind() {
+        HirKind::Literal(_) | HirKind::Class(_) => exact(hir),
+        HirKind::Capture(cap) => in_hir(&cap.sub),
+        HirKind::Repetition(rep) if rep.min > 0 => in_hir(&rep.sub),
+        HirKind::Concat(subs) => {
+            // Runs of exact pieces next to each other make longer literals,
+            // as do the literals required by anything else in between.
+            let mut best: Option<Vec<u8>> = None;
+            let mut run: Vec<u8> = Vec::new();
+            for sub in subs {
+                match exact(sub) {
+                    Some(lit) => run.extend_from_slice(&lit),
+                    None => {
+                        keep(std::mem::take(&mut run), &mut best);
+                        if let Some(lit) = in_hir(sub) {
+                            keep(lit, &mut best);
+                        }
+                    }
+                }
+            }
+            keep(run, &mut best);
+            best
+        }
+        // An alternation could do without any one of its literals, and the
+        // rest match nothing in particular.
+        _ => None,
+    }
+}
+
+/* Keep `lit` if it's longer than the `best` so far. */
+fn keep(lit: Vec<u8>, best: &mut Option<Vec<u8>>) {
+    if best.as_ref().map_or(true, |b| lit.len() > b.len()) {
+        *best = Some(lit);
+    }
+}
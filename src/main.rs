@@ -1,171 +1,2021 @@
+mod addr;
+mod archive;
+mod bom;
+mod chunk;
+mod csv;
+mod decompress;
+mod diff;
+mod encoding;
 mod err;
+mod escape;
+mod expr;
+#[cfg(feature = "fancy")]
+mod fancy;
+mod field;
+mod follow;
+mod format;
+mod fuzzy;
+mod glob;
+mod hash;
+#[cfg(feature = "http")]
+mod http;
+mod inplace;
+mod input;
+mod json;
+mod literal;
+mod matcher;
+mod mmap;
 mod opt;
+mod outpath;
+#[cfg(feature = "pcre2")]
+mod pcre2;
+mod printf;
+#[cfg(feature = "random")]
+mod random;
+mod select;
+mod stats;
+mod tee;
+mod template;
+mod time;
+mod transform;
+mod translate;
+mod walk;
 
-use std::{borrow::Cow, io::Write};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    collections::{HashMap, HashSet, VecDeque},
+    fs::{self, File},
+    io::{Cursor, Read, Write},
+    path::{Path, PathBuf},
+    process::ExitCode,
+    rc::Rc,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        mpsc, Mutex,
+    },
+    thread,
+    time::Instant,
+};
 
-use regex::bytes::Regex;
-use regex_chunker::ByteChunker;
+use aho_corasick::{AhoCorasick, MatchKind};
+use chunk::{Bounded, Chunk};
+use regex::bytes::{Regex, RegexBuilder, RegexSetBuilder};
+use regex_chunker::{ByteChunker, MatchDisposition};
 
 use err::FrErr;
-use opt::{MatchMode, Opts, OutputMode};
+use field::Target;
+use format::Found;
+use fuzzy::Fuzzy;
+use input::Inputs;
+use matcher::{
+    AnyOf, Caps, Matcher, Matches, MultiRegex, RegexMatcher, Timed, Verbatim, VerbatimSet,
+};
+use opt::{Engine, MatchMode, Opts, OutputMode};
+use stats::Stats;
+use template::Template;
+use walk::Walk;
 
-fn find_subslice<T>(haystack: &[T], needle: &[T]) -> Option<usize>
-where
-    T: PartialEq,
-{
-    if needle.len() > haystack.len() {
-        return None;
+/// Iterator over the "records" of the input to be matched against.
+type Records = Box<dyn Iterator<Item = Result<Vec<u8>, FrErr>>>;
+
+/// How much of the input to read at a time, with --threads.
+const BLOCK_SIZE: usize = 1 << 20;
+
+/// Something to do to each of a number of files.
+type Work<T> = fn(&mut Opts, &Path, Option<&dyn Matcher>) -> Result<T, FrErr>;
+
+/// A file's original and edited contents, and whether anything matched.
+type DryRun = (Vec<u8>, Vec<u8>, bool);
+
+/*
+In paragraph mode, a record can end with line breaks that weren't part of
+a delimiter (at the end of the input), and there can be empty ones (from
+blank lines at the start); the former are trimmed, and the latter dropped.
+*/
+fn paragraph(record: Result<Vec<u8>, FrErr>) -> Option<Result<Vec<u8>, FrErr>> {
+    match record {
+        Ok(mut record) => {
+            while let Some(b'\n' | b'\r') = record.last() {
+                record.pop();
+            }
+            (!record.is_empty()).then_some(Ok(record))
+        }
+        Err(e) => Some(Err(e)),
     }
+}
 
-    for (n, w) in haystack.windows(needle.len()).enumerate() {
-        if w == needle {
-            return Some(n);
+/*
+Split `input` into records of `len` bytes.
+*/
+fn fixed_records(input: Box<dyn Read>, len: usize) -> Records {
+    let mut input = Some(input);
+    Box::new(std::iter::from_fn(move || {
+        let mut buff: Vec<u8> = Vec::with_capacity(len);
+        match input.as_mut()?.take(len as u64).read_to_end(&mut buff) {
+            Ok(0) => None,
+            Ok(_) => Some(Ok(buff)),
+            Err(e) => {
+                // Give up on this input after an error.
+                input = None;
+                Some(Err(e.into()))
+            }
         }
+    }))
+}
+
+/// Where the record most recently read was in the input.
+#[derive(Default)]
+struct Position {
+    /// The name of the input file it's in.
+    file: RefCell<String>,
+    /// Its number in that file, counting from 1.
+    number: Cell<usize>,
+    /// How far into the file it started, in bytes.
+    offset: Cell<usize>,
+    /// Where the next one starts.
+    next: Cell<usize>,
+    /// Set if it's the last record, and the input didn't end with a
+    /// delimiter after it (so none should be printed after it either).
+    unterminated: Cell<bool>,
+    /// The delimiter it ended with, if that's to be printed after it
+    /// instead of `newline`.
+    ending: RefCell<Option<Vec<u8>>>,
+}
+
+impl Position {
+    /* Note that the input named `file` is about to be read from. */
+    fn start(&self, file: &RefCell<String>) {
+        self.file.replace(file.borrow().clone());
+        self.number.set(0);
+        self.next.set(0);
     }
 
-    None
+    /* Note that a record `len` bytes long (with any delimiter) was read. */
+    fn advance(&self, len: usize) {
+        self.offset.set(self.next.get());
+        self.next.set(self.next.get() + len);
+    }
 }
 
-/**
-Read input stream line-by-line, either replacing or extracting (and
-possibly replacing) text according to the semantics of the
-[`Regex::replace*`](https://docs.rs/regex/latest/regex/struct.Regex.html#method.replace)
-family of functions.
+/*
+Split `input` at each match of `delimiter`, keeping the delimiter on the
+end of the record before it.
 */
-fn regex_match(mut opts: Opts) -> Result<(), FrErr> {
-    let re = Regex::new(&opts.pattern)?;
-    let chunker = ByteChunker::new(opts.input, &opts.delimiter)?;
+fn chunks(input: Box<dyn Read>, delimiter: &str, buffer_size: Option<usize>) -> Records {
+    let chunker = ByteChunker::new(input, delimiter);
+    let chunker = match buffer_size {
+        Some(size) => chunker.map(|chunker| chunker.with_buffer_size(size)),
+        None => chunker,
+    };
+    match chunker {
+        Ok(chunker) => Box::new(
+            chunker
+                .with_match(MatchDisposition::Append)
+                .map(|res| res.map_err(FrErr::from)),
+        ),
+        Err(e) => Box::new(std::iter::once(Err(e.into()))),
+    }
+}
 
-    match opts.output_mode {
-        OutputMode::Replace(repl) => {
-            let repl = repl.as_bytes();
-            for chunk in chunker {
-                let chunk = chunk?;
-                let altered = re.replacen(&chunk, opts.max, repl);
-
-                match altered {
-                    Cow::Owned(mut v) => {
-                        if let Some(ref nl_bytes) = opts.newline {
-                            v.extend_from_slice(nl_bytes);
-                        }
-                        opts.output.write_all(&v)?;
+/*
+Like `chunks()`, but records longer than `limit` bytes are skipped with a
+warning (still counting towards the position of the records after them),
+or, if `editing`, are an error.
+*/
+fn bounded(
+    input: Box<dyn Read>,
+    delimiter: &str,
+    limit: usize,
+    buffer_size: Option<usize>,
+    editing: bool,
+    pos: &Rc<Position>,
+) -> Records {
+    let delimiter = match Regex::new(delimiter) {
+        Ok(delimiter) => delimiter,
+        Err(e) => return Box::new(std::iter::once(Err(e.into()))),
+    };
+    let chunks = Bounded::new(input, delimiter, limit);
+    let chunks = match buffer_size {
+        Some(size) => chunks.read_size(size),
+        None => chunks,
+    };
+    let pos = pos.clone();
+    Box::new(chunks.filter_map(move |res| {
+        let len = match res {
+            Ok(Chunk::Record(record)) => return Some(Ok(record)),
+            Ok(Chunk::Skipped(len)) => len,
+            Err(e) => return Some(Err(e.into())),
+        };
+        pos.advance(len);
+        pos.number.set(pos.number.get() + 1);
+        let msg = format!(
+            "record {} is longer than --max-record-bytes ({} bytes)",
+            pos.number.get(),
+            limit
+        );
+        if editing {
+            return Some(Err(FrErr::Misc(msg.into())));
+        }
+        eprintln!("{}: {}, so it was skipped", pos.file.borrow(), msg);
+        None
+    }))
+}
+
+/*
+Iterate over `inputs`, along with a function that says whether there are
+any more of them (opening the next one, if there is).
+*/
+fn peekable(inputs: Inputs) -> (impl Iterator<Item = Box<dyn Read>>, Rc<dyn Fn() -> bool>) {
+    let inputs = Rc::new(RefCell::new(inputs.peekable()));
+    let more = {
+        let inputs = inputs.clone();
+        move || inputs.borrow_mut().peek().is_some()
+    };
+    let inputs = std::iter::from_fn(move || inputs.borrow_mut().next());
+    (inputs, Rc::new(more))
+}
+
+/**
+Split the input into records. Usually this means chunking each input by
+`opts.delimiter`, but in multiline mode each whole input is a single
+record, with `opts.record_bytes` records are a fixed length, and with
+`opts.csv` they're CSV rows.
+*/
+fn records(inputs: Inputs, opts: &Opts) -> (Records, Rc<Position>) {
+    let position: Rc<Position> = Rc::default();
+    // Only needed for records that get printed with a delimiter after them.
+    let track_end = !opts.final_newline;
+    let pos = position.clone();
+    let name = inputs.name();
+    let records: Records = if opts.multiline {
+        Box::new(inputs.map(move |mut input| {
+            pos.start(&name);
+            let mut buff: Vec<u8> = Vec::new();
+            input.read_to_end(&mut buff)?;
+            pos.advance(buff.len());
+            Ok(buff)
+        }))
+    } else if let Some(len) = opts.record_bytes {
+        Box::new(inputs.flat_map(move |input| {
+            pos.start(&name);
+            let pos = pos.clone();
+            fixed_records(input, len).inspect(move |res| {
+                if let Ok(record) = res {
+                    pos.advance(record.len());
+                }
+            })
+        }))
+    } else if opts.csv {
+        let keep_endings = opts.keep_endings;
+        let (inputs, more) = peekable(inputs);
+        Box::new(inputs.flat_map(move |input| {
+            pos.start(&name);
+            let (pos, more) = (pos.clone(), more.clone());
+            csv::Rows::new(input).map(move |res| {
+                let mut row = res?;
+                pos.advance(row.len());
+                if row.last() == Some(&b'\n') {
+                    let at = match row.ends_with(b"\r\n") {
+                        true => row.len() - 2,
+                        false => row.len() - 1,
+                    };
+                    let ending = row.split_off(at);
+                    if keep_endings {
+                        pos.ending.replace(Some(ending));
                     }
-                    Cow::Borrowed(b) => {
-                        opts.output.write_all(b)?;
-                        if let Some(ref nl_bytes) = opts.newline {
-                            opts.output.write_all(nl_bytes)?;
-                        }
+                } else if track_end {
+                    pos.unterminated.set(!more());
+                }
+                Ok(row)
+            })
+        }))
+    } else {
+        let delimiter = opts.delimiter.clone();
+        let paragraphs = opts.paragraph;
+        let buffer_size = opts.buffer_size;
+        let max_record_bytes = opts.max_record_bytes;
+        // Edits to files mustn't leave any of them out.
+        let editing = opts.in_place || opts.diff || opts.check;
+        let keep_endings = opts.keep_endings && !paragraphs;
+        // The delimiter is kept on each record to tell whether it was
+        // there, and then taken off.
+        let end = match Regex::new(&format!(r"(?:{})\z", delimiter)) {
+            Ok(end) => end,
+            Err(e) => return (Box::new(std::iter::once(Err(e.into()))), position),
+        };
+        let track_end = track_end && !paragraphs;
+        let (inputs, more) = peekable(inputs);
+        Box::new(inputs.flat_map(move |input| -> Records {
+            pos.start(&name);
+            let chunks = match max_record_bytes {
+                Some(limit) => bounded(input, &delimiter, limit, buffer_size, editing, &pos),
+                None => chunks(input, &delimiter, buffer_size),
+            };
+            let (end, pos, more) = (end.clone(), pos.clone(), more.clone());
+            let records = chunks.map(move |res| -> Result<Vec<u8>, FrErr> {
+                let mut record = res?;
+                pos.advance(record.len());
+                match end.find(&record) {
+                    Some(m) if keep_endings => {
+                        pos.ending.replace(Some(record.split_off(m.start())));
                     }
+                    Some(m) => record.truncate(m.start()),
+                    None if track_end => pos.unterminated.set(!more()),
+                    None => {}
                 }
+                Ok(record)
+            });
+            match paragraphs {
+                true => Box::new(records.filter_map(paragraph)),
+                false => Box::new(records),
             }
+        }))
+    };
+    // Records are numbered once they're sure to be records (and not, say,
+    // blank lines before the first paragraph).
+    let pos = position.clone();
+    let stats = opts.stats.clone();
+    let records = Box::new(records.inspect(move |_| {
+        pos.number.set(pos.number.get() + 1);
+        if let Some(ref stats) = stats {
+            stats.records.add(1);
         }
-        OutputMode::Extract(repl) => {
-            let repl = repl.as_bytes();
-            let mut buff: Vec<u8> = Vec::new();
-            for chunk in chunker {
-                let chunk = chunk?;
+    }));
+    (records, position)
+}
+
+/*
+Start a line of extracted output with the number of the record the match
+is in, and/or the match's offset in the input, if asked for (like grep's
+-n and -b), each followed by `sep`.
+*/
+fn locate(opts: &Opts, n_record: usize, offset: usize, sep: u8, buff: &mut Vec<u8>) {
+    if opts.line_number {
+        buff.extend_from_slice(n_record.to_string().as_bytes());
+        buff.push(sep);
+    }
+    if opts.byte_offset {
+        buff.extend_from_slice(offset.to_string().as_bytes());
+        buff.push(sep);
+    }
+}
 
-                for cap in re.captures_iter(&chunk) {
-                    cap.expand(repl, &mut buff);
+/**
+Records of context printed around the ones matches are extracted from,
+like grep's -B and -A; groups of them that aren't next to each other are
+separated by `--` lines.
+*/
+struct Context {
+    before: usize,
+    after: usize,
+    /// The most recent records that weren't printed, in case a match
+    /// follows: (number, offset, record).
+    recent: VecDeque<(usize, usize, Vec<u8>)>,
+    /// How many more records to print after the last match.
+    after_left: usize,
+    /// The number of the last record printed.
+    last_printed: Option<usize>,
+}
+
+impl Context {
+    fn new((before, after): (usize, usize)) -> Context {
+        Context {
+            before,
+            after,
+            recent: VecDeque::with_capacity(before),
+            after_left: 0,
+            last_printed: None,
+        }
+    }
+
+    /// Whether there's still context to print after a match.
+    fn wants_more(&self) -> bool {
+        self.after_left > 0
+    }
+
+    /* Note that record number `n` is about to be printed. */
+    fn printing(&mut self, n: usize, opts: &Opts, out: &mut Vec<u8>) {
+        if self.before + self.after == 0 {
+            return;
+        }
+        if self.last_printed.is_some_and(|last| n > last + 1) {
+            out.extend_from_slice(b"--");
+            out.extend_from_slice(opts.newline.as_deref().unwrap_or(b"\n"));
+        }
+        self.last_printed = Some(n);
+    }
+
+    /// Record number `n` had something extracted; put the context before
+    /// it in `out`.
+    fn matched(&mut self, n: usize, opts: &Opts, out: &mut Vec<u8>) {
+        while let Some((n, offset, record)) = self.recent.pop_front() {
+            self.print(n, offset, &record, opts, out);
+        }
+        self.printing(n, opts, out);
+        self.after_left = self.after;
+    }
+
+    /// Record number `n` didn't; put it in `out` if it's context after a
+    /// match, or keep it in case it's context before one.
+    fn unmatched(
+        &mut self,
+        n: usize,
+        offset: usize,
+        record: Vec<u8>,
+        opts: &Opts,
+        out: &mut Vec<u8>,
+    ) {
+        if self.after_left > 0 {
+            self.after_left -= 1;
+            self.print(n, offset, &record, opts, out);
+        } else if self.before > 0 {
+            if self.recent.len() == self.before {
+                self.recent.pop_front();
+            }
+            self.recent.push_back((n, offset, record));
+        }
+    }
+
+    fn print(&mut self, n: usize, offset: usize, record: &[u8], opts: &Opts, out: &mut Vec<u8>) {
+        self.printing(n, opts, out);
+        locate(opts, n, offset, b'-', out);
+        out.extend_from_slice(record);
+        if let Some(ref nl_bytes) = opts.newline {
+            out.extend_from_slice(nl_bytes);
+        }
+    }
+}
+
+/*
+Append `newline` (if any) to `buff` after a record, or the delimiter the
+record had if that's being kept, unless that's the last record and the
+input didn't end with a delimiter.
+*/
+fn terminate(buff: &mut Vec<u8>, newline: &Option<Vec<u8>>, position: &Position) {
+    match (newline, &*position.ending.borrow()) {
+        (Some(_), _) if position.unterminated.get() => {}
+        (Some(_), Some(ending)) => buff.extend_from_slice(ending),
+        (Some(nl_bytes), None) => buff.extend_from_slice(nl_bytes),
+        (None, _) => {}
+    }
+}
+
+/// Build a `Matcher` for the pattern(s), if the mode needs one.
+fn mode_matcher(opts: &Opts) -> Result<Option<Box<dyn Matcher>>, FrErr> {
+    match opts.output_mode {
+        OutputMode::Translate(_) | OutputMode::Pairs(_) | OutputMode::Swap(..) => Ok(None),
+        _ => {
+            let matcher = matcher(opts)?;
+            match opts.stats {
+                Some(ref stats) if stats.timed => {
+                    Ok(Some(Box::new(Timed::new(matcher, stats.clone()))))
                 }
+                _ => Ok(Some(matcher)),
+            }
+        }
+    }
+}
 
-                if !buff.is_empty() {
-                    if let Some(ref nl_bytes) = opts.newline {
-                        buff.extend_from_slice(nl_bytes);
-                    }
-                    opts.output.write_all(&buff)?;
-                    buff.clear();
+/// Build the appropriate `Matcher` for the pattern(s).
+/*
+Each of `patterns` run by the regex crate (as compiled by `build`) if it
+can be, or by the fancy engine if not.
+*/
+#[cfg(feature = "fancy")]
+fn fancy_matcher(
+    patterns: &[String],
+    opts: &Opts,
+    build: impl Fn(&str) -> Result<Regex, regex::Error>,
+) -> Result<Box<dyn Matcher>, FrErr> {
+    let mut each: Vec<Box<dyn Matcher>> = Vec::with_capacity(patterns.len());
+    for patt in patterns.iter() {
+        each.push(match build(patt) {
+            Ok(re) => Box::new(RegexMatcher::new(re)),
+            Err(_) => Box::new(
+                fancy::Fancy::new(patt, opts.ignore_case, &opts.regex_flags)
+                    .map_err(|e| FrErr::Misc(format!("{}: {}", patt, e).into()))?,
+            ),
+        });
+    }
+    match each.len() {
+        1 => Ok(each.pop().unwrap()),
+        _ => Ok(Box::new(AnyOf::new(each))),
+    }
+}
+
+/* Each of `patterns` compiled by PCRE2. */
+#[cfg(feature = "pcre2")]
+fn pcre2_matcher(patterns: &[String], opts: &Opts) -> Result<Box<dyn Matcher>, FrErr> {
+    let mut each: Vec<Box<dyn Matcher>> = Vec::with_capacity(patterns.len());
+    for patt in patterns.iter() {
+        each.push(Box::new(
+            pcre2::Pcre2::new(patt, opts.ignore_case, &opts.regex_flags)
+                .map_err(|e| FrErr::Misc(format!("{}: {}", patt, e).into()))?,
+        ));
+    }
+    match each.len() {
+        1 => Ok(each.pop().unwrap()),
+        _ => Ok(Box::new(AnyOf::new(each))),
+    }
+}
+
+fn matcher(opts: &Opts) -> Result<Box<dyn Matcher>, FrErr> {
+    match opts.match_mode {
+        MatchMode::Regex | MatchMode::Glob => {
+            let flags = &opts.regex_flags;
+            let patterns: Vec<String> = opts
+                .patterns
+                .iter()
+                .map(|patt| String::from_utf8_lossy(patt))
+                .map(|patt| match opts.match_mode {
+                    MatchMode::Glob => glob::to_regex(&patt),
+                    _ => patt.into_owned(),
+                })
+                .map(|patt| match (opts.anchor_start, opts.anchor_end) {
+                    (true, true) => format!(r"\A(?:{})\z", patt),
+                    (true, false) => format!(r"\A(?:{})", patt),
+                    (false, true) => format!(r"(?:{})\z", patt),
+                    (false, false) => patt,
+                })
+                .collect();
+
+            let build = |patt: &str| {
+                RegexBuilder::new(patt)
+                    .case_insensitive(opts.ignore_case)
+                    .multi_line(flags.multi_line)
+                    .dot_matches_new_line(flags.dot_matches_new_line)
+                    .ignore_whitespace(flags.ignore_whitespace)
+                    .unicode(flags.unicode)
+                    .build()
+            };
+
+            #[cfg(feature = "pcre2")]
+            if opts.engine == Engine::Pcre2 {
+                return pcre2_matcher(&patterns, opts);
+            }
+
+            // The fancy engine only gets the patterns the regex crate
+            // can't do.
+            if opts.engine == Engine::Fancy && patterns.iter().any(|p| build(p).is_err()) {
+                #[cfg(feature = "fancy")]
+                return fancy_matcher(&patterns, opts, build);
+            }
+
+            let mut regexes = Vec::with_capacity(patterns.len());
+            for patt in patterns.iter() {
+                regexes.push(build(patt)?);
+            }
+
+            if regexes.len() == 1 {
+                let literal = literal::required(&patterns[0], opts.ignore_case, flags);
+                Ok(Box::new(
+                    RegexMatcher::new(regexes.pop().unwrap()).prefilter(literal),
+                ))
+            } else {
+                let set = RegexSetBuilder::new(&patterns)
+                    .case_insensitive(opts.ignore_case)
+                    .multi_line(flags.multi_line)
+                    .dot_matches_new_line(flags.dot_matches_new_line)
+                    .ignore_whitespace(flags.ignore_whitespace)
+                    .unicode(flags.unicode)
+                    .build()?;
+                Ok(Box::new(MultiRegex::new(set, regexes)))
+            }
+        }
+        MatchMode::Verbatim => {
+            let verbatim = |patt: &Vec<u8>| {
+                Verbatim::new(patt.clone())
+                    .ignore_case(opts.ignore_case)
+                    .anchor_start(opts.anchor_start)
+                    .anchor_end(opts.anchor_end)
+            };
+
+            if let Some(distance) = opts.fuzzy {
+                let mut each: Vec<Box<dyn Matcher>> = opts
+                    .patterns
+                    .iter()
+                    .map(|p| {
+                        let p = String::from_utf8_lossy(p);
+                        Box::new(Fuzzy::new(&p, distance).ignore_case(opts.ignore_case))
+                            as Box<dyn Matcher>
+                    })
+                    .collect();
+                if each.len() == 1 {
+                    Ok(each.pop().unwrap())
+                } else {
+                    Ok(Box::new(AnyOf::new(each)))
                 }
+            } else if opts.patterns.len() == 1 {
+                Ok(Box::new(verbatim(&opts.patterns[0])))
+            } else if !opts.anchor_start
+                && !opts.anchor_end
+                && (!opts.ignore_case || opts.patterns.iter().all(|p| p.is_ascii()))
+            {
+                Ok(Box::new(VerbatimSet::new(
+                    &opts.patterns,
+                    opts.ignore_case,
+                )?))
+            } else {
+                let each = opts
+                    .patterns
+                    .iter()
+                    .map(|p| Box::new(verbatim(p)) as Box<dyn Matcher>)
+                    .collect();
+                Ok(Box::new(AnyOf::new(each)))
             }
         }
     }
+}
 
-    opts.output.flush()?;
-    Ok(())
+/**
+Turn replacement text into a `Template`. In regex mode, replacement
+text may refer to capture groups (per the semantics of
+[`Regex::replace*`](https://docs.rs/regex/latest/regex/struct.Regex.html#method.replace));
+in verbatim mode, it is inserted as-is. Globs are matched as regexes,
+so they work the same way.
+*/
+fn template(repl: Option<&[u8]>, opts: &Opts, matcher: &dyn Matcher) -> Result<Template, FrErr> {
+    let template = match (repl, opts.match_mode) {
+        (None, _) => Template::whole_match(),
+        (Some(repl), MatchMode::Regex | MatchMode::Glob) => Template::parse(repl, matcher)
+            .map_err(|e| FrErr::Misc(e.into()))?
+            .live_time(opts.live_time)
+            .hash_salt(&opts.hash_salt),
+        (Some(repl), MatchMode::Verbatim) => Template::literal(repl),
+    };
+    Ok(template)
+}
+
+/*
+The template for the replacement text itself, which, unlike --before-text
+and --after-text, can be taken literally in any mode.
+*/
+fn replacement(repl: Option<&[u8]>, opts: &Opts, matcher: &dyn Matcher) -> Result<Template, FrErr> {
+    let repl = match (repl, &opts.lookup) {
+        (_, Some(table)) => Template::lookup(
+            table.clone(),
+            &opts.lookup_key,
+            opts.lookup_default.as_deref(),
+            matcher,
+        )
+        .map_err(|e| FrErr::Misc(e.into()))?,
+        (Some(repl), None) if opts.literal_replacement => Template::literal(repl),
+        (repl, None) => template(repl, opts, matcher)?,
+    };
+    Ok(repl
+        .preserve_case(opts.preserve_case)
+        .transforms(opts.transforms.clone()))
+}
+
+/*
+Append `text` (from --before-text or --after-text), expanded for the
+match `caps` in `chunk`, to `buff` as a record of its own.
+*/
+fn insert_text(
+    text: &Option<Template>,
+    chunk: &[u8],
+    caps: &Caps,
+    newline: &Option<Vec<u8>>,
+    buff: &mut Vec<u8>,
+) {
+    if let Some(text) = text {
+        text.expand(chunk, caps, buff);
+        if let Some(nl_bytes) = newline {
+            buff.extend_from_slice(nl_bytes);
+        }
+    }
+}
+
+/// The `Target` for the part of each record to match against, if not all.
+fn target(opts: &Opts) -> Option<Box<dyn Target>> {
+    if let Some(ref column) = opts.column {
+        Some(Box::new(csv::ColumnTarget::new(column.clone())))
+    } else if let Some(n) = opts.field {
+        Some(Box::new(field::Delimited::new(n, opts.field_sep.clone())))
+    } else {
+        let path = opts.json_field.as_deref()?;
+        Some(Box::new(json::PathTarget::new(path)))
+    }
+}
+
+/*
+Whether `record` is a header, which should be left alone: either one of
+the first `skip` records (counting down `skip`), or one that `target`
+wants to take note of (like the one that says which column is which).
+*/
+fn header(
+    target: &mut Option<Box<dyn Target>>,
+    skip: &mut usize,
+    record: &[u8],
+) -> Result<bool, FrErr> {
+    let named = match target {
+        Some(target) => target.header(record).map_err(|e| FrErr::Misc(e.into()))?,
+        None => false,
+    };
+    if named || *skip > 0 {
+        *skip = skip.saturating_sub(1);
+        return Ok(true);
+    }
+    Ok(false)
+}
+
+/*
+Whether there's no point reading any more input, because all that's wanted
+is whether anything matches (with --quiet), and something has.
+*/
+fn answered(opts: &Opts, matched: bool) -> bool {
+    opts.quiet && matched
 }
 
 /**
-Read the input stream line-by line, either (as governed by
-`opts.output_mode`) replacing or extracting (and maybe also
-replacing) text.
+Process `inputs` a record at a time, matching with `matcher`; returns
+whether anything matched.
 */
-fn static_match(mut opts: Opts) -> Result<(), FrErr> {
-    let patt = opts.pattern.as_bytes();
-    let chunker = ByteChunker::new(opts.input, &opts.delimiter)?;
+fn process(opts: &mut Opts, inputs: Inputs, matcher: &dyn Matcher) -> Result<bool, FrErr> {
+    let (mut chunker, position) = records(inputs, opts);
+    let mut found: Vec<Caps> = Vec::new();
     let mut buff: Vec<u8> = Vec::new();
+    let sel = &opts.selection;
+    let mut remaining = sel.max_total;
+    let mut target = target(opts);
+    let mut skip = opts.skip_lines;
+    let mut matched = false;
+    let stats = opts.stats.clone().unwrap_or_default();
+
+    // When the whole input is a single record, it already contains its
+    // own line endings, so none are added when printing records whole.
+    let newline = if opts.multiline {
+        None
+    } else {
+        opts.newline.clone()
+    };
+    let before = opts
+        .before_text
+        .as_deref()
+        .map(|text| template(Some(text), opts, matcher))
+        .transpose()?;
+    let after = opts
+        .after_text
+        .as_deref()
+        .map(|text| template(Some(text), opts, matcher))
+        .transpose()?;
 
     match opts.output_mode {
-        OutputMode::Replace(repl) => {
-            let repl = repl.as_bytes();
-            for chunk in chunker {
-                let chunk = chunk?;
-                let mut subslice = &chunk[..];
-                let mut n_replaced: usize = 0;
-
-                while n_replaced < opts.max {
-                    if let Some(n) = find_subslice(subslice, patt) {
-                        buff.extend_from_slice(&subslice[..n]);
-                        buff.extend_from_slice(repl);
-                        n_replaced += 1;
-                        let offs = n + patt.len();
-                        subslice = &subslice[offs..];
+        OutputMode::Replace(ref repl) => {
+            let repl = replacement(Some(repl.as_slice()), opts, matcher)?;
+            // The record, or its target field, with replacements made.
+            let mut text: Vec<u8> = Vec::new();
+
+            while !answered(opts, matched) {
+                let record = match chunker.next() {
+                    Some(record) => record?,
+                    None => break,
+                };
+                let selected = opts.address.selects(&record);
+                if header(&mut target, &mut skip, &record)? {
+                    if !opts.drop_header {
+                        buff.extend_from_slice(&record);
+                        terminate(&mut buff, &newline, &position);
+                        opts.output.write_all(&buff)?;
+                        buff.clear();
+                    }
+                    continue;
+                }
+                // Most records usually have nothing to replace in them, and
+                // it's quicker to find that out (without captures) and send
+                // them on as they are.
+                let plain = target.is_none() && !opts.invert;
+                if plain && (!selected || remaining == 0 || !matcher.is_match(&record)) {
+                    buff.extend_from_slice(&record);
+                    terminate(&mut buff, &newline, &position);
+                    opts.output.write_all(&buff)?;
+                    buff.clear();
+                    continue;
+                }
+                let field = target.as_ref().map(|t| t.field(&record));
+                let (chunk, span) = match field {
+                    Some(Some((ref span, ref value))) => (value.as_ref(), Some(span.clone())),
+                    _ => (record.as_slice(), None),
+                };
+                let missing = matches!(field, Some(None));
+                if opts.count_per_record {
+                    repl.reset_count();
+                }
+                // The match whose captures the --after-text gets expanded
+                // with, if this record gets operated on.
+                let mut acted: Option<Caps> = None;
+
+                if !selected || remaining == 0 || missing {
+                    text.extend_from_slice(chunk);
+                } else if opts.invert {
+                    if matcher.is_match(chunk) {
+                        text.extend_from_slice(chunk);
                     } else {
-                        break;
+                        let caps = Caps::whole(0, chunk.len());
+                        insert_text(&before, chunk, &caps, &opts.newline, &mut buff);
+                        repl.expand(chunk, &caps, &mut text);
+                        remaining -= 1;
+                        stats.replacements.add(1);
+                        stats.matches.add(1);
+                        acted = Some(caps);
+                    }
+                } else {
+                    sel.find(Matches::new(matcher, chunk), &mut found);
+                    found.truncate(remaining);
+                    remaining -= found.len();
+                    stats.replacements.add(found.len());
+                    stats.matches.add(found.len());
+                    if let Some(caps) = found.first() {
+                        insert_text(&before, chunk, caps, &opts.newline, &mut buff);
+                        acted = Some(caps.clone());
+                    }
+                    let mut last_end: usize = 0;
+
+                    for caps in found.iter() {
+                        let (start, end) = caps.span();
+                        text.extend_from_slice(&chunk[last_end..start]);
+                        repl.expand(chunk, caps, &mut text);
+                        last_end = end;
                     }
+
+                    text.extend_from_slice(&chunk[last_end..]);
                 }
 
-                if !subslice.is_empty() {
-                    buff.extend_from_slice(subslice)
+                matched |= acted.is_some();
+                match (span, &target) {
+                    (Some(span), Some(target)) => target.splice(&record, span, &text, &mut buff),
+                    _ => buff.extend_from_slice(&text),
                 }
-                if let Some(ref nl_bytes) = opts.newline {
-                    buff.extend_from_slice(nl_bytes);
+                text.clear();
+                terminate(&mut buff, &newline, &position);
+                if let Some(ref caps) = acted {
+                    insert_text(&after, chunk, caps, &opts.newline, &mut buff);
                 }
                 opts.output.write_all(&buff)?;
                 buff.clear();
             }
         }
-        OutputMode::Extract(repl) => {
-            let repl = repl.as_bytes();
-            for chunk in chunker {
-                let chunk = chunk?;
-                let mut subslice = &chunk[..];
-                let mut n_replaced: usize = 0;
+        OutputMode::Extract(ref repl) => {
+            let repl = replacement(repl.as_deref(), opts, matcher)?;
+            let mut context = Context::new(opts.context);
+            if let Some(ref format) = opts.format {
+                format.check(matcher).map_err(|e| FrErr::Misc(e.into()))?;
+            }
+            if let (Some(format), true) = (&opts.format, opts.format_header) {
+                let nl_bytes = opts.newline.as_deref().unwrap_or_default();
+                format.write_header(matcher, nl_bytes, &mut buff);
+                opts.output.write_all(&buff)?;
+                buff.clear();
+            }
+            // Context lines printed before a match.
+            let mut leading: Vec<u8> = Vec::new();
+            // With --unique, what's been extracted so far.
+            let mut seen: Option<HashSet<Vec<u8>>> = opts.unique.then(HashSet::new);
+            // With --count-by, how many times each thing has been, and
+            // (if it's a capture group's text that's counted) a template
+            // for what's counted.
+            let mut tally: Option<HashMap<Vec<u8>, usize>> =
+                opts.count_by.is_some().then(HashMap::new);
+            // Matches printed just as they are can be written straight from
+            // the record, rather than being copied into `buff` first.
+            let direct = repl.is_whole_match()
+                && opts.format.is_none()
+                && tally.is_none()
+                && seen.is_none()
+                && opts.labels.is_empty()
+                && before.is_none()
+                && after.is_none()
+                && opts.context == (0, 0);
+            let tally_key = match opts.count_by {
+                Some(Some(ref group)) => Some(
+                    Template::parse(format!("${{{}}}", group).as_bytes(), matcher)
+                        .map_err(|e| FrErr::Misc(format!("--count-by: {}", e).into()))?,
+                ),
+                _ => None,
+            };
 
-                while n_replaced < opts.max {
-                    if let Some(n) = find_subslice(subslice, patt) {
-                        buff.extend_from_slice(repl);
-                        n_replaced += 1;
-                        let offs = n + repl.len();
-                        subslice = &subslice[offs..];
-                    } else {
-                        break;
+            // Once nothing more can be extracted (or shown as context),
+            // stop reading.
+            while (remaining > 0 || context.wants_more()) && !answered(opts, matched) {
+                let record = match chunker.next() {
+                    Some(record) => record?,
+                    None => break,
+                };
+                if let (Some(seen), true) = (&mut seen, opts.unique_per_file) {
+                    if position.number.get() == 1 {
+                        seen.clear();
+                    }
+                }
+                let selected = opts.address.selects(&record);
+                if header(&mut target, &mut skip, &record)? {
+                    if !opts.drop_header {
+                        buff.extend_from_slice(&record);
+                        terminate(&mut buff, &newline, &position);
+                        opts.output.write_all(&buff)?;
+                        buff.clear();
                     }
+                    continue;
                 }
 
-                if !buff.is_empty() {
+                let extracted = 'extract: {
+                    if !selected || remaining == 0 {
+                        break 'extract false;
+                    }
+                    let (field_start, chunk) = match target {
+                        Some(ref target) => match target.field(&record) {
+                            Some((span, value)) => (span.start, value),
+                            None => break 'extract false,
+                        },
+                        None => (0, Cow::Borrowed(record.as_slice())),
+                    };
+                    // Where the chunk starts in the input.
+                    let offset = position.offset.get() + field_start;
+                    if opts.count_per_record {
+                        repl.reset_count();
+                    }
+
+                    let caps = if opts.invert {
+                        if matcher.is_match(&chunk) {
+                            break 'extract false;
+                        }
+                        let caps = Caps::whole(0, chunk.len());
+                        insert_text(&before, &chunk, &caps, &opts.newline, &mut buff);
+                        locate(opts, position.number.get(), offset, b':', &mut buff);
+                        let start = buff.len();
+                        repl.expand(&chunk, &caps, &mut buff);
+                        remaining -= 1;
+                        stats.matches.add(1);
+                        matched = true;
+                        if seen
+                            .as_mut()
+                            .is_some_and(|seen| !seen.insert(buff[start..].to_vec()))
+                        {
+                            buff.clear();
+                            break 'extract false;
+                        }
+                        caps
+                    } else {
+                        let matches = Matches::new(matcher, &chunk).overlapping(opts.overlapping);
+                        sel.find(matches, &mut found);
+                        found.truncate(remaining);
+                        remaining -= found.len();
+                        stats.matches.add(found.len());
+
+                        let caps = match found.first() {
+                            Some(caps) => caps.clone(),
+                            None => break 'extract false,
+                        };
+                        matched = true;
+                        if direct {
+                            let n = position.number.get();
+                            for (i, caps) in found.iter().enumerate() {
+                                if i > 0 {
+                                    opts.output.write_all(&opts.match_separator)?;
+                                }
+                                locate(opts, n, offset + caps.span().0, b':', &mut buff);
+                                opts.output.write_all(&buff)?;
+                                buff.clear();
+                                let (start, end) = caps.span();
+                                opts.output.write_all(&chunk[start..end])?;
+                            }
+                            if let Some(ref nl_bytes) = opts.newline {
+                                opts.output.write_all(nl_bytes)?;
+                            }
+                            break 'extract true;
+                        }
+                        insert_text(&before, &chunk, &caps, &opts.newline, &mut buff);
+                        let start = buff.len();
+                        if let Some(ref format) = opts.format {
+                            let file = position.file.borrow();
+                            let nl_bytes = opts.newline.as_deref().unwrap_or_default();
+                            for caps in found.iter() {
+                                let (start, end) = caps.span();
+                                if seen
+                                    .as_mut()
+                                    .is_some_and(|seen| !seen.insert(chunk[start..end].to_vec()))
+                                {
+                                    continue;
+                                }
+                                let found = Found {
+                                    file: &file,
+                                    record: position.number.get(),
+                                    line: &record,
+                                    offset,
+                                    column: field_start,
+                                    text: &chunk,
+                                    caps,
+                                };
+                                format.write(&found, matcher, nl_bytes, &mut buff);
+                            }
+                            if buff.is_empty() {
+                                break 'extract false;
+                            }
+                            break 'extract true;
+                        }
+                        for caps in found.iter() {
+                            let mark = buff.len();
+                            if mark > start {
+                                buff.extend_from_slice(&opts.match_separator);
+                            }
+                            let n = position.number.get();
+                            locate(opts, n, offset + caps.span().0, b':', &mut buff);
+                            let text_start = buff.len();
+                            if let Some(label) = opts.labels.get(caps.pattern()) {
+                                buff.extend_from_slice(label.as_bytes());
+                                buff.push(b':');
+                            }
+                            repl.expand(&chunk, caps, &mut buff);
+                            if let Some(ref mut tally) = tally {
+                                let key = match tally_key {
+                                    Some(ref template) => {
+                                        let mut key: Vec<u8> = Vec::new();
+                                        template.expand(&chunk, caps, &mut key);
+                                        key
+                                    }
+                                    None => buff[text_start..].to_vec(),
+                                };
+                                buff.truncate(mark);
+                                *tally.entry(key).or_insert(0) += 1;
+                            } else if seen
+                                .as_mut()
+                                .is_some_and(|seen| !seen.insert(buff[text_start..].to_vec()))
+                            {
+                                buff.truncate(mark);
+                            }
+                        }
+
+                        if buff.len() == start {
+                            buff.clear();
+                            break 'extract false;
+                        }
+                        caps
+                    };
+
                     if let Some(ref nl_bytes) = opts.newline {
                         buff.extend_from_slice(nl_bytes);
                     }
+                    insert_text(&after, &chunk, &caps, &opts.newline, &mut buff);
+                    true
+                };
+
+                if extracted {
+                    context.matched(position.number.get(), opts, &mut leading);
+                    opts.output.write_all(&leading)?;
+                    opts.output.write_all(&buff)?;
+                    leading.clear();
+                    buff.clear();
+                } else {
+                    let offset = position.offset.get();
+                    context.unmatched(position.number.get(), offset, record, opts, &mut buff);
                     opts.output.write_all(&buff)?;
                     buff.clear();
                 }
             }
+
+            if let Some(tally) = tally {
+                // Most frequent first, like sort | uniq -c | sort -rn.
+                let mut tally: Vec<(Vec<u8>, usize)> = tally.into_iter().collect();
+                tally.sort_unstable_by(|(a, m), (b, n)| n.cmp(m).then_with(|| a.cmp(b)));
+                let nl_bytes = opts.newline.as_deref().unwrap_or(b"\n");
+                for (value, n) in tally.iter() {
+                    buff.extend_from_slice(format!("{}\t", n).as_bytes());
+                    buff.extend_from_slice(value);
+                    buff.extend_from_slice(nl_bytes);
+                }
+                opts.output.write_all(&buff)?;
+                buff.clear();
+            }
+        }
+        OutputMode::Count(count_matches) => {
+            let mut count: usize = 0;
+
+            while remaining > 0 && !answered(opts, count > 0) {
+                let record = match chunker.next() {
+                    Some(record) => record?,
+                    None => break,
+                };
+                let selected = opts.address.selects(&record);
+                if header(&mut target, &mut skip, &record)? || !selected {
+                    continue;
+                }
+                let chunk = match target {
+                    Some(ref target) => match target.field(&record) {
+                        Some((_, value)) => value,
+                        None => continue,
+                    },
+                    None => Cow::Borrowed(record.as_slice()),
+                };
+
+                if count_matches && !opts.invert {
+                    let matches = Matches::new(matcher, &chunk).overlapping(opts.overlapping);
+                    sel.find(matches, &mut found);
+                    found.truncate(remaining);
+                    remaining -= found.len();
+                    stats.matches.add(found.len());
+                    count += found.len();
+                } else if matcher.is_match(&chunk) != opts.invert {
+                    remaining -= 1;
+                    stats.matches.add(1);
+                    count += 1;
+                }
+            }
+
+            matched = count > 0;
+            buff.extend_from_slice(count.to_string().as_bytes());
+            buff.extend_from_slice(opts.newline.as_deref().unwrap_or_default());
+            opts.output.write_all(&buff)?;
+            buff.clear();
+        }
+        // Handled by `main()` without ever building a matcher.
+        OutputMode::Translate(_) | OutputMode::Pairs(_) | OutputMode::Swap(..) => {
+            unreachable!()
+        }
+        OutputMode::Delete => {
+            while !answered(opts, matched) {
+                let record = match chunker.next() {
+                    Some(record) => record?,
+                    None => break,
+                };
+                let selected = opts.address.selects(&record);
+                let is_header = header(&mut target, &mut skip, &record)?;
+                if is_header && opts.drop_header {
+                    continue;
+                }
+                let chunk = match target {
+                    _ if is_header => None,
+                    Some(ref target) => target.field(&record).map(|(_, value)| value),
+                    None => Some(Cow::Borrowed(record.as_slice())),
+                };
+
+                if let Some(chunk) = chunk.filter(|chunk| {
+                    selected && remaining > 0 && matcher.is_match(chunk) != opts.invert
+                }) {
+                    remaining -= 1;
+                    stats.matches.add(1);
+                    matched = true;
+                    // Inserted text takes the place of the deleted record.
+                    if before.is_some() || after.is_some() {
+                        let mut caps = Caps::whole(0, chunk.len());
+                        if !opts.invert {
+                            Matches::new(matcher, &chunk).find_next(&mut caps);
+                        }
+                        insert_text(&before, &chunk, &caps, &opts.newline, &mut buff);
+                        insert_text(&after, &chunk, &caps, &opts.newline, &mut buff);
+                        opts.output.write_all(&buff)?;
+                        buff.clear();
+                    }
+                    continue;
+                }
+
+                buff.extend_from_slice(&record);
+                terminate(&mut buff, &newline, &position);
+                opts.output.write_all(&buff)?;
+                buff.clear();
+            }
         }
     }
 
     opts.output.flush()?;
-    Ok(())
+    Ok(matched)
 }
 
-fn main() -> Result<(), FrErr> {
-    let opts = Opts::new()?;
+/// Transliterate the whole of each input, `tr`-style.
+fn transliterate(opts: &mut Opts, inputs: Inputs) -> Result<bool, FrErr> {
+    let mut found: usize = 0;
+    if let OutputMode::Translate(ref tr) = opts.output_mode {
+        if opts.rejoin {
+            let mut buff: Vec<u8> = Vec::new();
+            let (mut records, position) = records(inputs, opts);
+            while !answered(opts, found > 0) {
+                let chunk = match records.next() {
+                    Some(chunk) => chunk?,
+                    None => break,
+                };
+                found += tr.translate(&mut chunk.as_slice(), &mut buff)?;
+                terminate(&mut buff, &opts.newline, &position);
+                opts.output.write_all(&buff)?;
+                buff.clear();
+            }
+        } else {
+            for mut input in inputs {
+                if answered(opts, found > 0) {
+                    break;
+                }
+                found += tr.translate(&mut input, &mut opts.output)?;
+            }
+        }
+    }
+    if let Some(ref stats) = opts.stats {
+        stats.matches.add(found);
+        stats.replacements.add(found);
+    }
+    opts.output.flush()?;
+    Ok(found > 0)
+}
 
-    let mode = opts.match_mode;
+/**
+Replace every pattern from --pairs with its replacement in a single pass
+over the whole of each input.
+*/
+fn replace_pairs(opts: &mut Opts, inputs: Inputs) -> Result<bool, FrErr> {
+    let mut matched = false;
+    let stats = opts.stats.clone().unwrap_or_default();
+    if let OutputMode::Pairs(ref pairs) = opts.output_mode {
+        let ac = AhoCorasick::builder()
+            .ascii_case_insensitive(opts.ignore_case)
+            .build(pairs.iter().map(|(patt, _)| patt))?;
+        let repls: Vec<&str> = pairs.iter().map(|(_, repl)| repl.as_str()).collect();
+        if opts.rejoin {
+            let mut buff: Vec<u8> = Vec::new();
+            let (mut records, position) = records(inputs, opts);
+            while !answered(opts, matched) {
+                let chunk = match records.next() {
+                    Some(chunk) => chunk?,
+                    None => break,
+                };
+                ac.replace_all_with_bytes(&chunk, &mut buff, |m, _, dst| {
+                    matched = true;
+                    stats.matches.add(1);
+                    stats.replacements.add(1);
+                    dst.extend_from_slice(repls[m.pattern().as_usize()].as_bytes());
+                    true
+                });
+                terminate(&mut buff, &opts.newline, &position);
+                opts.output.write_all(&buff)?;
+                buff.clear();
+            }
+        } else {
+            for input in inputs {
+                if answered(opts, matched) {
+                    break;
+                }
+                ac.try_stream_replace_all_with(input, &mut opts.output, |m, _, dst| {
+                    matched = true;
+                    stats.matches.add(1);
+                    stats.replacements.add(1);
+                    dst.write_all(repls[m.pattern().as_usize()].as_bytes())
+                })?;
+            }
+        }
+    }
+    opts.output.flush()?;
+    Ok(matched)
+}
+
+/**
+Exchange the two strings given to --swap throughout each record.
+*/
+fn swap(opts: &mut Opts, inputs: Inputs) -> Result<bool, FrErr> {
+    let mut matched = false;
+    let stats = opts.stats.clone().unwrap_or_default();
+    if let OutputMode::Swap(ref a, ref b) = opts.output_mode {
+        // Streaming replacement can't prefer longer matches, which matters
+        // when one string contains the other, so go a record at a time.
+        let ac = AhoCorasick::builder()
+            .ascii_case_insensitive(opts.ignore_case)
+            .match_kind(MatchKind::LeftmostLongest)
+            .build([a, b])?;
+        let repls = [b, a];
+        let newline = if opts.multiline {
+            None
+        } else {
+            opts.newline.clone()
+        };
+        let mut buff: Vec<u8> = Vec::new();
 
-    match mode {
-        MatchMode::Regex => regex_match(opts)?,
-        MatchMode::Verbatim => static_match(opts)?,
+        let (mut records, position) = records(inputs, opts);
+        while !answered(opts, matched) {
+            let chunk = match records.next() {
+                Some(chunk) => chunk?,
+                None => break,
+            };
+            if opts.address.selects(&chunk) {
+                ac.replace_all_with_bytes(&chunk, &mut buff, |m, _, dst| {
+                    matched = true;
+                    stats.matches.add(1);
+                    stats.replacements.add(1);
+                    dst.extend_from_slice(repls[m.pattern().as_usize()]);
+                    true
+                });
+            } else {
+                buff.extend_from_slice(&chunk);
+            }
+            terminate(&mut buff, &newline, &position);
+            opts.output.write_all(&buff)?;
+            buff.clear();
+        }
+    }
+    opts.output.flush()?;
+    Ok(matched)
+}
+
+/**
+Do whatever `opts` calls for to `inputs`, returning whether anything
+matched; `matcher` is only needed (and only built) for modes that match
+patterns.
+*/
+fn run(opts: &mut Opts, inputs: Inputs, matcher: Option<&dyn Matcher>) -> Result<bool, FrErr> {
+    let inputs = inputs.stats(opts.stats.clone());
+    match (&opts.output_mode, matcher) {
+        (OutputMode::Translate(_), _) => transliterate(opts, inputs),
+        (OutputMode::Pairs(_), _) => replace_pairs(opts, inputs),
+        (OutputMode::Swap(..), _) => swap(opts, inputs),
+        (_, Some(matcher)) => process(opts, inputs, matcher),
+        (_, None) => unreachable!(),
+    }
+}
+
+/*
+Create the file at `out_path` (and any directories it needs) to write the
+output for the input at `in_path`, unless it's the same file.
+*/
+fn create_output(in_path: &Path, out_path: &Path) -> Result<File, FrErr> {
+    if let (Ok(a), Ok(b)) = (in_path.canonicalize(), out_path.canonicalize()) {
+        if a == b {
+            return Err(FrErr::Misc(
+                "the output would overwrite the input (use -W to edit in place)".into(),
+            ));
+        }
+    }
+    if let Some(dir) = out_path.parent().filter(|dir| !dir.as_os_str().is_empty()) {
+        fs::create_dir_all(dir)?;
+    }
+    Ok(File::create(out_path)?)
+}
+
+/*
+Edit the file at `path` as if in place, but in memory, returning its
+original and edited contents, and whether anything matched; or nothing, if
+it's binary and to be skipped.
+*/
+fn dry_run(
+    opts: &mut Opts,
+    path: &Path,
+    matcher: Option<&dyn Matcher>,
+) -> Result<Option<DryRun>, FrErr> {
+    if opts.skip_binary && input::is_binary(path, opts.encoding).unwrap_or(false) {
+        return Ok(None);
+    }
+    let original = fs::read(path)?;
+    if decompress::Format::sniff(&original).is_some() {
+        return Err(FrErr::Misc("can't edit a compressed file in place".into()));
+    }
+    let edited: Rc<RefCell<Vec<u8>>> = Rc::default();
+    let inputs = Inputs::opened(Box::new(Cursor::new(original.clone())))
+        .named(path.display().to_string())
+        .encoding(opts.encoding);
+    let output = Box::new(diff::Capture::new(edited.clone()));
+    let output = encoding::writer(output, opts.output_encoding);
+    let keep_bom = opts.keep_bom.then(|| inputs.first_bom());
+    opts.output = bom::writer(output, opts.add_bom, keep_bom);
+    opts.address.reset();
+    let matched = run(opts, inputs, matcher)?;
+    opts.output.flush()?;
+    opts.output = Box::new(std::io::sink());
+    let edited = edited.take();
+    Ok(Some((original, edited, matched)))
+}
+
+/*
+Edit the file at `path` in place, returning whether anything matched; or
+nothing, if it's binary and to be skipped.
+*/
+fn edit_in_place(
+    opts: &mut Opts,
+    path: &Path,
+    matcher: Option<&dyn Matcher>,
+) -> Result<Option<bool>, FrErr> {
+    if opts.skip_binary && input::is_binary(path, opts.encoding).unwrap_or(false) {
+        return Ok(None);
+    }
+    let mut matched = false;
+    let backup = opts.backup.clone();
+    inplace::edit(path, backup.as_deref(), opts.preserve, |input, output| {
+        let inputs = Inputs::opened(input)
+            .named(path.display().to_string())
+            .encoding(opts.encoding);
+        let output = opt::buffered(output, opts.buffer_size, opts.flush);
+        let output = stats::writer(output, &opts.stats);
+        let output = encoding::writer(output, opts.output_encoding);
+        let keep_bom = opts.keep_bom.then(|| inputs.first_bom());
+        opts.output = bom::writer(output, opts.add_bom, keep_bom);
+        opts.address.reset();
+        matched = run(opts, inputs, matcher)?;
+        Ok(matched)
+    })?;
+    Ok(Some(matched))
+}
+
+/**
+Do `work` to each of `paths`, handing what comes of it to `done`, in the
+same order as `paths`. With --jobs, up to that many files are worked on at
+once, each thread with its own `Opts` (and matcher), made from the same
+arguments as `opts`.
+*/
+fn each_file<T: Send>(
+    opts: &mut Opts,
+    paths: &[PathBuf],
+    matcher: Option<&dyn Matcher>,
+    work: Work<T>,
+    mut done: impl FnMut(&Path, Result<T, String>) -> Result<(), FrErr>,
+) -> Result<(), FrErr> {
+    if opts.jobs < 2 || paths.len() < 2 {
+        for path in paths.iter() {
+            let res = work(opts, path, matcher).map_err(|e| e.to_string());
+            done(path, res)?;
+        }
+        return Ok(());
     }
 
-    Ok(())
+    let next = AtomicUsize::new(0);
+    let (tx, rx) = mpsc::channel::<(usize, Result<T, String>)>();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..opts.jobs.min(paths.len()))
+            .map(|_| {
+                let (tx, next, args) = (tx.clone(), &next, opts.args.clone());
+                scope.spawn(move || -> Option<Stats> {
+                    let setup = Opts::from_args(args).and_then(|opts| {
+                        let matcher = mode_matcher(&opts)?;
+                        Ok((opts, matcher))
+                    });
+                    let mut setup = setup.map_err(|e| e.to_string());
+                    loop {
+                        let n = next.fetch_add(1, Ordering::Relaxed);
+                        let path = match paths.get(n) {
+                            Some(path) => path,
+                            None => break,
+                        };
+                        let res = match setup {
+                            Ok((ref mut opts, ref matcher)) => {
+                                work(opts, path, matcher.as_deref()).map_err(|e| e.to_string())
+                            }
+                            Err(ref e) => Err(e.clone()),
+                        };
+                        // Nobody's listening if something's gone wrong.
+                        if tx.send((n, res)).is_err() {
+                            break;
+                        }
+                    }
+                    setup.ok()?.0.stats.as_deref().cloned()
+                })
+            })
+            .collect();
+        drop(tx);
+
+        // Results come in whatever order they're finished in, and wait
+        // here until those for the paths before them have been handled.
+        let mut waiting: HashMap<usize, Result<T, String>> = HashMap::new();
+        let mut handled: usize = 0;
+        let mut res = Ok(());
+        'results: for (n, result) in rx {
+            waiting.insert(n, result);
+            while let Some(result) = waiting.remove(&handled) {
+                res = done(&paths[handled], result);
+                handled += 1;
+                if res.is_err() {
+                    break 'results;
+                }
+            }
+        }
+        for worker in workers {
+            if let (Ok(Some(theirs)), Some(ref ours)) = (worker.join(), &opts.stats) {
+                ours.merge(&theirs);
+            }
+        }
+        res
+    })
+}
+
+/**
+Replace in `inputs` (just the one) with --threads threads: this one reads
+blocks of whole records and writes what becomes of them, in order, while
+the others, each with its own `Opts` (and matcher) made from the same
+arguments as `opts`, do the replacing.
+*/
+fn pipeline(opts: &mut Opts, inputs: Inputs, matcher: &dyn Matcher) -> Result<bool, FrErr> {
+    // Each thread counts for itself, so `$#` would start over in each
+    // block. Only the replacement's count starts over with each line.
+    if let OutputMode::Replace(ref repl) = opts.output_mode {
+        if replacement(Some(repl.as_slice()), opts, matcher)?.counts() && !opts.count_per_record {
+            return Err(FrErr::Misc(
+                "$# can't count across the whole input with --threads (try --count-per-line)"
+                    .into(),
+            ));
+        }
+    }
+    for text in [&opts.before_text, &opts.after_text].into_iter().flatten() {
+        if template(Some(text), opts, matcher)?.counts() {
+            return Err(FrErr::Misc(
+                "$# in --before-text or --after-text can't count across the whole input with --threads"
+                    .into(),
+            ));
+        }
+    }
+    // Blocks are cut after the last delimiter in them.
+    let delimiter = match opts.delimiter.as_str() {
+        r"\x00" => 0,
+        _ => b'\n',
+    };
+    // Blocks read but not yet written are limited, so a slow output
+    // doesn't mean the whole input ends up in memory.
+    let most_pending = 2 * opts.threads;
+
+    let (jobs, queue) = mpsc::channel::<(usize, Vec<u8>)>();
+    let queue = Mutex::new(queue);
+    let (results, done) = mpsc::channel::<(usize, Result<(Vec<u8>, bool), String>)>();
+    thread::scope(|scope| {
+        for _ in 0..opts.threads {
+            let (queue, results, args) = (&queue, results.clone(), opts.args.clone());
+            scope.spawn(move || {
+                let setup = Opts::from_args(args).and_then(|opts| {
+                    let matcher = mode_matcher(&opts)?;
+                    Ok((opts, matcher))
+                });
+                let mut setup = setup.map_err(|e| e.to_string());
+                loop {
+                    // The lock is only held while waiting for a block.
+                    let next = queue.lock().map(|queue| queue.recv());
+                    let (n, block) = match next {
+                        Ok(Ok(job)) => job,
+                        _ => break,
+                    };
+                    let res = match setup {
+                        Ok((ref mut opts, Some(ref matcher))) => {
+                            replace_block(opts, block, matcher.as_ref())
+                        }
+                        Ok((_, None)) => unreachable!(),
+                        Err(ref e) => Err(FrErr::Misc(e.clone().into())),
+                    };
+                    if results.send((n, res.map_err(|e| e.to_string()))).is_err() {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(results);
+
+        let mut matched = false;
+        let mut waiting: HashMap<usize, Vec<u8>> = HashMap::new();
+        let (mut sent, mut written) = (0, 0);
+        // Handle a finished block, and write any that are now next in line.
+        let mut handle = |(n, res): (usize, Result<(Vec<u8>, bool), String>),
+                          opts: &mut Opts,
+                          written: &mut usize|
+         -> Result<(), FrErr> {
+            let (output, found) = res.map_err(|e| FrErr::Misc(e.into()))?;
+            matched |= found;
+            waiting.insert(n, output);
+            while let Some(output) = waiting.remove(written) {
+                opts.output.write_all(&output)?;
+                *written += 1;
+            }
+            Ok(())
+        };
+
+        let mut buff: Vec<u8> = Vec::new();
+        for mut input in inputs {
+            let mut eof = false;
+            while !eof {
+                let start = buff.len();
+                buff.resize(start + BLOCK_SIZE, 0);
+                let n_read = input.read(&mut buff[start..])?;
+                buff.truncate(start + n_read);
+                eof = n_read == 0;
+                if buff.len() < BLOCK_SIZE && !eof {
+                    continue;
+                }
+                let end = match buff.iter().rposition(|&b| b == delimiter) {
+                    _ if eof => buff.len(),
+                    Some(n) => n + 1,
+                    // A record longer than a block; keep reading.
+                    None => continue,
+                };
+                if end == 0 {
+                    continue;
+                }
+                let rest = buff.split_off(end);
+                let block = std::mem::replace(&mut buff, rest);
+                // The queue is only gone if the threads are, which they
+                // only are if the results are.
+                if jobs.send((sent, block)).is_err() {
+                    break;
+                }
+                sent += 1;
+                while let Ok(result) = done.try_recv() {
+                    handle(result, opts, &mut written)?;
+                }
+                while sent - written >= most_pending {
+                    match done.recv() {
+                        Ok(result) => handle(result, opts, &mut written)?,
+                        Err(_) => break,
+                    }
+                }
+            }
+        }
+        drop(jobs);
+        for result in done.iter() {
+            handle(result, opts, &mut written)?;
+        }
+        opts.output.flush()?;
+        Ok(matched)
+    })
+}
+
+/*
+Replace in `block` (some whole records of a larger input), returning the
+result, and whether anything matched.
+*/
+fn replace_block(
+    opts: &mut Opts,
+    block: Vec<u8>,
+    matcher: &dyn Matcher,
+) -> Result<(Vec<u8>, bool), FrErr> {
+    let output: Rc<RefCell<Vec<u8>>> = Rc::default();
+    opts.output = Box::new(diff::Capture::new(output.clone()));
+    let inputs = Inputs::opened(Box::new(Cursor::new(block))).mid_stream();
+    let matched = process(opts, inputs, matcher)?;
+    let output = output.take();
+    Ok((output, matched))
+}
+
+/*
+Whether the inputs can be searched where they're mapped into memory (see
+`replace_mapped`): they're to be read as they are, a line at a time, with
+each line replaced on its own.
+*/
+fn mappable(opts: &Opts, paths: &[PathBuf]) -> bool {
+    opts.mmap
+        && matches!(opts.output_mode, OutputMode::Replace(_))
+        && target(opts).is_none()
+        && opts.skip_lines == 0
+        && !(opts.invert || opts.multiline || opts.paragraph || opts.csv || opts.keep_bom)
+        && opts.record_bytes.is_none()
+        && opts.encoding.is_none()
+        && paths
+            .iter()
+            .all(|path| fs::metadata(path).is_ok_and(|meta| meta.is_file()))
+}
+
+/**
+Replace in each of `paths` (plain files) in turn, like `process` does, but
+searching each file where it's mapped into memory, and writing the records
+that don't change straight from there.
+*/
+fn replace_mapped(
+    opts: &mut Opts,
+    paths: &[PathBuf],
+    matcher: &dyn Matcher,
+    failures: &Rc<Cell<usize>>,
+) -> Result<bool, FrErr> {
+    let repl = match opts.output_mode {
+        OutputMode::Replace(ref repl) => replacement(Some(repl.as_slice()), opts, matcher)?,
+        _ => unreachable!(),
+    };
+    let before = opts
+        .before_text
+        .as_deref()
+        .map(|text| template(Some(text), opts, matcher))
+        .transpose()?;
+    let after = opts
+        .after_text
+        .as_deref()
+        .map(|text| template(Some(text), opts, matcher))
+        .transpose()?;
+    let delimiter = Regex::new(&opts.delimiter)?;
+    let newline = opts.newline.clone().unwrap_or_default();
+    let stats = opts.stats.clone().unwrap_or_default();
+    let mut remaining = opts.selection.max_total;
+    let mut found: Vec<Caps> = Vec::new();
+    let mut buff: Vec<u8> = Vec::new();
+    let mut matched = false;
+
+    for (n, path) in paths.iter().enumerate() {
+        if answered(opts, matched) {
+            break;
+        }
+        if opts.skip_binary && input::is_binary(path, opts.encoding).unwrap_or(false) {
+            eprintln!(
+                "{}: binary file skipped (use -a to process it anyway)",
+                path.display()
+            );
+            continue;
+        }
+        let map = match File::open(path).and_then(|file| mmap::Map::new(&file)) {
+            Ok(map) => map,
+            Err(e) => {
+                eprintln!("{}: {}", path.display(), e);
+                failures.set(failures.get() + 1);
+                continue;
+            }
+        };
+        // A compressed file has to be decompressed first, of course.
+        let decompressed: Vec<u8>;
+        let data: &[u8] = match decompress::Format::sniff(&map) {
+            Some(_) => {
+                let mut buff: Vec<u8> = Vec::new();
+                let res = input::open(path, false).and_then(|mut r| r.read_to_end(&mut buff));
+                if let Err(e) = res {
+                    eprintln!("{}: {}", path.display(), e);
+                    failures.set(failures.get() + 1);
+                    continue;
+                }
+                decompressed = buff;
+                &decompressed
+            }
+            None => &map,
+        };
+        stats.files.add(1);
+        stats.bytes_in.add(data.len());
+        let mut rest = data.strip_prefix(bom::BOM).unwrap_or(data);
+        // The last record of the last input only gets a newline if it had
+        // a delimiter (or --final-newline says so).
+        let last = n + 1 == paths.len();
+
+        while !rest.is_empty() && !answered(opts, matched) {
+            let (record, terminated) = match delimiter.find(rest).filter(|m| !m.is_empty()) {
+                Some(m) => {
+                    let record = &rest[..m.start()];
+                    rest = &rest[m.end()..];
+                    (record, true)
+                }
+                None => (std::mem::take(&mut rest), false),
+            };
+            stats.records.add(1);
+            let nl_bytes: &[u8] = match terminated || !last || opts.final_newline {
+                true => &newline,
+                false => &[],
+            };
+            if opts.count_per_record {
+                repl.reset_count();
+            }
+            found.clear();
+            if remaining > 0 && opts.address.selects(record) && matcher.is_match(record) {
+                opts.selection
+                    .find(Matches::new(matcher, record), &mut found);
+                found.truncate(remaining);
+                remaining -= found.len();
+                stats.matches.add(found.len());
+                stats.replacements.add(found.len());
+            }
+            let first = match found.first() {
+                Some(caps) => caps,
+                None => {
+                    opts.output.write_all(record)?;
+                    opts.output.write_all(nl_bytes)?;
+                    continue;
+                }
+            };
+            matched = true;
+            insert_text(&before, record, first, &opts.newline, &mut buff);
+            let mut last_end: usize = 0;
+            for caps in found.iter() {
+                let (start, end) = caps.span();
+                buff.extend_from_slice(&record[last_end..start]);
+                repl.expand(record, caps, &mut buff);
+                last_end = end;
+            }
+            buff.extend_from_slice(&record[last_end..]);
+            buff.extend_from_slice(nl_bytes);
+            insert_text(&after, record, first, &opts.newline, &mut buff);
+            opts.output.write_all(&buff)?;
+            buff.clear();
+        }
+    }
+    opts.output.flush()?;
+    Ok(matched)
+}
+
+/**
+Do everything, returning whether anything matched.
+*/
+fn fresh() -> Result<bool, FrErr> {
+    let started = Instant::now();
+    let mut opts = Opts::new()?;
+    let output = std::mem::replace(&mut opts.output, Box::new(std::io::sink()));
+    opts.output = stats::writer(output, &opts.stats);
+    let failures: Rc<Cell<usize>> = Rc::default();
+    let mut matched = false;
+    let mut paths = opts.inputs.clone();
+    let walk = Walk::new(!opts.skip_binary, &failures)
+        .encoding(opts.encoding)
+        .include(opts.include.clone())
+        .exclude(opts.exclude.clone());
+    for dir in opts.recursive.iter() {
+        walk.files(dir, &mut paths);
+    }
+    let matcher = mode_matcher(&opts)?;
+    let matcher = matcher.as_deref();
+
+    if opts.diff || opts.check {
+        // Each file is edited as if in place, but only the changes (or
+        // just which files would change) are printed.
+        let mut output = std::mem::replace(&mut opts.output, Box::new(std::io::sink()));
+        let mut changed: usize = 0;
+        let check = opts.check;
+        each_file(&mut opts, &paths, matcher, dry_run, |path, res| {
+            match res {
+                Ok(None) => eprintln!(
+                    "{}: binary file skipped (use -a to process it anyway)",
+                    path.display()
+                ),
+                Ok(Some((original, edited, found))) => {
+                    matched |= found;
+                    // As with -W, a file with nothing replaced in it is
+                    // left alone.
+                    if !found || original == edited {
+                        return Ok(());
+                    }
+                    changed += 1;
+                    if check {
+                        writeln!(output, "{}", path.display())?;
+                    } else {
+                        let name = path.display().to_string();
+                        let mut diff: Vec<u8> = Vec::new();
+                        diff::unified(&original, &edited, &name, &name, &mut diff);
+                        output.write_all(&diff)?;
+                    }
+                }
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    failures.set(failures.get() + 1);
+                }
+            }
+            Ok(())
+        })?;
+        output.flush()?;
+        if opts.check {
+            if changed > 0 {
+                eprintln!("{} file(s) would be changed", changed);
+            }
+            // What matters is whether anything would change, not whether
+            // anything matched.
+            matched = changed == 0;
+        }
+    } else if opts.in_place {
+        // Each file is edited on its own, as if it were the only input.
+        each_file(&mut opts, &paths, matcher, edit_in_place, |path, res| {
+            match res {
+                Ok(None) => eprintln!(
+                    "{}: binary file skipped (use -a to edit it anyway)",
+                    path.display()
+                ),
+                Ok(Some(found)) => matched |= found,
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    failures.set(failures.get() + 1);
+                }
+            }
+            Ok(())
+        })?;
+    } else if let Some(template) = opts.output_template.clone() {
+        // Each file is processed on its own, into a file of its own.
+        for path in paths {
+            let mut inputs = Inputs::new(vec![path.clone()], failures.clone())
+                .encoding(opts.encoding)
+                .skip_binary(opts.skip_binary);
+            // Don't create an output file for an input that can't be read.
+            let input = match inputs.next() {
+                Some(input) => input,
+                None => continue,
+            };
+            let out_path = template.expand(&path);
+            let res = create_output(&path, &out_path).and_then(|output| {
+                let output = opt::buffered(output, opts.buffer_size, opts.flush);
+                let output = stats::writer(output, &opts.stats);
+                let output = encoding::writer(output, opts.output_encoding);
+                let keep_bom = opts.keep_bom.then(|| inputs.first_bom());
+                opts.output = bom::writer(output, opts.add_bom, keep_bom);
+                opts.address.reset();
+                let inputs = Inputs::opened(input).named(path.display().to_string());
+                run(&mut opts, inputs, matcher)
+            });
+            let res = res.map(|found| matched |= found);
+            if let Err(e) = res {
+                eprintln!("{}: {}", out_path.display(), e);
+                failures.set(failures.get() + 1);
+            }
+        }
+    } else if matches!(opts.output_mode, OutputMode::Count(_))
+        && paths.len() > 1
+        && opts.archive.is_none()
+    {
+        // Like grep -c, each file gets its own count.
+        for path in paths {
+            let mut inputs = Inputs::new(vec![path.clone()], failures.clone())
+                .encoding(opts.encoding)
+                .skip_binary(opts.skip_binary);
+            let input = match inputs.next() {
+                Some(input) => input,
+                None => continue,
+            };
+            write!(opts.output, "{}:", path.display())?;
+            opts.address.reset();
+            let inputs = Inputs::opened(input).named(path.display().to_string());
+            matched |= run(&mut opts, inputs, matcher)?;
+        }
+    } else if let Some(filter) = opts.archive.clone() {
+        // Each member of each archive is processed on its own, like a file
+        // edited in place.
+        let prefix: Rc<archive::Prefix> = Rc::default();
+        // Formatted matches say where they're from themselves.
+        if opts.format.is_none() {
+            let output = std::mem::replace(&mut opts.output, Box::new(std::io::sink()));
+            opts.output = Box::new(archive::Prefixed::new(output, prefix.clone()));
+        }
+        for path in paths {
+            let members = match input::open(&path, false).and_then(archive::members) {
+                Ok(members) => members,
+                Err(e) => {
+                    eprintln!("{}: {}", path.display(), e);
+                    failures.set(failures.get() + 1);
+                    continue;
+                }
+            };
+            for member in members {
+                let res = member.and_then(|member| {
+                    if !filter.is_match(&member.name) {
+                        return Ok(());
+                    }
+                    let name = format!("{}:{}", path.display(), member.name);
+                    let input = match opts.skip_binary {
+                        true => input::text_only(member.data)?,
+                        false => Some(member.data),
+                    };
+                    let input = match input {
+                        Some(input) => input,
+                        None => {
+                            eprintln!(
+                                "{}: binary file skipped (use -a to process it anyway)",
+                                name
+                            );
+                            return Ok(());
+                        }
+                    };
+                    prefix.set(format!("{}:", name).into_bytes());
+                    opts.address.reset();
+                    let inputs = Inputs::opened(input).named(name).encoding(opts.encoding);
+                    matched |= run(&mut opts, inputs, matcher)
+                        .map_err(|e| std::io::Error::other(format!("{}: {}", member.name, e)))?;
+                    // The next member's output starts on a line of its own,
+                    // even if this one's last line had no newline.
+                    if prefix.mid_line() {
+                        opts.output.write_all(b"\n")?;
+                    }
+                    // One header will do for all the members.
+                    opts.format_header = false;
+                    Ok(())
+                });
+                if let Err(e) = res {
+                    eprintln!("{}: {}", path.display(), e);
+                    failures.set(failures.get() + 1);
+                }
+            }
+        }
+    } else {
+        let inputs = Inputs::new(paths.clone(), failures.clone())
+            .encoding(opts.encoding)
+            .follow(opts.follow)
+            .skip_binary(opts.skip_binary);
+        let output = std::mem::replace(&mut opts.output, Box::new(std::io::sink()));
+        let keep_bom = opts.keep_bom.then(|| inputs.first_bom());
+        opts.output = bom::writer(output, opts.add_bom, keep_bom);
+        matched |= match matcher {
+            Some(matcher) if opts.threads > 1 => pipeline(&mut opts, inputs, matcher)?,
+            Some(matcher) if mappable(&opts, &paths) => {
+                replace_mapped(&mut opts, &paths, matcher, &failures)?
+            }
+            _ => run(&mut opts, inputs, matcher)?,
+        };
+    }
+
+    // With --time, as well as or instead of --stats.
+    if let Some(ref stats) = opts.stats {
+        let json = matches!(opts.format, Some(format::Format::Json));
+        eprint!("{}", stats.report(started.elapsed(), json));
+    }
+
+    match failures.get() {
+        0 => Ok(matched),
+        // As with grep, finding a match is all that matters when quiet.
+        _ if opts.quiet && matched => Ok(true),
+        n => Err(FrErr::Misc(
+            format!("{} input file(s) couldn't be processed", n).into(),
+        )),
+    }
+}
+
+/**
+Exit like grep does: 0 if anything matched, 1 if nothing did, and 2 if
+there was an error.
+*/
+fn main() -> ExitCode {
+    match fresh() {
+        Ok(true) => ExitCode::SUCCESS,
+        Ok(false) => ExitCode::from(1),
+        Err(e) => {
+            eprintln!("error: {}", e);
+            ExitCode::from(2)
+        }
+    }
 }
@@ -0,0 +1,579 @@
+/*!
+Finding matches in records.
+
+Both regex and verbatim matching feed the same processing loop; the
+[`Matcher`] trait is the interface between that loop and the various
+ways of looking for a pattern.
+*/
+use std::rc::Rc;
+
+use aho_corasick::{AhoCorasick, Input, MatchKind};
+use memchr::memmem::Finder;
+use regex::bytes::{Regex, RegexSet};
+
+use crate::{stats::Stats, FrErr};
+
+/// Locations of a match and of its capture groups.
+#[derive(Clone, Debug, Default)]
+pub struct Caps {
+    spans: Vec<Option<(usize, usize)>>,
+    pattern: usize,
+}
+
+impl Caps {
+    /// Caps describing a single match (with no capture groups) that
+    /// spans `start..end`.
+    pub fn whole(start: usize, end: usize) -> Caps {
+        Caps {
+            spans: vec![Some((start, end))],
+            pattern: 0,
+        }
+    }
+
+    /// Index of the pattern (when there's more than one) that matched.
+    pub fn pattern(&self) -> usize {
+        self.pattern
+    }
+
+    /// Start and end of the entire match.
+    pub fn span(&self) -> (usize, usize) {
+        self.spans[0].unwrap_or((0, 0))
+    }
+
+    /// How many capture groups there are, counting the whole match as
+    /// group 0.
+    pub fn n_groups(&self) -> usize {
+        self.spans.len()
+    }
+
+    /// Start and end of capture group `n`, if it participated in the match.
+    pub fn get(&self, n: usize) -> Option<(usize, usize)> {
+        self.spans.get(n).copied().flatten()
+    }
+
+    /// Set the spans of the capture groups of a match of the first
+    /// pattern, starting with the whole match.
+    #[cfg(any(feature = "fancy", feature = "pcre2"))]
+    pub fn set_groups(&mut self, spans: impl IntoIterator<Item = Option<(usize, usize)>>) {
+        self.spans.clear();
+        self.spans.extend(spans);
+        self.pattern = 0;
+    }
+
+    fn set_whole(&mut self, start: usize, end: usize) {
+        self.spans.clear();
+        self.spans.push(Some((start, end)));
+        self.pattern = 0;
+    }
+}
+
+pub trait Matcher {
+    /**
+    Look for a match in `haystack` that begins at or after `start`. If one
+    is found, its location (and the locations of any capture groups) are
+    written to `caps`.
+    */
+    fn find_at(&self, haystack: &[u8], start: usize, caps: &mut Caps) -> bool;
+
+    /// Return the number of patterns being searched for.
+    fn n_patterns(&self) -> usize {
+        1
+    }
+
+    /// Return the names of the capture groups in the given pattern, in
+    /// order, starting with the whole match; unnamed groups are `None`.
+    fn group_names(&self, _pattern: usize) -> Vec<Option<&str>> {
+        vec![None]
+    }
+
+    /// Return the index of the capture group in the given pattern with the
+    /// given name.
+    fn group_index(&self, pattern: usize, name: &str) -> Option<usize> {
+        self.group_names(pattern)
+            .iter()
+            .position(|n| *n == Some(name))
+    }
+
+    /// Return whether the pattern matches anywhere in `haystack`.
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.find_at(haystack, 0, &mut Caps::default())
+    }
+}
+
+/**
+Iterates over successive non-overlapping matches in a haystack.
+
+Empty matches are handled the same way the `regex` crate's iterators
+handle them: an empty match is never reported immediately after the end
+of the previous match.
+*/
+pub struct Matches<'a> {
+    matcher: &'a dyn Matcher,
+    haystack: &'a [u8],
+    start: usize,
+    last_end: Option<usize>,
+    overlapping: bool,
+}
+
+impl<'a> Matches<'a> {
+    pub fn new(matcher: &'a dyn Matcher, haystack: &'a [u8]) -> Self {
+        Matches {
+            matcher,
+            haystack,
+            start: 0,
+            last_end: None,
+            overlapping: false,
+        }
+    }
+
+    /**
+    Builder-pattern method for finding overlapping matches: each search
+    after the first starts one byte past the start of the previous match
+    instead of at its end.
+    */
+    pub fn overlapping(mut self, yes: bool) -> Self {
+        self.overlapping = yes;
+        self
+    }
+
+    /// Find the next match, writing its location to `caps`.
+    pub fn find_next(&mut self, caps: &mut Caps) -> bool {
+        while self.start <= self.haystack.len() {
+            if !self.matcher.find_at(self.haystack, self.start, caps) {
+                self.start = self.haystack.len() + 1;
+                return false;
+            }
+
+            let (start, end) = caps.span();
+            if self.overlapping {
+                self.start = start + 1;
+            } else if start == end {
+                self.start = end + 1;
+                if self.last_end == Some(end) {
+                    continue;
+                }
+            } else {
+                self.start = end;
+            }
+            self.last_end = Some(end);
+            return true;
+        }
+
+        false
+    }
+}
+
+pub struct RegexMatcher {
+    re: Regex,
+    /// A literal every match contains, to look for first.
+    literal: Option<Finder<'static>>,
+}
+
+impl RegexMatcher {
+    pub fn new(re: Regex) -> Self {
+        RegexMatcher { re, literal: None }
+    }
+
+    /// Skip searching haystacks that don't contain `literal`, which every
+    /// match of the regex must.
+    pub fn prefilter(mut self, literal: Option<Vec<u8>>) -> Self {
+        self.literal = literal.map(|lit| Finder::new(&lit).into_owned());
+        self
+    }
+
+    /* Whether `haystack` could have a match in it at all. */
+    fn could_match(&self, haystack: &[u8]) -> bool {
+        match self.literal {
+            Some(ref finder) => finder.find(haystack).is_some(),
+            None => true,
+        }
+    }
+}
+
+/*
+Search for `re` in `haystack` starting at `start`, writing the result
+to `caps`.
+*/
+fn regex_find_at(re: &Regex, haystack: &[u8], start: usize, caps: &mut Caps) -> bool {
+    match re.captures_at(haystack, start) {
+        Some(c) => {
+            caps.spans.clear();
+            caps.spans
+                .extend(c.iter().map(|m| m.map(|m| (m.start(), m.end()))));
+            caps.pattern = 0;
+            true
+        }
+        None => false,
+    }
+}
+
+impl Matcher for RegexMatcher {
+    fn find_at(&self, haystack: &[u8], start: usize, caps: &mut Caps) -> bool {
+        // Only the first search of a haystack is worth filtering; after
+        // that, there's been a match.
+        if start == 0 && !self.could_match(haystack) {
+            return false;
+        }
+        regex_find_at(&self.re, haystack, start, caps)
+    }
+
+    fn group_names(&self, _pattern: usize) -> Vec<Option<&str>> {
+        self.re.capture_names().collect()
+    }
+
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.could_match(haystack) && self.re.is_match(haystack)
+    }
+}
+
+/*
+Of several candidate matches, keep the leftmost; in case of a tie, keep
+the one found first.
+*/
+fn keep_leftmost(best: &mut Option<Caps>, these: Caps) {
+    match best {
+        Some(ref b) if b.span().0 <= these.span().0 => {}
+        _ => *best = Some(these),
+    }
+}
+
+fn take_best(best: Option<Caps>, caps: &mut Caps) -> bool {
+    match best {
+        Some(b) => {
+            *caps = b;
+            true
+        }
+        None => false,
+    }
+}
+
+/**
+Several regexes at once. At each point, the leftmost match of any of them
+is found; if more than one match starts at the same place, the pattern
+given first wins. Capture groups are numbered separately for each
+pattern.
+*/
+pub struct MultiRegex {
+    set: RegexSet,
+    regexes: Vec<Regex>,
+}
+
+impl MultiRegex {
+    /// `set` should be built from the same patterns (with the same
+    /// options) as `regexes`.
+    pub fn new(set: RegexSet, regexes: Vec<Regex>) -> Self {
+        MultiRegex { set, regexes }
+    }
+}
+
+impl Matcher for MultiRegex {
+    fn find_at(&self, haystack: &[u8], start: usize, caps: &mut Caps) -> bool {
+        let mut best: Option<Caps> = None;
+        for n in self.set.matches_at(haystack, start).iter() {
+            let mut these = Caps::default();
+            if regex_find_at(&self.regexes[n], haystack, start, &mut these) {
+                these.pattern = n;
+                keep_leftmost(&mut best, these);
+            }
+        }
+
+        take_best(best, caps)
+    }
+
+    fn n_patterns(&self) -> usize {
+        self.regexes.len()
+    }
+
+    fn group_names(&self, pattern: usize) -> Vec<Option<&str>> {
+        self.regexes[pattern].capture_names().collect()
+    }
+
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.set.is_match(haystack)
+    }
+}
+
+/**
+Decode the UTF-8 character at the beginning of `bytes`, returning it along
+with its encoded length. Returns `None` if `bytes` is empty or doesn't
+start with a valid UTF-8 sequence.
+*/
+pub fn decode_char(bytes: &[u8]) -> Option<(char, usize)> {
+    let len = match bytes.first()? {
+        0x00..=0x7f => 1,
+        0xc0..=0xdf => 2,
+        0xe0..=0xef => 3,
+        0xf0..=0xf7 => 4,
+        _ => return None,
+    };
+    let c = std::str::from_utf8(bytes.get(..len)?)
+        .ok()?
+        .chars()
+        .next()?;
+    Some((c, len))
+}
+
+/// Compare two characters by their lowercase forms.
+pub fn chars_eq_nocase(a: char, b: char) -> bool {
+    a == b || a.to_lowercase().eq(b.to_lowercase())
+}
+
+/*
+If `haystack` begins with something that case-insensitively matches
+`needle`, return the length of that something.
+*/
+fn nocase_prefix_len(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    let mut pos: usize = 0;
+    let mut npos: usize = 0;
+    while npos < needle.len() {
+        match decode_char(&needle[npos..]) {
+            Some((nc, nlen)) => {
+                let (hc, len) = decode_char(&haystack[pos..])?;
+                if !chars_eq_nocase(hc, nc) {
+                    return None;
+                }
+                pos += len;
+                npos += nlen;
+            }
+            // Bytes in the needle that aren't valid UTF-8 must match exactly.
+            None => {
+                if haystack.get(pos) != Some(&needle[npos]) {
+                    return None;
+                }
+                pos += 1;
+                npos += 1;
+            }
+        }
+    }
+    Some(pos)
+}
+
+/**
+Case-insensitive version of `Finder::find()`. Characters are compared by
+their lowercase forms, which may differ in encoded length, so this
+returns the start _and_ end of the match. Bytes that aren't valid UTF-8
+never match.
+*/
+fn find_subslice_nocase(haystack: &[u8], needle: &[u8]) -> Option<(usize, usize)> {
+    for n in 0..=haystack.len() {
+        if let Some(len) = nocase_prefix_len(&haystack[n..], needle) {
+            return Some((n, n + len));
+        }
+    }
+
+    None
+}
+
+/// Simple verbatim string matching.
+pub struct Verbatim {
+    pattern: Vec<u8>,
+    /// Searches for `pattern` (when case matters), built once.
+    finder: Finder<'static>,
+    ignore_case: bool,
+    anchor_start: bool,
+    anchor_end: bool,
+}
+
+impl Verbatim {
+    pub fn new(pattern: Vec<u8>) -> Self {
+        Verbatim {
+            finder: Finder::new(&pattern).into_owned(),
+            pattern,
+            ignore_case: false,
+            anchor_start: false,
+            anchor_end: false,
+        }
+    }
+
+    /// Builder-pattern method for matching without regard to case.
+    pub fn ignore_case(mut self, yes: bool) -> Self {
+        self.ignore_case = yes;
+        self
+    }
+
+    /// Builder-pattern method for only matching at the start of haystacks.
+    pub fn anchor_start(mut self, yes: bool) -> Self {
+        self.anchor_start = yes;
+        self
+    }
+
+    /// Builder-pattern method for only matching at the end of haystacks.
+    pub fn anchor_end(mut self, yes: bool) -> Self {
+        self.anchor_end = yes;
+        self
+    }
+
+    /*
+    Return the length of the match if `haystack` starts with the pattern.
+    */
+    fn prefix_match(&self, haystack: &[u8]) -> Option<usize> {
+        let patt = &self.pattern[..];
+        if self.ignore_case {
+            nocase_prefix_len(haystack, &self.pattern)
+        } else if haystack.starts_with(patt) {
+            Some(patt.len())
+        } else {
+            None
+        }
+    }
+
+    /*
+    Return the start of the match if the pattern occurs at the end of
+    `haystack`, no earlier than `start`.
+    */
+    fn suffix_match(&self, haystack: &[u8], start: usize) -> Option<usize> {
+        if self.ignore_case {
+            // Lowercase forms may differ in length, so the start of the
+            // match can't be worked out from the length of the pattern.
+            (start..=haystack.len())
+                .find(|&n| self.prefix_match(&haystack[n..]) == Some(haystack.len() - n))
+        } else {
+            let n = haystack.len().checked_sub(self.pattern.len())?;
+            (n >= start && haystack.ends_with(&self.pattern)).then_some(n)
+        }
+    }
+}
+
+impl Matcher for Verbatim {
+    fn find_at(&self, haystack: &[u8], start: usize, caps: &mut Caps) -> bool {
+        let found = match (self.anchor_start, self.anchor_end) {
+            (true, _) if start > 0 => None,
+            (true, false) => self.prefix_match(haystack).map(|end| (0, end)),
+            (true, true) => self
+                .prefix_match(haystack)
+                .filter(|&end| end == haystack.len())
+                .map(|end| (0, end)),
+            (false, true) => self
+                .suffix_match(haystack, start)
+                .map(|n| (n, haystack.len())),
+            (false, false) => {
+                let subslice = &haystack[start..];
+                let found = if self.ignore_case {
+                    find_subslice_nocase(subslice, &self.pattern)
+                } else {
+                    let len = self.pattern.len();
+                    self.finder.find(subslice).map(|n| (n, n + len))
+                };
+                found.map(|(m_start, m_end)| (start + m_start, start + m_end))
+            }
+        };
+
+        match found {
+            Some((m_start, m_end)) => {
+                caps.set_whole(m_start, m_end);
+                true
+            }
+            None => false,
+        }
+    }
+}
+
+/**
+Several verbatim strings at once, using the
+[Aho-Corasick](https://docs.rs/aho-corasick/latest/aho_corasick/)
+algorithm. Case-insensitive matching here only folds ASCII letters.
+*/
+pub struct VerbatimSet {
+    ac: AhoCorasick,
+}
+
+impl VerbatimSet {
+    pub fn new(patterns: &[Vec<u8>], ignore_case: bool) -> Result<Self, FrErr> {
+        let ac = AhoCorasick::builder()
+            .match_kind(MatchKind::LeftmostFirst)
+            .ascii_case_insensitive(ignore_case)
+            .build(patterns)?;
+        Ok(VerbatimSet { ac })
+    }
+}
+
+impl Matcher for VerbatimSet {
+    fn find_at(&self, haystack: &[u8], start: usize, caps: &mut Caps) -> bool {
+        match self.ac.find(Input::new(haystack).range(start..)) {
+            Some(m) => {
+                caps.set_whole(m.start(), m.end());
+                caps.pattern = m.pattern().as_usize();
+                true
+            }
+            None => false,
+        }
+    }
+
+    fn n_patterns(&self) -> usize {
+        self.ac.patterns_len()
+    }
+}
+
+/**
+Any of several `Matcher`s, for combinations that the more specialized
+multiple-pattern `Matcher`s can't handle. The leftmost match of any of
+them is found; ties go to the one listed first.
+*/
+pub struct AnyOf {
+    matchers: Vec<Box<dyn Matcher>>,
+}
+
+impl AnyOf {
+    pub fn new(matchers: Vec<Box<dyn Matcher>>) -> Self {
+        AnyOf { matchers }
+    }
+}
+
+impl Matcher for AnyOf {
+    fn find_at(&self, haystack: &[u8], start: usize, caps: &mut Caps) -> bool {
+        let mut best: Option<Caps> = None;
+        for (n, m) in self.matchers.iter().enumerate() {
+            let mut these = Caps::default();
+            if m.find_at(haystack, start, &mut these) {
+                these.pattern = n;
+                keep_leftmost(&mut best, these);
+            }
+        }
+
+        take_best(best, caps)
+    }
+
+    fn n_patterns(&self) -> usize {
+        self.matchers.len()
+    }
+
+    fn group_names(&self, pattern: usize) -> Vec<Option<&str>> {
+        self.matchers[pattern].group_names(0)
+    }
+}
+
+/// Another `Matcher`, timed, for `--time`.
+pub struct Timed {
+    inner: Box<dyn Matcher>,
+    stats: Rc<Stats>,
+}
+
+impl Timed {
+    pub fn new(inner: Box<dyn Matcher>, stats: Rc<Stats>) -> Self {
+        Timed { inner, stats }
+    }
+}
+
+impl Matcher for Timed {
+    fn find_at(&self, haystack: &[u8], start: usize, caps: &mut Caps) -> bool {
+        self.stats
+            .matching
+            .time(|| self.inner.find_at(haystack, start, caps))
+    }
+
+    fn n_patterns(&self) -> usize {
+        self.inner.n_patterns()
+    }
+
+    fn group_names(&self, pattern: usize) -> Vec<Option<&str>> {
+        self.inner.group_names(pattern)
+    }
+
+    fn group_index(&self, pattern: usize, name: &str) -> Option<usize> {
+        self.inner.group_index(pattern, name)
+    }
+
+    fn is_match(&self, haystack: &[u8]) -> bool {
+        self.stats.matching.time(|| self.inner.is_match(haystack))
+    }
+}
@@ -0,0 +1,122 @@
+/*!
+Memory-mapped input files, for `--mmap`.
+
+A mapped file can be searched where it lies, without being copied into
+buffers first. The mapping is read-only and private, but the file mustn't
+change while it's mapped: if it's truncated, reading the part that's gone
+can kill the process.
+
+Without the `mmap` feature (or off Unix), the file is just read into
+memory instead.
+*/
+use std::{fs::File, io, ops::Deref};
+
+#[cfg(all(unix, feature = "mmap"))]
+mod sys {
+    use std::ffi::{c_int, c_void};
+
+    pub const PROT_READ: c_int = 1;
+    pub const MAP_PRIVATE: c_int = 2;
+    pub const MAP_FAILED: *mut c_void = !0 as *mut c_void;
+
+    extern "C" {
+        // The offset is an off_t, which is as wide as a pointer on the
+        // platforms this is built for (and is always 0 here anyway).
+        pub fn mmap(
+            addr: *mut c_void,
+            len: usize,
+            prot: c_int,
+            flags: c_int,
+            fd: c_int,
+            offset: isize,
+        ) -> *mut c_void;
+        pub fn munmap(addr: *mut c_void, len: usize) -> c_int;
+    }
+}
+
+/// The contents of a file, mapped into memory.
+pub struct Map {
+    #[cfg(all(unix, feature = "mmap"))]
+    ptr: *mut std::ffi::c_void,
+    #[cfg(all(unix, feature = "mmap"))]
+    len: usize,
+    #[cfg(not(all(unix, feature = "mmap")))]
+    data: Vec<u8>,
+}
+
+impl Map {
+    /// Map all of `file` (which must be a regular file).
+    #[cfg(all(unix, feature = "mmap"))]
+    pub fn new(file: &File) -> io::Result<Map> {
+        use std::os::unix::io::AsRawFd;
+
+        let len = usize::try_from(file.metadata()?.len())
+            .map_err(|_| io::Error::other("too big to map"))?;
+        // An empty mapping isn't allowed, but there's nothing to map anyway.
+        if len == 0 {
+            return Ok(Map {
+                ptr: std::ptr::null_mut(),
+                len,
+            });
+        }
+        // SAFETY: this maps `len` bytes of an open file read-only, and
+        // checks whether that worked. The mapping outlives the file
+        // descriptor, and is unmapped when the Map is dropped.
+        let ptr = unsafe {
+            sys::mmap(
+                std::ptr::null_mut(),
+                len,
+                sys::PROT_READ,
+                sys::MAP_PRIVATE,
+                file.as_raw_fd(),
+                0,
+            )
+        };
+        if ptr == sys::MAP_FAILED {
+            return Err(io::Error::last_os_error());
+        }
+        Ok(Map { ptr, len })
+    }
+
+    /// Read all of `file` into memory.
+    #[cfg(not(all(unix, feature = "mmap")))]
+    pub fn new(file: &File) -> io::Result<Map> {
+        use std::io::Read;
+
+        let mut data: Vec<u8> = Vec::new();
+        (&*file).read_to_end(&mut data)?;
+        Ok(Map { data })
+    }
+}
+
+impl Deref for Map {
+    type Target = [u8];
+
+    #[cfg(all(unix, feature = "mmap"))]
+    fn deref(&self) -> &[u8] {
+        if self.len == 0 {
+            return &[];
+        }
+        // SAFETY: `ptr` is the start of a readable mapping `len` bytes long,
+        // which lasts as long as `self` does.
+        unsafe { std::slice::from_raw_parts(self.ptr as *const u8, self.len) }
+    }
+
+    #[cfg(not(all(unix, feature = "mmap")))]
+    fn deref(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+#[cfg(all(unix, feature = "mmap"))]
+impl Drop for Map {
+    fn drop(&mut self) {
+        if self.len > 0 {
+            // SAFETY: this is the mapping made in `new`, and nothing
+            // borrowed from it can outlive `self`.
+            unsafe {
+                sys::munmap(self.ptr, self.len);
+            }
+        }
+    }
+}
@@ -1,135 +1,1622 @@
-/*!
-Parsing command-line options.
-*/
-use std::{
-    fs::File,
-    io::{Read, Write},
-    path::PathBuf,
-};
-
-use clap::Parser;
-
-use crate::FrErr;
-
-#[cfg(not(windows))]
-static NEWLINE: &str = "\n";
-#[cfg(windows)]
-static NEWLINE: &str = "\r\n";
-
-static DEFAULT_REGEX_EXTRACT: &str = "$0";
-
-#[derive(Clone, Debug)]
-pub enum OutputMode {
-    Replace(String),
-    Extract(String),
-}
-
-#[derive(Clone, Copy, Debug)]
-pub enum MatchMode {
-    Regex,
-    Verbatim,
-}
-
-#[derive(Debug, Parser)]
-#[command(author, version, about)]
-struct CliOpts {
-    /// Pattern to find.
-    pattern: String,
-
-    /// Optional replacement.
-    replace: Option<String>,
-
-    /// Maximum number of replacements per line (default is all).
-    #[arg(short, long, value_name = "N")]
-    max: Option<usize>,
-
-    /// Print only found pattern (default is print everything).
-    #[arg(short = 'x', long = "extract")]
-    extract: bool,
-
-    /// Do simple verbatim string matching (default is regex matching).
-    #[arg(short, long)]
-    simple: bool,
-
-    /// Delimiter to separate "lines".
-    #[arg(short, long, value_name = "PATT",
-        default_value_t = String::from(r#"\r?\n"#))]
-    delimiter: String,
-
-    /// Print something other than a newline between chunks.
-    #[arg(short, long, value_name = "NL")]
-    newline: Option<Option<String>>,
-
-    /// Input file (default is stdin).
-    #[arg(short, long)]
-    input: Option<PathBuf>,
-
-    /// Output file (default is stdout).
-    #[arg(short, long)]
-    output: Option<PathBuf>,
-}
-
-pub struct Opts {
-    pub pattern: String,
-    pub max: usize,
-    pub output_mode: OutputMode,
-    pub match_mode: MatchMode,
-    pub delimiter: String,
-    pub newline: Option<Vec<u8>>,
-    pub input: Box<dyn Read>,
-    pub output: Box<dyn Write>,
-}
-
-impl Opts {
-    pub fn new() -> Result<Self, FrErr> {
-        let clio = CliOpts::parse();
-
-        let max = clio.max.unwrap_or(usize::MAX);
-
-        let output_mode = match (clio.extract, clio.replace) {
-            (_, None) => {
-                if clio.simple {
-                    OutputMode::Extract(clio.pattern.clone())
-                } else {
-                    OutputMode::Extract(DEFAULT_REGEX_EXTRACT.into())
-                }
-            }
-            (true, Some(repl)) => OutputMode::Extract(repl),
-            (false, Some(repl)) => OutputMode::Replace(repl),
-        };
-
-        let match_mode = if clio.simple {
-            MatchMode::Verbatim
-        } else {
-            MatchMode::Regex
-        };
-
-        let input: Box<dyn Read> = match clio.input {
-            Some(pbuf) => Box::new(File::open(pbuf)?),
-            None => Box::new(std::io::stdin().lock()),
-        };
-        let output: Box<dyn Write> = match clio.output {
-            Some(pbuf) => Box::new(File::create(pbuf)?),
-            None => Box::new(std::io::stdout().lock()),
-        };
-        let newline = match clio.newline {
-            // If the argument is absent, just use a newline sequence.
-            None => Some(Vec::from(NEWLINE)),
-            // If the argument is present but has no value, make it none.
-            Some(None) => None,
-            // If the argument is present and has a value, use that.
-            Some(Some(s)) => Some(Vec::from(s)),
-        };
-
-        Ok(Opts {
-            pattern: clio.pattern,
-            delimiter: clio.delimiter,
-            newline,
-            max,
-            output_mode,
-            match_mode,
-            input,
-            output,
-        })
-    }
-}
+/*!
+Parsing command-line options.
+*/
+use std::{
+    fmt::Display,
+    fs::{File, OpenOptions},
+    io::{self, BufWriter, IsTerminal, Read, Write},
+    path::{Path, PathBuf},
+    rc::Rc,
+    str::FromStr,
+};
+
+use clap::{error::ErrorKind, CommandFactory, Parser};
+
+use regex::bytes::{Regex, RegexBuilder};
+
+use crate::{
+    addr::{Address, LineRange},
+    csv::Column,
+    encoding::{self, Encoding},
+    escape::{decode_hex, escape_dollars, unescape},
+    format::{Format, MatchTemplate},
+    input::STDIN,
+    outpath::PathTemplate,
+    select::Selection,
+    stats::Stats,
+    tee::Tee,
+    template::Table,
+    transform::Transform,
+    translate::Translation,
+    walk::path_filter,
+    FrErr,
+};
+
+#[cfg(not(windows))]
+static NEWLINE: &str = "\n";
+#[cfg(windows)]
+static NEWLINE: &str = "\r\n";
+
+static DEFAULT_DELIMITER: &str = r"\r?\n";
+
+/// Delimits records in paragraph mode: a line break followed by one or
+/// more blank (or all-whitespace) lines.
+static PARAGRAPH_DELIMITER: &str = r"\r?\n(?:[ \t]*\r?\n)+";
+
+#[derive(Clone, Debug)]
+pub enum OutputMode {
+    Replace(Vec<u8>),
+    /// Print only matches, replaced with the given text, or the matched
+    /// text itself if `None`.
+    Extract(Option<Vec<u8>>),
+    /// Print only records that _don't_ match.
+    Delete,
+    /// Transliterate characters throughout the input; no matching at all.
+    Translate(Translation),
+    /// Replace each of a number of verbatim patterns with its own
+    /// replacement throughout the input; records are ignored.
+    Pairs(Vec<(String, String)>),
+    /// Exchange every occurrence of one verbatim string for the other.
+    Swap(Vec<u8>, Vec<u8>),
+    /// Print only how many records match, or (if `true`) how many matches
+    /// there are.
+    Count(bool),
+}
+
+/// When the output is written out of its buffer.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Flush {
+    /// When the buffer's full (or, on a terminal, at each line break).
+    Blocks,
+    /// After each record (or more often).
+    Records,
+    /// Straight away, because there's no buffer at all.
+    Always,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub enum MatchMode {
+    Regex,
+    Verbatim,
+    Glob,
+}
+
+/// What compiles and runs regexes.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Engine {
+    /// The `regex` crate.
+    Regex,
+    /// The `regex` crate, or (for what it can't do) `fancy::Fancy`.
+    Fancy,
+    /// PCRE2 (see `pcre2::Pcre2`).
+    Pcre2,
+}
+
+impl FromStr for Engine {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "regex" => Ok(Engine::Regex),
+            "fancy" if cfg!(feature = "fancy") => Ok(Engine::Fancy),
+            "fancy" => Err("fresh was built without the \"fancy\" feature".into()),
+            "pcre2" if cfg!(feature = "pcre2") => Ok(Engine::Pcre2),
+            "pcre2" => Err("fresh was built without the \"pcre2\" feature".into()),
+            _ => Err(format!(
+                "unknown engine {:?}; known engines are regex, fancy, and pcre2",
+                s
+            )),
+        }
+    }
+}
+
+/// Flags applied when compiling regexes, as with inline `(?msx-u)` groups.
+#[derive(Clone, Copy, Debug)]
+pub struct RegexFlags {
+    pub multi_line: bool,
+    pub dot_matches_new_line: bool,
+    pub ignore_whitespace: bool,
+    pub unicode: bool,
+}
+
+#[derive(Clone, Debug, Parser)]
+#[command(author, version, about)]
+struct CliOpts {
+    /// Pattern to find.
+    #[arg(required_unless_present_any = [
+        "regexp", "file", "pattern_hex", "translate", "pairs", "swap"
+    ])]
+    pattern: Option<String>,
+
+    /// Optional replacement.
+    replace: Option<String>,
+
+    /// Input files or http:// URLs, processed in turn (default is stdin,
+    /// as is -). Gzipped input is decompressed; bzip2, xz, and zstd input
+    /// isn't supported, and neither are https:// URLs.
+    #[arg(value_name = "FILE")]
+    files: Vec<PathBuf>,
+
+    /// Process every file under DIR (after any FILEs), skipping hidden
+    /// files, binary files, and anything matched by a .gitignore under DIR
+    /// (but not .git/info/exclude, global excludes, or .ignore files); may
+    /// be given more than once.
+    #[arg(short, long, value_name = "DIR")]
+    recursive: Vec<PathBuf>,
+
+    /// Also process the files listed in LIST (- is stdin), one per line,
+    /// or NUL-separated (as from `find -print0`), after any FILEs.
+    #[arg(long, value_name = "LIST")]
+    files_from: Option<PathBuf>,
+
+    /// Process files that look binary (having a NUL byte near the start)
+    /// as text, instead of skipping them.
+    #[arg(short = 'a', long)]
+    text: bool,
+
+    /// Process files that look binary as raw bytes: patterns aren't
+    /// Unicode-aware (as with --no-unicode), and records are split on, and
+    /// joined with, bare newlines.
+    #[arg(long, conflicts_with = "text")]
+    binary: bool,
+
+    /// Only process files found by -r that match this glob (like
+    /// '*.rs'); may be given more than once.
+    #[arg(long, value_name = "GLOB", requires = "recursive")]
+    include: Vec<String>,
+
+    /// Skip files (and directories) found by -r that match this glob
+    /// (like 'target/**'), even if they match an --include glob; may be
+    /// given more than once.
+    #[arg(long, value_name = "GLOB", requires = "recursive")]
+    exclude: Vec<String>,
+
+    /// Pattern to find; may be given more than once (replacement is then
+    /// the first positional argument).
+    #[arg(short = 'e', long, value_name = "PATTERN")]
+    regexp: Vec<String>,
+
+    /// Read patterns from a file, one per line (blank lines are ignored);
+    /// works like -e.
+    #[arg(short = 'f', long, value_name = "FILE")]
+    file: Option<PathBuf>,
+
+    /// Replace ${ENV:NAME} in replacements with the value of the
+    /// environment variable NAME.
+    #[arg(long)]
+    expand_env: bool,
+
+    /// Make {{now}} in replacements the time of each replacement, rather
+    /// than the time fresh started.
+    #[arg(long)]
+    time_per_match: bool,
+
+    /// Prepend SALT to matched text before hashing it for {{sha256}} or
+    /// {{md5}} in replacements.
+    #[arg(long, value_name = "SALT", default_value = "")]
+    hash_salt: String,
+
+    /// Restart the $# replacement counter at 1 on each line.
+    #[arg(long)]
+    count_per_line: bool,
+
+    /// Transform the text that replaces (or is extracted for) each match:
+    /// base64enc, base64dec, urlenc, urldec, htmlescape, htmlunescape,
+    /// trim, squeeze (runs of whitespace to one space), collapse-ws (trim
+    /// and squeeze), or jsonescape.
+    /// May be given more than once; transforms are applied in order.
+    #[arg(long, value_name = "NAME")]
+    transform: Vec<Transform>,
+
+    /// Make each replacement follow the case of the text it replaces
+    /// (all uppercase, all lowercase, or capitalized).
+    #[arg(long)]
+    preserve_case: bool,
+
+    /// Pattern to find, given as hex-encoded bytes; works like -e, but
+    /// implies -s.
+    #[arg(long, value_name = "HEX", conflicts_with = "glob")]
+    pattern_hex: Vec<String>,
+
+    /// Replacement, given as hex-encoded bytes.
+    #[arg(long, value_name = "HEX", conflicts_with = "delete")]
+    replace_hex: Option<String>,
+
+    /// Insert the replacement exactly as given, without expanding $1,
+    /// {{...}}, or any other template syntax.
+    #[arg(short = 'R', long, conflicts_with = "delete")]
+    literal_replacement: bool,
+
+    /// When extracting, prefix each match with LABEL: to show which
+    /// pattern it matched; give one per pattern, in order.
+    #[arg(long, value_name = "LABEL")]
+    label: Vec<String>,
+
+    /// Maximum number of replacements per line (default is all).
+    #[arg(short, long, value_name = "N")]
+    max: Option<usize>,
+
+    /// Maximum number of matches in the entire input (default is all).
+    #[arg(long, value_name = "N")]
+    max_total: Option<usize>,
+
+    /// Leave the first N matches in each line alone.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    skip: usize,
+
+    /// Only operate on the Nth match in each line; takes a comma-separated
+    /// list, or may be given more than once.
+    #[arg(long, value_name = "N", value_delimiter = ',')]
+    nth: Vec<usize>,
+
+    /// Only operate on the last N matches in each line.
+    #[arg(long, value_name = "N")]
+    last: Option<usize>,
+
+    /// Print only found pattern (default is print everything).
+    #[arg(short = 'x', long = "extract")]
+    extract: bool,
+
+    /// Delete lines that match.
+    #[arg(short = 'D', long, conflicts_with = "extract")]
+    delete: bool,
+
+    /// Print only the number of records that match (for each input file,
+    /// if there are several).
+    #[arg(
+        short = 'c',
+        long,
+        conflicts_with_all = ["extract", "delete", "write", "output_template"]
+    )]
+    count: bool,
+
+    /// Print only the number of matches, rather than of records that
+    /// match.
+    #[arg(
+        long,
+        conflicts_with_all = ["extract", "delete", "write", "output_template", "count"]
+    )]
+    count_matches: bool,
+
+    /// Print each match as a line of JSON, with the file and record it's
+    /// in, its byte offsets, and its named capture groups; the same as
+    /// --format json.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "format", "output_format", "delete", "count", "count_matches", "invert", "label",
+            "before_text", "after_text", "line_number", "byte_offset", "after_context",
+            "before_context", "context", "lookup",
+        ]
+    )]
+    json: bool,
+
+    /// Print each match in the given format: json, csv or tsv (with a
+    /// column for each capture group), grep (file:line:column:record, for
+    /// editors' quickfix lists), or github (Actions annotations).
+    #[arg(
+        long,
+        value_name = "FORMAT",
+        conflicts_with_all = [
+            "output_format", "delete", "count", "count_matches", "invert", "label", "before_text",
+            "after_text", "line_number", "byte_offset", "after_context",
+            "before_context", "context", "lookup",
+        ]
+    )]
+    format: Option<Format>,
+
+    /// With --format csv or tsv, start with a row of the capture groups'
+    /// names (or numbers, for unnamed ones).
+    #[arg(long)]
+    format_header: bool,
+
+    /// Print each match according to FMT, in which {file}, {line},
+    /// {offset}, {column}, {match}, and {N} or {name} (for a capture group)
+    /// are replaced with what they say.
+    #[arg(
+        long,
+        value_name = "FMT",
+        conflicts_with_all = [
+            "delete", "count", "count_matches", "invert", "label", "before_text",
+            "after_text", "line_number", "byte_offset", "after_context",
+            "before_context", "context", "lookup",
+        ]
+    )]
+    output_format: Option<MatchTemplate>,
+
+    /// Translate each character in FROM to the corresponding character in
+    /// TO, like tr; sets may contain ranges like a-z.
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["FROM", "TO"],
+        conflicts_with_all = [
+            "regexp", "file", "pattern_hex", "extract", "delete", "count", "count_matches",
+            "json", "format",
+            "output_format"
+        ]
+    )]
+    translate: Option<Vec<String>>,
+
+    /// Read tab-separated verbatim pattern/replacement pairs from a file,
+    /// one per line, and replace them all in a single pass.
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = [
+            "regexp", "file", "pattern_hex", "extract", "delete", "translate", "count",
+            "count_matches", "json", "format",
+            "output_format"
+        ]
+    )]
+    pairs: Option<PathBuf>,
+
+    /// Replace every A with B and every B with A, in a single pass; where
+    /// they overlap, the longer one wins.
+    #[arg(
+        long,
+        num_args = 2,
+        value_names = ["A", "B"],
+        conflicts_with_all = [
+            "regexp", "file", "pattern_hex", "extract", "delete", "translate",
+            "pairs", "count", "count_matches", "json", "format",
+            "output_format"
+        ]
+    )]
+    swap: Option<Vec<String>>,
+
+    /// Replace each match with its value in a file of tab-separated
+    /// key/value pairs, one per line, keyed by the text of the match (or
+    /// of the --lookup-key group).
+    #[arg(
+        long,
+        value_name = "FILE",
+        conflicts_with_all = ["delete", "replace_hex", "literal_replacement"]
+    )]
+    lookup: Option<PathBuf>,
+
+    /// Capture group (number or name) whose text is looked up with
+    /// --lookup.
+    #[arg(long, value_name = "GROUP", default_value = "0", requires = "lookup")]
+    lookup_key: String,
+
+    /// Text to replace matches that aren't found by --lookup with (the
+    /// default is to leave them alone).
+    #[arg(long, value_name = "TEXT", requires = "lookup")]
+    lookup_default: Option<String>,
+
+    /// When extracting, also find matches that overlap each other.
+    #[arg(long)]
+    overlapping: bool,
+
+    /// When extracting, print each distinct match only once.
+    #[arg(long)]
+    unique: bool,
+
+    /// Like --unique, but start afresh with each input file.
+    #[arg(long, conflicts_with = "unique")]
+    unique_per_file: bool,
+
+    /// When extracting, print how many times each distinct match (or,
+    /// with --count-by=GROUP, the text of that capture group) was found,
+    /// most frequent first, instead of the matches themselves.
+    #[arg(
+        long,
+        value_name = "GROUP",
+        num_args = 0..=1,
+        require_equals = true,
+        conflicts_with_all = [
+            "unique", "unique_per_file", "invert", "before_text", "after_text",
+            "line_number", "byte_offset", "after_context", "before_context", "context",
+            "json", "format", "output_format",
+        ]
+    )]
+    count_by: Option<Option<String>>,
+
+    /// When extracting, start each line of output with the number of the
+    /// record (counting from 1) the match was found in.
+    #[arg(short = 'N', long)]
+    line_number: bool,
+
+    /// When extracting, start each line of output with the offset (in
+    /// bytes, from the start of the file) of the match.
+    #[arg(short = 'b', long)]
+    byte_offset: bool,
+
+    /// When extracting, also print the N records after each one with a
+    /// match.
+    #[arg(short = 'A', long, value_name = "N")]
+    after_context: Option<usize>,
+
+    /// When extracting, also print the N records before each one with a
+    /// match.
+    #[arg(short = 'B', long, value_name = "N")]
+    before_context: Option<usize>,
+
+    /// Like -A N -B N.
+    #[arg(short = 'C', long, value_name = "N")]
+    context: Option<usize>,
+
+    /// Print TEXT as a line of its own before each line that gets operated
+    /// on; may refer to capture groups from the line's first match.
+    #[arg(long, value_name = "TEXT")]
+    before_text: Option<String>,
+
+    /// Print TEXT as a line of its own after each line that gets operated
+    /// on; may refer to capture groups from the line's first match.
+    #[arg(long, value_name = "TEXT")]
+    after_text: Option<String>,
+
+    /// Only operate on lines that also match this regex.
+    #[arg(short = 'G', long, value_name = "PATT")]
+    guard: Option<String>,
+
+    /// Don't operate on lines that match this regex.
+    #[arg(long, value_name = "PATT")]
+    unless: Option<String>,
+
+    /// Only operate on lines in the range START:END (inclusive, counting
+    /// from 1), with an optional :STEP; either end may be left open. May be
+    /// given more than once.
+    #[arg(long, value_name = "RANGE")]
+    lines: Vec<LineRange>,
+
+    /// Only operate on blocks of lines that begin with one matching the
+    /// START regex and end with one matching the END regex.
+    #[arg(long, num_args = 2, value_names = ["START", "END"])]
+    between: Option<Vec<String>>,
+
+    /// Interpret \n, \t, \r, \0, \\, and \xNN in replacements (and in
+    /// patterns, with -s).
+    #[arg(long)]
+    escapes: bool,
+
+    /// Do simple verbatim string matching (default is regex matching).
+    #[arg(short, long)]
+    simple: bool,
+
+    /// Operate on non-matching records, replacing (or extracting) them whole.
+    #[arg(short = 'v', long = "invert-match")]
+    invert: bool,
+
+    /// Only match entire records.
+    #[arg(short = 'L', long)]
+    line_regexp: bool,
+
+    /// Only match at the start of each record.
+    #[arg(long)]
+    starts_with: bool,
+
+    /// Only match at the end of each record.
+    #[arg(long)]
+    ends_with: bool,
+
+    /// Match verbatim patterns with up to N typos (edits).
+    #[arg(
+        long,
+        value_name = "N",
+        requires = "simple",
+        conflicts_with_all = ["line_regexp", "starts_with", "ends_with"]
+    )]
+    fuzzy: Option<usize>,
+
+    /// Interpret patterns as shell-style globs.
+    #[arg(long, conflicts_with = "simple")]
+    glob: bool,
+
+    /// Match without regard to case.
+    #[arg(short = 'I', long)]
+    ignore_case: bool,
+
+    /// Make ^ and $ match at the starts and ends of lines (regex flag m).
+    #[arg(long)]
+    line_anchors: bool,
+
+    /// Make . match newlines too (regex flag s).
+    #[arg(long)]
+    dot_all: bool,
+
+    /// Ignore whitespace and allow # comments in regexes (regex flag x).
+    #[arg(long)]
+    free_spacing: bool,
+
+    /// Turn off Unicode support in regexes (regex flag u).
+    #[arg(long)]
+    no_unicode: bool,
+
+    /// Regex engine: regex; fancy, which adds backreferences,
+    /// lookaround, and atomic groups (but can be much slower); or pcre2,
+    /// for Perl-compatible patterns (if built with the "pcre2" feature).
+    #[arg(
+        long,
+        value_name = "ENGINE",
+        default_value = "regex",
+        conflicts_with = "simple"
+    )]
+    engine: Engine,
+
+    /// Use PCRE2 for regexes (the same as --engine pcre2).
+    #[arg(long, conflicts_with_all = ["engine", "simple"])]
+    pcre2: bool,
+
+    /// Delimiter to separate "lines".
+    #[arg(short, long, value_name = "PATT",
+        default_value_t = String::from(DEFAULT_DELIMITER))]
+    delimiter: String,
+
+    /// Match against the whole input at once instead of line-by-line.
+    #[arg(short = 'U', long, conflicts_with = "delimiter")]
+    multiline: bool,
+
+    /// Treat paragraphs (separated by blank lines) as records instead of
+    /// lines, and print a blank line between them (like perl's -00).
+    #[arg(short = 'p', long, conflicts_with_all = ["delimiter", "multiline"])]
+    paragraph: bool,
+
+    /// Separate records with NUL bytes instead of line breaks, on input
+    /// and output (for `find -print0` and `xargs -0`).
+    #[arg(short = '0', long, conflicts_with_all = ["delimiter", "multiline", "paragraph"])]
+    null: bool,
+
+    /// Split the input into records of N bytes each (the last may be
+    /// shorter) instead of by a delimiter; nothing is printed between
+    /// records unless -n says otherwise.
+    #[arg(long, value_name = "N",
+        conflicts_with_all = ["delimiter", "multiline", "paragraph", "null"])]
+    record_bytes: Option<usize>,
+
+    /// Read the input as CSV, so each record is a row (whose quoted
+    /// fields may contain line breaks).
+    #[arg(long, conflicts_with_all = ["delimiter", "multiline", "paragraph", "null", "record_bytes"])]
+    csv: bool,
+
+    /// Skip records longer than N bytes (with a warning) instead of
+    /// reading them into memory, however long they are. With -W, --diff
+    /// or --check, such a record is an error instead, and the file is left
+    /// alone.
+    #[arg(long, value_name = "N",
+        conflicts_with_all = ["multiline", "record_bytes", "csv", "threads", "mmap"])]
+    max_record_bytes: Option<usize>,
+
+    /// With --csv, only match against this column, by number (from 1) or
+    /// by name (from the header row, which is left alone).
+    #[arg(long, value_name = "COLUMN", requires = "csv")]
+    column: Option<Column>,
+
+    /// Read each record as a line of JSON, and only match against the
+    /// string at the --field path in it.
+    #[arg(long, requires = "field", conflicts_with_all = ["csv", "multiline", "record_bytes"])]
+    jsonl: bool,
+
+    /// Only match against field N (from 1) of each record, leaving the
+    /// rest alone; with --jsonl, the path (like key.subkey.0) of the
+    /// string to match against instead.
+    #[arg(long, value_name = "N|PATH", conflicts_with = "csv")]
+    field: Option<String>,
+
+    /// Separate --field's fields with matches of this regex (default is
+    /// runs of whitespace, ignoring any at the start of the record).
+    #[arg(
+        long,
+        value_name = "PATT",
+        requires = "field",
+        conflicts_with = "jsonl"
+    )]
+    field_sep: Option<String>,
+
+    /// Copy the first N records to the output unchanged, without matching
+    /// against them.
+    #[arg(long, value_name = "N", default_value_t = 0)]
+    skip_lines: usize,
+
+    /// Copy the first record (a header) to the output unchanged; the same
+    /// as --skip-lines 1.
+    #[arg(long, conflicts_with = "skip_lines")]
+    header: bool,
+
+    /// Leave header records (from --skip-lines, --header, or a --column
+    /// name) out of the output altogether.
+    #[arg(long)]
+    drop_header: bool,
+
+    /// Print something other than a newline between chunks.
+    #[arg(short, long, value_name = "NL")]
+    newline: Option<Option<String>>,
+
+    /// Join output records with this instead of a newline; escapes like
+    /// \0, \t, and \n are interpreted, and '' joins them with nothing.
+    /// Unlike -n, this also splits --translate and --pairs input into
+    /// records.
+    #[arg(
+        long,
+        visible_alias = "record-separator",
+        value_name = "STR",
+        conflicts_with = "newline"
+    )]
+    output_delimiter: Option<String>,
+
+    /// When extracting, separate matches in the same record with this
+    /// (escapes are interpreted, as with --output-delimiter); by default
+    /// it's what goes between records, so each match is on a line of its
+    /// own.
+    #[arg(long, value_name = "STR")]
+    match_separator: Option<String>,
+
+    /// When extracting, end each match with a NUL byte instead of a
+    /// newline (for `xargs -0`), even if it has line breaks in it; unlike
+    /// -0, records are still read a line at a time.
+    #[arg(
+        long,
+        conflicts_with_all = ["newline", "output_delimiter", "match_separator", "count_by"]
+    )]
+    print0: bool,
+
+    /// Always end the last record with a newline (or -n or
+    /// --output-delimiter), even if the input didn't end with one.
+    #[arg(long)]
+    final_newline: bool,
+
+    /// Input file or http:// URL (https:// isn't supported); may be given
+    /// more than once, and goes before any positional FILEs.
+    #[arg(short, long, value_name = "FILE")]
+    input: Vec<PathBuf>,
+
+    /// Keep reading the input file as it grows, like `tail -f` (noticing
+    /// if it's truncated or replaced), until interrupted. The file is
+    /// checked for more four times a second, rather than watched.
+    #[arg(long, conflicts_with_all = ["write", "multiline", "recursive"])]
+    follow: bool,
+
+    /// Treat each input as a zip or tar(.gz) archive, processing each file
+    /// in it as a separate input, with each line of output prefixed by
+    /// ARCHIVE:MEMBER:.
+    #[arg(long, conflicts_with_all = ["write", "follow"])]
+    archive: bool,
+
+    /// Only process the archive members whose paths match this glob (like
+    /// '*.log' or 'logs/**').
+    #[arg(long, value_name = "GLOB", requires = "archive")]
+    members: Option<String>,
+
+    /// Output file (default is stdout).
+    #[arg(short, long)]
+    output: Option<PathBuf>,
+
+    /// Write the output to stdout as well as to the -o file, to watch it
+    /// as it's saved.
+    #[arg(long, requires = "output")]
+    tee: bool,
+
+    /// Add to the end of the -o file, instead of replacing what's in it.
+    #[arg(long, requires = "output")]
+    append: bool,
+
+    /// Refuse to write to an -o file that already exists.
+    #[arg(long, requires = "output", conflicts_with = "append")]
+    no_clobber: bool,
+
+    /// Print nothing; just exit with status 0 if anything matched, or 1 if
+    /// nothing did (2 means an error).
+    #[arg(short, long, conflicts_with_all = ["write", "output", "output_template"])]
+    quiet: bool,
+
+    /// At the end, report (on stderr) how many records, matches,
+    /// replacements, bytes, and files there were, and how long it took;
+    /// as JSON, with --json.
+    #[arg(long)]
+    stats: bool,
+
+    /// At the end, report (on stderr) how long it took, how much of that
+    /// went on reading, matching, and writing, and how many bytes and
+    /// records went by a second (for tuning --buffer-size, -j, and
+    /// --threads); as JSON, with --json.
+    #[arg(long)]
+    time: bool,
+
+    /// Write each input file's output to its own file, at a path made from
+    /// this template: {path}, {dir}, {name}, {stem}, and {ext} (with its
+    /// dot) are parts of the input's path, as in '{dir}/{stem}.new{ext}'.
+    /// Directories are created as needed.
+    #[arg(
+        long,
+        value_name = "TEMPLATE",
+        conflicts_with_all = ["write", "output", "follow", "archive"]
+    )]
+    output_template: Option<PathTemplate>,
+
+    /// Read input in this encoding instead of UTF-8: latin1, windows-1252,
+    /// utf-16le, utf-16be, or auto (guess for each input). Output is
+    /// written in the same encoding (auto writes UTF-8). Shift-JIS and
+    /// other multi-byte East Asian encodings aren't supported.
+    #[arg(long, value_name = "ENC")]
+    encoding: Option<Encoding>,
+
+    /// Write output in this encoding (default is --encoding's, or UTF-8).
+    #[arg(long, value_name = "ENC")]
+    output_encoding: Option<Encoding>,
+
+    /// Start the output with a byte order mark if the (first) input
+    /// started with one; by default, BOMs are removed (except from files
+    /// edited in place).
+    #[arg(long)]
+    keep_bom: bool,
+
+    /// Always start the output with a byte order mark.
+    #[arg(long, conflicts_with = "keep_bom")]
+    add_bom: bool,
+
+    /// Remove byte order marks from files edited in place too.
+    #[arg(long, conflicts_with_all = ["keep_bom", "add_bom"])]
+    strip_bom: bool,
+
+    /// Edit the input files in place, instead of writing to stdout.
+    #[arg(short = 'W', long, conflicts_with = "output")]
+    write: bool,
+
+    /// With -W, keep each original file, with SUFFIX added to its name.
+    #[arg(long, value_name = "SUFFIX", requires = "write")]
+    backup: Option<String>,
+
+    /// With -W, keep each file's timestamps and (where possible) owner
+    /// and group; its permissions are always kept.
+    #[arg(long, requires = "write")]
+    preserve: bool,
+
+    /// Instead of editing the input files in place, print a unified diff
+    /// of what -W would change.
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "write", "output", "output_template", "archive", "follow", "extract",
+            "count", "count_matches", "quiet", "json", "format", "output_format",
+        ]
+    )]
+    diff: bool,
+
+    /// Change nothing, but list the input files that -W would change, and
+    /// exit with status 1 if there are any (for CI checks).
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "write", "output", "output_template", "archive", "follow", "extract",
+            "count", "count_matches", "quiet", "json", "format", "output_format", "diff",
+        ]
+    )]
+    check: bool,
+
+    /// Edit (or --diff, or --check) up to N files at once, each in a thread
+    /// of its own; 0 means as many as there are CPUs. Output is in the
+    /// same order either way.
+    #[arg(short, long, value_name = "N", default_value_t = 1)]
+    jobs: usize,
+
+    /// Replace in a single large input with N threads: one reads and
+    /// writes while the others match, a block of lines at a time; 0 means
+    /// as many as there are CPUs. Only works when what happens to each
+    /// line doesn't depend on the lines before it.
+    #[arg(
+        long,
+        value_name = "N",
+        default_value_t = 1,
+        conflicts_with_all = [
+            "write", "diff", "check", "output_template", "archive", "follow", "recursive",
+            "max_total", "lines", "between", "skip_lines", "header", "column", "delimiter",
+            "multiline", "paragraph", "record_bytes", "csv", "stats", "time", "jobs",
+        ]
+    )]
+    threads: usize,
+
+    /// When replacing a line at a time, search input files where they're
+    /// mapped into memory instead of reading them in; faster for big
+    /// files, but they mustn't change while this runs. (Input that's
+    /// transcoded, split into fields, or not a plain file is read as
+    /// usual.)
+    #[arg(
+        long,
+        conflicts_with_all = [
+            "write", "diff", "check", "output_template", "archive", "follow", "threads",
+        ]
+    )]
+    mmap: bool,
+
+    /// Read input and write output BYTES at a time. Bigger buffers can be
+    /// quicker with very big files, or on network filesystems.
+    #[arg(long, value_name = "BYTES")]
+    buffer_size: Option<usize>,
+
+    /// Flush the output after each record, even when it isn't a terminal,
+    /// so it can be used as it comes in a pipeline (like `tail -f log |
+    /// fresh ... | other-tool`).
+    #[arg(long)]
+    line_buffered: bool,
+
+    /// Don't buffer the output at all.
+    #[arg(long, conflicts_with = "line_buffered")]
+    unbuffered: bool,
+
+    /// Set for the options of other threads (with -j or --threads), which
+    /// don't write to stdout.
+    #[arg(skip)]
+    worker: bool,
+}
+
+/**
+The options as given on the command line (with the inputs already worked
+out), for making another `Opts` just like them in another thread.
+*/
+#[derive(Clone)]
+pub struct Args(CliOpts);
+
+pub struct Opts {
+    pub patterns: Vec<Vec<u8>>,
+    /// If not empty, one label for each pattern.
+    pub labels: Vec<String>,
+    pub selection: Selection,
+    pub address: Address,
+    pub output_mode: OutputMode,
+    pub overlapping: bool,
+    /// When extracting, leave out matches already printed...
+    pub unique: bool,
+    /// ...in the same file.
+    pub unique_per_file: bool,
+    /// When extracting, tally the matches (or the text of this capture
+    /// group in them) instead of printing them.
+    pub count_by: Option<Option<String>>,
+    /// When extracting, prefix output with record numbers...
+    pub line_number: bool,
+    /// ...and/or byte offsets.
+    pub byte_offset: bool,
+    /// How many records of context to print before and after ones that
+    /// matches are extracted from.
+    pub context: (usize, usize),
+    /// When extracting, write matches in this format instead of as text...
+    pub format: Option<Format>,
+    /// ...starting with a header row.
+    pub format_header: bool,
+    pub preserve_case: bool,
+    /// Insert the replacement without expanding it as a template.
+    pub literal_replacement: bool,
+    /// Replace matches from this table instead (see `Template::lookup()`).
+    pub lookup: Option<Rc<Table>>,
+    pub lookup_key: String,
+    pub lookup_default: Option<Vec<u8>>,
+    pub transforms: Vec<Transform>,
+    /// Whether `{{now}}` is evaluated for each expansion.
+    pub live_time: bool,
+    pub hash_salt: Vec<u8>,
+    /// Restart `$#` counters with each record.
+    pub count_per_record: bool,
+    pub before_text: Option<Vec<u8>>,
+    pub after_text: Option<Vec<u8>>,
+    pub match_mode: MatchMode,
+    pub engine: Engine,
+    pub ignore_case: bool,
+    pub regex_flags: RegexFlags,
+    pub invert: bool,
+    /// Only match at the start of each record.
+    pub anchor_start: bool,
+    /// Only match at the end of each record.
+    pub anchor_end: bool,
+    pub fuzzy: Option<usize>,
+    pub delimiter: String,
+    pub multiline: bool,
+    /// Records are paragraphs (and `delimiter` is set accordingly).
+    pub paragraph: bool,
+    /// Records are this many bytes long, rather than delimited.
+    pub record_bytes: Option<usize>,
+    /// Records are CSV rows.
+    pub csv: bool,
+    /// Records longer than this are skipped, rather than read in whole.
+    pub max_record_bytes: Option<usize>,
+    /// Only this CSV column is matched against.
+    pub column: Option<Column>,
+    /// Only the string at this path in each JSON record is matched against.
+    pub json_field: Option<String>,
+    /// This many records at the start are headers, copied unchanged.
+    pub skip_lines: usize,
+    /// Don't print header records at all.
+    pub drop_header: bool,
+    /// Only this field (counting from 0) is matched against...
+    pub field: Option<usize>,
+    /// ...with fields separated by this, or whitespace if `None`.
+    pub field_sep: Option<Regex>,
+    pub newline: Option<Vec<u8>>,
+    /// Split the input into records to rejoin with `newline` even in modes
+    /// that otherwise pass it through whole (--translate and --pairs).
+    pub rejoin: bool,
+    /// End each record with the delimiter it had, rather than `newline`,
+    /// so that editing a file leaves alone what wasn't edited.
+    pub keep_endings: bool,
+    /// Put between matches extracted from the same record.
+    pub match_separator: Vec<u8>,
+    /// Print a newline after the last record even if the input had none.
+    pub final_newline: bool,
+    /// Input files, in order (`-` is stdin).
+    pub inputs: Vec<PathBuf>,
+    /// Directories whose files are processed after `inputs`.
+    pub recursive: Vec<PathBuf>,
+    /// Skip input files that look binary.
+    pub skip_binary: bool,
+    /// Edit each input file in place, with output going to it instead.
+    pub in_place: bool,
+    /// Show what editing in place would do, rather than doing it...
+    pub diff: bool,
+    /// ...or just which files it would change.
+    pub check: bool,
+    /// How many files to edit at once.
+    pub jobs: usize,
+    /// How many threads to replace in a single input with.
+    pub threads: usize,
+    /// Search input files where they're mapped into memory.
+    pub mmap: bool,
+    /// How much to read and write at a time, if not the default.
+    pub buffer_size: Option<usize>,
+    /// When output gets flushed.
+    pub flush: Flush,
+    /// What these options were made from.
+    pub args: Args,
+    /// Suffix to add to the names of copies of files edited in place.
+    pub backup: Option<String>,
+    /// Keep the timestamps and ownership of files edited in place.
+    pub preserve: bool,
+    /// Filters on the files found under `recursive`.
+    pub include: Vec<regex::Regex>,
+    pub exclude: Vec<regex::Regex>,
+    /// Keep reading the input as it grows.
+    pub follow: bool,
+    /// Read inputs as archives, processing just the members that match
+    /// this.
+    pub archive: Option<regex::Regex>,
+    /// Write each input's output to its own file, at a path made from this.
+    pub output_template: Option<PathTemplate>,
+    /// Encoding of the input, if not UTF-8.
+    pub encoding: Option<Encoding>,
+    /// Encoding of the output, if not UTF-8.
+    pub output_encoding: Option<Encoding>,
+    pub keep_bom: bool,
+    pub add_bom: bool,
+    pub output: Box<dyn Write>,
+    /// Output is going nowhere; only the exit status matters.
+    pub quiet: bool,
+    /// Counts (and times) of what was done, if they're to be reported.
+    pub stats: Option<Rc<Stats>>,
+}
+
+/// Exit with a usage error, the same way `clap` does.
+fn usage_error(msg: impl Display) -> ! {
+    CliOpts::command()
+        .error(ErrorKind::ArgumentConflict, msg)
+        .exit()
+}
+
+/// Compile a regex used for addressing records.
+fn address_regex(patt: &str, ignore_case: bool) -> Result<Regex, FrErr> {
+    let re = RegexBuilder::new(patt)
+        .case_insensitive(ignore_case)
+        .build()?;
+    Ok(re)
+}
+
+/// Read the pattern/replacement pairs for --pairs (or --lookup).
+fn read_pairs(pbuf: &Path) -> Result<Vec<(String, String)>, FrErr> {
+    let text = std::fs::read_to_string(pbuf)?;
+    let mut pairs = Vec::new();
+    for (n, line) in text.lines().enumerate() {
+        if line.is_empty() {
+            continue;
+        }
+        match line.split_once('\t') {
+            Some(("", _)) => usage_error(format!("{}:{}: empty key", pbuf.display(), n + 1)),
+            Some((patt, repl)) => pairs.push((patt.to_string(), repl.to_string())),
+            None => usage_error(format!("{}:{}: no tab in line", pbuf.display(), n + 1)),
+        }
+    }
+    if pairs.is_empty() {
+        usage_error(format!("{}: no pairs found", pbuf.display()));
+    }
+    Ok(pairs)
+}
+
+/*
+Read a list of paths, one per line, or separated by NULs if there are any.
+*/
+fn read_file_list(pbuf: &Path) -> Result<Vec<PathBuf>, FrErr> {
+    let mut list: Vec<u8> = Vec::new();
+    if pbuf.as_os_str() == STDIN {
+        std::io::stdin().lock().read_to_end(&mut list)?;
+    } else {
+        list = std::fs::read(pbuf)?;
+    }
+    let sep = if list.contains(&0) { b'\0' } else { b'\n' };
+    let paths = list
+        .split(|&b| b == sep)
+        .map(|path| match sep {
+            b'\n' => path.strip_suffix(b"\r").unwrap_or(path),
+            _ => path,
+        })
+        .filter(|path| !path.is_empty())
+        .map(path_from_bytes)
+        .collect();
+    Ok(paths)
+}
+
+#[cfg(unix)]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    use std::os::unix::ffi::OsStrExt;
+    PathBuf::from(std::ffi::OsStr::from_bytes(bytes))
+}
+
+#[cfg(not(unix))]
+fn path_from_bytes(bytes: &[u8]) -> PathBuf {
+    PathBuf::from(String::from_utf8_lossy(bytes).into_owned())
+}
+
+/**
+Replace each `${ENV:NAME}` in `text` with the value of the environment
+variable `NAME`. In modes where replacements are templates, `$`s in the
+values are escaped so they get inserted as-is.
+*/
+fn expand_env(text: &[u8], match_mode: MatchMode) -> Result<Vec<u8>, String> {
+    const OPEN: &[u8] = b"${ENV:";
+    let mut out: Vec<u8> = Vec::with_capacity(text.len());
+    let mut rest = text;
+
+    while let Some(n) = rest.windows(OPEN.len()).position(|w| w == OPEN) {
+        out.extend_from_slice(&rest[..n]);
+        rest = &rest[n + OPEN.len()..];
+        let end = rest
+            .iter()
+            .position(|&b| b == b'}')
+            .ok_or("unterminated ${ENV:...}")?;
+        let name = String::from_utf8_lossy(&rest[..end]);
+        let value = match std::env::var_os(name.as_ref()) {
+            Some(value) => value.to_string_lossy().into_owned(),
+            None => return Err(format!("environment variable {} isn't set", name)),
+        };
+        match match_mode {
+            MatchMode::Verbatim => out.extend_from_slice(value.as_bytes()),
+            _ => out.extend_from_slice(&escape_dollars(value.as_bytes())),
+        }
+        rest = &rest[end + 1..];
+    }
+
+    out.extend_from_slice(rest);
+    Ok(out)
+}
+
+/*
+Stdout, written a line at a time if it's a terminal, or if `live` (the
+output should be seen as it comes); otherwise it's written in blocks,
+which is much quicker than flushing each line.
+*/
+fn stdout(live: bool, size: Option<usize>, flush: Flush) -> Box<dyn Write> {
+    let stdout = io::stdout();
+    match flush {
+        Flush::Blocks if live || stdout.is_terminal() => Box::new(stdout.lock()),
+        _ => buffered(stdout.lock(), size, flush),
+    }
+}
+
+/**
+Write to `output` in blocks of `size` bytes (or of the default size), or
+more often, as `flush` says.
+*/
+pub fn buffered<W: Write + 'static>(
+    output: W,
+    size: Option<usize>,
+    flush: Flush,
+) -> Box<dyn Write> {
+    let blocks = |output| match size {
+        Some(size) => BufWriter::with_capacity(size, output),
+        None => BufWriter::new(output),
+    };
+    match flush {
+        Flush::Blocks => Box::new(blocks(output)),
+        Flush::Records => Box::new(Flushing(blocks(output))),
+        Flush::Always => Box::new(Flushing(output)),
+    }
+}
+
+/*
+Flushes what it writes to straight after each write, which is at least
+after each record.
+*/
+struct Flushing<W: Write>(W);
+
+impl<W: Write> Write for Flushing<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.0.write_all(buf)?;
+        self.0.flush()?;
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.0.flush()
+    }
+}
+
+/*
+Open the -o file at `path`, to add to the end of (if `append`), or to
+replace, unless it already exists and `no_clobber` is true.
+*/
+fn open_output(path: &Path, append: bool, no_clobber: bool) -> Result<File, FrErr> {
+    let mut options = OpenOptions::new();
+    match (append, no_clobber) {
+        (true, _) => options.append(true).create(true),
+        (false, true) => options.write(true).create_new(true),
+        (false, false) => options.write(true).create(true).truncate(true),
+    };
+    options.open(path).map_err(|e| match e.kind() {
+        io::ErrorKind::AlreadyExists => FrErr::Misc(
+            format!(
+                "{}: already exists (not overwriting it, because of --no-clobber)",
+                path.display()
+            )
+            .into(),
+        ),
+        _ => FrErr::Misc(format!("{}: {}", path.display(), e).into()),
+    })
+}
+
+impl Opts {
+    pub fn new() -> Result<Self, FrErr> {
+        Opts::from_args(Args(CliOpts::parse()))
+    }
+
+    /// Make the options again from what they were made from.
+    pub fn from_args(args: Args) -> Result<Self, FrErr> {
+        let Args(mut clio) = args;
+        let mut args = clio.clone();
+
+        let match_mode = if clio.simple || !clio.pattern_hex.is_empty() {
+            MatchMode::Verbatim
+        } else if clio.glob {
+            MatchMode::Glob
+        } else {
+            MatchMode::Regex
+        };
+
+        let escapes = clio.escapes;
+        let pattern_text = move |s: String| -> Vec<u8> {
+            if escapes {
+                unescape(&s).unwrap_or_else(|e| usage_error(e))
+            } else {
+                s.into_bytes()
+            }
+        };
+        let env = clio.expand_env;
+        // Text destined for a template (or, in `mode`, inserted literally).
+        let expanded = move |s: String, mode: MatchMode| -> Vec<u8> {
+            let text = pattern_text(s);
+            if env {
+                expand_env(&text, mode).unwrap_or_else(|e| usage_error(e))
+            } else {
+                text
+            }
+        };
+        let text = move |s: String| expanded(s, match_mode);
+        let hex = |s: &str| -> Vec<u8> {
+            decode_hex(s).unwrap_or_else(|e| usage_error(format!("{:?}: {}", s, e)))
+        };
+
+        // These modes take no pattern or replacement, so any positional
+        // arguments are all input files.
+        if clio.translate.is_some() || clio.pairs.is_some() || clio.swap.is_some() {
+            let args = clio.pattern.take().into_iter().chain(clio.replace.take());
+            clio.files.splice(0..0, args.map(PathBuf::from));
+        }
+
+        // With patterns supplied by -e, -f, or --pattern-hex, every
+        // positional argument shifts one place to the left.
+        let patterns: Vec<String> =
+            if clio.regexp.is_empty() && clio.file.is_none() && clio.pattern_hex.is_empty() {
+                clio.pattern.take().into_iter().collect()
+            } else {
+                if let Some(arg) = clio.replace.take() {
+                    clio.files.insert(0, PathBuf::from(arg));
+                }
+                clio.replace = clio.pattern.take();
+                let mut patterns = std::mem::take(&mut clio.regexp);
+                if let Some(ref pbuf) = clio.file {
+                    let text = std::fs::read_to_string(pbuf)?;
+                    patterns.extend(
+                        text.lines()
+                            .filter(|line| !line.is_empty())
+                            .map(String::from),
+                    );
+                }
+                if patterns.is_empty() && clio.pattern_hex.is_empty() {
+                    usage_error("no patterns given");
+                }
+                patterns
+            };
+
+        // Nor do matches printed in a format, counted, or deleted take a
+        // replacement, so (as with grep, and for editors that run it like
+        // grep) an argument after the pattern is an input file.
+        let no_replacement = clio.json
+            || clio.format.is_some()
+            || clio.output_format.is_some()
+            || clio.count
+            || clio.count_matches
+            || clio.delete;
+        if no_replacement {
+            if let Some(arg) = clio.replace.take() {
+                clio.files.insert(0, PathBuf::from(arg));
+            }
+        }
+        let mut patterns: Vec<Vec<u8>> = match match_mode {
+            MatchMode::Verbatim => patterns.into_iter().map(pattern_text).collect(),
+            _ => patterns.into_iter().map(String::into_bytes).collect(),
+        };
+        patterns.extend(clio.pattern_hex.iter().map(|s| hex(s)));
+
+        let literal_replacement = clio.literal_replacement || clio.replace_hex.is_some();
+        let replace = match (clio.replace_hex, clio.replace) {
+            (Some(_), Some(_)) => usage_error("--replace-hex given along with a replacement"),
+            // Bytes given in hex are always inserted verbatim.
+            (Some(s), None) => Some(hex(&s)),
+            (None, repl) if literal_replacement => repl.map(|s| expanded(s, MatchMode::Verbatim)),
+            (None, repl) => repl.map(text),
+        };
+
+        if let Some(n) = clio.fuzzy {
+            let n_chars = |p: &[u8]| match std::str::from_utf8(p) {
+                Ok(p) => p.chars().count(),
+                Err(_) => usage_error("--fuzzy patterns must be valid UTF-8"),
+            };
+            if patterns.iter().any(|p| n_chars(p) <= n) {
+                usage_error("--fuzzy distance must be less than the length of each pattern");
+            }
+        }
+
+        let mut nth = clio.nth;
+        nth.sort_unstable();
+        let selection = Selection {
+            max: clio.max.unwrap_or(usize::MAX),
+            skip: clio.skip,
+            nth,
+            last: clio.last,
+            max_total: clio.max_total.unwrap_or(usize::MAX),
+        };
+
+        if clio.lookup.is_some() && replace.is_some() {
+            usage_error("--lookup doesn't take a replacement");
+        }
+        let lookup: Option<Rc<Table>> = match clio.lookup {
+            Some(ref pbuf) => Some(Rc::new(
+                read_pairs(pbuf)?
+                    .into_iter()
+                    .map(|(k, v)| (k.into_bytes(), v.into_bytes()))
+                    .collect(),
+            )),
+            None => None,
+        };
+        let format = match (clio.json, clio.output_format) {
+            (true, _) => Some(Format::Json),
+            (false, Some(template)) => Some(Format::Template(template)),
+            (false, None) => clio.format,
+        };
+        if clio.format_header && !matches!(format, Some(Format::Csv | Format::Tsv)) {
+            usage_error("--format-header only works with --format csv or tsv");
+        }
+        let output_mode = if let Some(ref pair) = clio.translate {
+            match Translation::new(&pair[0], &pair[1]) {
+                Ok(tr) => OutputMode::Translate(tr),
+                Err(e) => usage_error(format!("--translate: {}", e)),
+            }
+        } else if let Some(ref pbuf) = clio.pairs {
+            OutputMode::Pairs(read_pairs(pbuf)?)
+        } else if let Some(pair) = clio.swap {
+            let mut pair = pair.into_iter().map(pattern_text);
+            let (a, b) = (pair.next().unwrap(), pair.next().unwrap());
+            if a.is_empty() || b.is_empty() || a == b {
+                usage_error("--swap needs two different, non-empty strings");
+            }
+            OutputMode::Swap(a, b)
+        } else {
+            match (clio.extract, replace) {
+                _ if clio.delete => OutputMode::Delete,
+                (_, Some(_)) if clio.count || clio.count_matches => {
+                    usage_error("there's no replacement when counting")
+                }
+                _ if clio.count || clio.count_matches => OutputMode::Count(clio.count_matches),
+                (_, Some(_)) if format.is_some() => {
+                    usage_error("there's no replacement with --json, --format, or --output-format")
+                }
+                _ if format.is_some() => OutputMode::Extract(None),
+                // The lookup table stands in for the replacement.
+                (false, None) if lookup.is_some() => OutputMode::Replace(Vec::new()),
+                (_, None) => OutputMode::Extract(None),
+                (true, Some(repl)) => OutputMode::Extract(Some(repl)),
+                (false, Some(repl)) => OutputMode::Replace(repl),
+            }
+        };
+        if clio.overlapping && !matches!(output_mode, OutputMode::Extract(_)) {
+            usage_error("--overlapping only works when extracting");
+        }
+        if (clio.unique || clio.unique_per_file) && !matches!(output_mode, OutputMode::Extract(_)) {
+            usage_error("--unique and --unique-per-file only work when extracting");
+        }
+        if clio.count_by.is_some() && !matches!(output_mode, OutputMode::Extract(_)) {
+            usage_error("--count-by only works when extracting");
+        }
+        if clio.print0 && !matches!(output_mode, OutputMode::Extract(_)) {
+            usage_error("--print0 only works when extracting");
+        }
+        if clio.threads != 1 && !matches!(output_mode, OutputMode::Replace(_)) {
+            usage_error("--threads only works when replacing");
+        }
+        if (clio.line_number || clio.byte_offset) && !matches!(output_mode, OutputMode::Extract(_))
+        {
+            usage_error("--line-number and --byte-offset only work when extracting");
+        }
+        let context = (
+            clio.before_context.or(clio.context).unwrap_or(0),
+            clio.after_context.or(clio.context).unwrap_or(0),
+        );
+        if context != (0, 0) && !matches!(output_mode, OutputMode::Extract(_)) {
+            usage_error("context (-A, -B, and -C) only works when extracting");
+        }
+        if !clio.label.is_empty() {
+            if !matches!(output_mode, OutputMode::Extract(_)) || clio.invert {
+                usage_error("--label only works when extracting matches");
+            }
+            if clio.label.len() != patterns.len() {
+                usage_error(format!(
+                    "{} labels given for {} patterns",
+                    clio.label.len(),
+                    patterns.len()
+                ));
+            }
+        }
+
+        let mut address = Address::default();
+        if let Some(ref patt) = clio.guard {
+            address.guard = Some(address_regex(patt, clio.ignore_case)?);
+        }
+        if let Some(ref patt) = clio.unless {
+            address.unless = Some(address_regex(patt, clio.ignore_case)?);
+        }
+        address.lines = clio.lines;
+        if let Some(ref pair) = clio.between {
+            address.between = Some((
+                address_regex(&pair[0], clio.ignore_case)?,
+                address_regex(&pair[1], clio.ignore_case)?,
+            ));
+        }
+
+        let path_filters = |globs: &[String]| -> Result<Vec<regex::Regex>, FrErr> {
+            let filters = globs.iter().map(|glob| path_filter(glob));
+            Ok(filters.collect::<Result<_, _>>()?)
+        };
+        let include = path_filters(&clio.include)?;
+        let exclude = path_filters(&clio.exclude)?;
+
+        let mut inputs = std::mem::take(&mut clio.input);
+        inputs.append(&mut clio.files);
+        if let Some(ref list) = clio.files_from {
+            if list.as_os_str() == STDIN && inputs.iter().any(|p| p.as_os_str() == STDIN) {
+                usage_error("stdin can't be both an input and the --files-from list");
+            }
+            inputs.extend(read_file_list(list)?);
+        } else if inputs.is_empty() && clio.recursive.is_empty() {
+            inputs.push(PathBuf::from(STDIN));
+        }
+        // The list of files may have come from stdin, so it can't be read
+        // again.
+        args.input = inputs.clone();
+        args.files_from = None;
+        // Other threads don't write to stdout (which this one has locked),
+        // or start threads of their own.
+        args.worker = true;
+        args.jobs = 1;
+        args.threads = 1;
+        if clio.jobs != 1 && !(clio.write || clio.diff || clio.check) {
+            usage_error("--jobs only works with -W, --diff, or --check");
+        }
+        let cpus = || std::thread::available_parallelism().map_or(1, |n| n.get());
+        let jobs = match clio.jobs {
+            0 => cpus(),
+            n => n,
+        };
+        if clio.threads != 1 && inputs.len() != 1 {
+            usage_error("--threads works on a single input");
+        }
+        let threads = match clio.threads {
+            0 => cpus(),
+            n => n,
+        };
+        if clio.follow && (inputs.len() != 1 || inputs[0].as_os_str() == STDIN) {
+            usage_error("--follow needs exactly one input file");
+        }
+        #[cfg(feature = "http")]
+        if clio.follow && inputs[0].to_str().is_some_and(crate::http::is_url) {
+            usage_error("--follow can only follow a file, not a URL");
+        }
+        if (clio.write || clio.diff || clio.check) && inputs.iter().any(|p| p.as_os_str() == STDIN)
+        {
+            usage_error(
+                "stdin can't be edited in place (-W, --diff, and --check need input files)",
+            );
+        }
+        if clio.output_template.is_some() && inputs.iter().any(|p| p.as_os_str() == STDIN) {
+            usage_error("--output-template needs input files, not stdin");
+        }
+        if clio.output_encoding == Some(Encoding::Auto) {
+            usage_error("the output encoding can't be auto");
+        }
+        let output_encoding = clio.output_encoding.or(clio.encoding);
+        if clio.buffer_size == Some(0) {
+            usage_error("--buffer-size must be at least 1");
+        }
+        let size = clio.buffer_size;
+        let flush = match (clio.line_buffered, clio.unbuffered) {
+            (_, true) => Flush::Always,
+            (true, _) => Flush::Records,
+            _ => Flush::Blocks,
+        };
+        let output: Box<dyn Write> = match clio.output {
+            _ if clio.quiet || clio.worker => Box::new(std::io::sink()),
+            Some(pbuf) if clio.tee => {
+                let file = open_output(&pbuf, clio.append, clio.no_clobber)?;
+                Box::new(Tee::new(vec![
+                    buffered(file, size, flush),
+                    stdout(true, size, flush),
+                ]))
+            }
+            Some(pbuf) => {
+                let file = open_output(&pbuf, clio.append, clio.no_clobber)?;
+                buffered(file, size, flush)
+            }
+            None => stdout(clio.follow, size, flush),
+        };
+        let output = encoding::writer(output, output_encoding);
+        let (json_field, field) = match clio.field {
+            Some(path) if clio.jsonl => (Some(path), None),
+            Some(n) => match n.parse::<usize>() {
+                Ok(n) if n > 0 => (None, Some(n - 1)),
+                _ => usage_error("--field must be a number (from 1), unless --jsonl"),
+            },
+            None => (None, None),
+        };
+        let field_sep = clio
+            .field_sep
+            .as_deref()
+            .map(|patt| address_regex(patt, false))
+            .transpose()?;
+
+        if clio.record_bytes == Some(0) {
+            usage_error("--record-bytes must be at least 1");
+        }
+        // Editing a file keeps its line endings, unless told otherwise.
+        let keep_endings = (clio.write || clio.diff || clio.check)
+            && clio.newline.is_none()
+            && clio.output_delimiter.is_none()
+            && !clio.print0;
+        let newline = match clio.newline {
+            None if clio.print0 => Some(vec![0]),
+            // If the argument is absent, just use a newline sequence (or
+            // a blank line between paragraphs).
+            None if clio.paragraph => Some(NEWLINE.repeat(2).into_bytes()),
+            None if clio.null => Some(vec![0]),
+            None if clio.record_bytes.is_some() => None,
+            None if clio.binary => Some(b"\n".to_vec()),
+            None => Some(Vec::from(NEWLINE)),
+            // If the argument is present but has no value, make it none.
+            Some(None) => None,
+            // If the argument is present and has a value, use that.
+            Some(Some(s)) => Some(Vec::from(s)),
+        };
+        let rejoin = clio.output_delimiter.is_some();
+        let newline = match clio.output_delimiter {
+            Some(s) => Some(unescape(&s).unwrap_or_else(|e| usage_error(e))),
+            None => newline,
+        };
+        let match_separator = match clio.match_separator {
+            Some(s) => unescape(&s).unwrap_or_else(|e| usage_error(e)),
+            None if clio.print0 => vec![0],
+            // Not a blank line between the matches in a paragraph.
+            None if clio.paragraph => Vec::from(NEWLINE),
+            None => newline.clone().unwrap_or_default(),
+        };
+
+        Ok(Opts {
+            patterns,
+            labels: clio.label,
+            delimiter: match (clio.paragraph, clio.null) {
+                (true, _) => PARAGRAPH_DELIMITER.to_string(),
+                (_, true) => String::from(r"\x00"),
+                _ if clio.binary && clio.delimiter == DEFAULT_DELIMITER => String::from(r"\n"),
+                _ => clio.delimiter,
+            },
+            multiline: clio.multiline,
+            paragraph: clio.paragraph,
+            record_bytes: clio.record_bytes,
+            max_record_bytes: clio.max_record_bytes,
+            csv: clio.csv,
+            column: clio.column,
+            json_field,
+            skip_lines: if clio.header { 1 } else { clio.skip_lines },
+            drop_header: clio.drop_header,
+            field,
+            field_sep,
+            newline,
+            rejoin,
+            keep_endings,
+            match_separator,
+            final_newline: clio.final_newline,
+            quiet: clio.quiet,
+            stats: (clio.stats || clio.time).then(|| Rc::new(Stats::new(clio.stats, clio.time))),
+            selection,
+            address,
+            output_mode,
+            overlapping: clio.overlapping,
+            unique: clio.unique || clio.unique_per_file,
+            unique_per_file: clio.unique_per_file,
+            count_by: clio.count_by,
+            line_number: clio.line_number,
+            byte_offset: clio.byte_offset,
+            context,
+            format,
+            format_header: clio.format_header,
+            preserve_case: clio.preserve_case,
+            literal_replacement,
+            lookup,
+            lookup_key: clio.lookup_key,
+            lookup_default: clio
+                .lookup_default
+                .map(|s| expanded(s, MatchMode::Verbatim)),
+            transforms: clio.transform,
+            live_time: clio.time_per_match,
+            hash_salt: clio.hash_salt.into_bytes(),
+            count_per_record: clio.count_per_line,
+            before_text: clio.before_text.map(text),
+            after_text: clio.after_text.map(text),
+            match_mode,
+            engine: match clio.pcre2 {
+                true => "pcre2".parse().unwrap_or_else(|e: String| usage_error(e)),
+                false => clio.engine,
+            },
+            ignore_case: clio.ignore_case,
+            regex_flags: RegexFlags {
+                multi_line: clio.line_anchors,
+                dot_matches_new_line: clio.dot_all,
+                ignore_whitespace: clio.free_spacing,
+                unicode: !(clio.no_unicode || clio.binary),
+            },
+            invert: clio.invert,
+            anchor_start: clio.line_regexp || clio.starts_with,
+            anchor_end: clio.line_regexp || clio.ends_with,
+            fuzzy: clio.fuzzy,
+            inputs,
+            recursive: clio.recursive,
+            skip_binary: !(clio.text || clio.binary),
+            in_place: clio.write,
+            diff: clio.diff,
+            check: clio.check,
+            jobs,
+            threads,
+            mmap: clio.mmap,
+            buffer_size: clio.buffer_size,
+            flush,
+            args: Args(args),
+            backup: clio.backup,
+            preserve: clio.preserve,
+            include,
+            exclude,
+            follow: clio.follow,
+            output_template: clio.output_template,
+            archive: match clio.archive {
+                true => Some(path_filter(clio.members.as_deref().unwrap_or("*"))?),
+                false => None,
+            },
+            encoding: clio.encoding,
+            output_encoding,
+            // A file that's edited keeps its BOM, unless told otherwise.
+            keep_bom: clio.keep_bom || ((clio.write || clio.diff || clio.check) && !clio.strip_bom),
+            add_bom: clio.add_bom,
+            output,
+        })
+    }
+}
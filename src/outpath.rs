@@ -0,0 +1,124 @@
+/*!
+Output paths computed from input paths, for writing each input's output
+to a file of its own.
+
+A template is a path with placeholders for parts of the input's path:
+
+  * `{path}` is the whole path, as given;
+  * `{dir}` is the directory it's in (`.` if none is given);
+  * `{name}` is the file name;
+  * `{stem}` is the file name without its extension;
+  * `{ext}` is the extension, with its `.` (or nothing, if there isn't
+    one).
+
+`{{` and `}}` stand for literal braces. So `{dir}/{stem}.new{ext}` puts the
+output for `logs/app.txt` in `logs/app.new.txt`, and `out/{path}` mirrors
+the input's path under `out/`.
+*/
+use std::{
+    ffi::OsString,
+    path::{Path, PathBuf},
+    str::FromStr,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Part {
+    Path,
+    Dir,
+    Name,
+    Stem,
+    Ext,
+}
+
+#[derive(Clone, Debug)]
+enum Piece {
+    Literal(String),
+    Part(Part),
+}
+
+/// A template for output paths.
+#[derive(Clone, Debug)]
+pub struct PathTemplate {
+    pieces: Vec<Piece>,
+}
+
+impl FromStr for PathTemplate {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut pieces: Vec<Piece> = Vec::new();
+        let mut literal = String::new();
+        let mut chars = s.chars().peekable();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' if chars.peek() == Some(&'{') => {
+                    chars.next();
+                    literal.push('{');
+                }
+                '}' if chars.peek() == Some(&'}') => {
+                    chars.next();
+                    literal.push('}');
+                }
+                '{' => {
+                    let name: String = chars.by_ref().take_while(|&c| c != '}').collect();
+                    let part = match name.as_str() {
+                        "path" => Part::Path,
+                        "dir" => Part::Dir,
+                        "name" => Part::Name,
+                        "stem" => Part::Stem,
+                        "ext" => Part::Ext,
+                        _ => {
+                            return Err(format!(
+                                "unknown placeholder {{{}}} (use {{path}}, {{dir}}, {{name}}, {{stem}}, or {{ext}})",
+                                name
+                            ))
+                        }
+                    };
+                    if !literal.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut literal)));
+                    }
+                    pieces.push(Piece::Part(part));
+                }
+                '}' => return Err("unmatched } (use }} for a literal one)".into()),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            pieces.push(Piece::Literal(literal));
+        }
+        if !pieces.iter().any(|p| matches!(p, Piece::Part(_))) {
+            return Err("the template doesn't refer to the input path, so every output would go to the same file".into());
+        }
+        Ok(PathTemplate { pieces })
+    }
+}
+
+impl PathTemplate {
+    /// The output path for the input at `input`.
+    pub fn expand(&self, input: &Path) -> PathBuf {
+        let mut out = OsString::new();
+        for piece in self.pieces.iter() {
+            match piece {
+                Piece::Literal(text) => out.push(text),
+                Piece::Part(Part::Path) => out.push(input),
+                Piece::Part(Part::Dir) => match input.parent() {
+                    Some(dir) if !dir.as_os_str().is_empty() => out.push(dir),
+                    _ => out.push("."),
+                },
+                Piece::Part(Part::Name) => {
+                    out.push(input.file_name().unwrap_or_default());
+                }
+                Piece::Part(Part::Stem) => {
+                    out.push(input.file_stem().unwrap_or_default());
+                }
+                Piece::Part(Part::Ext) => {
+                    if let Some(ext) = input.extension() {
+                        out.push(".");
+                        out.push(ext);
+                    }
+                }
+            }
+        }
+        PathBuf::from(out)
+    }
+}
@@ -0,0 +1,298 @@
+/*!
+Running patterns with PCRE2, for `--engine pcre2` (or `--pcre2`), so
+patterns written for `grep -P` or Perl (with `\K`, conditionals,
+recursion, and so on) work as they are.
+
+This binds the system's libpcre2-8 directly; it's only the handful of
+functions it takes to compile a pattern, match it, and read back where
+the groups matched.
+*/
+use std::{
+    cell::{Cell, RefCell},
+    ffi::{c_int, c_void},
+    ptr,
+};
+
+use crate::{
+    matcher::{Caps, Matcher},
+    opt::RegexFlags,
+};
+
+mod ffi {
+    use std::ffi::{c_int, c_void};
+
+    pub const CASELESS: u32 = 0x0000_0008;
+    pub const DOTALL: u32 = 0x0000_0020;
+    pub const EXTENDED: u32 = 0x0000_0080;
+    pub const MULTILINE: u32 = 0x0000_0400;
+    pub const UCP: u32 = 0x0002_0000;
+    pub const UTF: u32 = 0x0008_0000;
+    pub const MATCH_INVALID_UTF: u32 = 0x0400_0000;
+
+    pub const INFO_CAPTURECOUNT: u32 = 4;
+    pub const INFO_NAMECOUNT: u32 = 17;
+    pub const INFO_NAMEENTRYSIZE: u32 = 18;
+    pub const INFO_NAMETABLE: u32 = 19;
+
+    pub const ERROR_NOMATCH: c_int = -1;
+    pub const UNSET: usize = !0;
+
+    #[link(name = "pcre2-8")]
+    extern "C" {
+        pub fn pcre2_compile_8(
+            pattern: *const u8,
+            length: usize,
+            options: u32,
+            error_code: *mut c_int,
+            error_offset: *mut usize,
+            context: *mut c_void,
+        ) -> *mut c_void;
+        pub fn pcre2_code_free_8(code: *mut c_void);
+        pub fn pcre2_pattern_info_8(code: *const c_void, what: u32, where_: *mut c_void) -> c_int;
+        pub fn pcre2_match_data_create_from_pattern_8(
+            code: *const c_void,
+            context: *mut c_void,
+        ) -> *mut c_void;
+        pub fn pcre2_match_data_free_8(data: *mut c_void);
+        pub fn pcre2_match_8(
+            code: *const c_void,
+            subject: *const u8,
+            length: usize,
+            start_offset: usize,
+            options: u32,
+            data: *mut c_void,
+            context: *mut c_void,
+        ) -> c_int;
+        pub fn pcre2_get_ovector_pointer_8(data: *mut c_void) -> *mut usize;
+        pub fn pcre2_get_error_message_8(
+            error_code: c_int,
+            buffer: *mut u8,
+            length: usize,
+        ) -> c_int;
+    }
+}
+
+/* PCRE2's description of `code`. */
+fn error_message(code: c_int) -> String {
+    let mut buff = [0u8; 256];
+    let len = unsafe { ffi::pcre2_get_error_message_8(code, buff.as_mut_ptr(), buff.len()) };
+    match usize::try_from(len) {
+        Ok(len) => String::from_utf8_lossy(&buff[..len]).into_owned(),
+        Err(_) => format!("error {}", code),
+    }
+}
+
+/**
+A pattern compiled by PCRE2.
+*/
+pub struct Pcre2 {
+    code: *mut c_void,
+    /// Where matches are written; PCRE2 makes it once for each pattern.
+    data: RefCell<*mut c_void>,
+    names: Vec<Option<String>>,
+    /// Whether matches have to start on character boundaries.
+    utf: bool,
+    /// Set once an error has been warned about.
+    warned: Cell<bool>,
+}
+
+impl Pcre2 {
+    pub fn new(pattern: &str, ignore_case: bool, flags: &RegexFlags) -> Result<Pcre2, String> {
+        let options = [
+            (ignore_case, ffi::CASELESS),
+            (flags.multi_line, ffi::MULTILINE),
+            (flags.dot_matches_new_line, ffi::DOTALL),
+            (flags.ignore_whitespace, ffi::EXTENDED),
+            (flags.unicode, ffi::UTF | ffi::UCP | ffi::MATCH_INVALID_UTF),
+        ]
+        .iter()
+        .filter(|(on, _)| *on)
+        .fold(0, |options, (_, option)| options | option);
+
+        let (mut error, mut offset): (c_int, usize) = (0, 0);
+        let code = unsafe {
+            ffi::pcre2_compile_8(
+                pattern.as_ptr(),
+                pattern.len(),
+                options,
+                &mut error,
+                &mut offset,
+                ptr::null_mut(),
+            )
+        };
+        if code.is_null() {
+            return Err(format!("{} at offset {}", error_message(error), offset));
+        }
+        let data = unsafe { ffi::pcre2_match_data_create_from_pattern_8(code, ptr::null_mut()) };
+        let mut pcre2 = Pcre2 {
+            code,
+            data: RefCell::new(data),
+            names: Vec::new(),
+            utf: flags.unicode,
+            warned: Cell::new(false),
+        };
+        if data.is_null() {
+            return Err("out of memory".into());
+        }
+        pcre2.names = pcre2.read_names();
+        Ok(pcre2)
+    }
+
+    fn info<T: Default>(&self, what: u32) -> T {
+        let mut value = T::default();
+        unsafe { ffi::pcre2_pattern_info_8(self.code, what, &mut value as *mut T as *mut c_void) };
+        value
+    }
+
+    /*
+    The names of the capture groups, from the name table: an entry for
+    each name, a two-byte group number and then the name, with a NUL.
+    */
+    fn read_names(&self) -> Vec<Option<String>> {
+        let n_groups = self.info::<u32>(ffi::INFO_CAPTURECOUNT) as usize + 1;
+        let mut names: Vec<Option<String>> = vec![None; n_groups];
+        let n_names = self.info::<u32>(ffi::INFO_NAMECOUNT) as usize;
+        let entry_size = self.info::<u32>(ffi::INFO_NAMEENTRYSIZE) as usize;
+        let table = self.info::<usize>(ffi::INFO_NAMETABLE) as *const u8;
+        if n_names == 0 || table.is_null() {
+            return names;
+        }
+        let table = unsafe { std::slice::from_raw_parts(table, n_names * entry_size) };
+        for entry in table.chunks(entry_size) {
+            let group = usize::from(entry[0]) << 8 | usize::from(entry[1]);
+            let name = &entry[2..];
+            let len = name.iter().position(|&b| b == 0).unwrap_or(name.len());
+            if let Some(slot) = names.get_mut(group) {
+                *slot = Some(String::from_utf8_lossy(&name[..len]).into_owned());
+            }
+        }
+        names
+    }
+}
+
+impl Drop for Pcre2 {
+    fn drop(&mut self) {
+        unsafe {
+            ffi::pcre2_match_data_free_8(*self.data.get_mut());
+            ffi::pcre2_code_free_8(self.code);
+        }
+    }
+}
+
+impl Matcher for Pcre2 {
+    fn find_at(&self, haystack: &[u8], mut start: usize, caps: &mut Caps) -> bool {
+        if self.utf {
+            while haystack
+                .get(start)
+                .is_some_and(|&b| (0x80..0xc0).contains(&b))
+            {
+                start += 1;
+            }
+        }
+        if start > haystack.len() {
+            return false;
+        }
+        let data = self.data.borrow_mut();
+        let rc = unsafe {
+            ffi::pcre2_match_8(
+                self.code,
+                haystack.as_ptr(),
+                haystack.len(),
+                start,
+                0,
+                *data,
+                ptr::null_mut(),
+            )
+        };
+        if rc < 0 {
+            if rc != ffi::ERROR_NOMATCH && !self.warned.replace(true) {
+                eprintln!("warning: pcre2: {}", error_message(rc));
+            }
+            return false;
+        }
+        let ovector = unsafe {
+            let pairs = ffi::pcre2_get_ovector_pointer_8(*data);
+            std::slice::from_raw_parts(pairs, 2 * self.names.len())
+        };
+        caps.set_groups(ovector.chunks(2).map(|pair| match pair[0] {
+            ffi::UNSET => None,
+            start => Some((start, pair[1].max(start))),
+        }));
+        true
+    }
+
+    fn group_names(&self, _pattern: usize) -> Vec<Option<&str>> {
+        self.names.iter().map(Option::as_deref).collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FLAGS: RegexFlags = RegexFlags {
+        multi_line: false,
+        dot_matches_new_line: false,
+        ignore_whitespace: false,
+        unicode: true,
+    };
+
+    /* The groups of the first match of `pattern` in `haystack`, as text. */
+    fn find(pattern: &str, haystack: &str) -> Option<Vec<Option<String>>> {
+        let pcre2 = Pcre2::new(pattern, false, &FLAGS).unwrap();
+        let mut caps = Caps::default();
+        if !pcre2.find_at(haystack.as_bytes(), 0, &mut caps) {
+            return None;
+        }
+        let groups = (0..caps.n_groups())
+            .map(|n| {
+                caps.get(n)
+                    .map(|(start, end)| haystack[start..end].to_string())
+            })
+            .collect();
+        Some(groups)
+    }
+
+    fn matched(pattern: &str, haystack: &str) -> Option<String> {
+        find(pattern, haystack).and_then(|groups| groups[0].clone())
+    }
+
+    #[test]
+    fn perl_syntax() {
+        assert_eq!(matched(r"foo=\K\d+", "foo=42"), Some("42".into()));
+        assert_eq!(matched(r"(\w+) \1", "a bb bb"), Some("bb bb".into()));
+        assert_eq!(matched(r"(<)?\w+(?(1)>)", "x <y>"), Some("x".into()));
+        assert_eq!(
+            matched(r"\((?:[^()]|(?R))*\)", "f((a)(b))"),
+            Some("((a)(b))".into())
+        );
+        assert_eq!(matched(r"(?<=\$)\d+", "5 or $6"), Some("6".into()));
+        assert_eq!(matched(r"(?>a+)a", "aaa"), None);
+        assert_eq!(matched(r"\w+", "ünï"), Some("ünï".into()));
+    }
+
+    #[test]
+    fn groups() {
+        let groups = find(r"(?<year>\d{4})-(\d\d)(-(\d\d))?", "on 2024-05!").unwrap();
+        let expected = [Some("2024-05"), Some("2024"), Some("05"), None, None];
+        assert_eq!(groups, expected.map(|g| g.map(String::from)));
+        let pcre2 = Pcre2::new(r"(?<year>\d{4})-(?P<m>\d\d)", false, &FLAGS).unwrap();
+        assert_eq!(pcre2.group_names(0), vec![None, Some("year"), Some("m")]);
+    }
+
+    #[test]
+    fn starts_on_characters() {
+        let pcre2 = Pcre2::new(r"", false, &FLAGS).unwrap();
+        let mut caps = Caps::default();
+        assert!(pcre2.find_at("é".as_bytes(), 1, &mut caps));
+        assert_eq!(caps.span(), (2, 2));
+        // Invalid UTF-8 isn't an error.
+        assert!(pcre2.find_at(b"\xff", 0, &mut caps));
+    }
+
+    #[test]
+    fn errors() {
+        let err = Pcre2::new(r"(a", false, &FLAGS).err().unwrap();
+        assert!(err.contains("missing closing parenthesis"), "{}", err);
+    }
+}
@@ -0,0 +1,224 @@
+/*!
+`printf`-style formatting of captured text.
+
+A spec looks like `%[FLAGS][WIDTH][.PRECISION]CONVERSION`, where FLAGS are
+any of `-` (left-justify), `0` (pad with zeros), `+` (always show a sign),
+` ` (space in place of a plus sign), and `#` (prefix `0x`, `0o`, or `0b`).
+Conversions are:
+
+  * `d`, `i`, `u` decimal integer
+  * `x`, `X` hexadecimal, `o` octal, `b` binary integer
+  * `f`, `F` decimal number (precision defaults to 6)
+  * `e`, `E` scientific notation (precision defaults to 6)
+  * `s` the text itself (precision truncates it)
+
+Numeric conversions of text that isn't a number leave the text as-is.
+Integer conversions of a decimal number use its integer part, so `%x` of
+`3.5` is `3` (and of `-0.5`, `0`), as `printf` in C would if the number
+were cast to an integer first.
+*/
+use crate::expr::Num;
+
+#[derive(Clone, Debug)]
+pub struct Spec {
+    left: bool,
+    zero: bool,
+    plus: bool,
+    space: bool,
+    alt: bool,
+    width: usize,
+    precision: Option<usize>,
+    conv: char,
+}
+
+/*
+Split the leading run of digits off `s`, returning its value (if any).
+*/
+fn leading_number(s: &str) -> (Option<usize>, &str) {
+    let end = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    (s[..end].parse().ok(), &s[end..])
+}
+
+impl Spec {
+    /// Parse a spec like `%08.2f`.
+    pub fn parse(spec: &str) -> Option<Spec> {
+        let mut rest = spec.strip_prefix('%')?;
+        let mut s = Spec {
+            left: false,
+            zero: false,
+            plus: false,
+            space: false,
+            alt: false,
+            width: 0,
+            precision: None,
+            conv: 's',
+        };
+
+        loop {
+            match rest.chars().next()? {
+                '-' => s.left = true,
+                '0' => s.zero = true,
+                '+' => s.plus = true,
+                ' ' => s.space = true,
+                '#' => s.alt = true,
+                _ => break,
+            }
+            rest = &rest[1..];
+        }
+
+        let (width, r) = leading_number(rest);
+        s.width = width.unwrap_or(0);
+        rest = r;
+        if let Some(r) = rest.strip_prefix('.') {
+            let (precision, r) = leading_number(r);
+            s.precision = Some(precision.unwrap_or(0));
+            rest = r;
+        }
+
+        let mut chars = rest.chars();
+        s.conv = chars.next()?;
+        if !"diuxXobfFeEs".contains(s.conv) || chars.next().is_some() {
+            return None;
+        }
+        Some(s)
+    }
+
+    /*
+    Format the magnitude of `n` per the conversion, returning whether
+    it's negative along with the digits and any radix prefix.
+    */
+    fn digits(&self, n: Num) -> (bool, &'static str, String) {
+        let precision = self.precision.unwrap_or(6);
+        match self.conv {
+            'f' | 'F' => {
+                let x = n.as_f64();
+                (x < 0.0, "", format!("{:.*}", precision, x.abs()))
+            }
+            'e' | 'E' => {
+                let x = n.as_f64();
+                let s = format!("{:.*e}", precision, x.abs());
+                // Rust writes `1.5e3`; printf writes `1.5e+03`.
+                let (mantissa, exp) = s.split_once('e').unwrap_or((&s, "0"));
+                let exp: i32 = exp.parse().unwrap_or(0);
+                let sign = if exp < 0 { '-' } else { '+' };
+                let e = if self.conv == 'E' { 'E' } else { 'e' };
+                let s = format!("{}{}{}{:02}", mantissa, e, sign, exp.abs());
+                (x < 0.0, "", s)
+            }
+            _ => {
+                let n = match n {
+                    Num::Int(n) => n,
+                    Num::Float(x) => x.trunc() as i64,
+                };
+                let m = n.unsigned_abs();
+                let (prefix, s) = match self.conv {
+                    'x' => ("0x", format!("{:x}", m)),
+                    'X' => ("0X", format!("{:X}", m)),
+                    'o' => ("0o", format!("{:o}", m)),
+                    'b' => ("0b", format!("{:b}", m)),
+                    _ => ("", m.to_string()),
+                };
+                let prefix = if self.alt { prefix } else { "" };
+                // With integers, precision is the minimum number of digits.
+                let s = match self.precision {
+                    Some(p) if p > s.len() => format!("{}{}", "0".repeat(p - s.len()), s),
+                    _ => s,
+                };
+                (n < 0, prefix, s)
+            }
+        }
+    }
+
+    /// Format `text` according to this spec.
+    pub fn apply(&self, text: &str) -> String {
+        let (sign, prefix, body) = match self.conv {
+            's' => {
+                let body: String = match self.precision {
+                    Some(p) => text.chars().take(p).collect(),
+                    None => text.to_string(),
+                };
+                ("", "", body)
+            }
+            _ => match Num::parse(text) {
+                Some(n) => {
+                    let (negative, prefix, body) = self.digits(n);
+                    let sign = match (negative, self.plus, self.space) {
+                        (true, _, _) => "-",
+                        (false, true, _) => "+",
+                        (false, false, true) => " ",
+                        _ => "",
+                    };
+                    (sign, prefix, body)
+                }
+                None => return text.to_string(),
+            },
+        };
+
+        let len = sign.len() + prefix.len() + body.chars().count();
+        let pad = self.width.saturating_sub(len);
+        if self.left {
+            format!("{}{}{}{}", sign, prefix, body, " ".repeat(pad))
+        } else if self.zero && self.conv != 's' {
+            format!("{}{}{}{}", sign, prefix, "0".repeat(pad), body)
+        } else {
+            format!("{}{}{}{}", " ".repeat(pad), sign, prefix, body)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn printf(spec: &str, text: &str) -> String {
+        Spec::parse(spec).unwrap().apply(text)
+    }
+
+    #[test]
+    fn decimals() {
+        assert_eq!(printf("%08.2f", "3.14159"), "00003.14");
+        assert_eq!(printf("%08.2f", "-3.14159"), "-0003.14");
+        assert_eq!(printf("%.1f", "2"), "2.0");
+        assert_eq!(printf("%f", "1.5"), "1.500000");
+        assert_eq!(printf("%+.0f", "7"), "+7");
+        assert_eq!(printf("%e", "1500"), "1.500000e+03");
+        assert_eq!(printf("%.2E", "-0.00123"), "-1.23E-03");
+    }
+
+    #[test]
+    fn integers() {
+        assert_eq!(printf("%x", "255"), "ff");
+        assert_eq!(printf("%#X", "255"), "0XFF");
+        assert_eq!(printf("%#o", "8"), "0o10");
+        assert_eq!(printf("%b", "5"), "101");
+        assert_eq!(printf("%x", "-255"), "-ff");
+        assert_eq!(printf("%05d", "-42"), "-0042");
+        assert_eq!(printf("% d", "42"), " 42");
+        assert_eq!(printf("%.4d", "42"), "0042");
+        assert_eq!(printf("%6.4d", "42"), "  0042");
+        // Decimals are cut down to their integer part.
+        assert_eq!(printf("%x", "3.5"), "3");
+        assert_eq!(printf("%d", "-0.5"), "0");
+        assert_eq!(printf("%d", "-2.9"), "-2");
+    }
+
+    #[test]
+    fn strings() {
+        assert_eq!(printf("%-5s", "ab"), "ab   ");
+        assert_eq!(printf("%5s", "ab"), "   ab");
+        assert_eq!(printf("%05s", "ab"), "   ab");
+        assert_eq!(printf("%-5s", "toolong"), "toolong");
+        assert_eq!(printf("%.2s", "héllo"), "hé");
+        assert_eq!(printf("%4s", "né"), "  né");
+        // Text that isn't a number is left alone by numeric conversions.
+        assert_eq!(printf("%08.2f", "n/a"), "n/a");
+        assert_eq!(printf("%x", ""), "");
+    }
+
+    #[test]
+    fn bad_specs() {
+        for spec in ["", "d", "%", "%q", "%5", "%.2", "%dd", "%-"] {
+            assert!(Spec::parse(spec).is_none(), "{}", spec);
+        }
+    }
+}
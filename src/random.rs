@@ -0,0 +1,104 @@
+/*!
+Random values for `{{uuid}}` and `{{rand:...}}` placeholders.
+
+These need to look random, not resist attack, so a small SplitMix64
+generator seeded from the standard library's per-process hash keys and
+the clock does the job.
+*/
+use std::{
+    cell::Cell,
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    str::FromStr,
+    time::SystemTime,
+};
+
+/// The kinds of characters a `{{rand:KIND:N}}` token is made of.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Charset {
+    Hex,
+    Digits,
+    Alpha,
+    Alnum,
+}
+
+impl FromStr for Charset {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "hex" => Ok(Charset::Hex),
+            "dec" | "digits" => Ok(Charset::Digits),
+            "alpha" => Ok(Charset::Alpha),
+            "alnum" => Ok(Charset::Alnum),
+            _ => Err(format!("unknown character set {:?}", s)),
+        }
+    }
+}
+
+impl Charset {
+    fn chars(&self) -> &'static [u8] {
+        match self {
+            Charset::Hex => b"0123456789abcdef",
+            Charset::Digits => b"0123456789",
+            Charset::Alpha => b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ",
+            Charset::Alnum => b"0123456789abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ",
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct Rng {
+    state: Cell<u64>,
+}
+
+impl Default for Rng {
+    fn default() -> Self {
+        let mut hasher = RandomState::new().build_hasher();
+        if let Ok(d) = SystemTime::now().duration_since(SystemTime::UNIX_EPOCH) {
+            hasher.write_u128(d.as_nanos());
+        }
+        hasher.write_u32(std::process::id());
+        Rng {
+            state: Cell::new(hasher.finish()),
+        }
+    }
+}
+
+impl Rng {
+    pub fn next_u64(&self) -> u64 {
+        let state = self.state.get().wrapping_add(0x9e37_79b9_7f4a_7c15);
+        self.state.set(state);
+        let mut z = state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+        z ^ (z >> 31)
+    }
+
+    /// Append a random (version 4) UUID to `dst`.
+    pub fn uuid(&self, dst: &mut Vec<u8>) {
+        const HEX: &[u8; 16] = b"0123456789abcdef";
+        let mut bytes = [0u8; 16];
+        bytes[..8].copy_from_slice(&self.next_u64().to_le_bytes());
+        bytes[8..].copy_from_slice(&self.next_u64().to_le_bytes());
+        bytes[6] = (bytes[6] & 0x0f) | 0x40;
+        bytes[8] = (bytes[8] & 0x3f) | 0x80;
+
+        for (n, b) in bytes.iter().enumerate() {
+            if matches!(n, 4 | 6 | 8 | 10) {
+                dst.push(b'-');
+            }
+            dst.push(HEX[(b >> 4) as usize]);
+            dst.push(HEX[(b & 0xf) as usize]);
+        }
+    }
+
+    /// Append `len` random characters from `charset` to `dst`.
+    pub fn token(&self, charset: Charset, len: usize, dst: &mut Vec<u8>) {
+        let chars = charset.chars();
+        for _ in 0..len {
+            // The slight bias from taking a remainder doesn't matter here.
+            dst.push(chars[(self.next_u64() % chars.len() as u64) as usize]);
+        }
+    }
+}
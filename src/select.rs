@@ -0,0 +1,72 @@
+/*!
+Choosing which of the matches in each record get operated on.
+*/
+use crate::matcher::{Caps, Matches};
+
+#[derive(Clone, Debug)]
+pub struct Selection {
+    /// Operate on at most this many matches per record.
+    pub max: usize,
+    /// Leave this many matches at the start of each record alone.
+    pub skip: usize,
+    /// If not empty, only operate on these matches (counting from 1),
+    /// in ascending order.
+    pub nth: Vec<usize>,
+    /// If present, only operate on this many matches at the end of
+    /// each record.
+    pub last: Option<usize>,
+    /// Operate on at most this many matches in the entire input.
+    pub max_total: usize,
+}
+
+impl Selection {
+    /*
+    Whether, having found `n_found` matches, there's no point looking for
+    any more. (When counting from the end, every match must be found.)
+    */
+    fn enough(&self, n_found: usize) -> bool {
+        if self.last.is_some() {
+            return false;
+        }
+        if n_found >= self.skip.saturating_add(self.max) {
+            return true;
+        }
+        match self.nth.last() {
+            Some(&n) => n_found >= n,
+            None => false,
+        }
+    }
+
+    /// Whether the `n`th of `total` matches in a record (counting from 1)
+    /// should be operated on.
+    fn wants(&self, n: usize, total: usize) -> bool {
+        n > self.skip
+            && (self.nth.is_empty() || self.nth.binary_search(&n).is_ok())
+            && self.last.map_or(true, |last| n + last > total)
+    }
+
+    /**
+    Run through `matches`, replacing the contents of `found` with the
+    ones that should be operated on.
+    */
+    pub fn find(&self, mut matches: Matches, found: &mut Vec<Caps>) {
+        found.clear();
+        let mut caps = Caps::default();
+        while !self.enough(found.len()) && matches.find_next(&mut caps) {
+            found.push(caps.clone());
+        }
+
+        let total = found.len();
+        let mut n: usize = 0;
+        let mut n_selected: usize = 0;
+        found.retain(|_| {
+            n += 1;
+            if n_selected < self.max && self.wants(n, total) {
+                n_selected += 1;
+                true
+            } else {
+                false
+            }
+        });
+    }
+}
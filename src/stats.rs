@@ -0,0 +1,218 @@
+/*!
+Counting what a run did, for `--stats`, and timing it, for `--time`.
+
+The counters are shared (through an `Rc`) by everything that has
+something to count: the input and output streams count bytes, the inputs
+count files, and the processing loops count records, matches, and
+replacements. The totals are reported on stderr at the end.
+
+With `--time`, the streams also time how long reading and writing take,
+and the matcher is wrapped to time how long matching does; nothing is
+timed otherwise.
+*/
+use std::{
+    cell::Cell,
+    io::{self, Read, Write},
+    rc::Rc,
+    time::{Duration, Instant},
+};
+
+/// A number that only goes up.
+#[derive(Clone, Default)]
+pub struct Counter(Cell<u64>);
+
+impl Counter {
+    pub fn add(&self, n: usize) {
+        self.0.set(self.0.get() + n as u64);
+    }
+
+    pub fn get(&self) -> u64 {
+        self.0.get()
+    }
+}
+
+/// The time spent doing something, all told.
+#[derive(Clone, Default)]
+pub struct Timer(Cell<Duration>);
+
+impl Timer {
+    /// Do `f`, adding how long it takes.
+    pub fn time<T>(&self, f: impl FnOnce() -> T) -> T {
+        let start = Instant::now();
+        let result = f();
+        self.add(start.elapsed());
+        result
+    }
+
+    pub fn add(&self, time: Duration) {
+        self.0.set(self.0.get() + time);
+    }
+
+    pub fn get(&self) -> Duration {
+        self.0.get()
+    }
+}
+
+#[derive(Clone, Default)]
+pub struct Stats {
+    /// Whether the counts are to be reported (with `--stats`).
+    pub counted: bool,
+    /// Whether reading, matching, and writing are timed (with `--time`).
+    pub timed: bool,
+    pub records: Counter,
+    pub matches: Counter,
+    pub replacements: Counter,
+    pub bytes_in: Counter,
+    pub bytes_out: Counter,
+    pub files: Counter,
+    pub reading: Timer,
+    pub matching: Timer,
+    pub writing: Timer,
+}
+
+impl Stats {
+    pub fn new(counted: bool, timed: bool) -> Stats {
+        Stats {
+            counted,
+            timed,
+            ..Stats::default()
+        }
+    }
+
+    /// Add the counts (and times) in `other` (kept by another thread) to
+    /// these.
+    pub fn merge(&self, other: &Stats) {
+        self.records.add(other.records.get() as usize);
+        self.matches.add(other.matches.get() as usize);
+        self.replacements.add(other.replacements.get() as usize);
+        self.bytes_in.add(other.bytes_in.get() as usize);
+        self.bytes_out.add(other.bytes_out.get() as usize);
+        self.files.add(other.files.get() as usize);
+        self.reading.add(other.reading.get());
+        self.matching.add(other.matching.get());
+        self.writing.add(other.writing.get());
+    }
+
+    /// Describe the totals (and how long it all took, and how quickly the
+    /// input went by, and what the time went on, if timed), as lines of
+    /// text, or as a JSON object if `json` is true.
+    pub fn report(&self, elapsed: Duration, json: bool) -> String {
+        let mut fields: Vec<(&str, &str, String)> = Vec::new();
+        if self.counted {
+            let counts = [
+                ("records", "records processed", self.records.get()),
+                ("matches", "matches found", self.matches.get()),
+                ("replacements", "replacements made", self.replacements.get()),
+                ("bytes_in", "bytes in", self.bytes_in.get()),
+                ("bytes_out", "bytes out", self.bytes_out.get()),
+                ("files", "files", self.files.get()),
+            ];
+            for (key, label, n) in counts {
+                fields.push((key, label, n.to_string()));
+            }
+        }
+        let seconds = elapsed.as_secs_f64();
+        let unit = if json { "" } else { "s" };
+        fields.push(("elapsed", "elapsed", format!("{:.3}{}", seconds, unit)));
+        if self.timed {
+            // Whatever isn't reading, matching, or writing.
+            let other = elapsed
+                .saturating_sub(self.reading.get())
+                .saturating_sub(self.matching.get())
+                .saturating_sub(self.writing.get());
+            let times = [
+                ("reading", self.reading.get()),
+                ("matching", self.matching.get()),
+                ("writing", self.writing.get()),
+                ("other", other),
+            ];
+            for (key, time) in times {
+                fields.push((key, key, format!("{:.3}{}", time.as_secs_f64(), unit)));
+            }
+            let rate = |n: u64| match seconds > 0.0 {
+                true => format!("{:.0}", n as f64 / seconds),
+                false => "0".into(),
+            };
+            let bytes = rate(self.bytes_in.get());
+            fields.push(("bytes_per_sec", "bytes read per second", bytes));
+            let records = rate(self.records.get());
+            fields.push(("records_per_sec", "records per second", records));
+        }
+        if json {
+            let fields: Vec<String> = fields
+                .iter()
+                .map(|(key, _, value)| format!("\"{}\":{}", key, value))
+                .collect();
+            format!("{{{}}}\n", fields.join(","))
+        } else {
+            let mut text = String::new();
+            for (_, label, value) in fields.iter() {
+                text.push_str(&format!("{}: {}\n", label, value));
+            }
+            text
+        }
+    }
+}
+
+/// Counts the bytes read through it.
+struct CountIn {
+    inner: Box<dyn Read>,
+    stats: Rc<Stats>,
+}
+
+impl Read for CountIn {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        let n = match self.stats.timed {
+            true => self.stats.reading.time(|| self.inner.read(buf))?,
+            false => self.inner.read(buf)?,
+        };
+        self.stats.bytes_in.add(n);
+        Ok(n)
+    }
+}
+
+/// Counts the bytes written through it.
+struct CountOut {
+    inner: Box<dyn Write>,
+    stats: Rc<Stats>,
+}
+
+impl Write for CountOut {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        let n = match self.stats.timed {
+            true => self.stats.writing.time(|| self.inner.write(buf))?,
+            false => self.inner.write(buf)?,
+        };
+        self.stats.bytes_out.add(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        match self.stats.timed {
+            true => self.stats.writing.time(|| self.inner.flush()),
+            false => self.inner.flush(),
+        }
+    }
+}
+
+/// Count what's read from `input`, if there are `stats` to keep.
+pub fn reader(input: Box<dyn Read>, stats: &Option<Rc<Stats>>) -> Box<dyn Read> {
+    match stats {
+        Some(stats) => Box::new(CountIn {
+            inner: input,
+            stats: stats.clone(),
+        }),
+        None => input,
+    }
+}
+
+/// Count what's written to `output`, if there are `stats` to keep.
+pub fn writer(output: Box<dyn Write>, stats: &Option<Rc<Stats>>) -> Box<dyn Write> {
+    match stats {
+        Some(stats) => Box::new(CountOut {
+            inner: output,
+            stats: stats.clone(),
+        }),
+        None => output,
+    }
+}
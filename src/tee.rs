@@ -0,0 +1,33 @@
+/*!
+Writing the output to more than one place at once, for `--tee`.
+*/
+use std::io::{self, Write};
+
+/// Writes everything written to it to each of its outputs, in turn.
+pub struct Tee {
+    outputs: Vec<Box<dyn Write>>,
+}
+
+impl Tee {
+    pub fn new(outputs: Vec<Box<dyn Write>>) -> Tee {
+        Tee { outputs }
+    }
+}
+
+impl Write for Tee {
+    // Each output gets the whole of `buf`, or a short write to one of them
+    // would leave them out of step.
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        for output in self.outputs.iter_mut() {
+            output.write_all(buf)?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        for output in self.outputs.iter_mut() {
+            output.flush()?;
+        }
+        Ok(())
+    }
+}
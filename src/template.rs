@@ -0,0 +1,801 @@
+/*!
+Replacement text, with references to capture groups expanded per match.
+
+The basic syntax is the same as that of the `regex` crate's
+[`Captures::expand`](https://docs.rs/regex/latest/regex/bytes/struct.Captures.html#method.expand):
+`$N` or `${N}` for numbered groups, `$name` or `${name}` for named groups,
+and `$$` for a literal dollar sign. On top of that:
+
+  * `${name:-fallback}` inserts the text `fallback` if the group is empty
+    or didn't participate in the match.
+  * `${name:%spec}` formats the group with a `printf`-style spec, like
+    `%08.2f` or `%x` (see the `printf` module).
+  * `$#` is the number of times the template has been expanded (counting
+    from 1).
+  * `$[EXPRESSION]` is the result of some arithmetic (see the `expr`
+    module), where `{N}` or `{name}` stands for the number captured by a
+    group; it expands to nothing if a group doesn't hold a number.
+  * `{{now}}` or `{{now:FORMAT}}` is the current time (see the `time`
+    module for formats); by default it's the time the template was made,
+    but it can be the time of expansion instead.
+  * `{{sha256}}` and `{{md5}}` are digests (in hex) of the matched text,
+    optionally salted.
+  * `{{repeat:TEXT:N}}` is `TEXT` repeated `N` times, and
+    `{{pad:TEXT:WIDTH:FILL}}` is `TEXT` followed by enough `FILL`
+    characters (spaces if omitted) to make it `WIDTH` characters long;
+    `{{lpad:...}}` puts them in front instead. `TEXT` can be a group
+    reference like `$1` or `${name}`.
+  * With the `random` feature, `{{uuid}}` is a random UUID, and
+    `{{rand:KIND:N}}` is `N` random characters of `KIND` (`hex`, `dec`,
+    `alpha`, or `alnum`; `{{rand}}` alone is `{{rand:hex:16}}`), both
+    different for each expansion.
+  * A template can also be a lookup: the text of one group is looked up
+    in a table, and the match is replaced with what's found there.
+  * As in sed and Perl, `\U` and `\L` convert everything that follows to
+    upper- or lowercase (until `\E`), and `\u` and `\l` convert just the
+    next character.
+*/
+use std::{cell::Cell, collections::HashMap, rc::Rc, time::SystemTime};
+
+#[cfg(feature = "random")]
+use crate::random::{Charset, Rng};
+use crate::{
+    expr::{Expr, Num},
+    hash::Digest,
+    matcher::{decode_char, Caps, Matcher},
+    printf::Spec,
+    time,
+    transform::Transform,
+};
+
+/// Maps keys to the text that should replace them, for lookups.
+pub type Table = HashMap<Vec<u8>, Vec<u8>>;
+
+/// Default format for `{{now}}`.
+const ISO_8601: &str = "%Y-%m-%dT%H:%M:%SZ";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+enum Case {
+    Upper,
+    Lower,
+}
+
+#[derive(Clone, Debug)]
+enum Piece {
+    Literal(Vec<u8>),
+    Group(usize),
+    /// A named group whose index differs between patterns.
+    Named(Vec<Option<usize>>),
+    /// The number of times the template has been expanded.
+    Counter,
+    /// A group, formatted per a printf-style spec.
+    Formatted(Box<Piece>, Spec),
+    /// The result of an arithmetic expression, with its variables'
+    /// values taken from the given groups.
+    Calc(Expr, Vec<Piece>),
+    /// The current time, in the given format.
+    Now(String),
+    /// A digest of the matched text, in hex.
+    Hash(Digest),
+    /// Some text, repeated the given number of times.
+    Repeat(Box<Piece>, usize),
+    /// Some text, padded to the given width (in characters) with the
+    /// given character, on the left if `true`, else on the right.
+    Pad(Box<Piece>, usize, char, bool),
+    /// The value in the table under a group's text, or else the given
+    /// text, or else the whole match.
+    Lookup(Box<Piece>, Rc<Table>, Option<Vec<u8>>),
+    /// A random UUID.
+    #[cfg(feature = "random")]
+    Uuid,
+    /// A random token of the given number of characters.
+    #[cfg(feature = "random")]
+    Rand(Charset, usize),
+    /// A group, or the given text if the group is empty or didn't
+    /// participate in the match.
+    OrElse(Box<Piece>, Vec<u8>),
+    /// Convert the case of everything that follows (or stop, if `None`).
+    CaseAll(Option<Case>),
+    /// Convert the case of the next character.
+    CaseNext(Case),
+}
+
+#[derive(Clone, Debug)]
+pub struct Template {
+    pieces: Vec<Piece>,
+    preserve_case: bool,
+    /// Number of expansions so far, for `$#`.
+    count: Cell<usize>,
+    /// Applied, in order, to each whole expansion.
+    transforms: Vec<Transform>,
+    /// Prepended to matched text before hashing it.
+    salt: Vec<u8>,
+    /// When the template was made, for `{{now}}`.
+    made: SystemTime,
+    #[cfg(feature = "random")]
+    rng: Rng,
+    /// Whether `{{now}}` should be the time of each expansion instead.
+    live_time: bool,
+}
+
+impl Piece {
+    /* Whether this piece (or any piece in it) is `$#`. */
+    fn counts(&self) -> bool {
+        match self {
+            Piece::Counter => true,
+            Piece::Formatted(piece, _)
+            | Piece::Repeat(piece, _)
+            | Piece::Pad(piece, ..)
+            | Piece::Lookup(piece, ..)
+            | Piece::OrElse(piece, _) => piece.counts(),
+            Piece::Calc(_, pieces) => pieces.iter().any(Piece::counts),
+            _ => false,
+        }
+    }
+
+    /*
+    The span of the text in the match `caps` that a group piece refers
+    to, if any.
+    */
+    fn span(&self, caps: &Caps) -> Option<(usize, usize)> {
+        match self {
+            Piece::Group(n) => caps.get(*n),
+            Piece::Named(idxs) => idxs
+                .get(caps.pattern())
+                .copied()
+                .flatten()
+                .and_then(|n| caps.get(n)),
+            _ => None,
+        }
+    }
+
+    /*
+    The text a piece that's an argument to a placeholder stands for: its
+    literal text, or the text of the group it refers to.
+    */
+    fn text<'a>(&'a self, haystack: &'a [u8], caps: &Caps) -> &'a [u8] {
+        match self {
+            Piece::Literal(v) => v,
+            _ => match self.span(caps) {
+                Some((start, end)) => &haystack[start..end],
+                None => &[],
+            },
+        }
+    }
+}
+
+/*
+A reference to a capture group (with its fallback text, if it was
+written `${name:-fallback}`, or its format, if it was written
+`${name:%spec}`), and the length of the text (including the
+leading `$`) that made it up.
+*/
+struct CapRef<'a> {
+    name: &'a str,
+    default: Option<&'a [u8]>,
+    format: Option<Spec>,
+    len: usize,
+}
+
+fn is_cap_letter(b: u8) -> bool {
+    matches!(b, b'0'..=b'9' | b'a'..=b'z' | b'A'..=b'Z' | b'_')
+}
+
+/*
+Parse a capture group reference from the beginning of `text`, which
+should start with a `$`.
+*/
+fn find_cap_ref(text: &[u8]) -> Option<CapRef<'_>> {
+    if text.len() < 2 || text[0] != b'$' {
+        return None;
+    }
+
+    if text[1] == b'{' {
+        let end = text[2..].iter().position(|&b| b == b'}')? + 2;
+        let inside = &text[2..end];
+        let sep = inside.windows(2).position(|w| w == b":-" || w == b":%");
+        let (name, default, format) = match sep {
+            Some(n) if inside[n + 1] == b'-' => (&inside[..n], Some(&inside[n + 2..]), None),
+            Some(n) => {
+                let spec = std::str::from_utf8(&inside[n + 1..]).ok()?;
+                (&inside[..n], None, Some(Spec::parse(spec)?))
+            }
+            None => (inside, None, None),
+        };
+        return Some(CapRef {
+            name: std::str::from_utf8(name).ok()?,
+            default,
+            format,
+            len: end + 1,
+        });
+    }
+
+    let end = text[1..]
+        .iter()
+        .position(|&b| !is_cap_letter(b))
+        .map(|n| n + 1)
+        .unwrap_or(text.len());
+    if end == 1 {
+        return None;
+    }
+    Some(CapRef {
+        // Capture letters are all ASCII.
+        name: std::str::from_utf8(&text[1..end]).unwrap(),
+        default: None,
+        format: None,
+        len: end,
+    })
+}
+
+/*
+Make the piece that refers to the group `name` (a number or a name) in
+`matcher`'s pattern(s), if any pattern has such a group.
+*/
+fn group_piece(name: &str, matcher: &dyn Matcher) -> Option<Piece> {
+    if let Ok(n) = name.parse::<usize>() {
+        let exists = (0..matcher.n_patterns()).any(|p| n < matcher.group_names(p).len());
+        return exists.then_some(Piece::Group(n));
+    }
+    let idxs: Vec<Option<usize>> = (0..matcher.n_patterns())
+        .map(|p| matcher.group_index(p, name))
+        .collect();
+    if idxs.iter().all(|idx| *idx == idxs[0]) {
+        idxs[0].map(Piece::Group)
+    } else {
+        Some(Piece::Named(idxs))
+    }
+}
+
+/*
+Explain that the group `name` doesn't exist in any of `matcher`'s
+patterns, and list the groups that do.
+*/
+fn no_such_group(name: &str, matcher: &dyn Matcher) -> String {
+    let mut groups: Vec<String> = Vec::new();
+    for p in 0..matcher.n_patterns() {
+        for (n, group_name) in matcher.group_names(p).into_iter().enumerate() {
+            let group = match group_name {
+                Some(group_name) => format!("{} ({})", n, group_name),
+                None => n.to_string(),
+            };
+            if !groups.contains(&group) {
+                groups.push(group);
+            }
+        }
+    }
+    format!(
+        "replacement refers to group {:?}, which isn't in the pattern; \
+        available groups are: {}",
+        name,
+        groups.join(", ")
+    )
+}
+
+/*
+Parse a `$[EXPRESSION]` calculation from the beginning of `text`,
+returning it and its length.
+*/
+fn find_calc(text: &[u8], matcher: &dyn Matcher) -> Result<Option<(Piece, usize)>, String> {
+    let parsed = text.strip_prefix(b"$[").and_then(|rest| {
+        let end = rest.iter().position(|&b| b == b']')?;
+        Some((Expr::parse(&rest[..end])?, end))
+    });
+    let ((expr, names), end) = match parsed {
+        Some(parsed) => parsed,
+        None => return Ok(None),
+    };
+    let vars = names
+        .iter()
+        .map(|name| group_piece(name, matcher).ok_or_else(|| no_such_group(name, matcher)))
+        .collect::<Result<Vec<_>, _>>()?;
+    Ok(Some((Piece::Calc(expr, vars), end + 3)))
+}
+
+/*
+Parse the text argument of a placeholder like `{{repeat:TEXT:N}}`, which
+is either a capture group reference or literal text.
+*/
+fn text_arg(arg: &str, matcher: &dyn Matcher) -> Result<Piece, String> {
+    match find_cap_ref(arg.as_bytes()) {
+        Some(cap_ref)
+            if cap_ref.len == arg.len()
+                && cap_ref.default.is_none()
+                && cap_ref.format.is_none() =>
+        {
+            group_piece(cap_ref.name, matcher).ok_or_else(|| no_such_group(cap_ref.name, matcher))
+        }
+        _ => Ok(Piece::Literal(arg.as_bytes().to_vec())),
+    }
+}
+
+/*
+Parse a `{{name}}` or `{{name:ARGS}}` placeholder from the beginning of
+`text`, returning it and its length.
+*/
+fn find_placeholder(text: &[u8], matcher: &dyn Matcher) -> Result<Option<(Piece, usize)>, String> {
+    let rest = match text.strip_prefix(b"{{") {
+        Some(rest) => rest,
+        None => return Ok(None),
+    };
+    let end = match rest.windows(2).position(|w| w == b"}}") {
+        // An argument like `${1}` can end right before the closing `}}`.
+        Some(n) => n + rest[n + 2..].iter().take_while(|&&b| b == b'}').count(),
+        None => return Ok(None),
+    };
+    let inside = match std::str::from_utf8(&rest[..end]) {
+        Ok(inside) => inside,
+        Err(_) => return Ok(None),
+    };
+    let (name, args) = match inside.split_once(':') {
+        Some((name, args)) => (name, Some(args)),
+        None => (inside, None),
+    };
+
+    let piece = match (name, args) {
+        ("now", None) => Piece::Now(ISO_8601.to_string()),
+        ("now", Some(fmt)) => Piece::Now(fmt.to_string()),
+        ("sha256", None) => Piece::Hash(Digest::Sha256),
+        ("md5", None) => Piece::Hash(Digest::Md5),
+        #[cfg(feature = "random")]
+        ("uuid", None) => Piece::Uuid,
+        #[cfg(feature = "random")]
+        ("rand", None) => Piece::Rand(Charset::Hex, 16),
+        #[cfg(feature = "random")]
+        ("rand", Some(args)) => {
+            let (kind, len) = args.split_once(':').unwrap_or((args, "16"));
+            match (kind.parse(), len.parse()) {
+                (Ok(kind), Ok(len)) => Piece::Rand(kind, len),
+                _ => return Ok(None),
+            }
+        }
+        ("repeat", Some(args)) => {
+            let (arg, n) = match args.rsplit_once(':').map(|(a, n)| (a, n.parse())) {
+                Some((arg, Ok(n))) => (arg, n),
+                _ => return Ok(None),
+            };
+            Piece::Repeat(Box::new(text_arg(arg, matcher)?), n)
+        }
+        ("pad" | "lpad", Some(args)) => {
+            // The fill character comes last, so it can be a `:`.
+            let mut args = args.splitn(3, ':');
+            let arg = args.next().unwrap_or_default();
+            let width = args.next().map(str::parse::<usize>);
+            let mut fill = args.next().unwrap_or(" ").chars();
+            let (width, fill) = match (width, fill.next(), fill.next()) {
+                (Some(Ok(width)), Some(fill), None) => (width, fill),
+                _ => return Ok(None),
+            };
+            Piece::Pad(
+                Box::new(text_arg(arg, matcher)?),
+                width,
+                fill,
+                name == "lpad",
+            )
+        }
+        _ => return Ok(None),
+    };
+    Ok(Some((piece, end + 4)))
+}
+
+/*
+Parse a case conversion directive from the beginning of `text`, which
+should start with a `\`.
+*/
+fn find_case_op(text: &[u8]) -> Option<Piece> {
+    match text.get(1)? {
+        b'U' => Some(Piece::CaseAll(Some(Case::Upper))),
+        b'L' => Some(Piece::CaseAll(Some(Case::Lower))),
+        b'E' => Some(Piece::CaseAll(None)),
+        b'u' => Some(Piece::CaseNext(Case::Upper)),
+        b'l' => Some(Piece::CaseNext(Case::Lower)),
+        _ => None,
+    }
+}
+
+/*
+Append `text` to `dst`, converting the case of its characters as
+directed. Bytes that aren't valid UTF-8 are copied unchanged.
+*/
+fn push_cased(text: &[u8], all: Option<Case>, next: &mut Option<Case>, dst: &mut Vec<u8>) {
+    if all.is_none() && next.is_none() {
+        dst.extend_from_slice(text);
+        return;
+    }
+
+    let mut utf8 = [0u8; 4];
+    let mut rest = text;
+    while let Some(&b) = rest.first() {
+        let (c, len) = match decode_char(rest) {
+            Some(x) => x,
+            None => {
+                dst.push(b);
+                rest = &rest[1..];
+                continue;
+            }
+        };
+        match next.take().or(all) {
+            Some(Case::Upper) => {
+                for c in c.to_uppercase() {
+                    dst.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                }
+            }
+            Some(Case::Lower) => {
+                for c in c.to_lowercase() {
+                    dst.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                }
+            }
+            None => dst.extend_from_slice(&rest[..len]),
+        }
+        rest = &rest[len..];
+    }
+}
+
+/*
+Work out the case conversion that would make text look like `model`:
+all uppercase, all lowercase, or just the first character uppercase
+(returned as in `push_cased()`). Mixed case gives no conversion.
+*/
+fn case_of(model: &[u8]) -> (Option<Case>, Option<Case>) {
+    let mut first: Option<bool> = None;
+    let (mut n_upper, mut n_lower) = (0usize, 0usize);
+    for c in String::from_utf8_lossy(model).chars() {
+        if c.is_uppercase() {
+            n_upper += 1;
+            first.get_or_insert(true);
+        } else if c.is_lowercase() {
+            n_lower += 1;
+            first.get_or_insert(false);
+        }
+    }
+
+    match (first, n_upper, n_lower) {
+        (Some(true), 1, _) => (None, Some(Case::Upper)),
+        (_, _, 0) if n_upper > 0 => (Some(Case::Upper), None),
+        (_, 0, _) if n_lower > 0 => (Some(Case::Lower), None),
+        _ => (None, None),
+    }
+}
+
+impl Template {
+    fn new(pieces: Vec<Piece>) -> Template {
+        Template {
+            pieces,
+            preserve_case: false,
+            count: Cell::new(0),
+            transforms: Vec::new(),
+            salt: Vec::new(),
+            made: SystemTime::now(),
+            #[cfg(feature = "random")]
+            rng: Rng::default(),
+            live_time: false,
+        }
+    }
+
+    /// A template that inserts `text` verbatim.
+    pub fn literal(text: &[u8]) -> Template {
+        Template::new(vec![Piece::Literal(text.to_vec())])
+    }
+
+    /// A template that inserts the entire matched text.
+    pub fn whole_match() -> Template {
+        Template::new(vec![Piece::Group(0)])
+    }
+
+    /**
+    A template that looks up the text of the group `key` in `table`,
+    inserting `default` (or, without one, the entire matched text) when
+    it isn't there.
+    */
+    pub fn lookup(
+        table: Rc<Table>,
+        key: &str,
+        default: Option<&[u8]>,
+        matcher: &dyn Matcher,
+    ) -> Result<Template, String> {
+        let key = group_piece(key, matcher).ok_or_else(|| no_such_group(key, matcher))?;
+        Ok(Template::new(vec![Piece::Lookup(
+            Box::new(key),
+            table,
+            default.map(|d| d.to_vec()),
+        )]))
+    }
+
+    /**
+    Parse `text` for capture group references. Names of groups are
+    looked up in `matcher`'s pattern(s); a reference to a group that isn't
+    in any of them (and has no `:-default`) is an error.
+    */
+    pub fn parse(text: &[u8], matcher: &dyn Matcher) -> Result<Template, String> {
+        let mut pieces: Vec<Piece> = Vec::new();
+        let mut lit: Vec<u8> = Vec::new();
+        let mut rest = text;
+
+        while let Some(n) = rest.iter().position(|&b| matches!(b, b'$' | b'\\' | b'{')) {
+            lit.extend_from_slice(&rest[..n]);
+            rest = &rest[n..];
+
+            if rest[0] == b'{' {
+                match find_placeholder(rest, matcher)? {
+                    Some((piece, len)) => {
+                        if !lit.is_empty() {
+                            pieces.push(Piece::Literal(std::mem::take(&mut lit)));
+                        }
+                        pieces.push(piece);
+                        rest = &rest[len..];
+                    }
+                    None => {
+                        lit.push(b'{');
+                        rest = &rest[1..];
+                    }
+                }
+                continue;
+            }
+
+            if rest[0] == b'\\' {
+                match find_case_op(rest) {
+                    Some(piece) => {
+                        if !lit.is_empty() {
+                            pieces.push(Piece::Literal(std::mem::take(&mut lit)));
+                        }
+                        pieces.push(piece);
+                        rest = &rest[2..];
+                    }
+                    None => {
+                        lit.push(b'\\');
+                        rest = &rest[1..];
+                    }
+                }
+                continue;
+            }
+
+            if rest.get(1) == Some(&b'$') {
+                lit.push(b'$');
+                rest = &rest[2..];
+                continue;
+            }
+            if rest.get(1) == Some(&b'[') {
+                if let Some((piece, len)) = find_calc(rest, matcher)? {
+                    if !lit.is_empty() {
+                        pieces.push(Piece::Literal(std::mem::take(&mut lit)));
+                    }
+                    pieces.push(piece);
+                    rest = &rest[len..];
+                    continue;
+                }
+            }
+            if rest.get(1) == Some(&b'#') {
+                if !lit.is_empty() {
+                    pieces.push(Piece::Literal(std::mem::take(&mut lit)));
+                }
+                pieces.push(Piece::Counter);
+                rest = &rest[2..];
+                continue;
+            }
+
+            let cap_ref = match find_cap_ref(rest) {
+                Some(cap_ref) => cap_ref,
+                None => {
+                    lit.push(b'$');
+                    rest = &rest[1..];
+                    continue;
+                }
+            };
+            rest = &rest[cap_ref.len..];
+
+            let piece = match (group_piece(cap_ref.name, matcher), cap_ref.default) {
+                (Some(piece), None) => match cap_ref.format {
+                    Some(spec) => Piece::Formatted(Box::new(piece), spec),
+                    None => piece,
+                },
+                (Some(piece), Some(default)) => Piece::OrElse(Box::new(piece), default.to_vec()),
+                (None, Some(default)) => Piece::Literal(default.to_vec()),
+                (None, None) => return Err(no_such_group(cap_ref.name, matcher)),
+            };
+            if !lit.is_empty() {
+                pieces.push(Piece::Literal(std::mem::take(&mut lit)));
+            }
+            pieces.push(piece);
+        }
+
+        lit.extend_from_slice(rest);
+        if !lit.is_empty() {
+            pieces.push(Piece::Literal(lit));
+        }
+
+        Ok(Template::new(pieces))
+    }
+
+    /**
+    Builder-pattern method for making each expansion follow the case of
+    the text it matched: all uppercase, all lowercase, or capitalized.
+    */
+    pub fn preserve_case(mut self, yes: bool) -> Self {
+        self.preserve_case = yes;
+        self
+    }
+
+    /// Builder-pattern method for making `{{now}}` the time of each
+    /// expansion, rather than the time the template was made.
+    pub fn live_time(mut self, yes: bool) -> Self {
+        self.live_time = yes;
+        self
+    }
+
+    /// Builder-pattern method for transforming each expansion, after
+    /// anything else is done to it.
+    pub fn transforms(mut self, transforms: Vec<Transform>) -> Self {
+        self.transforms = transforms;
+        self
+    }
+
+    /// Builder-pattern method for salting the text hashed by `{{sha256}}`
+    /// and `{{md5}}`.
+    pub fn hash_salt(mut self, salt: &[u8]) -> Self {
+        self.salt = salt.to_vec();
+        self
+    }
+
+    /// Start counting expansions (for `$#`) over from 1.
+    pub fn reset_count(&self) {
+        self.count.set(0);
+    }
+
+    /// Whether expansions are numbered (with `$#`).
+    pub fn counts(&self) -> bool {
+        self.pieces.iter().any(Piece::counts)
+    }
+
+    /// Whether every expansion is just the matched text, as it is (so
+    /// there's no need to expand anything).
+    pub fn is_whole_match(&self) -> bool {
+        matches!(self.pieces.as_slice(), [Piece::Group(0)])
+            && !self.preserve_case
+            && self.transforms.is_empty()
+    }
+
+    /// Append the expansion of this template for the match `caps` in
+    /// `haystack` to `dst`.
+    pub fn expand(&self, haystack: &[u8], caps: &Caps, dst: &mut Vec<u8>) {
+        let start = dst.len();
+        let count = self.count.get() + 1;
+        self.count.set(count);
+        let mut all: Option<Case> = None;
+        let mut next: Option<Case> = None;
+
+        for piece in self.pieces.iter() {
+            let span = match piece {
+                Piece::Literal(v) => {
+                    push_cased(v, all, &mut next, dst);
+                    continue;
+                }
+                Piece::Formatted(group, spec) => {
+                    if let Some((start, end)) = group.span(caps) {
+                        let text = String::from_utf8_lossy(&haystack[start..end]);
+                        push_cased(spec.apply(&text).as_bytes(), all, &mut next, dst);
+                    }
+                    continue;
+                }
+                Piece::Calc(expr, vars) => {
+                    let vals: Vec<Option<Num>> = vars
+                        .iter()
+                        .map(|var| {
+                            let (start, end) = var.span(caps)?;
+                            let text = std::str::from_utf8(&haystack[start..end]).ok()?;
+                            Num::parse(text)
+                        })
+                        .collect();
+                    if let Some(n) = expr.eval(&vals) {
+                        push_cased(n.to_string().as_bytes(), all, &mut next, dst);
+                    }
+                    continue;
+                }
+                Piece::Hash(digest) => {
+                    let (m_start, m_end) = caps.span();
+                    let mut msg = self.salt.clone();
+                    msg.extend_from_slice(&haystack[m_start..m_end]);
+                    let mut hex: Vec<u8> = Vec::with_capacity(64);
+                    digest.hex(&msg, &mut hex);
+                    push_cased(&hex, all, &mut next, dst);
+                    continue;
+                }
+                #[cfg(feature = "random")]
+                Piece::Uuid => {
+                    let mut uuid: Vec<u8> = Vec::with_capacity(36);
+                    self.rng.uuid(&mut uuid);
+                    push_cased(&uuid, all, &mut next, dst);
+                    continue;
+                }
+                #[cfg(feature = "random")]
+                Piece::Rand(charset, len) => {
+                    let mut token: Vec<u8> = Vec::with_capacity(*len);
+                    self.rng.token(*charset, *len, &mut token);
+                    push_cased(&token, all, &mut next, dst);
+                    continue;
+                }
+                Piece::Now(fmt) => {
+                    let when = if self.live_time {
+                        SystemTime::now()
+                    } else {
+                        self.made
+                    };
+                    push_cased(time::format(fmt, when).as_bytes(), all, &mut next, dst);
+                    continue;
+                }
+                Piece::Counter => {
+                    push_cased(count.to_string().as_bytes(), all, &mut next, dst);
+                    continue;
+                }
+                Piece::Repeat(arg, n) => {
+                    push_cased(&arg.text(haystack, caps).repeat(*n), all, &mut next, dst);
+                    continue;
+                }
+                Piece::Pad(arg, width, fill, left) => {
+                    let text = arg.text(haystack, caps);
+                    let len = String::from_utf8_lossy(text).chars().count();
+                    let padding: String = std::iter::repeat(*fill)
+                        .take(width.saturating_sub(len))
+                        .collect();
+                    if *left {
+                        push_cased(padding.as_bytes(), all, &mut next, dst);
+                    }
+                    push_cased(text, all, &mut next, dst);
+                    if !*left {
+                        push_cased(padding.as_bytes(), all, &mut next, dst);
+                    }
+                    continue;
+                }
+                Piece::Lookup(key, table, default) => {
+                    let value = key
+                        .span(caps)
+                        .and_then(|(start, end)| table.get(&haystack[start..end]));
+                    match (value, default) {
+                        (Some(text), _) | (None, Some(text)) => {
+                            push_cased(text, all, &mut next, dst);
+                            continue;
+                        }
+                        (None, None) => Some(caps.span()),
+                    }
+                }
+                Piece::Group(_) | Piece::Named(_) => piece.span(caps),
+                Piece::OrElse(group, default) => match group.span(caps) {
+                    Some((start, end)) if start < end => Some((start, end)),
+                    _ => {
+                        push_cased(default, all, &mut next, dst);
+                        continue;
+                    }
+                },
+                Piece::CaseAll(case) => {
+                    all = *case;
+                    continue;
+                }
+                Piece::CaseNext(case) => {
+                    next = Some(*case);
+                    continue;
+                }
+            };
+            if let Some((start, end)) = span {
+                push_cased(&haystack[start..end], all, &mut next, dst);
+            }
+        }
+
+        if self.preserve_case {
+            let (m_start, m_end) = caps.span();
+            let (all, mut next) = case_of(&haystack[m_start..m_end]);
+            if all.is_some() || next.is_some() {
+                let text = dst.split_off(start);
+                push_cased(&text, all, &mut next, dst);
+            }
+        }
+
+        if !self.transforms.is_empty() {
+            let mut text = dst.split_off(start);
+            let mut scratch: Vec<u8> = Vec::with_capacity(text.len());
+            for t in self.transforms.iter() {
+                scratch.clear();
+                t.apply(&text, &mut scratch);
+                std::mem::swap(&mut text, &mut scratch);
+            }
+            dst.extend_from_slice(&text);
+        }
+    }
+}
@@ -0,0 +1,247 @@
+/*!
+Formatting timestamps, `strftime`-style.
+
+Times are always rendered in UTC. Supported conversions:
+
+  * `%Y` year, `%y` two-digit year, `%C` century
+  * `%m` month, `%b`/`%h` month name (abbreviated), `%B` month name
+  * `%d` day of the month, `%e` same but space-padded, `%j` day of the year
+  * `%a` weekday name (abbreviated), `%A` weekday name, `%u` weekday
+    (Monday is 1), `%w` weekday (Sunday is 0)
+  * `%H` hour, `%I` 12-hour hour, `%p` AM/PM, `%M` minute, `%S` second,
+    `%N` nanoseconds
+  * `%s` seconds since the epoch, `%z` `+0000`, `%Z` `UTC`
+  * `%F` = `%Y-%m-%d`, `%T` = `%H:%M:%S`, `%D` = `%m/%d/%y`, `%R` = `%H:%M`
+  * `%n` newline, `%t` tab, `%%` percent sign
+
+Anything else is copied through unchanged.
+*/
+use std::{
+    fmt::Write,
+    time::{SystemTime, UNIX_EPOCH},
+};
+
+static MONTHS: [&str; 12] = [
+    "January",
+    "February",
+    "March",
+    "April",
+    "May",
+    "June",
+    "July",
+    "August",
+    "September",
+    "October",
+    "November",
+    "December",
+];
+
+static WEEKDAYS: [&str; 7] = [
+    "Sunday",
+    "Monday",
+    "Tuesday",
+    "Wednesday",
+    "Thursday",
+    "Friday",
+    "Saturday",
+];
+
+/// A point in time, broken down into its calendar parts.
+struct Civil {
+    year: i64,
+    /// 1-12
+    month: u32,
+    /// 1-31
+    day: u32,
+    /// 1-366
+    yday: u32,
+    /// 0-6, with Sunday as 0
+    wday: u32,
+    hour: u32,
+    minute: u32,
+    second: u32,
+    nanos: u32,
+    epoch_secs: i64,
+}
+
+fn is_leap(year: i64) -> bool {
+    (year % 4 == 0 && year % 100 != 0) || year % 400 == 0
+}
+
+impl Civil {
+    /*
+    Break down a time given as seconds since the epoch. The date
+    calculation is Howard Hinnant's `civil_from_days()`.
+    */
+    fn new(epoch_secs: i64, nanos: u32) -> Civil {
+        let days = epoch_secs.div_euclid(86_400);
+        let secs = epoch_secs.rem_euclid(86_400) as u32;
+
+        let z = days + 719_468;
+        let era = z.div_euclid(146_097);
+        let doe = z.rem_euclid(146_097);
+        let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+        let year = yoe + era * 400 + i64::from(month <= 2);
+
+        const CUMULATIVE: [u32; 12] = [0, 31, 59, 90, 120, 151, 181, 212, 243, 273, 304, 334];
+        let leap_day = u32::from(month > 2 && is_leap(year));
+        let yday = CUMULATIVE[month as usize - 1] + day + leap_day;
+
+        Civil {
+            year,
+            month,
+            day,
+            yday,
+            // 1970-01-01 was a Thursday.
+            wday: (days + 4).rem_euclid(7) as u32,
+            hour: secs / 3600,
+            minute: secs / 60 % 60,
+            second: secs % 60,
+            nanos,
+            epoch_secs,
+        }
+    }
+}
+
+/// Render `time` according to `fmt`.
+pub fn format(fmt: &str, time: SystemTime) -> String {
+    let (secs, nanos) = match time.duration_since(UNIX_EPOCH) {
+        Ok(d) => (d.as_secs() as i64, d.subsec_nanos()),
+        Err(e) => {
+            let d = e.duration();
+            match d.subsec_nanos() {
+                0 => (-(d.as_secs() as i64), 0),
+                n => (-(d.as_secs() as i64) - 1, 1_000_000_000 - n),
+            }
+        }
+    };
+    let t = Civil::new(secs, nanos);
+
+    let mut out = String::new();
+    let mut chars = fmt.chars();
+    while let Some(c) = chars.next() {
+        if c != '%' {
+            out.push(c);
+            continue;
+        }
+        // Writing to a String can't fail.
+        let _ = match chars.next() {
+            Some('Y') => write!(out, "{}", t.year),
+            Some('y') => write!(out, "{:02}", t.year.rem_euclid(100)),
+            Some('C') => write!(out, "{:02}", t.year.div_euclid(100)),
+            Some('m') => write!(out, "{:02}", t.month),
+            Some('b') | Some('h') => write!(out, "{}", &MONTHS[t.month as usize - 1][..3]),
+            Some('B') => write!(out, "{}", MONTHS[t.month as usize - 1]),
+            Some('d') => write!(out, "{:02}", t.day),
+            Some('e') => write!(out, "{:2}", t.day),
+            Some('j') => write!(out, "{:03}", t.yday),
+            Some('a') => write!(out, "{}", &WEEKDAYS[t.wday as usize][..3]),
+            Some('A') => write!(out, "{}", WEEKDAYS[t.wday as usize]),
+            Some('u') => write!(out, "{}", if t.wday == 0 { 7 } else { t.wday }),
+            Some('w') => write!(out, "{}", t.wday),
+            Some('H') => write!(out, "{:02}", t.hour),
+            Some('I') => write!(out, "{:02}", (t.hour + 11) % 12 + 1),
+            Some('p') => write!(out, "{}", if t.hour < 12 { "AM" } else { "PM" }),
+            Some('M') => write!(out, "{:02}", t.minute),
+            Some('S') => write!(out, "{:02}", t.second),
+            Some('N') => write!(out, "{:09}", t.nanos),
+            Some('s') => write!(out, "{}", t.epoch_secs),
+            Some('z') => write!(out, "+0000"),
+            Some('Z') => write!(out, "UTC"),
+            Some('F') => write!(out, "{}-{:02}-{:02}", t.year, t.month, t.day),
+            Some('T') => write!(out, "{:02}:{:02}:{:02}", t.hour, t.minute, t.second),
+            Some('D') => write!(
+                out,
+                "{:02}/{:02}/{:02}",
+                t.month,
+                t.day,
+                t.year.rem_euclid(100)
+            ),
+            Some('R') => write!(out, "{:02}:{:02}", t.hour, t.minute),
+            Some('n') => writeln!(out),
+            Some('t') => write!(out, "\t"),
+            Some('%') => write!(out, "%"),
+            Some(c) => write!(out, "%{}", c),
+            None => write!(out, "%"),
+        };
+    }
+
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    const ALL: &str = "%Y %y %C %m %b %B %d %e %j %a %A %u %w %H %I %p %M %S %s %z %Z %F %T %D %R";
+
+    fn at(epoch_secs: i64) -> SystemTime {
+        match epoch_secs {
+            0.. => UNIX_EPOCH + Duration::from_secs(epoch_secs as u64),
+            _ => UNIX_EPOCH - Duration::from_secs(epoch_secs.unsigned_abs()),
+        }
+    }
+
+    // As printed by GNU `date -u -d @SECS`.
+    #[test]
+    fn dates() {
+        let cases = [
+            (
+                0,
+                "1970 70 19 01 Jan January 01  1 001 Thu Thursday 4 4 00 12 AM 00 00 0 +0000 UTC 1970-01-01 00:00:00 01/01/70 00:00",
+            ),
+            (
+                951782400,
+                "2000 00 20 02 Feb February 29 29 060 Tue Tuesday 2 2 00 12 AM 00 00 951782400 +0000 UTC 2000-02-29 00:00:00 02/29/00 00:00",
+            ),
+            (
+                1709210096,
+                "2024 24 20 02 Feb February 29 29 060 Thu Thursday 4 4 12 12 PM 34 56 1709210096 +0000 UTC 2024-02-29 12:34:56 02/29/24 12:34",
+            ),
+            (
+                4107542399,
+                "2100 00 21 02 Feb February 28 28 059 Sun Sunday 7 0 23 11 PM 59 59 4107542399 +0000 UTC 2100-02-28 23:59:59 02/28/00 23:59",
+            ),
+            (
+                -1,
+                "1969 69 19 12 Dec December 31 31 365 Wed Wednesday 3 3 23 11 PM 59 59 -1 +0000 UTC 1969-12-31 23:59:59 12/31/69 23:59",
+            ),
+            (
+                1000000000,
+                "2001 01 20 09 Sep September 09  9 252 Sun Sunday 7 0 01 01 AM 46 40 1000000000 +0000 UTC 2001-09-09 01:46:40 09/09/01 01:46",
+            ),
+            (
+                -2208988800,
+                "1900 00 19 01 Jan January 01  1 001 Mon Monday 1 1 00 12 AM 00 00 -2208988800 +0000 UTC 1900-01-01 00:00:00 01/01/00 00:00",
+            ),
+        ];
+        for (secs, expected) in cases {
+            assert_eq!(format(ALL, at(secs)), expected, "{}", secs);
+        }
+    }
+
+    #[test]
+    fn fractions_of_seconds() {
+        let time = UNIX_EPOCH + Duration::new(1, 5_000);
+        assert_eq!(format("%s.%N", time), "1.000005000");
+        // Before the epoch, the fraction still counts up from the second.
+        let time = UNIX_EPOCH - Duration::from_millis(250);
+        assert_eq!(format("%T.%N %s", time), "23:59:59.750000000 -1");
+    }
+
+    #[test]
+    fn other_text() {
+        let time = at(0);
+        assert_eq!(
+            format("100%% at %F%n%t%q", time),
+            "100% at 1970-01-01\n\t%q"
+        );
+        assert_eq!(format("end %", time), "end %");
+        assert_eq!(format("", time), "");
+    }
+}
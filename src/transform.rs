@@ -0,0 +1,275 @@
+/*!
+Transformations applied to the text that replaces (or, when extracting,
+stands for) each match, after any capture groups have been expanded.
+
+Transforms are given by name on the command line and applied in order.
+Any that can't make sense of their input leave it unchanged.
+*/
+use std::str::FromStr;
+
+use crate::escape::hex_digit;
+
+static BASE64: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transform {
+    Base64Enc,
+    Base64Dec,
+    UrlEnc,
+    UrlDec,
+    HtmlEscape,
+    HtmlUnescape,
+    Trim,
+    Squeeze,
+    CollapseWs,
+    JsonEscape,
+}
+
+impl FromStr for Transform {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "base64enc" => Ok(Transform::Base64Enc),
+            "base64dec" => Ok(Transform::Base64Dec),
+            "urlenc" => Ok(Transform::UrlEnc),
+            "urldec" => Ok(Transform::UrlDec),
+            "htmlescape" => Ok(Transform::HtmlEscape),
+            "htmlunescape" => Ok(Transform::HtmlUnescape),
+            "trim" => Ok(Transform::Trim),
+            "squeeze" => Ok(Transform::Squeeze),
+            "collapse-ws" => Ok(Transform::CollapseWs),
+            "jsonescape" => Ok(Transform::JsonEscape),
+            _ => Err(format!(
+                "unknown transform {:?} (expected one of base64enc, base64dec, \
+                urlenc, urldec, htmlescape, htmlunescape, trim, squeeze, collapse-ws, jsonescape)",
+                s
+            )),
+        }
+    }
+}
+
+fn base64_encode(input: &[u8], out: &mut Vec<u8>) {
+    for chunk in input.chunks(3) {
+        let b = [
+            chunk[0],
+            chunk.get(1).copied().unwrap_or(0),
+            chunk.get(2).copied().unwrap_or(0),
+        ];
+        let n = (u32::from(b[0]) << 16) | (u32::from(b[1]) << 8) | u32::from(b[2]);
+        for i in 0..4 {
+            if i <= chunk.len() {
+                out.push(BASE64[(n >> (18 - 6 * i) & 0x3f) as usize]);
+            } else {
+                out.push(b'=');
+            }
+        }
+    }
+}
+
+fn base64_decode(input: &[u8]) -> Option<Vec<u8>> {
+    let mut out: Vec<u8> = Vec::with_capacity(input.len() * 3 / 4);
+    let mut acc: u32 = 0;
+    let mut n_bits: u32 = 0;
+    let mut padding = false;
+
+    for &b in input.iter() {
+        let val = match b {
+            b'A'..=b'Z' => b - b'A',
+            b'a'..=b'z' => b - b'a' + 26,
+            b'0'..=b'9' => b - b'0' + 52,
+            b'+' | b'-' => 62,
+            b'/' | b'_' => 63,
+            b'=' => {
+                padding = true;
+                continue;
+            }
+            _ if b.is_ascii_whitespace() => continue,
+            _ => return None,
+        };
+        if padding {
+            return None;
+        }
+        acc = (acc << 6) | u32::from(val);
+        n_bits += 6;
+        if n_bits >= 8 {
+            n_bits -= 8;
+            out.push((acc >> n_bits) as u8);
+        }
+    }
+
+    Some(out)
+}
+
+/// Whether `b` can go in a URL without being percent-encoded.
+fn is_url_safe(b: u8) -> bool {
+    b.is_ascii_alphanumeric() || matches!(b, b'-' | b'.' | b'_' | b'~')
+}
+
+fn url_encode(input: &[u8], out: &mut Vec<u8>) {
+    const HEX: &[u8; 16] = b"0123456789ABCDEF";
+    for &b in input.iter() {
+        if is_url_safe(b) {
+            out.push(b);
+        } else {
+            out.extend_from_slice(&[b'%', HEX[(b >> 4) as usize], HEX[(b & 0xf) as usize]]);
+        }
+    }
+}
+
+fn url_decode(input: &[u8], out: &mut Vec<u8>) {
+    let mut n: usize = 0;
+    while let Some(&b) = input.get(n) {
+        let hex = input.get(n + 1..n + 3).and_then(|h| {
+            let hi = hex_digit(h[0])?;
+            let lo = hex_digit(h[1])?;
+            Some(hi * 16 + lo)
+        });
+        match hex {
+            Some(decoded) if b == b'%' => {
+                out.push(decoded);
+                n += 3;
+            }
+            _ => {
+                out.push(b);
+                n += 1;
+            }
+        }
+    }
+}
+
+fn html_escape(input: &[u8], out: &mut Vec<u8>) {
+    for &b in input.iter() {
+        match b {
+            b'&' => out.extend_from_slice(b"&amp;"),
+            b'<' => out.extend_from_slice(b"&lt;"),
+            b'>' => out.extend_from_slice(b"&gt;"),
+            b'"' => out.extend_from_slice(b"&quot;"),
+            b'\'' => out.extend_from_slice(b"&#39;"),
+            _ => out.push(b),
+        }
+    }
+}
+
+/*
+Decode the character entity (without its `&` and `;`) `name`.
+*/
+fn html_entity(name: &str) -> Option<char> {
+    match name {
+        "amp" => Some('&'),
+        "lt" => Some('<'),
+        "gt" => Some('>'),
+        "quot" => Some('"'),
+        "apos" => Some('\''),
+        "nbsp" => Some('\u{a0}'),
+        _ => {
+            let num = name.strip_prefix('#')?;
+            let code = match num.strip_prefix(|c| c == 'x' || c == 'X') {
+                Some(hex) => u32::from_str_radix(hex, 16).ok()?,
+                None => num.parse::<u32>().ok()?,
+            };
+            char::from_u32(code)
+        }
+    }
+}
+
+fn html_unescape(input: &[u8], out: &mut Vec<u8>) {
+    let mut n: usize = 0;
+    while let Some(&b) = input.get(n) {
+        if b == b'&' {
+            // Entities are short; don't look too far for the `;`.
+            let window = &input[n + 1..input.len().min(n + 12)];
+            let decoded = window.iter().position(|&b| b == b';').and_then(|end| {
+                let name = std::str::from_utf8(&window[..end]).ok()?;
+                Some((html_entity(name)?, end))
+            });
+            if let Some((c, end)) = decoded {
+                let mut utf8 = [0u8; 4];
+                out.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+                n += end + 2;
+                continue;
+            }
+        }
+        out.push(b);
+        n += 1;
+    }
+}
+
+/*
+Escape `input` for use inside a JSON string literal. Bytes that aren't
+part of valid UTF-8 become U+FFFD, since JSON can't represent them.
+*/
+fn json_escape(input: &[u8], out: &mut Vec<u8>) {
+    const HEX: &[u8; 16] = b"0123456789abcdef";
+    for c in String::from_utf8_lossy(input).chars() {
+        match c {
+            '"' => out.extend_from_slice(b"\\\""),
+            '\\' => out.extend_from_slice(b"\\\\"),
+            '\n' => out.extend_from_slice(b"\\n"),
+            '\r' => out.extend_from_slice(b"\\r"),
+            '\t' => out.extend_from_slice(b"\\t"),
+            '\u{8}' => out.extend_from_slice(b"\\b"),
+            '\u{c}' => out.extend_from_slice(b"\\f"),
+            c if c < ' ' => {
+                let b = c as u8;
+                out.extend_from_slice(b"\\u00");
+                out.extend_from_slice(&[HEX[(b >> 4) as usize], HEX[(b & 0xf) as usize]]);
+            }
+            c => {
+                let mut utf8 = [0u8; 4];
+                out.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+            }
+        }
+    }
+}
+
+fn trim(input: &[u8]) -> &[u8] {
+    let start = input
+        .iter()
+        .position(|b| !b.is_ascii_whitespace())
+        .unwrap_or(input.len());
+    let end = input
+        .iter()
+        .rposition(|b| !b.is_ascii_whitespace())
+        .map_or(start, |n| n + 1);
+    &input[start..end]
+}
+
+/*
+Replace each run of (ASCII) whitespace with a single space.
+*/
+fn squeeze(input: &[u8], out: &mut Vec<u8>) {
+    let mut in_space = false;
+    for &b in input.iter() {
+        if b.is_ascii_whitespace() {
+            if !in_space {
+                out.push(b' ');
+            }
+            in_space = true;
+        } else {
+            out.push(b);
+            in_space = false;
+        }
+    }
+}
+
+impl Transform {
+    /// Append the transformed `input` to `out`.
+    pub fn apply(&self, input: &[u8], out: &mut Vec<u8>) {
+        match self {
+            Transform::Base64Enc => base64_encode(input, out),
+            Transform::Base64Dec => match base64_decode(input) {
+                Some(decoded) => out.extend_from_slice(&decoded),
+                None => out.extend_from_slice(input),
+            },
+            Transform::UrlEnc => url_encode(input, out),
+            Transform::UrlDec => url_decode(input, out),
+            Transform::HtmlEscape => html_escape(input, out),
+            Transform::HtmlUnescape => html_unescape(input, out),
+            Transform::Trim => out.extend_from_slice(trim(input)),
+            Transform::Squeeze => squeeze(input, out),
+            Transform::CollapseWs => squeeze(trim(input), out),
+            Transform::JsonEscape => json_escape(input, out),
+        }
+    }
+}
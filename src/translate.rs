@@ -0,0 +1,167 @@
+/*!
+Character-by-character transliteration, like `tr`.
+
+Each character in the FROM set is replaced by the character at the same
+position in the TO set. Sets may contain ranges like `a-z`, and `\`
+escapes the following character (with `\n`, `\t`, `\r`, and `\0` also
+recognized). If TO is shorter than FROM, its last character is repeated
+to make up the difference.
+*/
+use std::{
+    collections::HashMap,
+    io::{Read, Write},
+};
+
+use crate::FrErr;
+
+/// How much input to read at a time.
+const BUFF_SIZE: usize = 64 * 1024;
+
+#[derive(Clone, Debug)]
+pub struct Translation {
+    map: HashMap<char, char>,
+}
+
+/*
+Expand a set like `a-z_\-` into the characters it contains, in order.
+*/
+fn expand_set(set: &str) -> Result<Vec<char>, String> {
+    let mut chars: Vec<char> = Vec::new();
+    // Whether each character was escaped, and so can't be part of a range.
+    let mut literal: Vec<bool> = Vec::new();
+    let mut it = set.chars();
+    while let Some(c) = it.next() {
+        if c == '\\' {
+            let c = match it.next() {
+                Some('n') => '\n',
+                Some('t') => '\t',
+                Some('r') => '\r',
+                Some('0') => '\0',
+                Some(c) => c,
+                None => '\\',
+            };
+            chars.push(c);
+            literal.push(true);
+        } else {
+            chars.push(c);
+            literal.push(false);
+        }
+    }
+
+    let mut out: Vec<char> = Vec::with_capacity(chars.len());
+    let mut n: usize = 0;
+    while n < chars.len() {
+        // A `-` at either end of the set stands for itself.
+        let is_range = n + 2 < chars.len() && chars[n + 1] == '-' && !literal[n + 1];
+        if is_range {
+            let (lo, hi) = (chars[n], chars[n + 2]);
+            if lo > hi {
+                return Err(format!("range {}-{} is backwards", lo, hi));
+            }
+            out.extend(lo..=hi);
+            n += 3;
+        } else {
+            out.push(chars[n]);
+            n += 1;
+        }
+    }
+
+    Ok(out)
+}
+
+impl Translation {
+    pub fn new(from: &str, to: &str) -> Result<Self, String> {
+        let from = expand_set(from)?;
+        let to = expand_set(to)?;
+        let last = match to.last() {
+            Some(&c) => c,
+            None if from.is_empty() => {
+                return Ok(Translation {
+                    map: HashMap::new(),
+                })
+            }
+            None => return Err("nothing to translate characters into".into()),
+        };
+
+        let map = from
+            .into_iter()
+            .zip(to.into_iter().chain(std::iter::repeat(last)))
+            .collect();
+        Ok(Translation { map })
+    }
+
+    /* Returns how many characters of `s` were in the set to translate. */
+    fn map_str(&self, s: &str, dst: &mut Vec<u8>) -> usize {
+        let mut utf8 = [0u8; 4];
+        let mut found: usize = 0;
+        for c in s.chars() {
+            let c = match self.map.get(&c) {
+                Some(&to) => {
+                    found += 1;
+                    to
+                }
+                None => c,
+            };
+            dst.extend_from_slice(c.encode_utf8(&mut utf8).as_bytes());
+        }
+        found
+    }
+
+    /**
+    Copy `input` to `output`, translating characters along the way, and
+    return how many there were to translate. Bytes that aren't valid UTF-8
+    are passed through unchanged.
+    */
+    pub fn translate(&self, input: &mut dyn Read, output: &mut dyn Write) -> Result<usize, FrErr> {
+        let mut inbuff: Vec<u8> = vec![0; BUFF_SIZE];
+        let mut outbuff: Vec<u8> = Vec::with_capacity(BUFF_SIZE);
+        // Length of an incomplete character left at the end of the last read.
+        let mut carry: usize = 0;
+        let mut found: usize = 0;
+
+        loop {
+            let n_read = input.read(&mut inbuff[carry..])?;
+            let end = carry + n_read;
+            let mut data = &inbuff[..end];
+
+            loop {
+                match std::str::from_utf8(data) {
+                    Ok(s) => {
+                        found += self.map_str(s, &mut outbuff);
+                        data = &[];
+                        break;
+                    }
+                    Err(e) => {
+                        let (good, bad) = data.split_at(e.valid_up_to());
+                        // This can't fail; it's already been checked.
+                        found += self.map_str(std::str::from_utf8(good).unwrap(), &mut outbuff);
+                        match e.error_len() {
+                            Some(n) => {
+                                outbuff.extend_from_slice(&bad[..n]);
+                                data = &bad[n..];
+                            }
+                            None => {
+                                data = bad;
+                                break;
+                            }
+                        }
+                    }
+                }
+            }
+
+            output.write_all(&outbuff)?;
+            outbuff.clear();
+
+            if n_read == 0 {
+                // Whatever's left over at the end is never going to be
+                // completed.
+                output.write_all(data)?;
+                break;
+            }
+            carry = data.len();
+            inbuff.copy_within(end - carry..end, 0);
+        }
+
+        Ok(found)
+    }
+}
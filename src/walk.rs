@@ -0,0 +1,253 @@
+/*!
+Finding the files to process under the directories given to -r.
+
+This follows the same defaults as `git` and `ripgrep`: anything matched
+by a `.gitignore` (in the directory being searched or one above it, up
+to the directory the walk started from) is skipped, as are hidden files
+and directories, and (unless asked otherwise) files that look binary.
+
+Supported `.gitignore` syntax is the usual: blank lines and lines that
+start with `#` are ignored; `!` negates a pattern; a trailing `/` makes
+a pattern match only directories; a pattern containing a `/` (other than
+a trailing one) is matched against the path relative to the
+`.gitignore`'s directory, and any other pattern against file names
+alone. Patterns are globs (see the `glob` module) in which `*` doesn't
+match `/` but `**` matches any number of directories.
+
+It's not everything `git` or ripgrep would ignore, though. Only
+`.gitignore` files are read: not `.git/info/exclude`, the global excludes
+file, `.ignore` files, or any `.gitignore` above the directory the walk
+started from. Symbolic links are followed to files, but not to
+directories.
+
+The files found can be further filtered by --include and --exclude globs,
+which work the same way, relative to the directory the walk started from.
+A file is processed if it matches no --exclude glob and (when there are
+any) some --include glob; directories matching an --exclude glob aren't
+searched at all.
+*/
+use std::{
+    cell::Cell,
+    fs,
+    path::{Path, PathBuf},
+};
+
+use regex::Regex;
+
+use crate::{encoding::Encoding, glob, input::is_binary};
+
+struct Rule {
+    re: Regex,
+    negate: bool,
+    dir_only: bool,
+}
+
+/// The rules from one `.gitignore` file.
+struct Ignore {
+    /// The directory the file is in.
+    base: PathBuf,
+    rules: Vec<Rule>,
+}
+
+/**
+Make the regex that matches (`/`-separated, relative) paths the way the
+given `.gitignore`-style glob does.
+*/
+pub fn path_filter(patt: &str) -> Result<Regex, regex::Error> {
+    let patt = patt.trim_end_matches('/');
+    if patt.contains('/') {
+        Regex::new(&glob::path_regex(patt.trim_start_matches('/')))
+    } else {
+        Regex::new(&glob::path_regex(&format!("**/{}", patt)))
+    }
+}
+
+/*
+The path of `path` relative to `base`, with `/` separators.
+*/
+fn relative(path: &Path, base: &Path) -> Option<String> {
+    let rel = path.strip_prefix(base).ok()?;
+    let rel: Vec<_> = rel.iter().map(|c| c.to_string_lossy()).collect();
+    Some(rel.join("/"))
+}
+
+/*
+Parse a line of a `.gitignore`, if it's a pattern.
+*/
+fn parse_rule(line: &str) -> Option<Rule> {
+    let line = line.trim_end();
+    if line.is_empty() || line.starts_with('#') {
+        return None;
+    }
+    let (negate, patt) = match line.strip_prefix('!') {
+        Some(patt) => (true, patt),
+        None => (false, line),
+    };
+    let (dir_only, patt) = match patt.strip_suffix('/') {
+        Some(patt) => (true, patt),
+        None => (false, patt),
+    };
+    Some(Rule {
+        re: path_filter(patt).ok()?,
+        negate,
+        dir_only,
+    })
+}
+
+impl Ignore {
+    /// Read the `.gitignore` in `dir`, if there is one.
+    fn read(dir: &Path) -> Option<Ignore> {
+        let text = fs::read_to_string(dir.join(".gitignore")).ok()?;
+        Some(Ignore {
+            base: dir.to_path_buf(),
+            rules: text.lines().filter_map(parse_rule).collect(),
+        })
+    }
+
+    /**
+    Whether this file says `path` should be ignored (`Some(true)`), or
+    explicitly not ignored (`Some(false)`); `None` if it doesn't say.
+    */
+    fn ignores(&self, path: &Path, is_dir: bool) -> Option<bool> {
+        let rel = relative(path, &self.base)?;
+        self.rules
+            .iter()
+            .rev()
+            .find(|rule| (is_dir || !rule.dir_only) && rule.re.is_match(&rel))
+            .map(|rule| !rule.negate)
+    }
+}
+
+pub struct Walk<'a> {
+    /// Whether to include binary files.
+    binary: bool,
+    /// What files are decoded from before telling whether they're binary.
+    encoding: Option<Encoding>,
+    /// If not empty, only files matching one of these are included.
+    include: Vec<Regex>,
+    /// Files (and directories) matching any of these are excluded.
+    exclude: Vec<Regex>,
+    /// Incremented for each file or directory that can't be read.
+    failures: &'a Cell<usize>,
+}
+
+impl<'a> Walk<'a> {
+    pub fn new(binary: bool, failures: &'a Cell<usize>) -> Walk<'a> {
+        Walk {
+            binary,
+            encoding: None,
+            include: Vec::new(),
+            exclude: Vec::new(),
+            failures,
+        }
+    }
+
+    /// Builder-pattern method for reading files in the given encoding
+    /// when telling whether they're binary.
+    pub fn encoding(mut self, encoding: Option<Encoding>) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Builder-pattern method for only including files matching one of
+    /// `include` (see `path_filter()`).
+    pub fn include(mut self, include: Vec<Regex>) -> Self {
+        self.include = include;
+        self
+    }
+
+    /// Builder-pattern method for excluding files (and directories)
+    /// matching any of `exclude` (see `path_filter()`).
+    pub fn exclude(mut self, exclude: Vec<Regex>) -> Self {
+        self.exclude = exclude;
+        self
+    }
+
+    /*
+    Whether --include and --exclude let through `path`, found under `root`.
+    */
+    fn filters_allow(&self, path: &Path, root: &Path, is_dir: bool) -> bool {
+        let rel = match relative(path, root) {
+            Some(rel) => rel,
+            None => return true,
+        };
+        if self.exclude.iter().any(|re| re.is_match(&rel)) {
+            return false;
+        }
+        is_dir || self.include.is_empty() || self.include.iter().any(|re| re.is_match(&rel))
+    }
+
+    fn fail(&self, path: &Path, e: std::io::Error) {
+        eprintln!("{}: {}", path.display(), e);
+        self.failures.set(self.failures.get() + 1);
+    }
+
+    /**
+    Append every file to be processed under `root` (or `root` itself, if
+    it isn't a directory) to `files`, in order by name.
+    */
+    pub fn files(&self, root: &Path, files: &mut Vec<PathBuf>) {
+        if root.is_dir() {
+            let mut ignores: Vec<Ignore> = Vec::new();
+            self.visit(root, root, &mut ignores, files);
+        } else {
+            files.push(root.to_path_buf());
+        }
+    }
+
+    fn visit(&self, dir: &Path, root: &Path, ignores: &mut Vec<Ignore>, files: &mut Vec<PathBuf>) {
+        let entries = match fs::read_dir(dir) {
+            Ok(entries) => entries,
+            Err(e) => return self.fail(dir, e),
+        };
+        let mut entries: Vec<_> = entries.filter_map(|e| e.ok()).collect();
+        entries.sort_by_key(|e| e.file_name());
+
+        let ignore = Ignore::read(dir);
+        let pushed = ignore.is_some();
+        ignores.extend(ignore);
+
+        for entry in entries {
+            let path = entry.path();
+            if entry.file_name().to_string_lossy().starts_with('.') {
+                continue;
+            }
+            // Symbolic links are only followed to files.
+            let is_dir = match entry.file_type() {
+                Ok(ft) if ft.is_symlink() => match fs::metadata(&path) {
+                    Ok(meta) if meta.is_file() => false,
+                    _ => continue,
+                },
+                Ok(ft) => ft.is_dir(),
+                Err(e) => {
+                    self.fail(&path, e);
+                    continue;
+                }
+            };
+            let ignored = ignores
+                .iter()
+                .rev()
+                .find_map(|ignore| ignore.ignores(&path, is_dir))
+                .unwrap_or(false);
+            if ignored || !self.filters_allow(&path, root, is_dir) {
+                continue;
+            }
+
+            if is_dir {
+                self.visit(&path, root, ignores, files);
+            } else if self.binary {
+                files.push(path);
+            } else {
+                match is_binary(&path, self.encoding) {
+                    Ok(false) => files.push(path),
+                    Ok(true) => {}
+                    Err(e) => self.fail(&path, e),
+                }
+            }
+        }
+
+        if pushed {
+            ignores.pop();
+        }
+    }
+}
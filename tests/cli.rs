@@ -0,0 +1,201 @@
+/*!
+Running the `fresh` binary on files, for what's hard to get at from
+inside: exit statuses, and inputs that have to come from files.
+*/
+use std::{
+    fs,
+    io::Write,
+    path::PathBuf,
+    process::{Command, Output, Stdio},
+    thread,
+    time::{Duration, Instant},
+};
+
+/* A fresh directory for `test` to put its files in. */
+fn scratch(test: &str) -> PathBuf {
+    let dir = std::env::temp_dir().join(format!("fresh-test-{}-{}", test, std::process::id()));
+    let _ = fs::remove_dir_all(&dir);
+    fs::create_dir_all(&dir).unwrap();
+    dir
+}
+
+/* Run fresh with `args`, with nothing on stdin. */
+fn fresh(args: &[&str]) -> Output {
+    Command::new(env!("CARGO_BIN_EXE_fresh"))
+        .args(args)
+        .stdin(Stdio::null())
+        .output()
+        .unwrap()
+}
+
+/* Run fresh with `args`, with `input` on stdin. */
+fn fresh_with(args: &[&str], input: &[u8]) -> Output {
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fresh"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .unwrap();
+    child.stdin.take().unwrap().write_all(input).unwrap();
+    child.wait_with_output().unwrap()
+}
+
+/* A tar archive of regular files with these names and contents. */
+fn tar(members: &[(&str, &[u8])]) -> Vec<u8> {
+    let mut archive: Vec<u8> = Vec::new();
+    for (name, data) in members {
+        let mut header = [0u8; 512];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        header[100..108].copy_from_slice(b"0000644\0");
+        header[124..136].copy_from_slice(format!("{:011o}\0", data.len()).as_bytes());
+        header[136..148].copy_from_slice(b"00000000000\0");
+        header[156] = b'0';
+        header[148..156].copy_from_slice(b"        ");
+        let sum: u32 = header.iter().map(|&b| u32::from(b)).sum();
+        header[148..156].copy_from_slice(format!("{:06o}\0 ", sum).as_bytes());
+        archive.extend_from_slice(&header);
+        archive.extend_from_slice(data);
+        archive.resize(archive.len().div_ceil(512) * 512, 0);
+    }
+    archive.resize(archive.len() + 1024, 0);
+    archive
+}
+
+#[test]
+fn archive_members_without_newlines() {
+    let dir = scratch("archive");
+    let path = dir.join("b.tar");
+    fs::write(
+        &path,
+        tar(&[("a.txt", b"ERROR one"), ("b.txt", b"ERROR two")]),
+    )
+    .unwrap();
+    let path = path.to_str().unwrap();
+    let out = fresh(&["--archive", "ERROR", "X", path]);
+    let expected = format!("{0}:a.txt:X one\n{0}:b.txt:X two\n", path);
+    assert_eq!(String::from_utf8_lossy(&out.stdout), expected);
+    assert_eq!(out.status.code(), Some(0));
+}
+
+#[test]
+fn archive_members_named_from_dot() {
+    let dir = scratch("archive-dot");
+    let path = dir.join("c.tar");
+    let members: &[(&str, &[u8])] = &[
+        ("./logs/a.log", b"ERROR one\n"),
+        ("././b.log", b"ERROR two\n"),
+    ];
+    fs::write(&path, tar(members)).unwrap();
+    let path = path.to_str().unwrap();
+    let out = fresh(&[
+        "--archive",
+        "--members",
+        "logs/**",
+        "-x",
+        "ERROR.*",
+        "-i",
+        path,
+    ]);
+    assert_eq!(
+        String::from_utf8_lossy(&out.stdout),
+        format!("{}:logs/a.log:ERROR one\n", path)
+    );
+}
+
+#[test]
+fn exit_statuses() {
+    let out = fresh_with(&["cat", "dog"], b"a cat\nnone\n");
+    assert_eq!(out.stdout, b"a dog\nnone\n");
+    assert_eq!(out.status.code(), Some(0));
+
+    // Input without a match is still passed through.
+    let out = fresh_with(&["cow", "dog"], b"a cat\nnone\n");
+    assert_eq!(out.stdout, b"a cat\nnone\n");
+    assert_eq!(out.status.code(), Some(1));
+
+    let out = fresh_with(&["-q", "cat"], b"a cat\n");
+    assert_eq!(
+        (out.stdout.as_slice(), out.status.code()),
+        (&b""[..], Some(0))
+    );
+    let out = fresh_with(&["-q", "cow"], b"a cat\n");
+    assert_eq!(
+        (out.stdout.as_slice(), out.status.code()),
+        (&b""[..], Some(1))
+    );
+    let out = fresh_with(&["-q", "cat"], b"");
+    assert_eq!(out.status.code(), Some(1));
+}
+
+#[test]
+fn error_statuses() {
+    let out = fresh_with(&["-q", "(unclosed", "x"], b"a cat\n");
+    assert_eq!(out.status.code(), Some(2));
+    assert!(!out.stderr.is_empty());
+
+    let dir = scratch("errors");
+    let missing = dir.join("missing.txt");
+    let out = fresh(&["-q", "cat", "-i", missing.to_str().unwrap()]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("missing.txt"));
+
+    let out = fresh(&["--no-such-option", "cat"]);
+    assert_eq!(out.status.code(), Some(2));
+}
+
+#[test]
+fn quiet_stops_at_first_match() {
+    // The input never ends, so this only finishes if fresh stops reading.
+    let mut child = Command::new(env!("CARGO_BIN_EXE_fresh"))
+        .args(["-q", "TODO"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .spawn()
+        .unwrap();
+    let mut stdin = child.stdin.take().unwrap();
+    stdin.write_all(b"one\nTODO: two\n").unwrap();
+    stdin.flush().unwrap();
+    let start = Instant::now();
+    let status = loop {
+        if let Some(status) = child.try_wait().unwrap() {
+            break status;
+        }
+        if start.elapsed() > Duration::from_secs(10) {
+            child.kill().unwrap();
+            panic!("fresh -q kept reading after a match");
+        }
+        thread::sleep(Duration::from_millis(10));
+    };
+    assert_eq!(status.code(), Some(0));
+    drop(stdin);
+}
+
+#[test]
+fn threads_refuse_counting_across_blocks() {
+    let dir = scratch("threads");
+    let path = dir.join("numbers.txt");
+    fs::write(&path, "1\n2\n10\n").unwrap();
+    let path = path.to_str().unwrap();
+    let out = fresh(&["--threads", "2", "1", "$#", path]);
+    assert_eq!(out.status.code(), Some(2));
+    assert!(String::from_utf8_lossy(&out.stderr).contains("--count-per-line"));
+    let out = fresh(&["--threads", "2", "--count-per-line", "1", "<$#>", path]);
+    assert_eq!(out.stdout, b"<1>\n2\n<1>0\n");
+
+    // The text around records is counted across the whole input, even
+    // with --count-per-line.
+    for text in ["--before-text", "--after-text"] {
+        for args in [
+            &["--threads", "2"][..],
+            &["--threads", "2", "--count-per-line"],
+        ] {
+            let out = fresh(&[args, &[text, "<$#>", "1", "x", path]].concat());
+            assert_eq!(out.status.code(), Some(2), "{} {:?}", text, args);
+            assert!(String::from_utf8_lossy(&out.stderr).contains(text));
+        }
+    }
+    let out = fresh(&["--threads", "2", "--before-text", "<$1>", "(1)", "x", path]);
+    assert_eq!(out.stdout, b"<1>\nx\n2\n<1>\nx0\n");
+    assert_eq!(out.status.code(), Some(0));
+}